@@ -0,0 +1,70 @@
+//! Runs arbitrary sequences of reads and seeks over arbitrary data
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rustune::bytereader::{ByteReader, Encoding};
+
+#[derive(Debug, Clone, Copy, Arbitrary)]
+enum Operation {
+    Seek(usize),
+    ReadBytes(usize),
+    ReadStr(usize),
+    ReadPaddedStr(usize, u8),
+    ReadCstr(usize),
+    PeekBytes(usize),
+    PeekU8,
+    ReadU8,
+    ReadI8,
+    ReadU16,
+    ReadI16,
+    ReadU24,
+    ReadU32,
+    ReadI32,
+    ReadF32,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input<'a> {
+    little_endian: bool,
+    data: &'a [u8],
+    operations: Vec<Operation>,
+}
+
+fuzz_target!(|input: Input| {
+    let encoding = match input.little_endian {
+        true => Encoding::LittleEndian,
+        false => Encoding::BigEndian,
+    };
+    let mut reader = ByteReader::new(input.data, encoding);
+
+    for operation in input.operations {
+        let before = reader.position();
+        let read = match operation {
+            Operation::Seek(position) => reader.seek(position).map(|_| ()),
+            Operation::ReadBytes(count) => reader.read_bytes(count).map(|_| ()),
+            Operation::ReadStr(length) => reader.read_str(length).map(|_| ()),
+            Operation::ReadPaddedStr(length, pad) => {
+                reader.read_padded_str(length, pad).map(|_| ())
+            }
+            Operation::ReadCstr(max) => reader.read_cstr(max).map(|_| ()),
+            Operation::PeekBytes(count) => reader.peek_bytes(count).map(|_| ()),
+            Operation::PeekU8 => reader.peek_u8().map(|_| ()),
+            Operation::ReadU8 => reader.read_u8().map(|_| ()),
+            Operation::ReadI8 => reader.read_i8().map(|_| ()),
+            Operation::ReadU16 => reader.read_u16().map(|_| ()),
+            Operation::ReadI16 => reader.read_i16().map(|_| ()),
+            Operation::ReadU24 => reader.read_u24().map(|_| ()),
+            Operation::ReadU32 => reader.read_u32().map(|_| ()),
+            Operation::ReadI32 => reader.read_i32().map(|_| ()),
+            Operation::ReadF32 => reader.read_f32().map(|_| ()),
+        };
+
+        // Peeks and failed reads leave the position alone, and it never passes the end
+        if read.is_err() || matches!(operation, Operation::PeekBytes(_) | Operation::PeekU8) {
+            assert_eq!(reader.position(), before);
+        }
+        assert!(reader.position() <= input.data.len());
+    }
+});
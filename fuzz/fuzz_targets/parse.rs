@@ -0,0 +1,19 @@
+//! Loads arbitrary files as modules, every way the library can look at them
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustune::formats::{mod_loader, mod_validator, mod_writer};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rustune::detect(data);
+    let _ = mod_validator::validate(data);
+
+    let Ok(song) = mod_loader::parse(data.to_vec()) else {
+        return;
+    };
+
+    // Whatever loads either saves again, or says why it can't
+    let _ = mod_writer::write(&song);
+    let _ = song.cells().count();
+});
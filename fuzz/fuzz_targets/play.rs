@@ -0,0 +1,32 @@
+//! Plays the start of arbitrary files that load as modules
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustune::formats::mod_loader;
+use rustune::{Engine, TrackerEngine};
+
+// About a second at the sample rate below, enough to get through a few rows
+const FRAMES: usize = 8000;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(song) = mod_loader::parse(data.to_vec()) else {
+        return;
+    };
+    let Ok(mut engine) = Engine::new(song) else {
+        return;
+    };
+
+    engine.set_channel_count(2);
+    engine.set_sample_rate(8000);
+
+    let mut buffer = vec![0.0; 512];
+    let mut frames = 0;
+    while frames < FRAMES && !engine.is_finished() {
+        frames += engine.render(&mut buffer).max(1);
+    }
+
+    // Seeking plays through the song up to the position without mixing
+    let (order, row) = engine.position();
+    engine.seek(order / 2, row / 2);
+});
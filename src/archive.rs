@@ -0,0 +1,549 @@
+//! Reading files out of ZIP archives, the way module collections are usually passed around
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::bitreader::BitReader;
+use crate::bytereader::{ByteReader, Codepage, Encoding};
+use crate::song::SongError;
+
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const CENTRAL_HEADER: u32 = 0x0201_4B50;
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+// The end of the central directory is this long, followed by a comment of up to 64 KiB
+const END_OF_DIRECTORY_SIZE: usize = 22;
+
+// General purpose flags
+const ENCRYPTED: u16 = 1;
+const UTF8_NAME: u16 = 1 << 11;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// A file in an [`Archive`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Path in the archive, with `/` between directories
+    pub name: String,
+    /// Size of the file once it's extracted
+    pub size: usize,
+    compressed_size: usize,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    // Where the local header of the file starts
+    offset: usize,
+}
+
+/// A ZIP archive in memory, borrowed or owned, whose files are extracted when they're read.
+///
+/// Files can be stored or compressed with deflate, which is what every ZIP tool writes by
+/// default. Encrypted files, other compression methods and ZIP64 archives of more than 4 GiB
+/// aren't supported.
+#[derive(Debug)]
+pub struct Archive<D> {
+    data: D,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl<D: AsRef<[u8]>> Archive<D> {
+    /// Reads the central directory, the list of files at the end of the archive
+    ///
+    /// # Errors
+    /// When `data` isn't a ZIP archive or its directory is cut short
+    pub fn new(data: D) -> Result<Self, SongError> {
+        let bytes = data.as_ref();
+        let mut reader = ByteReader::new(bytes, Encoding::LittleEndian);
+
+        // The end of the directory is found by its signature, searching back over the comment
+        let search = bytes.len().min(END_OF_DIRECTORY_SIZE + u16::MAX as usize);
+        let end = (END_OF_DIRECTORY_SIZE..=search)
+            .map(|distance| bytes.len() - distance)
+            .find(|&start| bytes[start..start + 4] == END_OF_DIRECTORY.to_le_bytes())
+            .ok_or(SongError::UnknownFormat)?;
+
+        // Skips the disk numbers and the entries on this disk
+        reader.seek(end + 10)?;
+        let count = reader.read_u16()? as usize;
+        let _directory_size = reader.read_u32()?;
+        let directory = reader.read_u32()?;
+        if directory == u32::MAX {
+            return Err(SongError::InvalidArchive("ZIP64 archives aren't supported"));
+        }
+        reader.seek(directory as usize)?;
+
+        let mut entries = Vec::with_capacity(count.min(bytes.len() / 46));
+        for _ in 0..count {
+            if reader.read_u32()? != CENTRAL_HEADER {
+                return Err(SongError::InvalidArchive("Broken central directory"));
+            }
+            // Skips the versions that made the file and are needed to extract it
+            reader.seek_relative(4)?;
+            let flags = reader.read_u16()?;
+            let method = reader.read_u16()?;
+            // Skips the modification time and date
+            reader.seek_relative(4)?;
+            let crc = reader.read_u32()?;
+            let compressed_size = reader.read_u32()? as usize;
+            let size = reader.read_u32()? as usize;
+            let name_length = reader.read_u16()? as usize;
+            let extra_length = reader.read_u16()? as usize;
+            let comment_length = reader.read_u16()? as usize;
+            // Skips the disk number and the file attributes
+            reader.seek_relative(8)?;
+            let offset = reader.read_u32()? as usize;
+
+            // Names are in the DOS code page unless they're marked as UTF-8
+            let name = if flags & UTF8_NAME != 0 {
+                let bytes = reader.read_bytes(name_length)?;
+                String::from_utf8_lossy(bytes).into_owned()
+            } else {
+                reader.read_str_lossy(name_length, Codepage::Cp437)?
+            };
+            reader.seek_relative((extra_length + comment_length) as i64)?;
+
+            // Directories are only there for their names
+            if !name.ends_with('/') {
+                entries.push(ArchiveEntry {
+                    name,
+                    size,
+                    compressed_size,
+                    method,
+                    flags,
+                    crc,
+                    offset,
+                });
+            }
+        }
+
+        Ok(Archive { data, entries })
+    }
+
+    /// The files in the archive, in the order they were added. Directories are left out
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// The file with the path `name`
+    pub fn find(&self, name: &str) -> Option<&ArchiveEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Extracts a file
+    ///
+    /// # Errors
+    /// When the file is encrypted or compressed in a way that isn't supported, or its data
+    /// is damaged
+    pub fn read(&self, entry: &ArchiveEntry) -> Result<Vec<u8>, SongError> {
+        if entry.flags & ENCRYPTED != 0 {
+            return Err(SongError::InvalidArchive(
+                "Encrypted files aren't supported",
+            ));
+        }
+
+        // The local header repeats the name, and may have extra fields of its own
+        let mut reader = ByteReader::new(self.data.as_ref(), Encoding::LittleEndian);
+        reader.seek(entry.offset)?;
+        if reader.read_u32()? != LOCAL_HEADER {
+            return Err(SongError::InvalidArchive("Broken file header"));
+        }
+        reader.seek_relative(22)?;
+        let name_length = reader.read_u16()? as usize;
+        let extra_length = reader.read_u16()? as usize;
+        reader.seek_relative((name_length + extra_length) as i64)?;
+        let compressed = reader.read_bytes(entry.compressed_size)?;
+
+        let data = match entry.method {
+            STORED => compressed.to_vec(),
+            DEFLATED => inflate(compressed, entry.size)?,
+            _ => {
+                return Err(SongError::InvalidArchive(
+                    "Only stored and deflated files are supported",
+                ))
+            }
+        };
+
+        if data.len() != entry.size || crc32(&data) != entry.crc {
+            return Err(SongError::InvalidArchive("The file is damaged"));
+        }
+        Ok(data)
+    }
+}
+
+/// The CRC-32 of ZIP archives and PNG images
+pub(crate) fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let crc = bytes.into_iter().fold(!0u32, |mut crc, &byte| {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+        crc
+    });
+    !crc
+}
+
+// Lengths and distances of matches start at these values, and have this many extra bits
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// The order the code lengths of the code length code are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const END_OF_BLOCK: u16 = 256;
+
+// The size comes from the archive, so no more than this is reserved before the data is there
+const MAX_RESERVED: usize = 1 << 20;
+
+/// Decompresses deflate data (RFC 1951) that extracts to `size` bytes.
+///
+/// # Errors
+/// When the data is damaged or cut short, or extracts to more than `size` bytes
+pub fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, SongError> {
+    let mut bits = BitReader::new(ByteReader::new(data, Encoding::LittleEndian));
+    let mut output = Vec::with_capacity(size.min(MAX_RESERVED));
+
+    loop {
+        let last = bits.read_bit()?;
+        match bits.read_bits(2)? {
+            0 => {
+                // Stored blocks start at a whole byte, with their length and its complement
+                bits.align();
+                let mut reader = bits.into_inner();
+                let length = reader.read_u16()?;
+                if reader.read_u16()? != !length {
+                    return Err(SongError::InvalidArchive("Broken deflate block"));
+                }
+                if output.len() + length as usize > size {
+                    return Err(SongError::InvalidArchive("The file is bigger than it says"));
+                }
+                output.extend_from_slice(reader.read_bytes(length as usize)?);
+                bits = BitReader::new(reader);
+            }
+            1 => {
+                let (literals, distances) = fixed_codes()?;
+                inflate_block(&mut bits, &mut output, &literals, &distances, size)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut output, &literals, &distances, size)?;
+            }
+            _ => return Err(SongError::InvalidArchive("Broken deflate block")),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+// Decodes the literals and matches of a compressed block, up to its end
+fn inflate_block(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+    size: usize,
+) -> Result<(), SongError> {
+    loop {
+        let symbol = literals.decode(bits)?;
+        if symbol < END_OF_BLOCK {
+            if output.len() == size {
+                return Err(SongError::InvalidArchive("The file is bigger than it says"));
+            }
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+
+        let index = (symbol - END_OF_BLOCK - 1) as usize;
+        let (Some(&base), Some(&extra)) = (LENGTH_BASES.get(index), LENGTH_EXTRA_BITS.get(index))
+        else {
+            return Err(SongError::InvalidArchive("Broken deflate data"));
+        };
+        let length = (base as u32 + bits.read_bits(extra as u32)?) as usize;
+
+        let index = distances.decode(bits)? as usize;
+        let (Some(&base), Some(&extra)) =
+            (DISTANCE_BASES.get(index), DISTANCE_EXTRA_BITS.get(index))
+        else {
+            return Err(SongError::InvalidArchive("Broken deflate data"));
+        };
+        let distance = (base as u32 + bits.read_bits(extra as u32)?) as usize;
+
+        if distance > output.len() {
+            return Err(SongError::InvalidArchive("Broken deflate data"));
+        }
+        if output.len() + length > size {
+            return Err(SongError::InvalidArchive("The file is bigger than it says"));
+        }
+        // Matches can overlap what they copy, repeating it
+        let start = output.len() - distance;
+        for offset in 0..length {
+            output.push(output[start + offset]);
+        }
+    }
+}
+
+// The codes of blocks compressed with the codes the format defines
+fn fixed_codes() -> Result<(Huffman, Huffman), SongError> {
+    let mut lengths = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+// Reads the codes a block is compressed with from its start
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), SongError> {
+    let literal_count = bits.read_bits(5)? as usize + 257;
+    let distance_count = bits.read_bits(5)? as usize + 1;
+    let code_length_count = bits.read_bits(4)? as usize + 4;
+
+    // The code lengths are compressed with a code of their own
+    let mut code_length_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[index] = bits.read_bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths)?;
+
+    let mut lengths = vec![0; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_lengths.decode(bits)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if index > 0 => (lengths[index - 1], 3 + bits.read_bits(2)?),
+            17 => (0, 3 + bits.read_bits(3)?),
+            18 => (0, 11 + bits.read_bits(7)?),
+            _ => return Err(SongError::InvalidArchive("Broken deflate codes")),
+        };
+
+        let end = index + repeat as usize;
+        if end > lengths.len() {
+            return Err(SongError::InvalidArchive("Broken deflate codes"));
+        }
+        lengths[index..end].fill(length);
+        index = end;
+    }
+
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(SongError::InvalidArchive("Broken deflate codes"));
+    }
+    let (literal_lengths, distance_lengths) = lengths.split_at(literal_count);
+    Ok((
+        Huffman::new(literal_lengths)?,
+        Huffman::new(distance_lengths)?,
+    ))
+}
+
+/// A canonical Huffman code, as deflate describes it by the length of every symbol's code
+struct Huffman {
+    // How many codes there are of every length
+    counts: [u16; 16],
+    // The symbols with codes, by the length of their code and then by value
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, SongError> {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // More codes of a length than there's room for can't be told apart. Fewer is fine,
+        // e.g. for blocks with a single distance
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(SongError::InvalidArchive("Broken deflate codes"));
+            }
+        }
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    // Reads a code a bit at a time, the first bit read is the highest of the code
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, SongError> {
+        // The first code of the current length, and the index of its symbol
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for &count in &self.counts[1..] {
+            code |= bits.read_bit()? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(SongError::InvalidArchive("Broken deflate data"))
+    }
+}
+
+// "ABABABABABABAB, a repeating pattern", compressed with the fixed codes
+#[cfg(test)]
+const FIXED: [u8; 27] = [
+    0x73, 0x74, 0x72, 0x44, 0x82, 0x3A, 0x0A, 0x89, 0x0A, 0x45, 0xA9, 0x05, 0xA9, 0x89, 0x25, 0x99,
+    0x79, 0xE9, 0x0A, 0x05, 0x89, 0x25, 0x25, 0xA9, 0x45, 0x79, 0x00,
+];
+
+// What `dynamic_text` makes, compressed with codes of its own
+#[cfg(test)]
+const DYNAMIC: [u8; 30] = [
+    0x15, 0x88, 0x81, 0x09, 0x00, 0x00, 0x0C, 0x82, 0x5E, 0xD9, 0x6B, 0xBA, 0xFD, 0x7F, 0xC3, 0x0A,
+    0x02, 0x35, 0x58, 0x60, 0xC4, 0x0B, 0x13, 0xCD, 0x1E, 0x87, 0x0A, 0xDD, 0x46, 0x1E,
+];
+
+#[cfg(test)]
+fn dynamic_text() -> Vec<u8> {
+    (0..40)
+        .map(|i| b"aaaaaaaabbbbcc d"[(i * i * 7 + i / 3) % 16])
+        .collect()
+}
+
+// An archive of (name, method, data as stored, extracted data), in the layout ZIP tools write
+#[cfg(test)]
+fn zip(files: &[(&str, u16, &[u8], &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut directory = Vec::new();
+
+    for &(name, method, stored, extracted) in files {
+        let offset = data.len() as u32;
+        let fields = |header: &mut Vec<u8>| {
+            header.extend_from_slice(&[20, 0, 0, 0]);
+            header.extend_from_slice(&method.to_le_bytes());
+            header.extend_from_slice(&[0; 4]);
+            header.extend_from_slice(&crc32(extracted).to_le_bytes());
+            header.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(extracted.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0; 2]);
+        };
+
+        data.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        fields(&mut data);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(stored);
+
+        directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        directory.extend_from_slice(&[20, 0]);
+        fields(&mut directory);
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let start = data.len() as u32;
+    data.extend_from_slice(&directory);
+    data.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    data.extend_from_slice(&start.to_le_bytes());
+    data.extend_from_slice(&[0; 2]);
+    data
+}
+
+#[test]
+fn inflate_fixed_codes() {
+    let text = b"ABABABABABABAB, a repeating pattern";
+    assert_eq!(inflate(&FIXED, text.len()).unwrap(), text);
+}
+
+#[test]
+fn inflate_dynamic_codes() {
+    let text = dynamic_text();
+    assert_eq!(inflate(&DYNAMIC, text.len()).unwrap(), text);
+}
+
+#[test]
+fn inflate_stored_blocks() {
+    let data = [
+        0, 3, 0, 0xFC, 0xFF, b'a', b'b', b'c', 1, 1, 0, 0xFE, 0xFF, b'd',
+    ];
+    assert_eq!(inflate(&data, 4).unwrap(), b"abcd");
+
+    // The complement of the length doesn't match
+    assert!(inflate(&[1, 3, 0, 0xFC, 0xFE, b'a', b'b', b'c'], 3).is_err());
+}
+
+#[test]
+fn inflate_limits_size() {
+    assert!(inflate(&FIXED, 10).is_err());
+    assert!(inflate(&FIXED[..20], 35).is_err());
+
+    // Sizes from a damaged header don't get reserved
+    let text = b"ABABABABABABAB, a repeating pattern";
+    assert_eq!(inflate(&FIXED, usize::MAX).unwrap(), text);
+}
+
+#[test]
+fn read_archive() {
+    let text = dynamic_text();
+    let data = zip(&[
+        ("songs/", STORED, b"", b""),
+        ("songs/stored.mod", STORED, b"stored", b"stored"),
+        ("songs/deflated.mod", DEFLATED, &DYNAMIC, &text),
+    ]);
+    let archive = Archive::new(&data).unwrap();
+
+    let names: Vec<&str> = (archive.entries().iter())
+        .map(|entry| entry.name.as_str())
+        .collect();
+    assert_eq!(names, ["songs/stored.mod", "songs/deflated.mod"]);
+
+    let stored = archive.find("songs/stored.mod").unwrap();
+    assert_eq!(archive.read(stored).unwrap(), b"stored");
+    let deflated = archive.find("songs/deflated.mod").unwrap();
+    assert_eq!(deflated.size, text.len());
+    assert_eq!(archive.read(deflated).unwrap(), text);
+    assert!(archive.find("missing.mod").is_none());
+}
+
+#[test]
+fn damaged_archive() {
+    assert!(matches!(
+        Archive::new(b"MOD data"),
+        Err(SongError::UnknownFormat)
+    ));
+
+    // The stored data doesn't match its CRC anymore
+    let mut data = zip(&[("song.mod", STORED, b"stored", b"stored")]);
+    data[30 + 8] = b'S';
+    let archive = Archive::new(&data).unwrap();
+    assert!(matches!(
+        archive.read(&archive.entries()[0]),
+        Err(SongError::InvalidArchive(_))
+    ));
+}
@@ -0,0 +1,131 @@
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::info;
+use crate::json::JsonObject;
+use crate::playlist::Playlist;
+use rustune::engine::{Engine, TrackerEngine};
+use rustune::export::loudness::{Loudness, LoudnessMeter};
+use rustune::song::{Song, SongError};
+
+// Frames rendered at once, as many as an offline render does
+const RENDER_CHUNK_FRAMES: usize = 4096;
+
+/// How long each stage of rendering a song took
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timings {
+    /// Reading and parsing the module
+    pub parsing: Duration,
+    /// Creating the engine, which plays through the song once to measure it
+    pub setup: Duration,
+    /// Rendering the audio
+    pub mixing: Duration,
+    /// Length of the rendered audio in seconds
+    pub audio: f64,
+    /// Of the rendered audio, not measured for totals
+    pub loudness: Option<Loudness>,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.parsing + self.setup + self.mixing
+    }
+
+    /// How many seconds of audio are mixed per second
+    pub fn realtime_factor(&self) -> f64 {
+        self.audio / self.mixing.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Adds the fields to a JSON object, times in milliseconds
+    pub fn json(&self, object: JsonObject) -> JsonObject {
+        let object = object
+            .value("parsing_ms", milliseconds(self.parsing))
+            .value("setup_ms", milliseconds(self.setup))
+            .value("mixing_ms", milliseconds(self.mixing))
+            .value("total_ms", milliseconds(self.total()))
+            .value("audio_seconds", self.audio)
+            .value("realtime_factor", self.realtime_factor());
+        info::loudness_json(object, self.loudness)
+    }
+}
+
+impl std::ops::Add for Timings {
+    type Output = Timings;
+
+    fn add(self, other: Timings) -> Timings {
+        Timings {
+            parsing: self.parsing + other.parsing,
+            setup: self.setup + other.setup,
+            mixing: self.mixing + other.mixing,
+            audio: self.audio + other.audio,
+            loudness: None,
+        }
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Parsing  {:>10.2} ms", milliseconds(self.parsing))?;
+        writeln!(f, "  Setup    {:>10.2} ms", milliseconds(self.setup))?;
+        writeln!(
+            f,
+            "  Mixing   {:>10.2} ms   {:.2} s of audio, {:.1}x realtime",
+            milliseconds(self.mixing),
+            self.audio,
+            self.realtime_factor()
+        )?;
+        write!(f, "  Total    {:>10.2} ms", milliseconds(self.total()))?;
+        if let Some(loudness) = self.loudness {
+            write!(f, "\n  Loudness {loudness}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders the playlist entry at `path` without keeping the audio, timing every stage. The audio is
+/// measured for its loudness on the way, which isn't counted as mixing.
+///
+/// `create_engine` sets up the engine the way it would be for playback, so the options
+/// that affect mixing (oversampling, the mixer, ...) are measured as well.
+pub fn bench(
+    playlist: &Playlist,
+    path: &Path,
+    sample_rate: u32,
+    create_engine: impl FnOnce(Song) -> Result<Engine, SongError>,
+) -> Result<Timings, SongError> {
+    let start = Instant::now();
+    let song = playlist.load(path)?;
+    let parsing = start.elapsed();
+
+    let start = Instant::now();
+    let mut engine = create_engine(song)?;
+    engine.set_channel_count(2);
+    engine.set_sample_rate(sample_rate);
+    let setup = start.elapsed();
+
+    let mut meter = LoudnessMeter::new(sample_rate, 2);
+    let mut buffer = vec![0.0f32; RENDER_CHUNK_FRAMES * 2];
+    let mut mixing = Duration::ZERO;
+    let mut frames = 0;
+    while !engine.is_finished() {
+        let start = Instant::now();
+        let rendered = engine.render(&mut buffer);
+        mixing += start.elapsed();
+
+        meter.add(&buffer[..rendered * 2]);
+        frames += rendered;
+    }
+
+    Ok(Timings {
+        parsing,
+        setup,
+        mixing,
+        audio: frames as f64 / sample_rate as f64,
+        loudness: Some(meter.loudness()),
+    })
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
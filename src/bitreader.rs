@@ -0,0 +1,102 @@
+//! Reading values that aren't a whole number of bytes wide, as in compressed samples
+
+use crate::bytereader::ByteReader;
+use crate::song::SongError;
+
+/// Reads values of any width up to 32 bits from a [`ByteReader`], starting at the lowest bit
+/// of every byte. This is the order of the compressed samples of Impulse Tracker 2.14 and
+/// MO3.
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    reader: ByteReader<'a>,
+    // Bits read from `reader` but not handed out yet, the next one is the lowest
+    buffer: u64,
+    bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    /// Reads bits from the position `reader` is at
+    pub fn new(reader: ByteReader<'a>) -> Self {
+        BitReader {
+            reader,
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    /// Reads a value `count` bits wide, the first bit read is the lowest of the value
+    ///
+    /// # Errors
+    /// When there are less than `count` bits left, nothing is read then
+    ///
+    /// # Panics
+    /// When `count` is more than 32
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bitreader::BitReader;
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = BitReader::new(ByteReader::new(&[0b1010_0111], Encoding::LittleEndian));
+    ///
+    /// assert_eq!(reader.read_bits(3).unwrap(), 0b111);
+    /// assert_eq!(reader.read_bits(5).unwrap(), 0b10100);
+    /// assert!(reader.read_bits(1).is_err());
+    /// ```
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, SongError> {
+        assert!(count <= 32, "Can't read {count} bits at once, at most 32");
+
+        if count > self.bits {
+            // All bytes are read at once, so a read that fails doesn't take any
+            let needed = (count - self.bits).div_ceil(8) as usize;
+            for &byte in self.reader.read_bytes(needed)? {
+                self.buffer |= (byte as u64) << self.bits;
+                self.bits += 8;
+            }
+        }
+
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.bits -= count;
+
+        Ok(value as u32)
+    }
+
+    /// Reads a single bit
+    ///
+    /// # Errors
+    /// When every bit has been read
+    pub fn read_bit(&mut self) -> Result<bool, SongError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Skips the bits left of the current byte, so the next read starts at a whole byte
+    pub fn align(&mut self) {
+        self.buffer = 0;
+        self.bits = 0;
+    }
+
+    /// Gives back the byte reader, positioned after the last byte bits were read from
+    pub fn into_inner(self) -> ByteReader<'a> {
+        self.reader
+    }
+}
+
+#[test]
+fn read_across_bytes() {
+    use crate::bytereader::Encoding;
+
+    let data = [0xFF, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9A];
+    let mut reader = BitReader::new(ByteReader::new(&data, Encoding::LittleEndian));
+
+    assert_eq!(reader.read_bits(4).unwrap(), 0xF);
+    assert_eq!(reader.read_bits(9).unwrap(), 0x00F);
+    assert!(!reader.read_bit().unwrap());
+    reader.align();
+    assert_eq!(reader.read_bits(0).unwrap(), 0);
+    assert_eq!(reader.read_bits(32).unwrap(), 0x78563412);
+
+    // Only 8 bits are left
+    assert!(reader.read_bits(9).is_err());
+    assert_eq!(reader.read_bits(8).unwrap(), 0x9A);
+    assert_eq!(reader.into_inner().position(), data.len());
+}
@@ -1,12 +1,62 @@
+//! Reading binary module files
+
+use alloc::string::{String, ToString};
+
 use crate::song::SongError;
 
-#[derive(Debug)]
+/// Byte order of the values in the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum Encoding {
     LittleEndian,
     BigEndian,
 }
 
+/// Character set of the text in a format that predates UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codepage {
+    /// ISO 8859-1, used on the Amiga
+    Latin1,
+    /// The character set of DOS, with accented letters and box drawing characters
+    Cp437,
+}
+
+// Characters of code page 437 from 0x80 on, the lower half is ASCII
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+impl Codepage {
+    fn decode(self, byte: u8) -> char {
+        match self {
+            // The first 256 code points of Unicode are Latin-1
+            Codepage::Latin1 => byte as char,
+            Codepage::Cp437 if byte < 0x80 => byte as char,
+            Codepage::Cp437 => CP437_HIGH[byte as usize - 0x80],
+        }
+    }
+
+    /// The byte for `c`, `?` for characters the codepage doesn't have
+    pub(crate) fn encode(self, c: char) -> u8 {
+        let byte = match self {
+            Codepage::Latin1 => u8::try_from(c).ok(),
+            Codepage::Cp437 if c.is_ascii() => Some(c as u8),
+            Codepage::Cp437 => (CP437_HIGH.iter())
+                .position(|&high| high == c)
+                .map(|index| index as u8 + 0x80),
+        };
+        byte.unwrap_or(b'?')
+    }
+}
+
+/// Reads values one after another from a byte slice, keeping track of the position
 #[derive(Debug)]
 pub struct ByteReader<'a> {
     data: &'a [u8],
@@ -57,11 +107,59 @@ impl<'a> ByteReader<'a> {
         &self.encoding
     }
 
+    /// Reads with a different byte order for the duration of `f`, for formats that mix
+    /// both. The reader's own encoding is restored afterwards.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(&[0x01, 0x02, 0x01, 0x02], Encoding::LittleEndian);
+    ///
+    /// let big = reader.with_encoding(Encoding::BigEndian, |reader| reader.read_u16());
+    /// assert_eq!(big.unwrap(), 0x0102);
+    /// assert_eq!(reader.read_u16().unwrap(), 0x0201);
+    /// ```
+    pub fn with_encoding<T>(&mut self, encoding: Encoding, f: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = core::mem::replace(&mut self.encoding, encoding);
+        let result = f(self);
+        self.encoding = previous;
+
+        result
+    }
+
     /// Returns the current position the reader is at
     pub fn position(&self) -> usize {
         self.position
     }
 
+    /// Returns the amount of bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    /// Whether every byte has been read
+    pub fn is_eof(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Reads everything from the position to the end of the data
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(&[0x01, 0x02, 0x03], Encoding::LittleEndian);
+    /// reader.read_u8().unwrap();
+    ///
+    /// assert_eq!(reader.take_rest(), [0x02, 0x03]);
+    /// assert!(reader.is_eof());
+    /// ```
+    pub fn take_rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.position..];
+        self.position = self.data.len();
+
+        rest
+    }
+
     /// Seeks to a specific position in the byte stream.
     ///
     /// # Arguments
@@ -72,17 +170,15 @@ impl<'a> ByteReader<'a> {
     ///
     /// # Example
     /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
     /// let mut reader = ByteReader::new(&[0x01, 0x02, 0x03], Encoding::LittleEndian);
     /// assert_eq!(reader.seek(2).unwrap(), 0); // Moves to position 2, returns old position
-    /// assert_eq!(reader.read_u8().unwrap, 0x03); // Reads the value at offset 2, which is 0x03
+    /// assert_eq!(reader.read_u8().unwrap(), 0x03); // Reads the value at offset 2, which is 0x03
     /// ```
     pub fn seek(&mut self, position: usize) -> Result<usize, SongError> {
         let size = self.data.len();
         if position > size {
-            return Err(SongError::Read(format!(
-                "Out of bounds seek at {}; EOF: {}",
-                position, size
-            )));
+            return Err(SongError::OutOfBounds { position, size });
         }
 
         let old_pos = self.position;
@@ -91,6 +187,90 @@ impl<'a> ByteReader<'a> {
         Ok(old_pos)
     }
 
+    /// Moves the position forwards, or backwards for negative offsets.
+    ///
+    /// # Errors
+    /// When the new position is before the start or past the end of the data. Positions
+    /// before the start are reported as 0.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(&[0x01, 0x02, 0x03], Encoding::LittleEndian);
+    /// reader.seek_relative(2).unwrap(); // Skips two bytes, returns the old position
+    /// reader.seek_relative(-1).unwrap();
+    /// assert_eq!(reader.read_u8().unwrap(), 0x02);
+    /// ```
+    pub fn seek_relative(&mut self, offset: i64) -> Result<usize, SongError> {
+        let position = (self.position as i64).saturating_add(offset);
+        // Only catches positions before the start, `seek` checks the end
+        let position = usize::try_from(position).map_err(|_| SongError::OutOfBounds {
+            position: 0,
+            size: self.data.len(),
+        })?;
+
+        self.seek(position)
+    }
+
+    /// Moves to `distance` bytes before the end of the data, for structures that are found
+    /// from the end of a file.
+    ///
+    /// # Errors
+    /// When the data is shorter than `distance`
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(b"DATATAG!", Encoding::LittleEndian);
+    /// reader.seek_from_end(4).unwrap();
+    /// assert_eq!(reader.read_str(4).unwrap(), "TAG!");
+    /// ```
+    pub fn seek_from_end(&mut self, distance: usize) -> Result<usize, SongError> {
+        let size = self.data.len();
+        let position = size
+            .checked_sub(distance)
+            .ok_or(SongError::OutOfBounds { position: 0, size })?;
+
+        self.seek(position)
+    }
+
+    /// Creates a reader over `length` bytes from `offset`, with the same encoding. It starts
+    /// at position 0 of the region and can't read past its end, so chunks can be parsed on
+    /// their own. This reader's position isn't changed.
+    ///
+    /// # Errors
+    /// When the region doesn't fit in the data
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let reader = ByteReader::new(b"CMODSONG", Encoding::BigEndian);
+    /// let mut chunk = reader.sub_reader(4, 2).unwrap();
+    ///
+    /// assert_eq!(chunk.read_bytes(2).unwrap(), b"SO");
+    /// assert!(chunk.read_u8().is_err());
+    /// ```
+    pub fn sub_reader(&self, offset: usize, length: usize) -> Result<ByteReader<'a>, SongError> {
+        let size = self.data.len();
+        if offset > size {
+            return Err(SongError::OutOfBounds {
+                position: offset,
+                size,
+            });
+        }
+        if length > size - offset {
+            return Err(SongError::UnexpectedEof {
+                offset,
+                wanted: length,
+            });
+        }
+
+        Ok(ByteReader::new(
+            &self.data[offset..offset + length],
+            self.encoding,
+        ))
+    }
+
     /// Reads a chunk of bytes from the stream
     ///
     /// # Arguments
@@ -101,6 +281,7 @@ impl<'a> ByteReader<'a> {
     ///
     /// # Example
     /// ```rust
+    /// # use rustune::bytereader::{ByteReader, Encoding};
     /// let data: [u8; 12] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01];
     /// let mut reader = ByteReader::new(&data, Encoding::LittleEndian);
     ///
@@ -108,19 +289,47 @@ impl<'a> ByteReader<'a> {
     /// assert_eq!(reader.read_bytes(6).unwrap(), [0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
     /// ```
     pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], SongError> {
-        if self.position + count > self.data.len() {
-            return Err(SongError::Read(format!(
-                "Not enough data to read {} bytes at offset {}",
-                count, self.position
-            )));
-        }
-
-        let slice = &self.data[self.position..self.position + count];
+        let slice = self.peek_bytes(count)?;
         self.position += count;
 
         Ok(slice)
     }
 
+    /// Returns the next bytes of the stream without moving past them
+    ///
+    /// # Arguments
+    /// * `count` - The amount of bytes to look at
+    ///
+    /// # Errors
+    /// When there are less than `count` bytes left
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(b"M.K.", Encoding::BigEndian);
+    ///
+    /// assert_eq!(reader.peek_bytes(2).unwrap(), b"M.");
+    /// assert_eq!(reader.read_bytes(4).unwrap(), b"M.K."); // Still reads from the start
+    /// ```
+    pub fn peek_bytes(&self, count: usize) -> Result<&'a [u8], SongError> {
+        if count > self.remaining() {
+            return Err(SongError::UnexpectedEof {
+                offset: self.position,
+                wanted: count,
+            });
+        }
+
+        Ok(&self.data[self.position..self.position + count])
+    }
+
+    /// Returns the next byte without moving past it
+    ///
+    /// # Errors
+    /// When the reader is at the end of the data
+    pub fn peek_u8(&self) -> Result<u8, SongError> {
+        Ok(self.peek_bytes(1)?[0])
+    }
+
     /// Reads a string with the given length, trimming any null characters.
     ///
     /// # Arguments
@@ -131,6 +340,7 @@ impl<'a> ByteReader<'a> {
     ///
     /// # Example
     /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
     /// let data: &[u8] = b"Hello, world!";
     /// let mut reader = ByteReader::new(&data, Encoding::LittleEndian);
     ///
@@ -138,17 +348,103 @@ impl<'a> ByteReader<'a> {
     /// assert_eq!(reader.read_str(8).unwrap(), ", world!"); // Read ", world!"
     /// ```
     pub fn read_str(&mut self, length: usize) -> Result<String, SongError> {
-        let bytes = self.read_bytes(length).map_err(|_| {
-            SongError::Read(format!(
-                "Not enough data to read string of length {} at offset {}",
-                length, self.position
-            ))
-        })?;
+        self.read_padded_str(length, 0)
+    }
+
+    /// Reads a string in a field of `length` bytes, trimming the `pad` bytes it was filled up
+    /// with. Some formats pad with spaces rather than null characters.
+    ///
+    /// # Errors
+    /// When the reader reads out of bounds, or the string isn't valid UTF-8
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(b"Intro   ", Encoding::LittleEndian);
+    /// assert_eq!(reader.read_padded_str(8, b' ').unwrap(), "Intro");
+    /// ```
+    pub fn read_padded_str(&mut self, length: usize, pad: u8) -> Result<String, SongError> {
+        let offset = self.position;
+        let bytes = self.read_bytes(length)?;
+        let end = bytes
+            .iter()
+            .rposition(|&byte| byte != pad)
+            .map_or(0, |last| last + 1);
+
+        self.decode_str(offset, &bytes[..end])
+    }
+
+    /// Reads a string that ends with a null character, which is read as well but not part
+    /// of the string. Reads `max` bytes when there's no null character among them.
+    ///
+    /// # Errors
+    /// When the data ends before a null character or `max` bytes, or the string isn't valid
+    /// UTF-8
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(b"Bass\0Lead", Encoding::LittleEndian);
+    ///
+    /// assert_eq!(reader.read_cstr(16).unwrap(), "Bass");
+    /// assert_eq!(reader.read_cstr(4).unwrap(), "Lead");
+    /// ```
+    pub fn read_cstr(&mut self, max: usize) -> Result<String, SongError> {
+        let offset = self.position;
+        let available = &self.data[offset..];
+        let field = &available[..max.min(available.len())];
+
+        let (length, read) = match field.iter().position(|&byte| byte == 0) {
+            Some(null) => (null, null + 1),
+            None => (max, max),
+        };
+        let bytes = self.read_bytes(read)?;
+
+        self.decode_str(offset, &bytes[..length])
+    }
 
-        let string = std::str::from_utf8(bytes)
-            .map_err(|e| SongError::Read(format!("UTF-8 error: {}", e)))?
-            .trim_end_matches("\0")
-            .to_string();
+    // Turns bytes read from `offset` into a string. Like other reads that fail, the position
+    // goes back to where it was when they aren't UTF-8
+    fn decode_str(&mut self, offset: usize, bytes: &[u8]) -> Result<String, SongError> {
+        match core::str::from_utf8(bytes) {
+            Ok(string) => Ok(string.to_string()),
+            Err(source) => {
+                self.position = offset;
+                Err(SongError::InvalidUtf8 { offset, source })
+            }
+        }
+    }
+
+    /// Reads a string with the given length like [`read_str`](Self::read_str), but decodes
+    /// text that isn't valid UTF-8 from `codepage` instead of failing. Names in older formats
+    /// are rarely UTF-8, so this never fails on their contents.
+    ///
+    /// # Errors
+    /// When the reader reads out of bounds.
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Codepage, Encoding};
+    /// let mut reader = ByteReader::new(b"Caf\xe9\0\0", Encoding::LittleEndian);
+    /// assert_eq!(reader.read_str_lossy(6, Codepage::Latin1).unwrap(), "Café");
+    /// ```
+    pub fn read_str_lossy(
+        &mut self,
+        length: usize,
+        codepage: Codepage,
+    ) -> Result<String, SongError> {
+        let bytes = self.read_bytes(length)?;
+        let end = bytes
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        let bytes = &bytes[..end];
+
+        // Names that are already UTF-8 were most likely written by a newer tool
+        let string = match core::str::from_utf8(bytes) {
+            Ok(string) => string.to_string(),
+            Err(_) => bytes.iter().map(|&byte| codepage.decode(byte)).collect(),
+        };
 
         Ok(string)
     }
@@ -208,6 +504,26 @@ impl<'a> ByteReader<'a> {
         }
     }
 
+    /// Read a unsigned 24-bit integer, accounting for the byteorder automatically
+    ///
+    /// # Errors
+    /// When there is not enough data to be read
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{ByteReader, Encoding};
+    /// let mut reader = ByteReader::new(&[0x01, 0x02, 0x03], Encoding::LittleEndian);
+    /// assert_eq!(reader.read_u24().unwrap(), 0x030201);
+    /// ```
+    pub fn read_u24(&mut self) -> Result<u32, SongError> {
+        let bytes: [u8; 3] = self.read_bytes(3)?.to_array();
+
+        match self.encoding {
+            Encoding::BigEndian => Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])),
+            Encoding::LittleEndian => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])),
+        }
+    }
+
     /// Read a signed 32-bit integer, accounting for the byteorder automatically
     ///
     /// # Errors
@@ -220,6 +536,14 @@ impl<'a> ByteReader<'a> {
             Encoding::LittleEndian => Ok(i32::from_le_bytes(bytes.to_array())),
         }
     }
+
+    /// Read a 32-bit IEEE 754 float, accounting for the byteorder automatically
+    ///
+    /// # Errors
+    /// When there is not enough data to be read
+    pub fn read_f32(&mut self) -> Result<f32, SongError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
 }
 
 #[test]
@@ -273,8 +597,146 @@ fn read_multiple_bytes() {
 #[test]
 fn read_string() {
     let data: &[u8] = b"Hello, world!";
-    let mut reader = ByteReader::new(&data, Encoding::LittleEndian);
+    let mut reader = ByteReader::new(data, Encoding::LittleEndian);
 
     assert_eq!(reader.read_str(5).unwrap(), "Hello"); // Read "Hello"
     assert_eq!(reader.read_str(8).unwrap(), ", world!"); // Read ", world!"
 }
+
+#[test]
+fn huge_read_fails() {
+    let mut reader = ByteReader::new(&[0x01, 0x02, 0x03], Encoding::LittleEndian);
+    reader.seek(1).unwrap();
+
+    // Would overflow the end offset
+    assert!(reader.read_bytes(usize::MAX).is_err());
+    assert_eq!(reader.position(), 1);
+}
+
+#[test]
+fn peek_keeps_position() {
+    let mut reader = ByteReader::new(&[0x01, 0x02, 0x03, 0x04], Encoding::BigEndian);
+    reader.seek(1).unwrap();
+
+    assert_eq!(reader.peek_u8().unwrap(), 0x02);
+    assert_eq!(reader.peek_bytes(3).unwrap(), [0x02, 0x03, 0x04]);
+    assert!(reader.peek_bytes(4).is_err());
+    assert_eq!(reader.position(), 1);
+
+    assert_eq!(reader.read_u24().unwrap(), 0x020304);
+    assert!(reader.peek_u8().is_err());
+}
+
+#[test]
+fn read_float() {
+    let data = 1.5f32.to_be_bytes();
+    assert_eq!(
+        ByteReader::new(&data, Encoding::BigEndian)
+            .read_f32()
+            .unwrap(),
+        1.5
+    );
+
+    let data = (-0.25f32).to_le_bytes();
+    assert_eq!(
+        ByteReader::new(&data, Encoding::LittleEndian)
+            .read_f32()
+            .unwrap(),
+        -0.25
+    );
+}
+
+#[test]
+fn scoped_encoding() {
+    let data = [0x00, 0x01, 0x00, 0x01, 0x00, 0x01];
+    let mut reader = ByteReader::new(&data, Encoding::BigEndian);
+
+    let value = reader.with_encoding(Encoding::LittleEndian, |reader| {
+        assert_eq!(reader.read_u16().unwrap(), 0x0100);
+        // Nesting switches back to the enclosing encoding
+        reader.with_encoding(Encoding::BigEndian, |reader| reader.read_u16().unwrap())
+    });
+    assert_eq!(value, 0x0001);
+
+    assert_eq!(*reader.encoding(), Encoding::BigEndian);
+    assert_eq!(reader.read_u16().unwrap(), 0x0001);
+}
+
+#[test]
+fn sub_reader_is_bounded() {
+    let data = [0x01, 0x02, 0x03, 0x04, 0x05];
+    let mut reader = ByteReader::new(&data, Encoding::LittleEndian);
+    reader.seek(4).unwrap();
+
+    let mut chunk = reader.sub_reader(1, 3).unwrap();
+    assert_eq!(chunk.read_u16().unwrap(), 0x0302);
+    assert!(chunk.read_u16().is_err());
+    assert!(chunk.seek(4).is_err());
+    assert_eq!(reader.position(), 4);
+
+    assert!(reader.sub_reader(5, 0).is_ok());
+    assert!(reader.sub_reader(3, 3).is_err());
+    assert!(reader.sub_reader(6, 0).is_err());
+    assert!(reader.sub_reader(1, usize::MAX).is_err());
+}
+
+#[test]
+fn read_legacy_string() {
+    let data = b"\xc9t\xe9\0\xc9\xcd\xbb\0Na\xc3\xafve";
+    let mut reader = ByteReader::new(data, Encoding::LittleEndian);
+
+    assert!(reader.read_str(4).is_err());
+    assert_eq!(reader.read_str_lossy(4, Codepage::Latin1).unwrap(), "Été");
+    assert_eq!(reader.read_str_lossy(4, Codepage::Cp437).unwrap(), "╔═╗");
+    assert_eq!(reader.read_str_lossy(6, Codepage::Cp437).unwrap(), "Naïve");
+}
+
+#[test]
+fn relative_seeking() {
+    let mut reader = ByteReader::new(&[0x01, 0x02, 0x03, 0x04], Encoding::LittleEndian);
+
+    assert_eq!(reader.seek_relative(3).unwrap(), 0);
+    assert_eq!(reader.seek_relative(-2).unwrap(), 3);
+    assert_eq!(reader.read_u8().unwrap(), 0x02);
+
+    assert!(reader.seek_relative(-3).is_err());
+    assert!(reader.seek_relative(4).is_err());
+    assert!(reader.seek_relative(i64::MAX).is_err());
+    assert_eq!(reader.position(), 2);
+
+    assert_eq!(reader.seek_from_end(1).unwrap(), 2);
+    assert_eq!(reader.read_u8().unwrap(), 0x04);
+    assert!(reader.seek_from_end(5).is_err());
+    reader.seek_from_end(4).unwrap();
+    assert_eq!(reader.position(), 0);
+}
+
+#[test]
+fn terminated_and_padded_strings() {
+    let mut reader = ByteReader::new(b"Kick\0Snare  \xff\0Hat", Encoding::LittleEndian);
+
+    assert_eq!(reader.read_cstr(8).unwrap(), "Kick");
+    assert_eq!(reader.read_padded_str(7, b' ').unwrap(), "Snare");
+    assert!(reader.read_cstr(8).is_err());
+    assert_eq!(reader.position(), 12);
+
+    reader.seek(14).unwrap();
+    // Runs out of data before a null character
+    assert!(reader.read_cstr(8).is_err());
+    assert_eq!(reader.read_cstr(3).unwrap(), "Hat");
+}
+
+#[test]
+fn remaining_bytes() {
+    let mut reader = ByteReader::new(&[0x01, 0x02, 0x03, 0x04], Encoding::LittleEndian);
+    assert_eq!(reader.remaining(), 4);
+
+    reader.read_u16().unwrap();
+    assert_eq!(reader.remaining(), 2);
+    assert!(!reader.is_eof());
+
+    assert_eq!(reader.take_rest(), [0x03, 0x04]);
+    assert_eq!(reader.remaining(), 0);
+    assert!(reader.is_eof());
+    assert!(reader.take_rest().is_empty());
+}
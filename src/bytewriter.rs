@@ -0,0 +1,174 @@
+//! Writing binary module files
+
+use alloc::vec::Vec;
+
+use crate::bytereader::{Codepage, Encoding};
+use crate::song::SongError;
+
+/// Appends values one after another to a byte buffer, the counterpart of
+/// [`ByteReader`](crate::bytereader::ByteReader)
+#[derive(Debug)]
+pub struct ByteWriter {
+    data: Vec<u8>,
+    encoding: Encoding,
+}
+
+impl ByteWriter {
+    /// Starts an empty buffer, writing multi-byte values in the given byte order
+    pub fn new(encoding: Encoding) -> Self {
+        ByteWriter {
+            data: Vec::new(),
+            encoding,
+        }
+    }
+
+    /// Returns the byte order the writer was instantiated with
+    pub fn encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    /// Returns the amount of bytes written so far, which is where the next value goes
+    pub fn position(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Gives back everything written
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Writes `string` into a field of `length` bytes, padding it with null characters
+    ///
+    /// # Errors
+    /// When the string is longer than the field
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::Encoding;
+    /// # use rustune::bytewriter::ByteWriter;
+    /// let mut writer = ByteWriter::new(Encoding::BigEndian);
+    /// writer.write_str("Hello", 8).unwrap();
+    ///
+    /// assert_eq!(writer.into_inner(), b"Hello\0\0\0");
+    /// ```
+    pub fn write_str(&mut self, string: &str, length: usize) -> Result<(), SongError> {
+        if string.len() > length {
+            return Err(SongError::LimitExceeded {
+                what: "String length",
+                found: string.len(),
+                limit: length,
+            });
+        }
+
+        self.write_bytes(string.as_bytes());
+        self.data.resize(self.data.len() + length - string.len(), 0);
+
+        Ok(())
+    }
+
+    /// Writes `string` into a field of `length` bytes like [`write_str`](Self::write_str), but
+    /// in `codepage`, the way [`read_str_lossy`](crate::bytereader::ByteReader::read_str_lossy)
+    /// reads it back. Characters the codepage doesn't have become `?`
+    ///
+    /// # Errors
+    /// When the string has more characters than the field has bytes
+    ///
+    /// # Example
+    /// ```
+    /// # use rustune::bytereader::{Codepage, Encoding};
+    /// # use rustune::bytewriter::ByteWriter;
+    /// let mut writer = ByteWriter::new(Encoding::BigEndian);
+    /// writer.write_str_lossy("Café", 6, Codepage::Latin1).unwrap();
+    ///
+    /// assert_eq!(writer.into_inner(), b"Caf\xe9\0\0");
+    /// ```
+    pub fn write_str_lossy(
+        &mut self,
+        string: &str,
+        length: usize,
+        codepage: Codepage,
+    ) -> Result<(), SongError> {
+        let bytes: Vec<u8> = string.chars().map(|c| codepage.encode(c)).collect();
+        if bytes.len() > length {
+            return Err(SongError::LimitExceeded {
+                what: "String length",
+                found: bytes.len(),
+                limit: length,
+            });
+        }
+
+        self.write_bytes(&bytes);
+        self.data.resize(self.data.len() + length - bytes.len(), 0);
+
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_i8(&mut self, value: i8) {
+        self.data.push(value as u8);
+    }
+
+    /// Writes an unsigned 16-bit integer in the byte order of the writer
+    pub fn write_u16(&mut self, value: u16) {
+        match self.encoding {
+            Encoding::BigEndian => self.write_bytes(&value.to_be_bytes()),
+            Encoding::LittleEndian => self.write_bytes(&value.to_le_bytes()),
+        }
+    }
+
+    /// Writes a signed 16-bit integer in the byte order of the writer
+    pub fn write_i16(&mut self, value: i16) {
+        self.write_u16(value as u16);
+    }
+
+    /// Writes an unsigned 32-bit integer in the byte order of the writer
+    pub fn write_u32(&mut self, value: u32) {
+        match self.encoding {
+            Encoding::BigEndian => self.write_bytes(&value.to_be_bytes()),
+            Encoding::LittleEndian => self.write_bytes(&value.to_le_bytes()),
+        }
+    }
+
+    /// Writes a signed 32-bit integer in the byte order of the writer
+    pub fn write_i32(&mut self, value: i32) {
+        self.write_u32(value as u32);
+    }
+}
+
+#[test]
+fn write_values() {
+    let mut be_writer = ByteWriter::new(Encoding::BigEndian);
+    be_writer.write_u8(0x01);
+    be_writer.write_u16(0x0203);
+    be_writer.write_u32(0x04050607);
+    assert_eq!(
+        be_writer.into_inner(),
+        [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]
+    );
+
+    let mut le_writer = ByteWriter::new(Encoding::LittleEndian);
+    le_writer.write_u8(0x01);
+    le_writer.write_u16(0x0302);
+    le_writer.write_u32(0x07060504);
+    assert_eq!(
+        le_writer.into_inner(),
+        [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07]
+    );
+}
+
+#[test]
+fn write_padded_string() {
+    let mut writer = ByteWriter::new(Encoding::BigEndian);
+    writer.write_str("Hi", 4).unwrap();
+    assert!(writer.write_str("Too long", 4).is_err());
+
+    assert_eq!(writer.position(), 4);
+    assert_eq!(writer.into_inner(), b"Hi\0\0");
+}
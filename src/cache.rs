@@ -0,0 +1,196 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bincode::Options;
+use rustune::{Song, SongError};
+
+// Turned off with --no-cache
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Parses every module again instead of using the cache
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// `rustune/songs/VERSION` in `$XDG_CACHE_HOME`, or in `~/.cache` when that isn't set. Every
+/// version has its own, as the structures of songs may change between them
+fn directory() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+    let songs = cache_home.join("rustune").join("songs");
+    Some(songs.join(env!("CARGO_PKG_VERSION")))
+}
+
+/// The song in the module file `data`, from the cache of parsed songs when it's been parsed
+/// before, or from `parse` which is then cached.
+///
+/// Songs are found by the MD5 hash and length of the file, so a file that changes is parsed
+/// again and copies of a file share an entry. Problems with the cache are only logged, the
+/// song is parsed then as if there was none.
+///
+/// # Errors
+/// When `parse` fails
+pub fn load(
+    data: &[u8],
+    parse: impl FnOnce() -> Result<Song, SongError>,
+) -> Result<Song, SongError> {
+    match directory() {
+        Some(directory) => load_from(&directory, data, parse),
+        None => parse(),
+    }
+}
+
+// `load` with the cache in `directory`
+fn load_from(
+    directory: &Path,
+    data: &[u8],
+    parse: impl FnOnce() -> Result<Song, SongError>,
+) -> Result<Song, SongError> {
+    let key = Key::new(data);
+    let path = directory.join(key.file_name());
+
+    match fs::read(&path) {
+        Ok(cached) => match read_entry(&cached, &key) {
+            Ok(song) => return Ok(song),
+            Err(e) => log::debug!("Ignoring the cached song {}: {e}", path.display()),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => log::debug!("Failed to read the cached song {}: {e}", path.display()),
+    }
+
+    let song = parse()?;
+    if let Err(e) = store(directory, &path, &key, &song) {
+        log::debug!("Failed to cache the song in {}: {e}", path.display());
+    }
+    Ok(song)
+}
+
+// What a song is cached under. The file name has all of it, and every entry repeats it with
+// the version that wrote it, which is checked when the entry is read
+#[derive(Debug, PartialEq, Eq)]
+struct Key {
+    length: u64,
+    hash: [u8; 16],
+}
+
+impl Key {
+    fn new(data: &[u8]) -> Key {
+        Key {
+            length: data.len() as u64,
+            hash: md5::compute(data).0,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        format!(
+            "{:032x}-{}.song",
+            u128::from_be_bytes(self.hash),
+            self.length
+        )
+    }
+}
+
+// The version that wrote an entry and its key, before the song
+type Header = (String, u64, [u8; 16]);
+
+fn read_entry(mut cached: &[u8], key: &Key) -> Result<Song, String> {
+    let options = options(cached.len());
+    let (version, length, hash): Header =
+        (options.deserialize_from(&mut cached)).map_err(|e| e.to_string())?;
+
+    if version != env!("CARGO_PKG_VERSION") {
+        return Err(format!("It was written by version {version}"));
+    }
+    if (Key { length, hash }) != *key {
+        return Err(String::from("It's the song of another file"));
+    }
+    options.deserialize(cached).map_err(|e| e.to_string())
+}
+
+fn write_entry(key: &Key, song: &Song) -> bincode::Result<Vec<u8>> {
+    let options = options(usize::MAX);
+    let mut data = options.serialize(&(env!("CARGO_PKG_VERSION"), key.length, key.hash))?;
+    data.extend(options.serialize(song)?);
+    Ok(data)
+}
+
+fn store(directory: &Path, path: &Path, key: &Key, song: &Song) -> io::Result<()> {
+    let data = write_entry(key, song).map_err(io::Error::other)?;
+
+    // Written next to it first, so other players never read a song that's half written
+    fs::create_dir_all(directory)?;
+    let partial = path.with_extension(format!("{}.partial", std::process::id()));
+    fs::write(&partial, data)?;
+    fs::rename(&partial, path)
+}
+
+// Reads no more than `limit` bytes, so a damaged cache file can't ask for huge allocations
+fn options(limit: usize) -> impl Options + Copy {
+    bincode::DefaultOptions::new().with_limit(limit as u64)
+}
+
+#[cfg(test)]
+fn test_song() -> Song {
+    crate::square_song(4)
+        .title("Cached")
+        .empty_pattern()
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn keys_have_the_hash_and_length() {
+    assert_eq!(
+        Key::new(b"abc").file_name(),
+        "900150983cd24fb0d6963f7d28e17f72-3.song"
+    );
+    assert_eq!(Key::new(b"abc"), Key::new(b"abc"));
+    assert_ne!(Key::new(b"abc"), Key::new(b"abd"));
+    assert_ne!(Key::new(b"abc"), Key::new(b"abc\0"));
+}
+
+#[test]
+fn entries_are_checked() {
+    let key = Key::new(b"module");
+    let song = test_song();
+    let entry = write_entry(&key, &song).unwrap();
+    assert_eq!(
+        read_entry(&entry, &key).unwrap().metadata().title(),
+        "Cached"
+    );
+
+    // The song of another file that ended up under the same name
+    assert!(read_entry(&entry, &Key::new(b"other")).is_err());
+
+    // Songs may be stored differently by other versions
+    let options = options(usize::MAX);
+    let mut entry = options.serialize(&("0.0.0", key.length, key.hash)).unwrap();
+    entry.extend(options.serialize(&song).unwrap());
+    assert!(read_entry(&entry, &key).is_err());
+}
+
+#[test]
+fn damaged_entries_are_parsed_again() {
+    let directory = env::temp_dir().join(format!("rustune-cache-{}", std::process::id()));
+    let data = b"module";
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join(Key::new(data).file_name()), b"damaged").unwrap();
+
+    let song = load_from(&directory, data, || Ok(test_song())).unwrap();
+    assert_eq!(song.metadata().title(), "Cached");
+
+    // It's been replaced by the song that was parsed
+    let song = load_from(&directory, data, || unreachable!()).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(song.metadata().title(), "Cached");
+}
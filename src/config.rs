@@ -0,0 +1,94 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Defaults for the command line options, read from a TOML file.
+///
+/// Keys are named after the options they set (`fixed-point = true` for `--fixed-point`),
+/// anything left out keeps the built-in default. Options given on the command line
+/// always win over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    // Output
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub float: Option<bool>,
+    pub swap_stereo: Option<bool>,
+
+    // Mixing
+    pub oversample: Option<usize>,
+    pub fixed_point: Option<bool>,
+    pub pan_law: Option<String>,
+    pub speed_factor: Option<f32>,
+    pub pitch: Option<f32>,
+
+    // Playback
+    #[serde(rename = "loop")]
+    pub loop_count: Option<u32>,
+    pub fade: Option<f32>,
+    pub crossfade: Option<f32>,
+
+    // Display
+    pub tui: Option<bool>,
+    pub scope: Option<bool>,
+    pub piano_roll: Option<bool>,
+    pub spectrum: Option<bool>,
+    pub spectrum_bands: Option<u16>,
+    pub progress: Option<bool>,
+
+    // Scrobbling, with `--features scrobble`
+    pub lastfm: Option<LastFm>,
+    pub listenbrainz: Option<ListenBrainz>,
+}
+
+/// The `[lastfm]` table: an API account from <https://www.last.fm/api/account/create>, and
+/// the session key of the user whose listens are scrobbled, which `--lastfm-login` gets
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+#[cfg_attr(not(feature = "scrobble"), allow(dead_code))]
+pub struct LastFm {
+    pub api_key: String,
+    pub api_secret: String,
+    pub session_key: Option<String>,
+}
+
+/// The `[listenbrainz]` table: the user token from <https://listenbrainz.org/settings/>, and
+/// the server for self-hosted instances
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+#[cfg_attr(not(feature = "scrobble"), allow(dead_code))]
+pub struct ListenBrainz {
+    pub token: String,
+    pub url: Option<String>,
+}
+
+impl Config {
+    /// `rustune/config.toml` in `$XDG_CONFIG_HOME`, or in `~/.config` when that isn't set
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+        Some(config_home.join("rustune").join("config.toml"))
+    }
+
+    /// Reads the config at `path`. A missing file is an empty config when `optional` is set,
+    /// so the default location doesn't have to exist
+    pub fn load(path: &Path, optional: bool) -> Result<Config, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if optional && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(format!("Failed to read {}: {e}", path.display())),
+        };
+
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,526 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::json::{self, JsonObject, Value};
+#[cfg(test)]
+use crate::player::Player;
+use crate::player::{Handle, Status};
+use crate::playlist;
+use crate::PlaybackEvent;
+
+// Error codes of the JSON-RPC 2.0 specification, and one of the range it leaves to servers
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const NOT_LOADED: i32 = -32000;
+
+// Longest request that's read, longer ones end the connection
+const MAX_LINE: usize = 64 * 1024;
+
+// Connections that send nothing for this long are closed, so they don't keep their threads
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// The loudest volume a client can set, as in the terminal interface
+const MAX_VOLUME: f64 = 2.0;
+
+/// Why a request failed, sent back as the error of its response
+struct Error {
+    code: i32,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Error {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// State shared by the threads serving the clients
+#[derive(Clone)]
+struct Server {
+    status: Arc<Mutex<Status>>,
+    player: Handle,
+    events: Sender<PlaybackEvent>,
+}
+
+/// Lets other programs control playback over TCP with JSON-RPC 2.0, one request per line
+/// and one response per line for every request with an `id`.
+///
+/// The methods are:
+/// - `status`, which returns `state` (`playing`, `paused` or `stopped`) and, while a song is
+///   loaded, its playlist `index`, `title`, `path`, and `elapsed` and `duration` in seconds
+/// - `play` and `pause`
+/// - `seek` with `orders`, how many orders to jump forwards or backwards
+/// - `volume` with `volume`, 1.0 being the unchanged output, up to 2.0
+/// - `load` with the `index` of a playlist entry, or the `path` of a module which is added to
+///   the playlist
+/// - `next`, `previous` and `quit`
+///
+/// Parameters are given by name. Controls return `true` once they've been passed on to the
+/// player, which acts on them right after. A request longer than 64 KiB gets an error and
+/// ends the connection, as does sending nothing for 5 minutes.
+///
+/// # Errors
+/// When `host` and `port` can't be listened on
+pub fn serve(
+    host: &str,
+    port: u16,
+    status: Arc<Mutex<Status>>,
+    player: Handle,
+    events: Sender<PlaybackEvent>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind((host, port))?;
+    log::info!(
+        "Listening for control requests on {}",
+        listener.local_addr()?
+    );
+
+    let server = Server {
+        status,
+        player,
+        events,
+    };
+    thread::spawn(move || accept_clients(listener, server, IDLE_TIMEOUT));
+
+    Ok(())
+}
+
+// Handles every client that connects on a thread of its own, until it disconnects or sends
+// nothing for `timeout`
+fn accept_clients(listener: TcpListener, server: Server, timeout: Duration) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::debug!("Control connection failed: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+            log::debug!("Control connection failed: {e}");
+            continue;
+        }
+
+        let server = server.clone();
+        thread::spawn(move || {
+            if let Err(e) = server.handle_client(stream) {
+                log::debug!("Control connection closed: {e}");
+            }
+        });
+    }
+}
+
+impl Server {
+    fn handle_client(&self, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            let read = (&mut reader).take(MAX_LINE as u64).read_line(&mut line)?;
+            if read == 0 {
+                return Ok(());
+            }
+            // The rest of the request can't be told apart from the next one
+            if read == MAX_LINE && !line.ends_with('\n') {
+                let error = Error::new(INVALID_REQUEST, format!("Longer than {MAX_LINE} bytes"));
+                writeln!(writer, "{}", response(&Value::Null, Err(error)))?;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Request too long",
+                ));
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.respond(&line) {
+                writeln!(writer, "{response}")?;
+            }
+        }
+    }
+
+    // The response to a request, `None` for notifications, which have no id
+    fn respond(&self, request: &str) -> Option<String> {
+        let request = match json::parse(request) {
+            Ok(request) => request,
+            Err(e) => return Some(response(&Value::Null, Err(Error::new(PARSE_ERROR, e)))),
+        };
+
+        let id = request.get("id").cloned();
+        let result = match request.get("method").and_then(Value::as_str) {
+            Some(method) => self.call(method, request.get("params")),
+            None => Err(Error::new(INVALID_REQUEST, "No method")),
+        };
+
+        id.map(|id| response(&id, result))
+    }
+
+    fn call(&self, method: &str, params: Option<&Value>) -> Result<String, Error> {
+        let param = |name: &str| params.and_then(|params| params.get(name));
+        let missing = |name: &str| Error::new(INVALID_PARAMS, format!("Expected {name}"));
+
+        match method {
+            "status" => return Ok(self.status()),
+            "play" => self.player.play(),
+            "pause" => self.player.pause(),
+            "seek" => {
+                let orders = param("orders").and_then(Value::as_i64);
+                let orders = orders.ok_or_else(|| missing("orders as a whole number"))?;
+                self.player.seek_orders(orders as isize);
+            }
+            "volume" => {
+                let volume = param("volume").and_then(Value::as_f64);
+                let volume = volume.filter(|volume| (0.0..=MAX_VOLUME).contains(volume));
+                self.player
+                    .set_volume(volume.ok_or_else(|| missing("volume from 0 to 2"))? as f32);
+            }
+
+            "load" => {
+                let event = if let Some(index) = param("index").and_then(Value::as_i64) {
+                    let index = usize::try_from(index).map_err(|_| missing("a playlist index"))?;
+                    PlaybackEvent::Select(index)
+                } else if let Some(path) = param("path").and_then(Value::as_str) {
+                    // Checked here, so the client hears about it
+                    let path = PathBuf::from(path);
+                    playlist::load(&path).map_err(|e| Error::new(NOT_LOADED, e.to_string()))?;
+                    PlaybackEvent::Open(path)
+                } else {
+                    return Err(missing("index or path"));
+                };
+                return self.send_event(event);
+            }
+            "next" => return self.send_event(PlaybackEvent::Next),
+            "previous" => return self.send_event(PlaybackEvent::Previous),
+            "quit" => return self.send_event(PlaybackEvent::Quit),
+
+            _ => return Err(Error::new(METHOD_NOT_FOUND, format!("No method {method}"))),
+        }
+
+        Ok(String::from("true"))
+    }
+
+    fn send_event(&self, event: PlaybackEvent) -> Result<String, Error> {
+        let _ = self.events.send(event);
+        Ok(String::from("true"))
+    }
+
+    fn status(&self) -> String {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+
+        let Some(song) = &status.song else {
+            return JsonObject::new().string("state", "stopped").finish();
+        };
+        let state = if status.paused { "paused" } else { "playing" };
+        JsonObject::new()
+            .string("state", state)
+            .value("index", song.index)
+            .string("title", &song.title)
+            .string("path", &song.path.to_string_lossy())
+            .raw("elapsed", &json::number(song.elapsed))
+            .raw("duration", &json::number(Some(song.duration)))
+            .finish()
+    }
+}
+
+// A JSON-RPC response to the request with `id`
+fn response(id: &Value, result: Result<String, Error>) -> String {
+    let object = JsonObject::new().string("jsonrpc", "2.0");
+    let object = match result {
+        Ok(result) => object.raw("result", &result),
+        Err(Error { code, message }) => {
+            let error = JsonObject::new()
+                .value("code", code)
+                .string("message", &message);
+            object.raw("error", &error.finish())
+        }
+    };
+    object.raw("id", &id.to_string()).finish()
+}
+
+#[cfg(test)]
+fn test_server() -> (Server, Player, std::sync::mpsc::Receiver<PlaybackEvent>) {
+    let (events, receiver) = std::sync::mpsc::channel();
+    let (player, handle) = Player::new(events.clone(), false);
+    let server = Server {
+        status: player.status(),
+        player: handle,
+        events,
+    };
+    (server, player, receiver)
+}
+
+// A song of two patterns, with a square wave starting on the first row that lasts a few
+// seconds
+#[cfg(test)]
+fn test_song() -> rustune::Song {
+    use rustune::song::Note;
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    crate::square_song(4)
+        .empty_pattern()
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .orders(&[0, 1])
+        .build()
+        .unwrap()
+}
+
+// Plays the test song as playlist entry 3
+#[cfg(test)]
+fn load_test_song(server: &Server, player: &mut Player) {
+    use crate::player::{Details, Entry};
+    use rustune::engine::Engine;
+    use rustune::TrackerEngine;
+
+    let mut engine = Engine::new(test_song()).unwrap();
+    engine.set_sample_rate(44100);
+    engine.set_channel_count(2);
+    server.player.load(Entry {
+        id: 3,
+        engine,
+        data: Details {
+            title: String::from("Test"),
+            path: PathBuf::from("test.mod"),
+            header: None,
+        },
+    });
+    player.tick();
+}
+
+// The result of a request, or the code of its error
+#[cfg(test)]
+fn call(server: &Server, request: &str) -> Result<Value, i64> {
+    let response = json::parse(&server.respond(request).unwrap()).unwrap();
+    assert_eq!(response.get("jsonrpc"), Some(&Value::String("2.0".into())));
+    match response.get("result") {
+        Some(result) => Ok(result.clone()),
+        None => Err((response.get("error"))
+            .and_then(|error| error.get("code"))
+            .and_then(Value::as_i64)
+            .unwrap()),
+    }
+}
+
+#[cfg(test)]
+fn state(server: &Server) -> String {
+    let status = call(server, r#"{"id": 1, "method": "status"}"#).unwrap();
+    status.get("state").and_then(Value::as_str).unwrap().into()
+}
+
+#[test]
+fn status() {
+    let (server, mut player, _events) = test_server();
+    assert_eq!(state(&server), "stopped");
+
+    load_test_song(&server, &mut player);
+    let status = call(&server, r#"{"id": 1, "method": "status"}"#).unwrap();
+    assert_eq!(status.get("state").and_then(Value::as_str), Some("playing"));
+    assert_eq!(status.get("index").and_then(Value::as_i64), Some(3));
+    assert_eq!(status.get("title").and_then(Value::as_str), Some("Test"));
+    assert_eq!(status.get("path").and_then(Value::as_str), Some("test.mod"));
+    assert!(status.get("elapsed").and_then(Value::as_f64).is_some());
+    assert!(status.get("duration").and_then(Value::as_f64).unwrap() > 0.0);
+}
+
+#[test]
+fn play_and_pause() {
+    let (server, mut player, _events) = test_server();
+    load_test_song(&server, &mut player);
+
+    assert_eq!(
+        call(&server, r#"{"id": 1, "method": "pause"}"#),
+        Ok(Value::Bool(true))
+    );
+    player.tick();
+    assert_eq!(state(&server), "paused");
+
+    assert_eq!(
+        call(&server, r#"{"id": 1, "method": "play"}"#),
+        Ok(Value::Bool(true))
+    );
+    player.tick();
+    assert_eq!(state(&server), "playing");
+}
+
+#[test]
+fn seek() {
+    let (server, mut player, _events) = test_server();
+    load_test_song(&server, &mut player);
+
+    let request = r#"{"id": 1, "method": "seek", "params": {"orders": 1}}"#;
+    assert_eq!(call(&server, request), Ok(Value::Bool(true)));
+    player.tick();
+    assert_eq!(server.player.position().0, 1);
+
+    let request = r#"{"id": 1, "method": "seek", "params": {"orders": -1}}"#;
+    assert_eq!(call(&server, request), Ok(Value::Bool(true)));
+    player.tick();
+    assert_eq!(server.player.position().0, 0);
+
+    for params in [
+        "",
+        r#", "params": {"orders": 1.5}"#,
+        r#", "params": {"orders": "1"}"#,
+    ] {
+        let request = format!(r#"{{"id": 1, "method": "seek"{params}}}"#);
+        assert_eq!(call(&server, &request), Err(INVALID_PARAMS as i64));
+    }
+}
+
+#[test]
+fn volume() {
+    let (server, mut player, _events) = test_server();
+    load_test_song(&server, &mut player);
+    let mut buffer = vec![0.0; 2048];
+
+    let request = r#"{"id": 1, "method": "volume", "params": {"volume": 0}}"#;
+    assert_eq!(call(&server, request), Ok(Value::Bool(true)));
+    player.render(&mut buffer, Duration::ZERO);
+    assert!(buffer.iter().all(|&sample| sample == 0.0));
+
+    let request = r#"{"id": 1, "method": "volume", "params": {"volume": 2}}"#;
+    assert_eq!(call(&server, request), Ok(Value::Bool(true)));
+    player.render(&mut buffer, Duration::ZERO);
+    assert!(buffer.iter().any(|&sample| sample != 0.0));
+
+    for volume in ["-0.1", "2.1", "\"1\"", "null"] {
+        let request =
+            format!(r#"{{"id": 1, "method": "volume", "params": {{"volume": {volume}}}}}"#);
+        assert_eq!(call(&server, &request), Err(INVALID_PARAMS as i64));
+    }
+}
+
+#[test]
+fn load() {
+    let (server, _player, events) = test_server();
+
+    let request = r#"{"id": 1, "method": "load", "params": {"index": 2}}"#;
+    assert_eq!(call(&server, request), Ok(Value::Bool(true)));
+    assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Select(2))));
+
+    let path = std::env::temp_dir().join(format!("rustune-control-{}.mod", std::process::id()));
+    test_song().save(&path).unwrap();
+    let request = format!(
+        r#"{{"id": 1, "method": "load", "params": {{"path": {}}}}}"#,
+        json::string(&path.to_string_lossy())
+    );
+    assert_eq!(call(&server, &request), Ok(Value::Bool(true)));
+    assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Open(opened)) if opened == path));
+    std::fs::remove_file(&path).unwrap();
+
+    // Modules that don't load aren't added to the playlist
+    assert_eq!(call(&server, &request), Err(NOT_LOADED as i64));
+    for params in [
+        "",
+        r#", "params": {"index": -1}"#,
+        r#", "params": {"index": 0.5}"#,
+    ] {
+        let request = format!(r#"{{"id": 1, "method": "load"{params}}}"#);
+        assert_eq!(call(&server, &request), Err(INVALID_PARAMS as i64));
+    }
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn next_previous_and_quit() {
+    let (server, _player, events) = test_server();
+
+    for method in ["next", "previous", "quit"] {
+        let request = format!(r#"{{"id": 1, "method": "{method}"}}"#);
+        assert_eq!(call(&server, &request), Ok(Value::Bool(true)));
+    }
+    assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Next)));
+    assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Previous)));
+    assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Quit)));
+}
+
+#[test]
+fn invalid_requests() {
+    let (server, _player, events) = test_server();
+
+    assert_eq!(
+        call(&server, r#"{"id": 1, "method": "rewind"}"#),
+        Err(METHOD_NOT_FOUND as i64)
+    );
+    assert_eq!(call(&server, r#"{"id": 1}"#), Err(INVALID_REQUEST as i64));
+    assert_eq!(call(&server, r#"{"id": 1, "#), Err(PARSE_ERROR as i64));
+    let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+    assert_eq!(call(&server, &nested), Err(PARSE_ERROR as i64));
+
+    // Errors without an id are only answered when the id couldn't be read
+    let response = json::parse(&server.respond("{").unwrap()).unwrap();
+    assert_eq!(response.get("id"), Some(&Value::Null));
+    assert_eq!(server.respond(r#"{"method": "rewind"}"#), None);
+
+    // Notifications act without an answer
+    assert_eq!(server.respond(r#"{"method": "next"}"#), None);
+    assert!(matches!(events.try_recv(), Ok(PlaybackEvent::Next)));
+}
+
+#[test]
+fn echoes_the_id() {
+    let (server, _player, _events) = test_server();
+
+    for id in [r#""abc""#, "7", "null"] {
+        let request = format!(r#"{{"id": {id}, "method": "next"}}"#);
+        let response = json::parse(&server.respond(&request).unwrap()).unwrap();
+        assert_eq!(response.get("id"), Some(&json::parse(id).unwrap()));
+    }
+}
+
+#[test]
+fn limits_request_length() {
+    let (server, _player, _events) = test_server();
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let handler = thread::spawn(move || server.handle_client(stream));
+
+    client
+        .write_all(b"\n{\"id\": 1, \"method\": \"status\"}\n")
+        .unwrap();
+    client.write_all(&[b' '; MAX_LINE + 1]).unwrap();
+
+    let mut responses = BufReader::new(client).lines();
+    let response = json::parse(&responses.next().unwrap().unwrap()).unwrap();
+    assert_eq!(response.get("id"), Some(&Value::Number(1.0)));
+    let response = json::parse(&responses.next().unwrap().unwrap()).unwrap();
+    let code = (response.get("error"))
+        .and_then(|error| error.get("code"))
+        .and_then(Value::as_i64);
+    assert_eq!(code, Some(INVALID_REQUEST as i64));
+
+    assert!(handler.join().unwrap().is_err());
+    assert!(responses.next().is_none());
+}
+
+#[test]
+fn closes_idle_connections() {
+    let (server, _player, _events) = test_server();
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || accept_clients(listener, server, Duration::from_millis(100)));
+
+    // The server hangs up on a client that never sends anything
+    let mut client = TcpStream::connect(address).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    assert_eq!(client.read(&mut [0; 16]).unwrap(), 0);
+}
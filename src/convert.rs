@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::playlist::Playlist;
+use crate::DEFAULT_RENDER_RATE;
+use crate::{create_engine, glob, Args, ConvertArgs, EditArgs, OptimizeArgs, RepairArgs};
+use rustune::engine::{Engine, TrackerEngine};
+use rustune::export::{self, loudness, tags::Tags, wav::WavFormat, OutputFormat};
+use rustune::formats::{mod_editor, mod_optimizer, mod_repair, mod_writer};
+use rustune::song::{Song, SongError};
+
+/// A module to render, and the file to render it to
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// How every file of a batch is rendered
+pub struct Settings {
+    pub format: OutputFormat,
+    pub sample_rate: u32,
+    pub wav_format: WavFormat,
+}
+
+/// Pairs every input with a file named after it with `extension`, in `out_dir` or next to
+/// the input without one.
+///
+/// Inputs from different directories may have the same name, those get a number appended
+/// so they don't overwrite each other.
+pub fn jobs(inputs: Vec<PathBuf>, out_dir: Option<&Path>, extension: &str) -> Vec<Job> {
+    let mut used: HashMap<PathBuf, usize> = HashMap::new();
+
+    inputs
+        .into_iter()
+        .map(|input| {
+            let stem = input
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("module"));
+            let dir = out_dir.or_else(|| input.parent()).unwrap_or(Path::new(""));
+
+            let count = used.entry(dir.join(&stem)).or_default();
+            *count += 1;
+            let name = match *count {
+                1 => stem,
+                count => format!("{stem}-{count}"),
+            };
+
+            let output = dir.join(format!("{name}.{extension}"));
+            Job { input, output }
+        })
+        .collect()
+}
+
+/// Renders the jobs on `threads` threads at once, calling `report` with how long each took or
+/// why it failed as they finish.
+///
+/// A failing file doesn't stop the batch, and doesn't leave a partial file behind.
+/// `create_engine` sets up the engine the way it would be for playback.
+pub fn convert_all(
+    jobs: &[Job],
+    settings: &Settings,
+    threads: usize,
+    create_engine: impl Fn(Song) -> Result<Engine, SongError> + Sync,
+    mut report: impl FnMut(&Job, Result<Duration, String>),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, jobs.len().max(1)) {
+            let sender = sender.clone();
+            let (next, create_engine) = (&next, &create_engine);
+
+            // Each thread takes the next job that hasn't been started yet, until none are left
+            scope.spawn(move || {
+                while let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = convert(job, settings, create_engine);
+                    if sender.send((job, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (job, result) in receiver {
+            report(job, result);
+        }
+    });
+}
+
+/// Saves the module of a job in the format of its output, e.g. to edit a MOD as XM
+pub fn save_module(job: &Job) -> Result<Duration, String> {
+    let start = Instant::now();
+
+    let song = Song::new(&job.input)?;
+    song.save(&job.output)
+        .map_err(|e| format!("Failed to save {}: {e}", job.output.display()))?;
+
+    Ok(start.elapsed())
+}
+
+fn convert(
+    job: &Job,
+    settings: &Settings,
+    create_engine: impl Fn(Song) -> Result<Engine, SongError>,
+) -> Result<Duration, String> {
+    let start = Instant::now();
+
+    let song = Song::new(&job.input)?;
+
+    let sample_rate = (settings.format.required_sample_rate()).unwrap_or(settings.sample_rate);
+    let setup = |song| {
+        let mut engine: Engine = create_engine(song)?;
+        engine.set_channel_count(2);
+        engine.set_sample_rate(sample_rate);
+        Ok::<_, SongError>(engine)
+    };
+
+    let mut tags = Tags::from_song(song.metadata());
+    // The gain tags come before the audio, so the song is rendered once to measure it
+    if settings.format.has_gain_tags() {
+        tags.loudness = Some(loudness::measure(&mut setup(song.clone())?));
+    }
+    let mut engine = setup(song)?;
+
+    let mut sink = export::create_sink(
+        &job.output,
+        settings.format,
+        sample_rate,
+        2,
+        settings.wav_format,
+        &tags,
+    )
+    .map_err(|e| format!("Failed to create {}: {e}", job.output.display()))?;
+
+    let rendered = export::render(&mut engine, sink.as_mut()).and_then(|_| sink.finish());
+    if let Err(e) = rendered {
+        // Nothing is removed when the song fails to load, an earlier render is kept then
+        let _ = fs::remove_file(&job.output);
+        return Err(e.to_string());
+    }
+
+    Ok(start.elapsed())
+}
+
+pub fn convert_files(args: &Args, convert: &ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let rendering = convert.to.is_none();
+    if rendering && args.loop_region.is_some() && args.max_time.is_none() {
+        return Err("A loop region repeats forever, use --max-time to limit the render".into());
+    }
+    if rendering && args.loop_count == 0 && args.max_time.is_none() {
+        return Err("--loop 0 repeats forever, use --max-time to limit the render".into());
+    }
+
+    let paths: Vec<PathBuf> = convert
+        .inputs
+        .iter()
+        .flat_map(|input| glob::expand(input))
+        .collect();
+    let inputs = Playlist::from_paths(&paths)?;
+    if inputs.is_empty() {
+        return Err("No modules match".into());
+    }
+
+    if let Some(out_dir) = &convert.out_dir {
+        fs::create_dir_all(out_dir)
+            .map_err(|e| format!("Failed to create {}: {e}", out_dir.display()))?;
+    }
+
+    let extension = (convert.to.as_deref()).unwrap_or(convert.format.extension());
+    let jobs = jobs(
+        inputs.entries().to_vec(),
+        convert.out_dir.as_deref(),
+        extension,
+    );
+    let settings = Settings {
+        format: convert.format,
+        sample_rate: args.sample_rate.unwrap_or(DEFAULT_RENDER_RATE),
+        wav_format: if args.float {
+            WavFormat::Float32
+        } else {
+            WavFormat::Int16
+        },
+    };
+
+    let threads = match convert.jobs {
+        Some(jobs) => jobs as usize,
+        None => thread::available_parallelism().map_or(1, |threads| threads.get()),
+    };
+
+    let mut failed = 0;
+    let mut report = |job: &Job, result: Result<Duration, String>| match result {
+        Ok(time) => println!(
+            "OK    {} -> {} ({:.2} s)",
+            job.input.display(),
+            job.output.display(),
+            time.as_secs_f64()
+        ),
+        Err(e) => {
+            failed += 1;
+            println!("FAIL  {}: {e}", job.input.display());
+        }
+    };
+
+    if rendering {
+        convert_all(
+            &jobs,
+            &settings,
+            threads,
+            |song| create_engine(song, args),
+            &mut report,
+        );
+    } else {
+        // Saving takes no time next to rendering, it's done one after another
+        for job in &jobs {
+            report(job, save_module(job));
+        }
+    }
+
+    println!("Converted {} of {} files", jobs.len() - failed, jobs.len());
+    if failed > 0 {
+        return Err(format!("{failed} files failed to convert").into());
+    }
+
+    Ok(())
+}
+
+pub fn optimize_file(optimize: &OptimizeArgs) -> Result<(), Box<dyn Error>> {
+    let song = Song::new(&optimize.input)?;
+    let (optimized, optimization) = mod_optimizer::optimize(&song);
+
+    let data = mod_writer::write(&optimized)?;
+    fs::write(&optimize.output, &data)
+        .map_err(|e| format!("Failed to save {}: {e}", optimize.output.display()))?;
+
+    let before = fs::metadata(&optimize.input)?.len();
+    println!(
+        "Removed {} patterns and {} samples, and {} bytes after sample loops",
+        optimization.patterns_removed, optimization.samples_removed, optimization.bytes_truncated
+    );
+    println!(
+        "{} bytes -> {} bytes, saved to {}",
+        before,
+        data.len(),
+        optimize.output.display()
+    );
+
+    Ok(())
+}
+
+pub fn repair_file(repair: &RepairArgs) -> Result<(), Box<dyn Error>> {
+    let data = fs::read(&repair.input)
+        .map_err(|e| format!("Failed to read {}: {e}", repair.input.display()))?;
+    let (song, fixes) = mod_repair::repair(&data)?;
+
+    let data = mod_writer::write(&song)?;
+    fs::write(&repair.output, &data)
+        .map_err(|e| format!("Failed to save {}: {e}", repair.output.display()))?;
+
+    if fixes.is_empty() {
+        println!("Nothing to repair");
+    }
+    for fix in &fixes {
+        println!("{fix}");
+    }
+    println!("Saved to {}", repair.output.display());
+
+    Ok(())
+}
+
+pub fn edit_file(edit: &EditArgs) -> Result<(), Box<dyn Error>> {
+    let changes = mod_editor::MetadataEdit {
+        title: edit.title.clone(),
+        sample_names: edit.sample_name.clone(),
+        sample_volumes: edit.volume.clone(),
+        sample_finetunes: edit.finetune.clone(),
+    };
+    if changes.is_empty() {
+        return Err(
+            "Nothing to change, give --title, --sample-name, --volume or --finetune".into(),
+        );
+    }
+
+    let data = fs::read(&edit.input)
+        .map_err(|e| format!("Failed to read {}: {e}", edit.input.display()))?;
+    // Samples are numbered from 1 in messages, like everywhere else
+    let edited = mod_editor::edit(&data, &changes).map_err(|e| match e {
+        SongError::NoSuchSample { index } => format!("Sample {} doesn't exist", index + 1),
+        e => e.to_string(),
+    })?;
+
+    let output = edit.output.as_ref().unwrap_or(&edit.input);
+    fs::write(output, &edited).map_err(|e| format!("Failed to save {}: {e}", output.display()))?;
+    println!("Saved to {}", output.display());
+
+    Ok(())
+}
+
+/// Writes the samples of every file to `dir` as WAV files, for --export-samples
+pub fn export_samples(playlist: &Playlist, dir: &Path) -> Result<(), Box<dyn Error>> {
+    for path in playlist.entries() {
+        let song = playlist.load(path)?;
+
+        // Keep the samples of different files apart when exporting several
+        let dir = if playlist.len() > 1 {
+            dir.join(path.file_stem().unwrap_or_default())
+        } else {
+            dir.to_path_buf()
+        };
+
+        let written = export::samples::export_samples(&song, &dir)?;
+        println!(
+            "Exported {} samples from {} to {}",
+            written.len(),
+            path.display(),
+            dir.display()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,298 @@
+use std::path::{Path, PathBuf};
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::jam::{KEYS, OCTAVES};
+use rustune::song::{Note, Song, SongError, SongLineDisplay};
+use rustune::tracker;
+
+// Where the fields of a channel start in a line of `SongLineDisplay`, after the row number,
+// and how wide they are
+const ROW_NUMBER_WIDTH: usize = 2;
+const CHANNEL_WIDTH: usize = 13;
+
+/// The part of a pattern cell the cursor is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Note,
+    Sample,
+    Effect,
+}
+
+impl Field {
+    // Offset into the channel's part of a line, and width
+    fn columns(self) -> (usize, usize) {
+        match self {
+            Field::Note => (3, 3),
+            Field::Sample => (7, 2),
+            Field::Effect => (10, 3),
+        }
+    }
+
+    // Digits typed into the field before the cursor moves on
+    fn digits(self) -> usize {
+        match self {
+            Field::Note => 1,
+            Field::Sample => 2,
+            Field::Effect => 3,
+        }
+    }
+}
+
+/// What a key pressed in the editor asks of the interface
+pub enum Action {
+    None,
+    /// Leave the editor, dropping unsaved changes
+    Close,
+    /// Save the song, see [`Editor::save`]
+    Save,
+}
+
+/// Edits the patterns of a song a cell at a time, the way ProTracker does: notes are entered
+/// from the keyboard like a piano, sample numbers in decimal and effects in hex. Edits are
+/// heard once they're saved and the song is reloaded.
+pub struct Editor {
+    // The playlist entry that's edited, and its file
+    index: usize,
+    path: PathBuf,
+    song: Song,
+
+    pattern: usize,
+    row: usize,
+    channel: usize,
+    field: Field,
+    // Digits already typed into the field
+    digit: usize,
+
+    octave: u8,
+    // Entered with every note, the last sample number typed in
+    sample: u8,
+    modified: bool,
+    // Shown in the status line until the next key press, e.g. why saving failed
+    message: Option<String>,
+}
+
+impl Editor {
+    /// Starts editing `song`, the playlist entry `index` loaded from `path`, at `row` of
+    /// `pattern`
+    pub fn new(index: usize, path: &Path, song: Song, pattern: usize, row: usize) -> Self {
+        let pattern = pattern.min(song.patterns().len().saturating_sub(1));
+        let row = row.min(song.pattern(pattern).map_or(0, Vec::len).saturating_sub(1));
+
+        Editor {
+            index,
+            path: path.to_path_buf(),
+            song,
+            pattern,
+            row,
+            channel: 0,
+            field: Field::Note,
+            digit: 0,
+            octave: 2,
+            sample: 1,
+            modified: false,
+            message: None,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn pattern(&self) -> usize {
+        self.pattern
+    }
+
+    /// Row, channel and field of the cursor
+    pub fn cursor(&self) -> (usize, usize, Field) {
+        (self.row, self.channel, self.field)
+    }
+
+    /// Every line of the edited pattern in tracker notation
+    pub fn lines(&self) -> Vec<String> {
+        let Some(pattern) = self.song.pattern(self.pattern) else {
+            return Vec::new();
+        };
+
+        (0..pattern.len())
+            .map(|lineno| {
+                SongLineDisplay {
+                    pattern,
+                    sample_metadata: self.song.metadata().instruments(),
+                    lineno,
+                }
+                .to_string()
+            })
+            .collect()
+    }
+
+    /// Characters of a line the cursor's field takes up
+    pub fn cursor_columns(&self) -> std::ops::Range<usize> {
+        let (offset, width) = self.field.columns();
+        let start = ROW_NUMBER_WIDTH + CHANNEL_WIDTH * self.channel + offset;
+        start..start + width
+    }
+
+    /// For the status line: the octave notes are entered in, and whether there's anything to
+    /// save
+    pub fn status(&self) -> String {
+        let mut status = format!(
+            "Editing   Octave {}   Sample {:02}",
+            self.octave, self.sample
+        );
+        if self.modified {
+            status += "   Modified";
+        }
+        if let Some(message) = &self.message {
+            status += &format!("   {message}");
+        }
+        status
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        self.message = None;
+
+        match key.code {
+            KeyCode::Esc => return Action::Close,
+            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Action::Save
+            }
+
+            KeyCode::Up => self.move_rows(-1),
+            KeyCode::Down => self.move_rows(1),
+            KeyCode::PageUp => self.move_patterns(-1),
+            KeyCode::PageDown => self.move_patterns(1),
+            KeyCode::Left => self.move_fields(-1),
+            KeyCode::Right => self.move_fields(1),
+            KeyCode::BackTab => self.move_channels(-1),
+            KeyCode::Tab => self.move_channels(1),
+            KeyCode::Char('<') => self.octave = (self.octave - 1).max(*OCTAVES.start()),
+            KeyCode::Char('>') => self.octave = (self.octave + 1).min(*OCTAVES.end()),
+
+            KeyCode::Delete | KeyCode::Backspace => {
+                self.edit(|note, field| match field {
+                    Field::Note => (note.sample, note.period) = (0, 0),
+                    Field::Sample => note.sample = 0,
+                    Field::Effect => (note.effect, note.argument) = (0, 0),
+                });
+                self.move_rows(1);
+            }
+            KeyCode::Char(key) => self.type_key(key.to_ascii_lowercase()),
+            _ => {}
+        }
+
+        Action::None
+    }
+
+    /// Saves the song to its file as MOD
+    pub fn save(&mut self) -> Result<(), SongError> {
+        self.song.save(&self.path)?;
+        self.modified = false;
+        self.message = Some(String::from("Saved"));
+        Ok(())
+    }
+
+    /// Shown in the status line until the next key press
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    fn type_key(&mut self, key: char) {
+        let sample_count = self.song.metadata().instruments().len();
+
+        match self.field {
+            Field::Note => {
+                let Some(&(_, semitone)) = KEYS.iter().find(|&&(mapped, _)| mapped == key) else {
+                    return;
+                };
+                // C-1 is note 24, patterns hold periods without finetune
+                let number = (self.octave + 1) * 12 + semitone;
+                let Some(period) = tracker::protracker_note_to_period(number as usize, 0) else {
+                    return;
+                };
+                let sample = self.sample;
+                self.edit(|note, _| (note.sample, note.period) = (sample, period));
+            }
+            Field::Sample => {
+                let Some(value) = key.to_digit(10) else {
+                    return;
+                };
+                let Some(note) = self.song.note_mut(self.pattern, self.row, self.channel) else {
+                    return;
+                };
+                // Tens first, then ones
+                let sample = match self.digit {
+                    0 => value as u8 * 10,
+                    _ => note.sample / 10 * 10 + value as u8,
+                };
+                if sample as usize > sample_count {
+                    self.message = Some(format!("The song has {sample_count} samples"));
+                    return;
+                }
+                note.sample = sample;
+                self.modified = true;
+                if sample > 0 {
+                    self.sample = sample;
+                }
+            }
+            Field::Effect => {
+                let Some(value) = key.to_digit(16) else {
+                    return;
+                };
+                let value = value as u8;
+                let digit = self.digit;
+                self.edit(|note, _| match digit {
+                    0 => note.effect = value,
+                    1 => note.argument = value << 4 | note.argument & 0x0F,
+                    _ => note.argument = note.argument & 0xF0 | value,
+                });
+            }
+        }
+
+        // The cursor moves down once the field is complete
+        self.digit += 1;
+        if self.digit >= self.field.digits() {
+            self.move_rows(1);
+        }
+    }
+
+    // Changes the note under the cursor
+    fn edit(&mut self, change: impl FnOnce(&mut Note, Field)) {
+        if let Some(note) = self.song.note_mut(self.pattern, self.row, self.channel) {
+            change(note, self.field);
+            self.modified = true;
+        }
+    }
+
+    fn move_rows(&mut self, rows: isize) {
+        let count = self.song.pattern(self.pattern).map_or(1, Vec::len).max(1);
+        self.row = (self.row as isize + rows).rem_euclid(count as isize) as usize;
+        self.digit = 0;
+    }
+
+    fn move_patterns(&mut self, patterns: isize) {
+        let last = self.song.patterns().len().saturating_sub(1);
+        self.pattern = self.pattern.saturating_add_signed(patterns).min(last);
+        let count = self.song.pattern(self.pattern).map_or(1, Vec::len);
+        self.row = self.row.min(count.saturating_sub(1));
+        self.digit = 0;
+    }
+
+    fn move_channels(&mut self, channels: isize) {
+        let count = self.song.metadata().channels().max(1);
+        self.channel = (self.channel as isize + channels).rem_euclid(count as isize) as usize;
+        self.digit = 0;
+    }
+
+    // Moves through the fields of a channel, and on to the next or previous channel
+    fn move_fields(&mut self, fields: isize) {
+        const FIELDS: [Field; 3] = [Field::Note, Field::Sample, Field::Effect];
+        let channels = self.song.metadata().channels().max(1);
+
+        let current = self.channel * 3 + FIELDS.iter().position(|&f| f == self.field).unwrap_or(0);
+        let next = (current as isize + fields).rem_euclid((channels * 3) as isize) as usize;
+        self.channel = next / 3;
+        self.field = FIELDS[next % 3];
+        self.digit = 0;
+    }
+}
@@ -0,0 +1,95 @@
+//! Rendered audio as an iterator of stereo frames
+
+use super::{TrackerEngine, DEFAULT_SAMPLE_RATE};
+
+// Frames rendered at once, handed out one by one
+const CHUNK_FRAMES: usize = 1024;
+
+/// Stereo frames of an engine, see [`TrackerEngine::frames`]
+pub struct Frames<'a, E: TrackerEngine> {
+    engine: &'a mut E,
+    buffer: Vec<f32>,
+    // Next frame of `buffer` to hand out, and how many are filled
+    position: usize,
+    filled: usize,
+}
+
+impl<'a, E: TrackerEngine> Frames<'a, E> {
+    pub(super) fn new(engine: &'a mut E) -> Self {
+        if engine.channel_count() == 0 {
+            engine.set_channel_count(2);
+        }
+        if engine.sample_rate() == 0 {
+            engine.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        }
+
+        Frames {
+            buffer: vec![0.0; CHUNK_FRAMES * engine.channel_count() as usize],
+            engine,
+            position: 0,
+            filled: 0,
+        }
+    }
+}
+
+impl<E: TrackerEngine> Iterator for Frames<'_, E> {
+    type Item = [f32; 2];
+
+    fn next(&mut self) -> Option<[f32; 2]> {
+        if self.position == self.filled {
+            if self.engine.is_finished() {
+                return None;
+            }
+
+            self.filled = self.engine.render(&mut self.buffer);
+            self.position = 0;
+
+            if self.filled == 0 {
+                return None;
+            }
+        }
+
+        let channels = self.engine.channel_count() as usize;
+        let frame = &self.buffer[self.position * channels..];
+        self.position += 1;
+
+        // Mono is played on both sides
+        Some(match channels {
+            1 => [frame[0], frame[0]],
+            _ => [frame[0], frame[1]],
+        })
+    }
+}
+
+#[test]
+fn frames_match_render() {
+    use super::Engine;
+    use crate::song::{square_song, Note};
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    let song = square_song(4)
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .build()
+        .unwrap();
+
+    let mut rendered = Engine::new(song.clone()).unwrap();
+    rendered.set_channel_count(2);
+    rendered.set_sample_rate(DEFAULT_SAMPLE_RATE);
+    let mut buffer = vec![0.0; 4096];
+    let mut expected = Vec::new();
+    while !rendered.is_finished() {
+        let frames = rendered.render(&mut buffer);
+        expected.extend_from_slice(&buffer[..frames * 2]);
+    }
+
+    let mut engine = Engine::new(song).unwrap();
+    let frames: Vec<f32> = engine.frames().flatten().collect();
+
+    assert_eq!(frames, expected);
+}
@@ -1,29 +1,277 @@
-use crate::tracker::Tracker;
+//! Song playback: the engines that turn patterns and samples into audio
+
+use std::sync::Arc;
+
+use crate::song::{Note, SongError};
+use crate::tracker::{Clock, Tracker};
 use crate::Song;
 use mod_engine::ModEngine;
 
+mod frames;
 mod mod_engine;
+mod oversample;
+mod player;
+mod scope;
+
+pub use frames::Frames;
+pub use mod_engine::supports_effect;
+pub use player::{Player, PlayerEntry, PlayerHandle, PlayerListener};
+pub use scope::ScopeBuffer;
+
+// Engines play at this rate until they're given one
+pub(crate) const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// The log target effects are traced to, see [`TrackerEngine::set_trace_effects`]
+pub const TRACE_EFFECTS_TARGET: &str = "rustune::effects";
+
+/// The mixing path an engine renders with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mixer {
+    /// Floating point accumulators and sample positions
+    #[default]
+    Float,
+    /// i32 accumulators and 16.16 sample positions, deterministic across platforms
+    FixedPoint,
+}
+
+/// How a channel's panning splits its output between left and right
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PanLaw {
+    /// The sides' gains add up to 1, as trackers mix. At the center each side is 6 dB down,
+    /// which makes centered channels 3 dB quieter in total than hard-panned ones
+    #[default]
+    Linear,
+    /// The sides' powers add up to 1, so channels are as loud wherever they're panned
+    ConstantPower,
+}
+
+/// A section of the song that repeats instead of playing on, both ends are (order, row) and
+/// the end row is included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopRegion {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
 
+/// What a tracker channel is playing at a moment, see [`TrackerEngine::channel_snapshot`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelSnapshot {
+    /// Period the channel plays at with its effects, 0 while it's silent
+    pub period: u16,
+    /// 0-64
+    pub volume: u8,
+    /// Number of the sample it played last, from 1, 0 before it played any
+    pub sample: u8,
+    /// Frame of the sample it plays next
+    pub position: u32,
+}
+
+impl ChannelSnapshot {
+    /// The pitch in semitones, counted like [`crate::tracker::parse_note`] counts them.
+    /// Slides and vibrato land between notes. `None` while the channel is silent
+    ///
+    /// ```
+    /// use rustune::engine::ChannelSnapshot;
+    ///
+    /// let snapshot = ChannelSnapshot { period: 428, volume: 64, sample: 1, position: 0 };
+    /// assert_eq!(snapshot.pitch().map(f32::round), Some(36.0)); // C-2
+    /// ```
+    pub fn pitch(&self) -> Option<f32> {
+        // Period of the lowest note, number 0
+        const LOWEST: f32 = 3424.0;
+        (self.period != 0).then(|| 12.0 * (LOWEST / self.period as f32).log2())
+    }
+}
+
+/// Plays a [`Song`], with the engine for its format.
+///
+/// Everything is done through [`TrackerEngine`], which this implements by handing every
+/// call to the engine inside.
 pub enum Engine {
+    /// ProTracker and compatible MOD files
     Mod(mod_engine::ModEngine),
 }
 
 pub trait TrackerEngine {
+    /// Processes the next tick: new rows, effects and jumps, without mixing any audio
     fn next_tick(&mut self);
+    /// Whether the song ended, after its last loop, fade out or the maximum time
     fn is_finished(&self) -> bool;
+    /// Whether the song is fading out, it finishes once the fade is done
+    fn is_fading(&self) -> bool;
+
+    /// The order (position in the pattern table) and row that plays next
+    fn position(&self) -> (usize, usize);
+    /// How many orders the song plays
+    fn song_length(&self) -> usize;
+    /// Continues playback from the given order and row, returns false if it's outside the song
+    fn seek(&mut self, order: usize, row: usize) -> bool;
+
+    /// Length of a single playthrough of the song in seconds, worked out from the pattern data
+    fn duration(&self) -> f64;
+    /// How far into that playthrough the current position is, `None` if the playthrough
+    /// never reaches it (e.g. it's skipped by a jump)
+    fn elapsed(&self) -> Option<f64>;
+
+    /// Repeats `region` until it's cleared with `None`, returns false if it's outside the song.
+    /// Playback continues normally until the end of the region is reached
+    fn set_loop_region(&mut self, region: Option<LoopRegion>) -> bool;
+    /// The region set with [`set_loop_region`](TrackerEngine::set_loop_region), if any
+    fn loop_region(&self) -> Option<LoopRegion>;
+
+    /// How many times the song is played before it ends, 0 loops forever
+    fn set_loop_count(&mut self, count: u32);
+    /// How many times the song played through since it started
+    fn times_played(&self) -> u32;
+    /// The order and row the song continues at once it played through, where it loops.
+    /// Known from the start, engines play the song through when they're created
+    fn loop_point(&self) -> (usize, usize);
+    /// Fades out over `seconds` after the last loop instead of stopping abruptly
+    fn set_fade_out(&mut self, seconds: f32);
+    /// Ends (or starts fading out) once this many seconds have been played
+    fn set_max_time(&mut self, seconds: Option<f64>);
+    /// Ends the song once the output has been silent for this many seconds, for songs that
+    /// end by going quiet while their orders keep looping. `None` (the default) plays on
+    fn set_silence_limit(&mut self, seconds: Option<f32>);
+    /// Mixes the current tick's audio into `buffer`, without advancing to the next tick
     fn get_audio_buffer(&mut self, buffer: &mut [f32]);
 
+    /// Frames rendered since the current tick started
     fn samples_since_tick(&self) -> usize;
     fn set_samples_since_tick(&mut self, value: usize);
+    /// Frames every tick lasts at the current tempo and sample rate
     fn samples_per_tick(&self) -> usize;
 
+    /// Output sample rate in Hz
     fn sample_rate(&self) -> u32;
     fn set_sample_rate(&mut self, value: u32);
 
+    /// Output channels, 1 (mono) or 2 (stereo)
     fn channel_count(&self) -> u16;
     fn set_channel_count(&mut self, value: u16);
 
+    /// Prints every row to stdout as it's played, off by default
+    fn set_print_rows(&mut self, enabled: bool);
+    /// Logs every effect on every tick it's processed, with its channel and position,
+    /// including effects that aren't played. Off by default.
+    ///
+    /// The effects are logged at trace level to [`TRACE_EFFECTS_TARGET`], so the logger
+    /// has to let those through
+    fn set_trace_effects(&mut self, enabled: bool);
+
+    /// How many times the output rate the engine mixes at internally
+    fn oversampling(&self) -> usize;
+    /// Mixes at 1, 2 or 4 times the output rate, to reduce aliasing
+    fn set_oversampling(&mut self, factor: usize);
+
+    /// Plays ticks `factor` times as fast, without changing the pitch
+    fn set_speed_factor(&mut self, factor: f32);
+    /// Plays every note `factor` times higher, without changing the tempo
+    fn set_pitch_factor(&mut self, factor: f32);
+    /// The clock the periods of the song count in, e.g. for modules written on an NTSC Amiga
+    fn set_clock(&mut self, clock: Clock);
+
+    /// Silences a tracker channel (0-based) without affecting playback otherwise
+    fn set_channel_muted(&mut self, channel: usize, muted: bool);
+    /// Whether a tracker channel (0-based) is silenced
+    fn is_channel_muted(&self, channel: usize) -> bool;
+    /// Pans a tracker channel (0-based) from 0 (left) to 255 (right) instead of where the song
+    /// pans it, e.g. to fix modules with broken hard panning. `None` leaves it to the song
+    fn set_channel_panning(&mut self, channel: usize, panning: Option<u8>);
+    /// Swaps the left and right output, for listeners used to the mirrored layout of some
+    /// Amiga rips. Only the output changes, the panning of channels stays as it is
+    fn set_swap_stereo(&mut self, swapped: bool);
+    /// How panning splits channels between left and right, [`PanLaw::Linear`] by default
+    fn set_pan_law(&mut self, law: PanLaw);
+    /// Volume (0-64) a tracker channel (0-based) plays at, 0 for channels the song doesn't have
+    fn channel_volume(&self, channel: usize) -> u8;
+    /// What a tracker channel (0-based) is playing, a silent channel for channels the song
+    /// doesn't have
+    fn channel_snapshot(&self, channel: usize) -> ChannelSnapshot;
+    /// Plays a note on a tracker channel (0-based) right away, as if it were in the pattern
+    /// at the current row, e.g. for playing the song's samples live. Its effect applies at
+    /// once unless it changes the song's position or speed
+    fn play_note(&mut self, channel: usize, note: Note);
+
+    /// Records the output of every channel into `scope`, or stops recording with `None`
+    fn set_scope(&mut self, scope: Option<Arc<ScopeBuffer>>);
+
+    /// Length of a tick in seconds at the current tempo
     fn tick_duration(&self) -> f32;
+    /// Ticks every row lasts at the moment, effects change it
+    fn speed(&self) -> u8;
+    /// The tick of the current row that's processed next, counting from 0
+    fn tick(&self) -> u8;
+
+    /// The song being played
+    fn song(&self) -> &Song;
+
+    /// Renders interleaved audio into `buffer`, processing ticks at the exact frame they are due.
+    ///
+    /// This is the offline render path, it doesn't depend on any audio device and is also used
+    /// by the audio callback.
+    ///
+    /// # Returns
+    /// The amount of frames rendered, which is less than requested if the song finished.
+    /// The remainder of the buffer is filled with silence.
+    fn render(&mut self, buffer: &mut [f32]) -> usize {
+        let channels = self.channel_count() as usize;
+        let total_frames = buffer.len() / channels;
+
+        let mut frame = 0;
+        while frame < total_frames {
+            // A new tick starts at the first frame of its interval
+            if self.samples_since_tick() == 0 {
+                self.next_tick();
+            }
+
+            if self.is_finished() {
+                buffer[frame * channels..].fill(0.0);
+                break;
+            }
+
+            // The tick length may have changed while processing the tick (e.g. by a tempo effect)
+            let samples_per_tick = self.samples_per_tick().max(1);
+            let frames = samples_per_tick
+                .saturating_sub(self.samples_since_tick())
+                .clamp(1, total_frames - frame);
+            self.get_audio_buffer(&mut buffer[frame * channels..(frame + frames) * channels]);
+            frame += frames;
+
+            let samples_since_tick = self.samples_since_tick() + frames;
+            if samples_since_tick >= samples_per_tick {
+                self.set_samples_since_tick(0);
+            } else {
+                self.set_samples_since_tick(samples_since_tick);
+            }
+        }
+
+        frame
+    }
+
+    /// Renders the song as stereo frames, for use with iterator adapters. Mono engines give
+    /// the same sample on both sides.
+    ///
+    /// Engines without channels or a sample rate yet are set to stereo at 44100 Hz.
+    ///
+    /// ```no_run
+    /// use rustune::{Engine, Song, TrackerEngine};
+    ///
+    /// let mut engine = Engine::new(Song::new("song.mod".as_ref())?)?;
+    ///
+    /// // The loudest sample of the first 30 seconds
+    /// let peak = (engine.frames().take(44100 * 30))
+    ///     .flatten()
+    ///     .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    /// # let _ = peak;
+    /// # Ok::<(), rustune::SongError>(())
+    /// ```
+    fn frames(&mut self) -> Frames<'_, Self>
+    where
+        Self: Sized,
+    {
+        Frames::new(self)
+    }
 }
 
 // could probably simplify a lot of this with a macro
@@ -40,6 +288,90 @@ impl TrackerEngine for Engine {
         }
     }
 
+    fn is_fading(&self) -> bool {
+        match self {
+            Engine::Mod(e) => e.is_fading(),
+        }
+    }
+
+    fn position(&self) -> (usize, usize) {
+        match self {
+            Engine::Mod(e) => e.position(),
+        }
+    }
+
+    fn song_length(&self) -> usize {
+        match self {
+            Engine::Mod(e) => e.song_length(),
+        }
+    }
+
+    fn seek(&mut self, order: usize, row: usize) -> bool {
+        match self {
+            Engine::Mod(e) => e.seek(order, row),
+        }
+    }
+
+    fn duration(&self) -> f64 {
+        match self {
+            Engine::Mod(e) => e.duration(),
+        }
+    }
+
+    fn elapsed(&self) -> Option<f64> {
+        match self {
+            Engine::Mod(e) => e.elapsed(),
+        }
+    }
+
+    fn set_loop_region(&mut self, region: Option<LoopRegion>) -> bool {
+        match self {
+            Engine::Mod(e) => e.set_loop_region(region),
+        }
+    }
+
+    fn loop_region(&self) -> Option<LoopRegion> {
+        match self {
+            Engine::Mod(e) => e.loop_region(),
+        }
+    }
+
+    fn set_loop_count(&mut self, count: u32) {
+        match self {
+            Engine::Mod(e) => e.set_loop_count(count),
+        }
+    }
+
+    fn times_played(&self) -> u32 {
+        match self {
+            Engine::Mod(e) => e.times_played(),
+        }
+    }
+
+    fn loop_point(&self) -> (usize, usize) {
+        match self {
+            Engine::Mod(e) => e.loop_point(),
+        }
+    }
+
+    fn set_fade_out(&mut self, seconds: f32) {
+        match self {
+            Engine::Mod(e) => e.set_fade_out(seconds),
+        }
+    }
+
+    fn set_max_time(&mut self, seconds: Option<f64>) {
+        match self {
+            Engine::Mod(e) => e.set_max_time(seconds),
+        }
+    }
+
+    fn set_silence_limit(&mut self, seconds: Option<f32>) {
+        match self {
+            Engine::Mod(e) => e.set_silence_limit(seconds),
+        }
+    }
+
     fn get_audio_buffer(&mut self, buffer: &mut [f32]) {
         match self {
             Engine::Mod(e) => e.get_audio_buffer(buffer),
@@ -86,19 +418,173 @@ impl TrackerEngine for Engine {
         }
     }
 
+    fn set_print_rows(&mut self, enabled: bool) {
+        match self {
+            Engine::Mod(e) => e.set_print_rows(enabled),
+        }
+    }
+
+    fn set_trace_effects(&mut self, enabled: bool) {
+        match self {
+            Engine::Mod(e) => e.set_trace_effects(enabled),
+        }
+    }
+
+    fn oversampling(&self) -> usize {
+        match self {
+            Engine::Mod(e) => e.oversampling(),
+        }
+    }
+
+    fn set_oversampling(&mut self, factor: usize) {
+        match self {
+            Engine::Mod(e) => e.set_oversampling(factor),
+        }
+    }
+
+    fn set_speed_factor(&mut self, factor: f32) {
+        match self {
+            Engine::Mod(e) => e.set_speed_factor(factor),
+        }
+    }
+
+    fn set_pitch_factor(&mut self, factor: f32) {
+        match self {
+            Engine::Mod(e) => e.set_pitch_factor(factor),
+        }
+    }
+
+    fn set_clock(&mut self, clock: Clock) {
+        match self {
+            Engine::Mod(e) => e.set_clock(clock),
+        }
+    }
+
+    fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        match self {
+            Engine::Mod(e) => e.set_channel_muted(channel, muted),
+        }
+    }
+
+    fn is_channel_muted(&self, channel: usize) -> bool {
+        match self {
+            Engine::Mod(e) => e.is_channel_muted(channel),
+        }
+    }
+
+    fn set_channel_panning(&mut self, channel: usize, panning: Option<u8>) {
+        match self {
+            Engine::Mod(e) => e.set_channel_panning(channel, panning),
+        }
+    }
+
+    fn set_swap_stereo(&mut self, swapped: bool) {
+        match self {
+            Engine::Mod(e) => e.set_swap_stereo(swapped),
+        }
+    }
+
+    fn set_pan_law(&mut self, law: PanLaw) {
+        match self {
+            Engine::Mod(e) => e.set_pan_law(law),
+        }
+    }
+
+    fn channel_volume(&self, channel: usize) -> u8 {
+        match self {
+            Engine::Mod(e) => e.channel_volume(channel),
+        }
+    }
+
+    fn channel_snapshot(&self, channel: usize) -> ChannelSnapshot {
+        match self {
+            Engine::Mod(e) => e.channel_snapshot(channel),
+        }
+    }
+
+    fn play_note(&mut self, channel: usize, note: Note) {
+        match self {
+            Engine::Mod(e) => e.play_note(channel, note),
+        }
+    }
+
+    fn set_scope(&mut self, scope: Option<Arc<ScopeBuffer>>) {
+        match self {
+            Engine::Mod(e) => e.set_scope(scope),
+        }
+    }
+
     fn tick_duration(&self) -> f32 {
         match self {
             Engine::Mod(e) => e.tick_duration(),
         }
     }
+
+    fn speed(&self) -> u8 {
+        match self {
+            Engine::Mod(e) => e.speed(),
+        }
+    }
+
+    fn tick(&self) -> u8 {
+        match self {
+            Engine::Mod(e) => e.tick(),
+        }
+    }
+
+    fn song(&self) -> &Song {
+        match self {
+            Engine::Mod(e) => e.song(),
+        }
+    }
 }
 
 impl Engine {
-    pub fn new(song: Song) -> Engine {
+    /// Creates the engine for `song` with the default mixer. It renders in stereo at
+    /// 44100 Hz until it's told otherwise
+    ///
+    /// # Errors
+    /// When there's no engine for the song's tracker yet
+    pub fn new(song: Song) -> Result<Engine, SongError> {
+        Engine::with_mixer(song, Mixer::default())
+    }
+
+    /// Creates the engine for `song`, mixing with `mixer`, in stereo at 44100 Hz
+    ///
+    /// # Errors
+    /// When there's no engine for the song's tracker yet
+    pub fn with_mixer(song: Song, mixer: Mixer) -> Result<Engine, SongError> {
         match song.metadata.tracker {
-            Tracker::ProTracker | Tracker::NoiseTracker => Engine::Mod(ModEngine::new(song)),
+            Tracker::ProTracker | Tracker::NoiseTracker => {
+                Ok(Engine::Mod(ModEngine::new(song, mixer)))
+            }
 
-            _ => todo!(),
+            tracker => Err(SongError::UnsupportedTracker(tracker)),
         }
     }
 }
+
+#[test]
+fn renders_without_being_set_up() {
+    use crate::song::{square_song, Note};
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    let song = square_song(4)
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .build()
+        .unwrap();
+
+    let mut engine = Engine::new(song).unwrap();
+    assert_eq!(engine.channel_count(), 2);
+    assert_eq!(engine.sample_rate(), DEFAULT_SAMPLE_RATE);
+
+    let mut buffer = vec![0.0; 2048];
+    assert_eq!(engine.render(&mut buffer), 1024);
+    assert!(buffer.iter().any(|&sample| sample != 0.0));
+}
@@ -1,7 +1,10 @@
-use std::fmt::Display;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use super::TrackerEngine;
-use crate::tracker;
+use super::oversample::Decimator;
+use super::{ChannelSnapshot, LoopRegion, Mixer, PanLaw, ScopeBuffer, TrackerEngine};
+use crate::tracker::fingerprint::{self, Quirks};
+use crate::tracker::{self, Clock};
 use crate::{song, Song};
 
 macro_rules! define_getter_setter {
@@ -16,10 +19,20 @@ macro_rules! define_getter_setter {
     };
 }
 
+// Full scale of a fixed-point mixed channel: 8-bit sample * 6-bit volume * 8-bit panning
+const FIXED_FULL_SCALE: f32 = (128 * 64 * 255) as f32;
+
 pub struct ModEngine {
     pub song: Song,
+    // How the tracker the song was made with plays it
+    quirks: Quirks,
     pub current_row: usize,
     pub current_pattern: usize,
+    // Position in the pattern table
+    pub current_order: usize,
+
+    // Set once the last row of the last order has been played
+    pub finished: bool,
 
     // Current tick
     pub tick: u8,
@@ -28,15 +41,74 @@ pub struct ModEngine {
     // BPM (determines how long a tick lasts)
     pub tempo: u16,
 
+    // Position to continue at after the current row, set by Bxx/Dxx
+    pub pending_jump: Option<(usize, usize)>,
+
+    // Section to repeat, its end jumps back to its start
+    pub loop_region: Option<LoopRegion>,
+
+    // How many times the song is played before ending (0 = forever), and how often it has been
+    pub loop_count: u32,
+    pub times_played: u32,
+    // Where the song continued the last time it played through, the start of its loop
+    pub loop_point: (usize, usize),
+
+    // Length of the fade out after the last loop in seconds, and its progress in frames
+    pub fade_seconds: f32,
+    pub fade: Option<(usize, usize)>,
+    // Set when the song reached its end, the fade starts with the next tick at the loop point
+    pub fade_pending: bool,
+
+    // Seconds of audio played so far, and the limit after which the song ends or fades out
+    pub time_played: f64,
+    pub max_time: Option<f64>,
+
+    // Seconds of silent output after which the song ends, and the silent frames so far
+    pub silence_limit: Option<f32>,
+    pub silent_frames: usize,
+
     pub channels: Vec<ChannelState>,
+    // Muted channels still advance, but aren't heard
+    pub muted: Vec<bool>,
+    // Panning that replaces the song's for a channel, whatever its effects do
+    pub panning: Vec<Option<u8>>,
+    pub pan_law: PanLaw,
+    // Left and right trade places in the output
+    pub swap_stereo: bool,
 
     // Used by the main thread to advance, only if no audio output is used
     pub tick_duration: f32,
 
+    // When each row starts during a single playthrough, and how long that takes in seconds.
+    // Both are measured at a speed factor of 1
+    pub row_times: HashMap<(usize, usize), f64>,
+    pub duration: f64,
+
+    // Scale the tick rate and the pitch of every note, 1.0 plays the song as written
+    pub speed_factor: f32,
+    pub pitch_factor: f32,
+    // What periods count in, PAL unless set otherwise
+    pub clock: Clock,
+
     // Audio output device
     pub sample_rate: u32,
     pub channel_count: u16,
 
+    // Print rows and pattern changes to stdout as they are played
+    pub print_rows: bool,
+    // Print every effect to stderr on every tick it's processed
+    pub trace_effects: bool,
+
+    // Which mixing path is used, chosen at construction
+    pub mixer: Mixer,
+
+    // Channels are mixed at `sample_rate * oversampling` and decimated back down
+    pub oversampling: usize,
+    pub decimator: Option<Decimator>,
+
+    // Receives the output of each channel when attached, with the frames not yet handed to it
+    pub scope: Option<(Arc<ScopeBuffer>, Vec<Vec<f32>>)>,
+
     // Used by the audio thread to advance
     pub samples_since_tick: usize,
     pub samples_per_tick: usize,
@@ -56,12 +128,20 @@ pub struct ChannelState {
     pub position_in_sample: f32, // For mixing audio
     pub sample_step: f32,        // Based on period + sample_rate
 
+    // Same as above, for the fixed-point mixer. Both have 16 fractional bits
+    pub position_fixed: u64,
+    pub sample_step_fixed: u32,
+
+    // Last mixed value before panning, only used for scopes
+    pub output: f32,
+
     pub base_period: u16,
 
-    pub repeat_offset: u16,
-    pub repeat_length: u16,
+    pub repeat_offset: u32,
+    pub repeat_length: u32,
 
-    pub arp_counter: u8,
+    // Finetune of the sample, in eighths of a semitone
+    pub finetune: i8,
 }
 
 impl Default for ChannelState {
@@ -80,16 +160,19 @@ impl Default for ChannelState {
             position_in_sample: 0.0,
             sample_step: 0.0,
 
+            position_fixed: 0,
+            sample_step_fixed: 0,
+
+            output: 0.0,
+
             base_period: 0,
-            arp_counter: 0,
+            finetune: 0,
         }
     }
 }
 
-fn split_nibbles(x: u8) -> (u8, u8) {
-    let x = (x & 0xF0) >> 4;
-    let y = x & 0x0F;
-    (x, y)
+fn split_nibbles(byte: u8) -> (u8, u8) {
+    (byte >> 4, byte & 0x0F)
 }
 
 // use the type system to your advantage:
@@ -97,7 +180,7 @@ fn split_nibbles(x: u8) -> (u8, u8) {
 // have to leave notes about what certain magic values
 // mean all over the place
 // also guarantees safety, so that by the point
-// you decode what the effect is, you know its 
+// you decode what the effect is, you know its
 // correct and you're ready to apply it confidently
 // and safely
 enum SubEffect {
@@ -106,6 +189,7 @@ enum SubEffect {
     RetriggerNote(u8),
 }
 
+#[allow(clippy::enum_variant_names)]
 enum Effect {
     Arpeggio { x: u8, y: u8 },
     PortamentoUp(u16),
@@ -113,9 +197,9 @@ enum Effect {
     TonePortamento(u16),
     Vibrato { speed: u8, depth: u8 },
     VolumeSlide { slide_up: u8, slide_down: u8 },
-    PositionJump,
+    PositionJump(u8),
     SetVolume,
-    PatternBreak,
+    PatternBreak(u8),
     ExtendedEffect(SubEffect),
     SetSpeed(u8),
     SetTempo(u8),
@@ -143,9 +227,13 @@ impl Effect {
                     slide_down,
                 }
             }
-            0xB => PositionJump,
+            0xB => PositionJump(arg),
             0xC => SetVolume,
-            0xD => PatternBreak,
+            0xD => {
+                // The row is stored as decimal digits
+                let (tens, ones) = split_nibbles(arg);
+                PatternBreak(tens * 10 + ones)
+            }
             0xE => {
                 use SubEffect::*;
                 let (sub, sub_arg) = split_nibbles(arg);
@@ -166,41 +254,167 @@ impl Effect {
             }
             _ => return None,
         };
-        return Some(effect);
+        Some(effect)
+    }
+}
+
+/// Whether the engine knows how to play `effect` with `argument`
+pub fn supports_effect(effect: u8, argument: u8) -> bool {
+    Effect::from_effect_and_arg_bytes(effect, argument).is_some()
+}
+
+// What an effect does, as `--trace-effects` prints it. Effects that are decoded but not
+// played (yet) say so
+fn describe_effect(effect: u8, argument: u8) -> String {
+    use Effect::*;
+    use SubEffect::*;
+    let Some(decoded) = Effect::from_effect_and_arg_bytes(effect, argument) else {
+        return String::from("unsupported, ignored");
+    };
+
+    match decoded {
+        Arpeggio { x, y } => format!("arpeggio +{x} +{y} semitones"),
+        PortamentoUp(step) => format!("portamento up {step}"),
+        PortamentoDown(step) => format!("portamento down {step}"),
+        TonePortamento(step) => format!("tone portamento {step}"),
+        Vibrato { speed, depth } => {
+            format!("vibrato speed {speed} depth {depth}, not implemented")
+        }
+        VolumeSlide {
+            slide_up,
+            slide_down,
+        } => {
+            if slide_up > 0 {
+                format!("volume slide up {slide_up}")
+            } else {
+                format!("volume slide down {slide_down}")
+            }
+        }
+        PositionJump(order) => format!("position jump to order {order}"),
+        SetVolume => format!("set volume {}", argument.min(64)),
+        PatternBreak(row) => format!("pattern break to row {row}"),
+        ExtendedEffect(FinePortmamentoUp(step)) => format!("fine portamento up {step}"),
+        ExtendedEffect(FinePortamentoDown(step)) => format!("fine portamento down {step}"),
+        ExtendedEffect(RetriggerNote(ticks)) => {
+            format!("retrigger every {ticks} ticks, not implemented")
+        }
+        SetSpeed(0) => String::from("set speed 0, ignored"),
+        SetSpeed(speed) => format!("set speed {speed}"),
+        SetTempo(tempo) => format!("set tempo {tempo}"),
     }
 }
 
+/// Effects that affect the whole engine rather than a single channel
+enum GlobalEffect {
+    PositionJump(u8),
+    PatternBreak(u8),
+    SetSpeed(u8),
+    SetTempo(u8),
+}
+
 impl ChannelState {
-    fn process_effects(&mut self, tick: u8) {
-        use Effect::*;
-        let Some(effect) = Effect::from_effect_and_arg_bytes(self.effect, self.effect_arg) else {
-            panic!("Unknown effect: {} {}", self.effect, self.effect_arg)
+    // Starts the note of a pattern cell, at the first tick of its row
+    fn trigger(&mut self, note: &song::Note, samples: &[song::Sample]) {
+        let new_period = note.period;
+        // Numbers of samples the song doesn't have are treated as no sample at all
+        let sample_number = match note.sample as usize {
+            number if number > samples.len() => 0,
+            number => number,
         };
+        let mut new_sample_index = sample_number;
+
+        if new_period != 0 {
+            if new_sample_index == 0 {
+                new_sample_index = self.sample_index;
+            }
+
+            self.position_in_sample = 0.0;
+            self.position_fixed = 0;
+            self.base_period = new_period;
+
+            // Set repeat info from sample metadata
+            if new_sample_index > 0 {
+                let sample_meta = &samples[new_sample_index - 1];
+                self.repeat_offset = sample_meta.repeat_offset;
+                self.repeat_length = sample_meta.repeat_length;
+                self.volume = sample_meta.volume.min(64);
+                self.finetune = sample_meta.finetune;
+                self.sample_index = new_sample_index - 1;
+            }
+
+            // Patterns hold the periods of notes without finetune, the sample's finetune shifts them
+            if self.finetune != 0 {
+                self.base_period = tracker::protracker_period_to_semitone(new_period, 0)
+                    .and_then(|note| tracker::protracker_note_to_period(note, self.finetune))
+                    .unwrap_or(new_period);
+            }
+
+            self.effect = note.effect;
+            self.effect_arg = note.argument;
+            self.period = self.base_period;
+            self.sample_step = 0.0;
+            self.sample_step_fixed = 0;
+        } else if sample_number != 0 {
+            // Instrument only: update instrument, but do NOT reset position or period
+            let sample_meta = &samples[new_sample_index - 1];
+            self.repeat_offset = sample_meta.repeat_offset;
+            self.repeat_length = sample_meta.repeat_length;
+            self.volume = sample_meta.volume.min(64);
+            self.finetune = sample_meta.finetune;
+
+            self.sample_index = new_sample_index - 1;
+            self.effect = note.effect;
+            self.effect_arg = note.argument;
+        } else {
+            // Effect only: just update effect/argument
+            self.effect = note.effect;
+            self.effect_arg = note.argument;
+        }
+    }
+
+    // How far the sample moves per frame at the channel's period
+    fn update_sample_step(
+        &mut self,
+        clock: Clock,
+        mixing_rate: f32,
+        pitch_factor: f64,
+        clock_fixed: u64,
+    ) {
+        if self.period != 0 && mixing_rate > 0.0 {
+            let freq = (tracker::period_to_frequency(self.period, clock) * pitch_factor) as f32;
+
+            self.sample_step = freq / mixing_rate;
+            self.sample_step_fixed =
+                (clock_fixed / (self.period as u64 * 2 * mixing_rate as u64)) as u32;
+        }
+    }
+
+    // Period of the note `semitones` above the one the channel plays, with its finetune. Past
+    // the highest note the period stays as it is
+    fn note_period(&self, semitones: u8) -> u16 {
+        tracker::protracker_period_to_semitone(self.base_period, self.finetune)
+            .and_then(|note| {
+                tracker::protracker_note_to_period(note + semitones as usize, self.finetune)
+            })
+            .unwrap_or(self.base_period)
+    }
+
+    fn process_effects(&mut self, tick: u8) -> Option<GlobalEffect> {
+        use Effect::*;
+        // Effects the engine doesn't know are skipped, the validator reports them
+        let effect = Effect::from_effect_and_arg_bytes(self.effect, self.effect_arg)?;
         match effect {
             Arpeggio { x, y } => {
-                if tick > 0 {
-                    if x == 0 && y == 0 {
-                        return;
-                    }
-
-                    if tick == 0 || tick == 1 {
-                        // On tick 0 and 1, play the actual note (no change)
-                        if tick == 1 {
-                            self.arp_counter = 1;
-                        } else {
-                            self.arp_counter = 0;
-                        }
-                        self.period = self.base_period;
-                    } else {
-                        let offset = match self.arp_counter % 3 {
-                            1 => x,
-                            2 => y,
-                            _ => 0,
-                        };
-                        self.period = self.base_period.saturating_add(offset as u16);
-                        self.arp_counter = self.arp_counter.wrapping_add(1);
-                    }
+                if tick == 0 || (x == 0 && y == 0) {
+                    return None;
                 }
+
+                // Cycles through the note, x and y semitones above it
+                self.period = match tick % 3 {
+                    1 => self.note_period(x),
+                    2 => self.note_period(y),
+                    _ => self.base_period,
+                };
             }
 
             PortamentoUp(step) => {
@@ -228,7 +442,10 @@ impl ChannelState {
                 }
             }
 
-            Vibrato { speed, depth } => {
+            Vibrato {
+                speed: _speed,
+                depth: _depth,
+            } => {
                 if tick > 0 {
                     // TODO: Implement vibrato logic using a sine wave table
                 }
@@ -247,10 +464,10 @@ impl ChannelState {
                 }
             }
 
-            PositionJump => {
+            PositionJump(order) => {
                 if tick == 0 {
                     // Position Jump (Bxx): Jumps to a specific pattern
-                    // TODO: Implement position jump logic in the engine
+                    return Some(GlobalEffect::PositionJump(order));
                 }
             }
 
@@ -261,30 +478,24 @@ impl ChannelState {
                 }
             }
 
-            PatternBreak => {
+            PatternBreak(row) => {
                 if tick == 0 {
                     // Pattern Break (Dxx): Jumps to a specific row in the next pattern
-                    // TODO: Implement pattern break logic in the engine
+                    return Some(GlobalEffect::PatternBreak(row));
                 }
             }
 
             ExtendedEffect(sub_effect) => {
                 use SubEffect::*;
                 match sub_effect {
-                    FinePortmamentoUp(step) => {
-                        if tick == 0 {
-                            self.period = self.base_period.saturating_sub(step);
-                        }
+                    FinePortmamentoUp(step) if tick == 0 => {
+                        self.period = self.base_period.saturating_sub(step);
                     }
-                    FinePortamentoDown(step) => {
-                        if tick == 0 {
-                            self.period = self.base_period.saturating_add(step);
-                        }
+                    FinePortamentoDown(step) if tick == 0 => {
+                        self.period = self.base_period.saturating_add(step);
                     }
-                    RetriggerNote(note_tick) => {
-                        if tick % note_tick == 0 {
-                            // TODO: Retrigger the note
-                        }
+                    RetriggerNote(note_tick) if tick.is_multiple_of(note_tick) => {
+                        // TODO: Retrigger the note
                     }
                     _ => {}
                 }
@@ -293,16 +504,18 @@ impl ChannelState {
             // 0xF: Set Speed/Tempo
             SetSpeed(speed) => {
                 if tick == 0 {
-                    // TODO: Update the engine's speed
+                    return Some(GlobalEffect::SetSpeed(speed));
                 }
             }
 
             SetTempo(tempo) => {
                 if tick == 0 {
-                    // TODO: Update the engine's tempo
+                    return Some(GlobalEffect::SetTempo(tempo));
                 }
             }
         }
+
+        None
     }
 }
 
@@ -318,6 +531,18 @@ impl TrackerEngine for ModEngine {
         self.tick_duration
     }
 
+    fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    fn tick(&self) -> u8 {
+        self.tick
+    }
+
+    fn song(&self) -> &Song {
+        &self.song
+    }
+
     fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
@@ -327,214 +552,764 @@ impl TrackerEngine for ModEngine {
         self.update_samples_per_tick();
     }
 
+    fn set_print_rows(&mut self, enabled: bool) {
+        self.print_rows = enabled;
+    }
+
+    fn set_trace_effects(&mut self, enabled: bool) {
+        self.trace_effects = enabled;
+    }
+
+    fn oversampling(&self) -> usize {
+        self.oversampling
+    }
+
+    fn set_oversampling(&mut self, factor: usize) {
+        self.oversampling = factor.max(1);
+        self.decimator = if self.oversampling > 1 {
+            Some(Decimator::new(self.oversampling))
+        } else {
+            None
+        };
+    }
+
+    fn set_speed_factor(&mut self, factor: f32) {
+        self.speed_factor = factor;
+        self.set_tempo(self.tempo);
+    }
+
+    fn set_pitch_factor(&mut self, factor: f32) {
+        self.pitch_factor = factor;
+    }
+
+    fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(state) = self.muted.get_mut(channel) {
+            *state = muted;
+        }
+    }
+
+    fn is_channel_muted(&self, channel: usize) -> bool {
+        self.muted.get(channel).copied().unwrap_or(false)
+    }
+
+    fn set_channel_panning(&mut self, channel: usize, panning: Option<u8>) {
+        if let Some(state) = self.panning.get_mut(channel) {
+            *state = panning;
+        }
+    }
+
+    fn set_swap_stereo(&mut self, swapped: bool) {
+        self.swap_stereo = swapped;
+    }
+
+    fn set_pan_law(&mut self, law: PanLaw) {
+        self.pan_law = law;
+    }
+
+    fn channel_volume(&self, channel: usize) -> u8 {
+        self.channels
+            .get(channel)
+            .map_or(0, |channel| channel.volume)
+    }
+
+    fn channel_snapshot(&self, channel: usize) -> ChannelSnapshot {
+        let Some(state) = self.channels.get(channel) else {
+            return ChannelSnapshot::default();
+        };
+
+        let position = match self.mixer {
+            Mixer::Float => state.position_in_sample as usize,
+            Mixer::FixedPoint => (state.position_fixed >> 16) as usize,
+        };
+        let length = match self.song.samples.get(state.sample_index).map(|pcm| &**pcm) {
+            Some(song::PCMData::I8(data)) => data.len(),
+            _ => 0,
+        };
+        // Notes that played past the end of their sample are silent until the next one
+        let sounding = state.period != 0 && position < length;
+
+        ChannelSnapshot {
+            period: if sounding { state.period } else { 0 },
+            volume: state.volume,
+            sample: if state.period != 0 {
+                state.sample_index as u8 + 1
+            } else {
+                0
+            },
+            position: position as u32,
+        }
+    }
+
+    fn play_note(&mut self, channel: usize, note: song::Note) {
+        let mixing_rate = self.mixing_rate();
+        let pitch_factor = self.pitch_factor as f64;
+        let clock_fixed = (self.clock.hz() * 65536.0 * pitch_factor) as u64;
+        let Some(state) = self.channels.get_mut(channel) else {
+            return;
+        };
+
+        state.trigger(&note, &self.song.metadata.samples);
+        // Jumps and speed changes are left to the patterns, only the note's own effect applies
+        let _ = state.process_effects(0);
+        state.update_sample_step(self.clock, mixing_rate, pitch_factor, clock_fixed);
+    }
+
+    fn set_scope(&mut self, scope: Option<Arc<ScopeBuffer>>) {
+        self.scope = scope.map(|scope| (scope, vec![Vec::new(); self.channels.len()]));
+    }
+
     fn get_audio_buffer(&mut self, buffer: &mut [f32]) {
         let num_channels = self.channel_count as usize;
-        let samples_per_buffer = buffer.len() / num_channels as usize;
+        let samples_per_buffer = buffer.len() / num_channels;
 
         // For each output sample (frame)
         for i in 0..samples_per_buffer {
-            let mut left = 0.0f32;
-            let mut right = 0.0f32;
-
-            // Mix all tracker channels
-            for (_, channel) in self.channels.iter_mut().enumerate() {
-                // Get sample data for this channel
-                let sample = match &self.song.samples[channel.sample_index] {
-                    song::PCMData::I8(data) => data,
-                    _ => continue,
-                };
-
-                // Fetch sample value (simple nearest-neighbor, can use interpolation for quality)
-                let pos = channel.position_in_sample as usize;
-                let sample_val = if pos < sample.len() {
-                    // Convert 8-bit sample [-128,127] to [-1.0,1.0]
-                    sample[pos] as f32 / 128.0
-                } else {
-                    0.0
-                };
-
-                // Apply volume (0..64)
-                let vol = channel.volume.min(64) as f32 / 64.0;
-                let out_val = sample_val * vol;
+            let (left, right) = match self.decimator.take() {
+                Some(mut decimator) => {
+                    for _ in 0..decimator.factor() {
+                        let frame = self.mix_frame();
+                        decimator.push(frame);
+                    }
 
-                let pan = channel.panning as f32 / 255.0;
-                left += out_val * (1.0 - pan);
-                right += out_val * pan;
+                    let frame = decimator.output();
+                    self.decimator = Some(decimator);
+                    frame
+                }
+                None => self.mix_frame(),
+            };
 
-                channel.position_in_sample += channel.sample_step;
+            if let Some(limit) = self.silence_limit {
+                self.count_silence(left == 0.0 && right == 0.0, limit);
             }
 
+            let gain = self.next_fade_gain();
+            let (left, right) = if self.swap_stereo {
+                (right * gain, left * gain)
+            } else {
+                (left * gain, right * gain)
+            };
+
             for ch in 0..num_channels {
                 buffer[i * num_channels + ch] = match ch {
-                    0 => left.clamp(-1.0, 1.0),  // Left
-                    1 => right.clamp(-1.0, 1.0), // Right
-                    _ => 0.0,                    // Silence for other channels
+                    0 if num_channels == 1 => ((left + right) * 0.5).clamp(-1.0, 1.0), // Mono
+                    0 => left.clamp(-1.0, 1.0),                                        // Left
+                    1 => right.clamp(-1.0, 1.0),                                       // Right
+                    _ => 0.0, // Silence for other channels
                 };
             }
         }
+
+        if let Some((scope, frames)) = &mut self.scope {
+            scope.push(frames);
+            frames.iter_mut().for_each(Vec::clear);
+        }
     }
 
     fn is_finished(&self) -> bool {
-        return self.current_row == 64;
+        self.finished
+    }
+
+    fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    fn position(&self) -> (usize, usize) {
+        (self.current_order, self.current_row)
+    }
+
+    fn song_length(&self) -> usize {
+        self.song.metadata.song_length as usize
+    }
+
+    fn seek(&mut self, order: usize, row: usize) -> bool {
+        if order >= self.song.metadata.song_length as usize || row >= 64 {
+            return false;
+        }
+
+        self.reset();
+
+        // Play through the song without mixing, so speed, tempo and notes
+        // are the same as they would've been when reaching the position normally
+        let print_rows = self.print_rows;
+        let trace_effects = self.trace_effects;
+        let loop_count = self.loop_count;
+        let max_time = self.max_time.take();
+        let loop_region = self.loop_region.take();
+        self.print_rows = false;
+        self.trace_effects = false;
+        self.loop_count = 0;
+
+        let max_rows = self.song.metadata.song_length as usize * 64;
+        let mut rows = 0;
+        while self.position() != (order, row) && rows <= max_rows && !self.finished {
+            self.next_tick();
+
+            if self.tick == 0 {
+                rows += 1;
+            }
+        }
+
+        self.print_rows = print_rows;
+        self.trace_effects = trace_effects;
+        self.loop_count = loop_count;
+        self.max_time = max_time;
+        self.loop_region = loop_region;
+        self.finished = false;
+        self.times_played = 0;
+        self.time_played = 0.0;
+
+        // The position is skipped over by jumps, go there directly
+        if self.position() != (order, row) {
+            self.current_order = order;
+            self.current_row = row;
+            self.current_pattern = self.song.metadata.pattern_table[order] as usize;
+            self.tick = 0;
+            self.pending_jump = None;
+        }
+
+        true
+    }
+
+    fn set_loop_region(&mut self, region: Option<LoopRegion>) -> bool {
+        let song_length = self.song.metadata.song_length as usize;
+        let valid = |(order, row): (usize, usize)| order < song_length && row < 64;
+
+        if region.is_some_and(|region| !valid(region.start) || !valid(region.end)) {
+            return false;
+        }
+
+        self.loop_region = region;
+        true
+    }
+
+    fn loop_region(&self) -> Option<LoopRegion> {
+        self.loop_region
+    }
+
+    fn duration(&self) -> f64 {
+        self.duration / self.speed_factor as f64
+    }
+
+    fn elapsed(&self) -> Option<f64> {
+        let time = self.row_times.get(&self.position())?;
+        Some(time / self.speed_factor as f64)
+    }
+
+    fn set_loop_count(&mut self, count: u32) {
+        self.loop_count = count;
+    }
+
+    fn times_played(&self) -> u32 {
+        self.times_played
+    }
+
+    fn loop_point(&self) -> (usize, usize) {
+        self.loop_point
+    }
+
+    fn set_fade_out(&mut self, seconds: f32) {
+        self.fade_seconds = seconds.max(0.0);
+    }
+
+    fn set_max_time(&mut self, seconds: Option<f64>) {
+        self.max_time = seconds;
+    }
+
+    fn set_silence_limit(&mut self, seconds: Option<f32>) {
+        self.silence_limit = seconds;
     }
 
     fn next_tick(&mut self) {
+        // The previous tick moved past the last order
+        if self.current_order >= self.song.metadata.song_length as usize {
+            self.finished = true;
+        }
+
+        if self.finished {
+            return;
+        }
+
+        if std::mem::take(&mut self.fade_pending) {
+            self.start_fade();
+        }
+
+        if self.max_time.is_some_and(|max| self.time_played >= max) && self.fade.is_none() {
+            self.max_time = None;
+
+            if !self.start_fade() {
+                self.finished = true;
+                return;
+            }
+        }
+
+        self.time_played += self.tick_duration as f64;
+
         let pattern = &self.song.patterns[self.current_pattern];
         let line = &pattern[self.current_row];
 
-        if self.tick == 0 {
+        if self.tick == 0 && self.print_rows {
             print_line(pattern, &self.song.metadata.samples, self.current_row);
         }
 
+        let mixing_rate = self.mixing_rate();
+        let pitch_factor = self.pitch_factor as f64;
+        // The clock with 16 fractional bits
+        let clock_fixed = (self.clock.hz() * 65536.0 * pitch_factor) as u64;
+        let trace_position =
+            (self.trace_effects).then_some((self.current_order, self.current_row, self.tick));
+        let mut global_effects = Vec::new();
         for (index, channel) in self.channels.iter_mut().enumerate() {
             if self.tick == 0 {
-                let note = line.get(index).unwrap();
-                let new_period = note.period;
-                let mut new_sample_index = note.sample as usize;
-
-                if new_period != 0 {
-                    if new_sample_index == 0 {
-                        new_sample_index = channel.sample_index;
-                    }
-
-                    channel.position_in_sample = 0.0;
-                    channel.base_period = new_period;
-                    channel.arp_counter = 0;
-
-                    // Set repeat info from sample metadata
-                    if new_sample_index > 0 {
-                        let sample_meta = &self.song.metadata.samples[new_sample_index - 1];
-                        channel.repeat_offset = sample_meta.repeat_offset;
-                        channel.repeat_length = sample_meta.repeat_length;
-                        channel.volume = sample_meta.volume.min(64);
-                        channel.sample_index = new_sample_index - 1;
-                    }
+                channel.trigger(line.get(index).unwrap(), &self.song.metadata.samples);
+            }
 
-                    channel.effect = note.effect;
-                    channel.effect_arg = note.argument;
-                    channel.period = channel.base_period;
-                    channel.sample_step = 0.0;
-                } else if note.sample != 0 {
-                    // Instrument only: update instrument, but do NOT reset position or period
-                    let sample_meta = &self.song.metadata.samples[new_sample_index - 1];
-                    channel.repeat_offset = sample_meta.repeat_offset;
-                    channel.repeat_length = sample_meta.repeat_length;
-                    channel.volume = sample_meta.volume.min(64);
-
-                    channel.sample_index = new_sample_index - 1;
-                    channel.effect = note.effect;
-                    channel.effect_arg = note.argument;
-                } else {
-                    // Effect only: just update effect/argument
-                    channel.effect = note.effect;
-                    channel.effect_arg = note.argument;
+            if let Some((order, row, tick)) = trace_position {
+                let (effect, argument) = (channel.effect, channel.effect_arg);
+                if effect != 0 || argument != 0 {
+                    log::trace!(
+                        target: super::TRACE_EFFECTS_TARGET,
+                        "order {order:>3} row {row:>2} tick {tick:>2} channel {:>2}: \
+                         {effect:X}{argument:02X} {}",
+                        index + 1,
+                        describe_effect(effect, argument)
+                    );
                 }
             }
 
-            channel.process_effects(self.tick);
+            if let Some(effect) = channel.process_effects(self.tick) {
+                global_effects.push(effect);
+            }
 
-            // Amiga PAL clock for MOD: 7093789.2 Hz
-            if channel.period != 0 {
-                let freq = 7093789.2 / (channel.period as f32 * 2.0);
+            channel.update_sample_step(self.clock, mixing_rate, pitch_factor, clock_fixed);
+        }
 
-                channel.sample_step = freq / self.sample_rate as f32;
-            }
+        for effect in global_effects {
+            self.apply_global_effect(effect);
         }
 
         self.tick += 1;
         if self.tick >= self.speed {
             self.tick = 0;
-            self.current_row += 1;
-
-            if self.current_row == 64 {
-                self.current_row = 0;
-                self.current_pattern += 1;
-
-                // Optionally, loop back to the first pattern if at the end of the song
-                if self.current_pattern >= self.song.patterns.len() {
-                    self.current_pattern = 0;
-                }
-
-                println!("Playing pattern: {}", self.current_pattern);
-            }
+            self.advance_row();
         }
     }
 }
 
 impl ModEngine {
-    pub fn new(song: Song) -> Self {
+    pub fn new(song: Song, mixer: Mixer) -> Self {
         let mut channels = Vec::with_capacity(song.metadata.channel_count as usize);
         for _ in 0..song.metadata.channel_count {
             channels.push(ChannelState::default());
         }
 
-        ModEngine {
+        let current_pattern = song.metadata.pattern_table[0] as usize;
+        let muted = vec![false; channels.len()];
+        let panning = vec![None; channels.len()];
+        let (speed, tempo) = (song.metadata.initial_speed, song.metadata.initial_tempo);
+        let quirks = fingerprint::fingerprint(&song, None).quirks();
+
+        let mut engine = ModEngine {
             song,
+            quirks,
             current_row: 0,
-            current_pattern: 0,
+            current_pattern,
+            current_order: 0,
+            finished: false,
 
             tick: 0,
-            speed: 6,
-            tempo: 125,
-            tick_duration: 2.5 / 125.0,
+            speed,
+            tempo,
+            tick_duration: 2.5 / tempo as f32,
+            row_times: HashMap::new(),
+            duration: 0.0,
+            speed_factor: 1.0,
+            pitch_factor: 1.0,
+            clock: Clock::Pal,
+
+            pending_jump: None,
+
+            loop_region: None,
+
+            loop_count: 1,
+            times_played: 0,
+            loop_point: (0, 0),
+
+            fade_seconds: 0.0,
+            fade: None,
+            fade_pending: false,
+
+            time_played: 0.0,
+            max_time: None,
 
-            channel_count: 0,
+            silence_limit: None,
+            silent_frames: 0,
+
+            channel_count: 2,
+
+            print_rows: false,
+            trace_effects: false,
+
+            mixer,
+            oversampling: 1,
+            decimator: None,
+
+            scope: None,
 
             samples_per_tick: 0,
             samples_since_tick: 0,
 
             channels,
+            muted,
+            panning,
+            pan_law: PanLaw::default(),
+            swap_stereo: false,
             sample_rate: 0,
+        };
+
+        engine.measure();
+        engine.set_sample_rate(super::DEFAULT_SAMPLE_RATE);
+        engine
+    }
+
+    // Plays through the song once without mixing, noting when every row starts
+    fn measure(&mut self) {
+        let print_rows = self.print_rows;
+        self.print_rows = false;
+
+        while !self.finished {
+            if self.tick == 0 {
+                let time = self.time_played;
+                self.row_times.entry(self.position()).or_insert(time);
+            }
+
+            self.next_tick();
         }
+
+        self.duration = self.time_played;
+        self.print_rows = print_rows;
+        self.reset();
+    }
+
+    // Puts the playback state back to the start of the song, keeping all settings
+    fn reset(&mut self) {
+        self.current_order = 0;
+        self.current_row = 0;
+        self.current_pattern = self.song.metadata.pattern_table[0] as usize;
+        self.finished = false;
+
+        self.tick = 0;
+        self.speed = self.song.metadata.initial_speed;
+        self.set_tempo(self.song.metadata.initial_tempo);
+
+        self.pending_jump = None;
+        self.times_played = 0;
+        self.fade = None;
+        self.fade_pending = false;
+        self.time_played = 0.0;
+        self.silent_frames = 0;
+
+        self.samples_since_tick = 0;
+        self.channels
+            .iter_mut()
+            .for_each(|c| *c = ChannelState::default());
+    }
+
+    // The rate channels are mixed at before decimation
+    fn mixing_rate(&self) -> f32 {
+        self.sample_rate as f32 * self.oversampling as f32
+    }
+
+    // Mixes all tracker channels into a single stereo frame at the mixing rate
+    fn mix_frame(&mut self) -> (f32, f32) {
+        let frame = match self.mixer {
+            Mixer::Float => self.mix_frame_float(),
+            Mixer::FixedPoint => self.mix_frame_fixed(),
+        };
+
+        if let Some((_, frames)) = &mut self.scope {
+            for (history, channel) in frames.iter_mut().zip(&self.channels) {
+                history.push(channel.output);
+            }
+        }
+
+        frame
+    }
+
+    fn mix_frame_float(&mut self) -> (f32, f32) {
+        let mut left = 0.0f32;
+        let mut right = 0.0f32;
+
+        let channels = (self.channels.iter_mut()).zip(self.muted.iter().zip(&self.panning));
+        for (channel, (&muted, &panning)) in channels {
+            channel.output = 0.0;
+
+            // Channels that haven't played a note yet are silent
+            if channel.period == 0 {
+                continue;
+            }
+
+            // Get sample data for this channel, songs built in code may have none
+            let sample = match self
+                .song
+                .samples
+                .get(channel.sample_index)
+                .map(|pcm| &**pcm)
+            {
+                Some(song::PCMData::I8(data)) => data,
+                _ => continue,
+            };
+
+            // Fetch sample value (simple nearest-neighbor, can use interpolation for quality)
+            let pos = channel.position_in_sample as usize;
+            let sample_val = if pos < sample.len() {
+                // Convert 8-bit sample [-128,127] to [-1.0,1.0]
+                sample[pos] as f32 / 128.0
+            } else {
+                0.0
+            };
+
+            // Apply volume (0..64)
+            let vol = channel.volume.min(64) as f32 / 64.0;
+            let out_val = if muted { 0.0 } else { sample_val * vol };
+            channel.output = out_val;
+
+            let (left_gain, right_gain) =
+                pan_gains(self.pan_law, panning.unwrap_or(channel.panning));
+            left += out_val * left_gain;
+            right += out_val * right_gain;
+
+            channel.position_in_sample += channel.sample_step;
+        }
+
+        (left, right)
+    }
+
+    // Integer only version of `mix_frame_float`, so every platform produces the exact same output
+    fn mix_frame_fixed(&mut self) -> (f32, f32) {
+        let mut left = 0i32;
+        let mut right = 0i32;
+
+        let channels = (self.channels.iter_mut()).zip(self.muted.iter().zip(&self.panning));
+        for (channel, (&muted, &panning)) in channels {
+            channel.output = 0.0;
+
+            if channel.period == 0 {
+                continue;
+            }
+
+            let sample = match self
+                .song
+                .samples
+                .get(channel.sample_index)
+                .map(|pcm| &**pcm)
+            {
+                Some(song::PCMData::I8(data)) => data,
+                _ => continue,
+            };
+
+            let pos = (channel.position_fixed >> 16) as usize;
+            let sample_val = sample.get(pos).map_or(0, |&s| s as i32);
+
+            let out_val = if muted {
+                0
+            } else {
+                sample_val * channel.volume.min(64) as i32
+            };
+            channel.output = out_val as f32 / (128 * 64) as f32;
+
+            let (left_gain, right_gain) =
+                pan_gains_fixed(self.pan_law, panning.unwrap_or(channel.panning));
+            left += out_val * left_gain;
+            right += out_val * right_gain;
+
+            channel.position_fixed += channel.sample_step_fixed as u64;
+        }
+
+        (
+            left as f32 / FIXED_FULL_SCALE,
+            right as f32 / FIXED_FULL_SCALE,
+        )
     }
 
     fn update_samples_per_tick(&mut self) {
         self.samples_per_tick = (self.sample_rate as f32 * self.tick_duration) as usize
     }
 
+    fn apply_global_effect(&mut self, effect: GlobalEffect) {
+        match effect {
+            GlobalEffect::PositionJump(order) => {
+                // A break on the same row keeps its row
+                let row = self.pending_jump.map_or(0, |(_, row)| row);
+                self.pending_jump = Some((order as usize, row));
+            }
+            GlobalEffect::PatternBreak(row) => {
+                // A jump on the same row keeps its order
+                let order = self
+                    .pending_jump
+                    .map_or(self.current_order + 1, |(order, _)| order);
+                self.pending_jump = Some((order, row.min(63) as usize));
+            }
+            // F00 stops the song in some trackers, it's ignored here
+            GlobalEffect::SetSpeed(speed) if speed > 0 => self.speed = speed,
+            GlobalEffect::SetSpeed(_) => {}
+            GlobalEffect::SetTempo(tempo) => self.set_tempo(tempo as u16),
+        }
+    }
+
+    // Moves to the next row once all ticks of the current one have been played
+    fn advance_row(&mut self) {
+        let song_length = self.song.metadata.song_length as usize;
+
+        let (order, row) = match self.pending_jump.take() {
+            Some(position) => position,
+            None if self.current_row + 1 >= 64 => (self.current_order + 1, 0),
+            None => (self.current_order, self.current_row + 1),
+        };
+
+        let jumped_back =
+            order < self.current_order || (order == self.current_order && row <= self.current_row);
+
+        // The loop region repeats without counting as a loop of the song
+        let region_start = self
+            .loop_region
+            .filter(|region| region.end == self.position())
+            .map(|region| region.start);
+
+        let (order, row) = if let Some(start) = region_start {
+            start
+        } else if order >= song_length {
+            // The restart position is only valid if it points inside the song
+            let restart = self.song.metadata.end_jump as usize;
+            let restart = if self.quirks.restart && restart < song_length {
+                restart
+            } else {
+                0
+            };
+
+            self.loop_point = (restart, 0);
+            self.on_song_end((restart, 0))
+        } else if jumped_back {
+            self.loop_point = (order, row);
+            self.on_song_end((order, row))
+        } else {
+            (order, row)
+        };
+
+        if order != self.current_order && order < song_length {
+            self.current_pattern = self.song.metadata.pattern_table[order] as usize;
+
+            if self.print_rows {
+                println!("Playing pattern: {}", self.current_pattern);
+            }
+        }
+
+        self.current_order = order;
+        self.current_row = row;
+    }
+
+    // Master volume for the next output frame, ends the song once a fade out completes
+    fn next_fade_gain(&mut self) -> f32 {
+        let Some((remaining, total)) = &mut self.fade else {
+            return 1.0;
+        };
+
+        let gain = *remaining as f32 / *total as f32;
+        *remaining = remaining.saturating_sub(1);
+
+        if *remaining == 0 {
+            self.finished = true;
+        }
+
+        gain
+    }
+
+    // Counts silent output frames, ends the song once there were `limit` seconds of them in a
+    // row. Mixed channels only add up to exactly 0 when none of them plays anything
+    fn count_silence(&mut self, silent: bool, limit: f32) {
+        if !silent {
+            self.silent_frames = 0;
+            return;
+        }
+
+        self.silent_frames += 1;
+        if self.silent_frames as f32 >= limit * self.sample_rate as f32 {
+            self.finished = true;
+        }
+    }
+
+    // Decides where to continue once the song loops, returns a position past the end to stop
+    fn on_song_end(&mut self, restart: (usize, usize)) -> (usize, usize) {
+        let end = (self.song.metadata.song_length as usize, 0);
+        self.times_played = self.times_played.saturating_add(1);
+
+        if self.fade.is_some()
+            || self.fade_pending
+            || self.loop_count == 0
+            || self.times_played < self.loop_count
+        {
+            return restart;
+        }
+
+        // Keep playing from the loop point while fading out, if possible. The tick that
+        // reached the end is still played at full volume
+        if self.fade_frames() > 0 {
+            self.fade_pending = true;
+            return restart;
+        }
+
+        end
+    }
+
+    // Length of the fade out in output frames, 0 without one
+    fn fade_frames(&self) -> usize {
+        (self.fade_seconds * self.sample_rate as f32) as usize
+    }
+
+    // Starts fading out, returns false if there's no fade out (or nothing to fade) to do
+    fn start_fade(&mut self) -> bool {
+        let fade_frames = self.fade_frames();
+        if fade_frames == 0 {
+            return false;
+        }
+
+        self.fade = Some((fade_frames, fade_frames));
+        true
+    }
+
     fn set_tempo(&mut self, tempo: u16) {
         self.tempo = tempo;
-        self.tick_duration = 2.5 / tempo as f32;
+        self.tick_duration = 2.5 / tempo as f32 / self.speed_factor;
 
         self.update_samples_per_tick();
     }
 }
 
-// rewritten as a `Display` implementation for more flexibility, such as
-// ability to log the line to other output streams, ie. files, etc.
-struct SongLineDisplay<'a> {
-    pattern: &'a song::Pattern,
-    sample_metadata: &'a [song::Sample],
-    lineno: usize,
+// Gains of the left and right side for a panning from 0 (left) to 255 (right)
+fn pan_gains(law: PanLaw, panning: u8) -> (f32, f32) {
+    let pan = panning as f32 / 255.0;
+    match law {
+        PanLaw::Linear => (1.0 - pan, pan),
+        PanLaw::ConstantPower => ((1.0 - pan).sqrt(), pan.sqrt()),
+    }
 }
 
-impl<'a> Display for SongLineDisplay<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let SongLineDisplay {
-            pattern,
-            sample_metadata,
-            lineno,
-        } = self;
-        let line = pattern.get(*lineno).unwrap();
-        let line_str = line
-            .iter()
-            .map(|note| {
-                let finetune = sample_metadata.get(note.sample as usize).unwrap().finetune;
-                let pnote = tracker::protracker_period_to_note(note.period, finetune);
-
-                pnote.unwrap_or(String::from("---"))
-            })
-            .collect::<Vec<_>>()
-            .join(" ");
-
-        writeln!(f, "{:02}: {}", lineno, line_str)
+// `pan_gains` scaled to 0-255, with an integer square root so it's the same everywhere
+fn pan_gains_fixed(law: PanLaw, panning: u8) -> (i32, i32) {
+    let pan = panning as i32;
+    match law {
+        PanLaw::Linear => (255 - pan, pan),
+        PanLaw::ConstantPower => ((255 * (255 - pan)).isqrt(), (255 * pan).isqrt()),
     }
 }
 
-fn print_line(pattern: &song::Pattern, sample_metadata: &Vec<song::Sample>, lineno: usize) {
+fn print_line(pattern: &song::Pattern, sample_metadata: &[song::Sample], lineno: usize) {
     println!(
         "{}",
-        SongLineDisplay {
+        song::SongLineDisplay {
             pattern,
             sample_metadata,
             lineno
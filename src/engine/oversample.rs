@@ -0,0 +1,158 @@
+use std::f32::consts::PI;
+
+// Amount of filter taps per unit of oversampling, a higher value gives a steeper
+// cutoff at the cost of more multiplications per output frame
+const TAPS_PER_FACTOR: usize = 16;
+
+/// Low-pass filters and decimates a stereo signal mixed at `factor` times the output rate.
+///
+/// Mixing at a higher internal rate and filtering everything above the output
+/// Nyquist frequency removes most of the aliasing caused by high pitched notes,
+/// without needing a proper resampler for every channel.
+#[derive(Debug, Clone)]
+pub struct Decimator {
+    factor: usize,
+    taps: Vec<f32>,
+
+    // Ring buffer of the last `taps.len()` input frames
+    history: Vec<(f32, f32)>,
+    position: usize,
+}
+
+impl Decimator {
+    /// Creates a decimator for the given oversampling factor (must be atleast 2)
+    pub fn new(factor: usize) -> Self {
+        assert!(
+            factor >= 2,
+            "Decimator requires an oversampling factor >= 2"
+        );
+
+        let len = TAPS_PER_FACTOR * factor + 1;
+        let center = (len - 1) as f32 / 2.0;
+
+        // Cutoff slightly below the output Nyquist frequency, normalized to the internal rate
+        let cutoff = 0.45 / factor as f32;
+
+        // Blackman windowed sinc
+        let mut taps: Vec<f32> = (0..len)
+            .map(|i| {
+                let x = i as f32 - center;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (2.0 * PI * cutoff * x).sin() / (PI * x)
+                };
+
+                let phase = 2.0 * PI * i as f32 / (len - 1) as f32;
+                let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+
+                sinc * window
+            })
+            .collect();
+
+        // Normalize to unity gain at DC
+        let sum: f32 = taps.iter().sum();
+        taps.iter_mut().for_each(|t| *t /= sum);
+
+        Decimator {
+            factor,
+            taps,
+            history: vec![(0.0, 0.0); len],
+            position: 0,
+        }
+    }
+
+    /// The oversampling factor this decimator was created for
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Pushes a single frame at the internal rate into the filter
+    pub fn push(&mut self, frame: (f32, f32)) {
+        self.history[self.position] = frame;
+        self.position = (self.position + 1) % self.history.len();
+    }
+
+    /// Computes the filtered output frame from the most recently pushed frames
+    pub fn output(&self) -> (f32, f32) {
+        let len = self.history.len();
+        let mut left = 0.0;
+        let mut right = 0.0;
+
+        // `position` points at the oldest frame in the ring buffer
+        for (i, tap) in self.taps.iter().enumerate() {
+            let (l, r) = self.history[(self.position + i) % len];
+            left += l * tap;
+            right += r * tap;
+        }
+
+        (left, right)
+    }
+}
+
+// Gain for a sine at `frequency` (a fraction of the output rate) mixed at `factor` times the
+// output rate, measured by correlating the filtered output with the sine
+#[cfg(test)]
+fn response(factor: usize, frequency: f32) -> f32 {
+    let mut decimator = Decimator::new(factor);
+    let step = 2.0 * PI * frequency / factor as f32;
+
+    let (mut sin, mut cos) = (0.0f32, 0.0f32);
+    for frame in 0..4096 {
+        for i in 0..factor {
+            let sample = (step * (frame * factor + i) as f32).sin();
+            decimator.push((sample, -sample));
+        }
+
+        let (left, right) = decimator.output();
+        assert_eq!(left, -right);
+
+        // Skips the frames where the filter is still filling up
+        if frame >= 1024 {
+            let phase = step * (frame * factor) as f32;
+            sin += left * phase.sin();
+            cos += left * phase.cos();
+        }
+    }
+    2.0 * (sin * sin + cos * cos).sqrt() / 3072.0
+}
+
+#[test]
+fn passes_dc_unchanged() {
+    for factor in [2, 4] {
+        let mut decimator = Decimator::new(factor);
+        for _ in 0..decimator.taps.len() {
+            decimator.push((0.5, -0.25));
+        }
+
+        let (left, right) = decimator.output();
+        assert!((left - 0.5).abs() < 1e-4, "{factor}x: {left}");
+        assert!((right + 0.25).abs() < 1e-4, "{factor}x: {right}");
+    }
+}
+
+#[test]
+fn passes_audible_frequencies() {
+    for factor in [2, 4] {
+        for frequency in [0.01, 0.1, 0.2, 0.3] {
+            let gain = 20.0 * response(factor, frequency).log10();
+            assert!(gain.abs() < 0.1, "{factor}x at {frequency}: {gain} dB");
+        }
+    }
+}
+
+#[test]
+fn attenuates_above_nyquist() {
+    // What would alias back below 0.3 of the output rate is at least 70 dB down, up to the
+    // Nyquist frequency of the oversampled rate
+    for factor in [2, 4] {
+        let frequencies = [0.7, 0.8, 0.9, 1.0, 1.25, 1.5, 2.0];
+        for frequency in frequencies
+            .into_iter()
+            .filter(|&f| f <= factor as f32 / 2.0)
+        {
+            let gain = 20.0 * response(factor, frequency).log10();
+            assert!(gain < -70.0, "{factor}x at {frequency}: {gain} dB");
+        }
+    }
+}
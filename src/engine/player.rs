@@ -0,0 +1,657 @@
+//! Playing songs on an audio thread, controlled from any other
+
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use super::{Engine, LoopRegion, TrackerEngine};
+use crate::song::Note;
+
+/// A song handed to a [`Player`], with whatever the application keeps about it
+pub struct PlayerEntry<T = ()> {
+    /// Identifies the song when it's [reloaded](PlayerHandle::reload)
+    pub id: usize,
+    pub engine: Engine,
+    pub data: T,
+}
+
+/// Sent from a [`PlayerHandle`] to the [`Player`], applied before the next buffer is rendered
+enum Command<T> {
+    Load(Box<PlayerEntry<T>>),
+    Queue(Box<PlayerEntry<T>>),
+    Skip,
+    Reload(Box<PlayerEntry<T>>),
+    SetPaused(bool),
+    Seek(usize, usize),
+    SeekOrders(isize),
+    SetVolume(f32),
+    SetChannelMuted(usize, bool),
+    SetLoopRegion(Option<LoopRegion>),
+    PlayNote(usize, Note),
+}
+
+/// Where the player is, as of the last rendered buffer
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    position: (usize, usize),
+    elapsed: Option<f64>,
+    paused: bool,
+    finished: bool,
+}
+
+/// Told what a [`Player`] does as it renders, on the thread it renders on.
+///
+/// `frame` is how many frames into the buffer being rendered it happened, to tell when
+/// it's heard.
+pub trait PlayerListener<T> {
+    /// Another song started playing, `None` once the last one ended
+    fn started(&mut self, _entry: Option<&mut PlayerEntry<T>>, _frame: usize) {}
+
+    /// The playing song moved to a new order or row
+    fn row(&mut self, _engine: &Engine, _frame: usize) {}
+
+    /// The playing song was replaced by a new version of it
+    fn reloaded(&mut self, _entry: &PlayerEntry<T>) {}
+
+    fn paused(&mut self, _paused: bool) {}
+}
+
+impl<T> PlayerListener<T> for () {}
+
+/// Owns the playing engines on the thread that renders their audio, usually the callback
+/// of an audio library. Everything else controls it through [`PlayerHandle`]s, so a user
+/// interface never has to lock an engine.
+///
+/// Songs can be queued to follow the playing one without a gap, or to overlap with its
+/// fade out when crossfading.
+///
+/// ```no_run
+/// use rustune::engine::{Engine, Player};
+/// use rustune::Song;
+///
+/// let engine = Engine::new(Song::new("song.mod".as_ref())?)?;
+/// let (mut player, handle) = Player::new(engine);
+///
+/// // Moved into the audio callback, e.g. of cpal
+/// std::thread::spawn(move || {
+///     let mut buffer = vec![0.0; 2048];
+///     while !player.is_finished() {
+///         player.render(&mut buffer);
+///         // Send `buffer` to the audio output
+///     }
+/// });
+///
+/// handle.seek(4, 0);
+/// handle.mute_channel(2, true);
+/// println!("{:?}", handle.position());
+/// # Ok::<(), rustune::SongError>(())
+/// ```
+pub struct Player<T = ()> {
+    commands: Receiver<Command<T>>,
+    state: Arc<Mutex<State>>,
+
+    current: Option<PlayerEntry<T>>,
+    queued: Option<PlayerEntry<T>>,
+    // The previous song finishing its fade out underneath the current one
+    fading: Option<Engine>,
+    // Start the queued song as soon as the current one starts fading out
+    crossfade: bool,
+
+    paused: bool,
+    volume: f32,
+
+    // The last row reported to listeners
+    last_position: Option<(usize, usize)>,
+    // Mixing space for the fading song
+    scratch: Vec<f32>,
+}
+
+/// Controls a [`Player`] from another thread. Clones control the same player.
+///
+/// Commands sent after the player was dropped are ignored.
+pub struct PlayerHandle<T = ()> {
+    commands: Sender<Command<T>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Player {
+    /// Takes over `engine`, which should be set up (sample rate, channels, loops) beforehand
+    pub fn new(engine: Engine) -> (Player, PlayerHandle) {
+        let (mut player, handle) = Player::idle();
+        player.current = Some(PlayerEntry {
+            id: 0,
+            engine,
+            data: (),
+        });
+        player.update_state();
+
+        (player, handle)
+    }
+}
+
+impl<T> Player<T> {
+    /// A player with nothing to play, until songs are [loaded](PlayerHandle::load)
+    pub fn idle() -> (Player<T>, PlayerHandle<T>) {
+        let (commands, receiver) = mpsc::channel();
+        let state = Arc::new(Mutex::new(State {
+            finished: true,
+            ..State::default()
+        }));
+
+        let player = Player {
+            commands: receiver,
+            state: state.clone(),
+            current: None,
+            queued: None,
+            fading: None,
+            crossfade: false,
+            paused: false,
+            volume: 1.0,
+            last_position: None,
+            scratch: Vec::new(),
+        };
+
+        (player, PlayerHandle { commands, state })
+    }
+
+    /// Starts the queued song when the playing one starts fading out, rather than once it
+    /// ended. Both play until the fade out is over
+    pub fn set_crossfade(&mut self, crossfade: bool) {
+        self.crossfade = crossfade;
+    }
+
+    /// Applies the commands sent since the last call, then fills `buffer` with interleaved
+    /// audio like [`TrackerEngine::render`].
+    ///
+    /// # Returns
+    /// The amount of frames of songs rendered, 0 while paused. The rest of the buffer is
+    /// silence
+    pub fn render(&mut self, buffer: &mut [f32]) -> usize {
+        self.render_with(buffer, &mut ())
+    }
+
+    /// Renders like [`render`](Self::render), telling `listener` about the songs and rows
+    /// that start in `buffer`
+    pub fn render_with(
+        &mut self,
+        buffer: &mut [f32],
+        listener: &mut impl PlayerListener<T>,
+    ) -> usize {
+        self.update(listener);
+
+        let mut frames = 0;
+        if self.paused {
+            buffer.fill(0.0);
+        } else {
+            let mut offset = 0;
+            while offset < buffer.len() {
+                let Some(PlayerEntry { engine, .. }) = &mut self.current else {
+                    buffer[offset..].fill(0.0);
+                    break;
+                };
+
+                // Every tick is rendered on its own, so each row is reported at the frame it
+                // starts
+                let channels = engine.channel_count() as usize;
+                let frame = offset / channels;
+                let tick_left = match engine.samples_since_tick() {
+                    0 => engine.samples_per_tick(),
+                    since => engine.samples_per_tick().saturating_sub(since),
+                };
+                let end = buffer.len().min(offset + tick_left.max(1) * channels);
+                let rendered = engine.render(&mut buffer[offset..end]);
+                offset += rendered * channels;
+                frames += rendered;
+
+                self.report_row(listener, frame);
+                // The next song continues right where this one ended
+                self.advance(listener, frame);
+            }
+
+            if let Some(fading) = &mut self.fading {
+                self.scratch.resize(buffer.len(), 0.0);
+                fading.render(&mut self.scratch);
+                buffer
+                    .iter_mut()
+                    .zip(&self.scratch)
+                    .for_each(|(sample, faded)| *sample += faded);
+
+                if fading.is_finished() {
+                    self.fading = None;
+                }
+            }
+        }
+
+        if self.volume != 1.0 {
+            buffer.iter_mut().for_each(|sample| *sample *= self.volume);
+        }
+
+        self.update_state();
+        frames
+    }
+
+    /// Advances by a single tick without mixing, for playback without an audio device.
+    ///
+    /// # Returns
+    /// How long the tick lasts in seconds, or `None` if nothing is playing.
+    pub fn tick_with(&mut self, listener: &mut impl PlayerListener<T>) -> Option<f32> {
+        self.update(listener);
+
+        let engine = &mut self.current.as_mut()?.engine;
+        if self.paused {
+            return Some(engine.tick_duration());
+        }
+
+        engine.next_tick();
+        let duration = engine.tick_duration();
+
+        if let Some(fading) = &mut self.fading {
+            fading.next_tick();
+            if fading.is_finished() {
+                self.fading = None;
+            }
+        }
+
+        self.report_row(listener, 0);
+        self.advance(listener, 0);
+        self.update_state();
+
+        Some(duration)
+    }
+
+    /// Whether there's nothing left to play
+    pub fn is_finished(&self) -> bool {
+        self.current.is_none()
+    }
+
+    /// The engine playing the current song, e.g. for its sample rate and channels
+    pub fn engine(&self) -> Option<&Engine> {
+        self.current.as_ref().map(|entry| &entry.engine)
+    }
+
+    fn update(&mut self, listener: &mut impl PlayerListener<T>) {
+        while let Ok(command) = self.commands.try_recv() {
+            let current = self.current.as_mut().map(|entry| &mut entry.engine);
+            match command {
+                Command::Load(entry) => {
+                    self.current = Some(*entry);
+                    self.queued = None;
+                    self.fading = None;
+                    self.started(listener, 0);
+                }
+                Command::Queue(entry) => self.queued = Some(*entry),
+                Command::Skip => {
+                    self.current = self.queued.take();
+                    self.fading = None;
+                    self.started(listener, 0);
+                }
+                Command::Reload(entry) => self.reload(*entry, listener),
+                Command::SetPaused(paused) => {
+                    self.paused = paused;
+                    listener.paused(paused);
+                }
+                // Seeking outside the song is ignored rather than ending it
+                Command::Seek(order, row) => {
+                    if let Some(engine) = current {
+                        engine.seek(order, row);
+                    }
+                }
+                Command::SeekOrders(offset) => {
+                    if let Some(engine) = current {
+                        let (order, _) = engine.position();
+                        if let Some(order) = order.checked_add_signed(offset) {
+                            engine.seek(order, 0);
+                        }
+                    }
+                }
+                Command::SetVolume(volume) => self.volume = volume,
+                Command::SetChannelMuted(channel, muted) => {
+                    if let Some(engine) = current {
+                        engine.set_channel_muted(channel, muted);
+                    }
+                }
+                Command::SetLoopRegion(region) => {
+                    if let Some(engine) = current {
+                        engine.set_loop_region(region);
+                    }
+                }
+                Command::PlayNote(channel, note) => {
+                    if let Some(engine) = current {
+                        engine.play_note(channel, note);
+                    }
+                }
+            }
+        }
+
+        // A song queued after the previous one already ended
+        if self.current.is_none() && self.queued.is_some() {
+            self.current = self.queued.take();
+            self.started(listener, 0);
+        }
+    }
+
+    fn reload(&mut self, mut entry: PlayerEntry<T>, listener: &mut impl PlayerListener<T>) {
+        if let Some(queued) = (self.queued.as_mut()).filter(|queued| queued.id == entry.id) {
+            *queued = entry;
+            return;
+        }
+
+        let Some(current) = (self.current.as_mut()).filter(|current| current.id == entry.id) else {
+            return;
+        };
+
+        // The nearest position that still exists, if the song got shorter
+        let (order, row) = current.engine.position();
+        let last_order = entry.engine.song_length().saturating_sub(1);
+        if order > last_order {
+            entry.engine.seek(last_order, 0);
+        } else {
+            entry.engine.seek(order, row);
+        }
+
+        *current = entry;
+        self.last_position = None;
+        listener.reloaded(current);
+    }
+
+    // Moves on to the queued song once the current one ends, or starts fading out when
+    // crossfading
+    fn advance(&mut self, listener: &mut impl PlayerListener<T>, frame: usize) {
+        let Some(PlayerEntry { engine, .. }) = &self.current else {
+            return;
+        };
+
+        let crossfading = self.crossfade && engine.is_fading() && self.queued.is_some();
+        if !engine.is_finished() && !crossfading {
+            return;
+        }
+
+        let previous = mem::replace(&mut self.current, self.queued.take());
+        if crossfading {
+            self.fading = previous.map(|entry| entry.engine);
+        }
+
+        self.started(listener, frame);
+    }
+
+    fn started(&mut self, listener: &mut impl PlayerListener<T>, frame: usize) {
+        self.last_position = None;
+        listener.started(self.current.as_mut(), frame);
+    }
+
+    fn report_row(&mut self, listener: &mut impl PlayerListener<T>, frame: usize) {
+        let Some(PlayerEntry { engine, .. }) = &self.current else {
+            return;
+        };
+
+        // Once the last order has been played the position is past it, with nothing to show
+        let position = engine.position();
+        if position.0 >= engine.song_length() || self.last_position == Some(position) {
+            return;
+        }
+
+        self.last_position = Some(position);
+        listener.row(engine, frame);
+    }
+
+    fn update_state(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(PlayerEntry { engine, .. }) = &self.current {
+            state.position = engine.position();
+            state.elapsed = engine.elapsed();
+        }
+        state.paused = self.paused;
+        state.finished = self.current.is_none();
+    }
+}
+
+impl<T> PlayerHandle<T> {
+    /// Replaces whatever is playing with this song, and forgets the queued one
+    pub fn load(&self, entry: PlayerEntry<T>) {
+        self.send(Command::Load(Box::new(entry)));
+    }
+
+    /// Plays this song once the current one ends, without a gap. Replaces the song queued
+    /// before
+    pub fn queue(&self, entry: PlayerEntry<T>) {
+        self.send(Command::Queue(Box::new(entry)));
+    }
+
+    /// Stops the current song and continues with the queued one, if there is one
+    pub fn skip(&self) {
+        self.send(Command::Skip);
+    }
+
+    /// A new version of the current or queued song with the same id, which takes its place.
+    /// The current song continues from the same position
+    pub fn reload(&self, entry: PlayerEntry<T>) {
+        self.send(Command::Reload(Box::new(entry)));
+    }
+
+    /// Continues playback after [`pause`](Self::pause)
+    pub fn play(&self) {
+        self.send(Command::SetPaused(false));
+    }
+
+    /// Renders silence until [`play`](Self::play) is called, keeping the position
+    pub fn pause(&self) {
+        self.send(Command::SetPaused(true));
+    }
+
+    /// Continues playback from the given order and row, if they're inside the song
+    pub fn seek(&self, order: usize, row: usize) {
+        self.send(Command::Seek(order, row));
+    }
+
+    /// Jumps this many orders forwards or backwards, to the start of the order
+    pub fn seek_orders(&self, offset: isize) {
+        self.send(Command::SeekOrders(offset));
+    }
+
+    /// Master volume, 1.0 is the unchanged output
+    pub fn set_volume(&self, volume: f32) {
+        self.send(Command::SetVolume(volume));
+    }
+
+    /// Mutes or unmutes a channel, counting from 0
+    pub fn mute_channel(&self, channel: usize, muted: bool) {
+        self.send(Command::SetChannelMuted(channel, muted));
+    }
+
+    /// Repeats a section of the current song, or stops repeating it with `None`
+    pub fn set_loop_region(&self, region: Option<LoopRegion>) {
+        self.send(Command::SetLoopRegion(region));
+    }
+
+    /// Plays a note on a channel (counting from 0) of the current song right away
+    pub fn play_note(&self, channel: usize, note: Note) {
+        self.send(Command::PlayNote(channel, note));
+    }
+
+    /// The order and row that plays next
+    pub fn position(&self) -> (usize, usize) {
+        self.state().position
+    }
+
+    /// Seconds into a playthrough of the song, see [`TrackerEngine::elapsed`]
+    pub fn elapsed(&self) -> Option<f64> {
+        self.state().elapsed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state().paused
+    }
+
+    /// Whether there's nothing left to play, as of the last rendered buffer
+    pub fn is_finished(&self) -> bool {
+        self.state().finished
+    }
+
+    fn send(&self, command: Command<T>) {
+        let _ = self.commands.send(command);
+    }
+
+    fn state(&self) -> State {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Derived, these would need `T` to be `Clone` and `Debug` as well
+impl<T> Clone for PlayerHandle<T> {
+    fn clone(&self) -> Self {
+        PlayerHandle {
+            commands: self.commands.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Debug for PlayerHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlayerHandle")
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+fn test_engine(patterns: usize) -> Engine {
+    use crate::song::square_song;
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    let mut builder = square_song(4);
+    for _ in 0..patterns {
+        builder = builder.empty_pattern();
+    }
+    let orders: Vec<u8> = (0..patterns as u8).collect();
+    let song = builder.note(0, 0, 0, note).orders(&orders).build().unwrap();
+
+    let mut engine = Engine::new(song).unwrap();
+    engine.set_sample_rate(44100);
+    engine.set_channel_count(2);
+    engine
+}
+
+// Records what a player tells its listener
+#[cfg(test)]
+#[derive(Default)]
+struct Recorder {
+    started: Vec<Option<usize>>,
+    rows: Vec<(usize, usize)>,
+    reloaded: Vec<usize>,
+}
+
+#[cfg(test)]
+impl PlayerListener<&'static str> for Recorder {
+    fn started(&mut self, entry: Option<&mut PlayerEntry<&'static str>>, _frame: usize) {
+        self.started.push(entry.map(|entry| entry.id));
+    }
+
+    fn row(&mut self, engine: &Engine, _frame: usize) {
+        self.rows.push(engine.position());
+    }
+
+    fn reloaded(&mut self, entry: &PlayerEntry<&'static str>) {
+        self.reloaded.push(entry.id);
+    }
+}
+
+#[test]
+fn handle_controls_player() {
+    let (mut player, handle) = Player::new(test_engine(2));
+    let mut buffer = vec![0.0; 2048];
+
+    assert_eq!(player.render(&mut buffer), 1024);
+    assert!(buffer.iter().any(|&sample| sample != 0.0));
+
+    handle.pause();
+    assert_eq!(player.render(&mut buffer), 0);
+    assert!(buffer.iter().all(|&sample| sample == 0.0));
+    assert!(handle.is_paused());
+
+    let other = handle.clone();
+    other.play();
+    other.seek(1, 32);
+    player.render(&mut buffer);
+    assert!(!handle.is_paused());
+    assert_eq!(handle.position().0, 1);
+
+    other.seek_orders(-1);
+    player.render(&mut buffer);
+    assert_eq!(handle.position().0, 0);
+}
+
+#[test]
+fn queued_song_follows_without_a_gap() {
+    let (mut player, handle) = Player::idle();
+    let mut recorder = Recorder::default();
+    let mut buffer = vec![0.0; 2048];
+
+    assert!(player.is_finished());
+    handle.load(PlayerEntry {
+        id: 1,
+        engine: test_engine(1),
+        data: "first",
+    });
+    handle.queue(PlayerEntry {
+        id: 2,
+        engine: test_engine(1),
+        data: "second",
+    });
+
+    // Renders the first song to its end, and the start of the second right after it
+    let mut frames = 0;
+    while recorder.started.len() < 2 {
+        frames += player.render_with(&mut buffer, &mut recorder);
+    }
+    assert_eq!(recorder.started, [Some(1), Some(2)]);
+    assert!(frames > 0);
+    assert_eq!(recorder.rows.first(), Some(&(0, 0)));
+    assert_eq!(
+        recorder.rows.iter().filter(|&&row| row == (0, 0)).count(),
+        2
+    );
+
+    handle.skip();
+    player.render_with(&mut buffer, &mut recorder);
+    assert_eq!(recorder.started.last(), Some(&None));
+    assert!(handle.is_finished());
+}
+
+#[test]
+fn reload_keeps_the_position() {
+    let (mut player, handle) = Player::idle();
+    let mut recorder = Recorder::default();
+    let mut buffer = vec![0.0; 2048];
+
+    handle.load(PlayerEntry {
+        id: 1,
+        engine: test_engine(3),
+        data: "old",
+    });
+    handle.seek(2, 16);
+    player.render_with(&mut buffer, &mut recorder);
+
+    // A song that isn't playing or queued isn't replaced
+    handle.reload(PlayerEntry {
+        id: 2,
+        engine: test_engine(3),
+        data: "other",
+    });
+    // The new version is shorter, so it continues on its last order
+    handle.reload(PlayerEntry {
+        id: 1,
+        engine: test_engine(2),
+        data: "new",
+    });
+    player.render_with(&mut buffer, &mut recorder);
+
+    assert_eq!(recorder.reloaded, [1]);
+    assert_eq!(handle.position(), (1, 0));
+}
@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// The most recent output of every tracker channel, shared between the audio thread and a
+/// visualization such as an oscilloscope.
+///
+/// The engine only records channel output while a scope buffer is attached, see
+/// [`TrackerEngine::set_scope`](super::TrackerEngine::set_scope).
+#[derive(Debug)]
+pub struct ScopeBuffer {
+    length: usize,
+    channels: Mutex<Vec<VecDeque<f32>>>,
+}
+
+// Only the terminal interface reads scopes so far
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+impl ScopeBuffer {
+    /// Creates a buffer keeping the last `length` frames of `channel_count` channels
+    pub fn new(channel_count: usize, length: usize) -> Self {
+        ScopeBuffer {
+            length,
+            channels: Mutex::new(vec![VecDeque::from(vec![0.0; length]); channel_count]),
+        }
+    }
+
+    /// Appends frames to each channel, `frames[channel]` holds the new values of that channel.
+    ///
+    /// This never blocks, if a reader is busy with the buffer the frames are dropped instead,
+    /// since stalling the audio thread would be worse than a skipped scope update.
+    pub fn push(&self, frames: &[Vec<f32>]) {
+        let Ok(mut channels) = self.channels.try_lock() else {
+            return;
+        };
+
+        for (history, new) in channels.iter_mut().zip(frames) {
+            history.extend(new);
+
+            let excess = history.len().saturating_sub(self.length);
+            history.drain(..excess);
+        }
+    }
+
+    /// Copies the recorded frames of every channel, oldest first
+    pub fn snapshot(&self) -> Vec<Vec<f32>> {
+        let channels = self.channels.lock().unwrap_or_else(|e| e.into_inner());
+        channels
+            .iter()
+            .map(|history| history.iter().copied().collect())
+            .collect()
+    }
+}
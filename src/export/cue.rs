@@ -0,0 +1,105 @@
+//! CUE sheets, which split a render of a whole playlist into tracks for players and CD
+//! burning software
+
+use std::io::{self, Write};
+
+use super::OutputFormat;
+
+// Positions in CUE sheets are given in minutes, seconds and CD frames, 75 a second
+const FRAMES_PER_SECOND: u64 = 75;
+
+/// Where the songs of a playlist start in the single file it was rendered to.
+///
+/// ```
+/// use rustune::export::cue::CueSheet;
+/// use rustune::export::OutputFormat;
+///
+/// let mut sheet = CueSheet::new("mix.wav", OutputFormat::Wav, 44100);
+/// sheet.add_track("first song", 0);
+/// sheet.add_track("second song", 44100 * 90);
+///
+/// let mut cue = Vec::new();
+/// sheet.write(&mut cue)?;
+/// assert!(String::from_utf8(cue)?.contains("TRACK 02 AUDIO"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct CueSheet {
+    file: String,
+    format: OutputFormat,
+    sample_rate: u32,
+    // Title and first frame of every track
+    tracks: Vec<(String, u64)>,
+}
+
+impl CueSheet {
+    /// A sheet without tracks for the audio file `file`, named as the sheet refers to it
+    /// (usually without its directory, it's found next to the sheet)
+    pub fn new(file: &str, format: OutputFormat, sample_rate: u32) -> Self {
+        CueSheet {
+            file: file.to_owned(),
+            format,
+            sample_rate,
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Adds a track named `title` starting at `frame`, after the tracks added before
+    pub fn add_track(&mut self, title: &str, frame: u64) {
+        self.tracks.push((title.to_owned(), frame));
+    }
+
+    /// Writes the sheet
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "REM COMMENT \"Rendered by rustune\"")?;
+        let file_type = self.format.cue_file_type();
+        writeln!(writer, "FILE {} {file_type}", quote(&self.file))?;
+
+        for (number, (title, frame)) in (1..).zip(&self.tracks) {
+            writeln!(writer, "  TRACK {number:02} AUDIO")?;
+            writeln!(writer, "    TITLE {}", quote(title))?;
+            writeln!(writer, "    INDEX 01 {}", self.timestamp(*frame))?;
+        }
+
+        writer.flush()
+    }
+
+    // `mm:ss:ff` for an audio frame, rounded down to the CD frame it's in. Minutes go past 99
+    // for long playlists, which most players accept
+    fn timestamp(&self, frame: u64) -> String {
+        let frames = frame * FRAMES_PER_SECOND / self.sample_rate.max(1) as u64;
+        let seconds = frames / FRAMES_PER_SECOND;
+
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 60,
+            seconds % 60,
+            frames % FRAMES_PER_SECOND
+        )
+    }
+}
+
+// Strings are quoted, and can't hold quotes themselves
+fn quote(value: &str) -> String {
+    let value: String = (value.chars())
+        .map(|c| if c == '"' { '\'' } else { c })
+        .filter(|c| !c.is_control())
+        .collect();
+    format!("\"{value}\"")
+}
+
+#[test]
+fn track_positions() {
+    let mut sheet = CueSheet::new("songs \"live\".wav", OutputFormat::Wav, 48000);
+    sheet.add_track("intro", 0);
+    // 61.5 seconds and 2 CD frames in
+    sheet.add_track("title", 48000 * 61 + 24000 + 640 * 2);
+
+    let mut cue = Vec::new();
+    sheet.write(&mut cue).unwrap();
+    let cue = String::from_utf8(cue).unwrap();
+
+    assert!(cue.contains("FILE \"songs 'live'.wav\" WAVE\n"));
+    assert!(cue.contains("  TRACK 01 AUDIO\n    TITLE \"intro\"\n    INDEX 01 00:00:00\n"));
+    assert!(cue.contains("  TRACK 02 AUDIO\n    TITLE \"title\"\n    INDEX 01 01:01:39\n"));
+}
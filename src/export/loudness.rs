@@ -0,0 +1,325 @@
+//! Loudness of rendered audio as EBU R 128 measures it (ITU-R BS.1770), so renders can be
+//! normalized to the same level
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::io;
+
+use super::{AudioSink, RENDER_CHUNK_FRAMES};
+use crate::engine::{Engine, TrackerEngine};
+
+// Loudness is measured over 400 ms blocks that overlap by 75%, so a block ends every 100 ms
+const STEP_SECONDS: f64 = 0.1;
+const STEPS_PER_BLOCK: usize = 4;
+// Blocks quieter than this are left out, and then those more than 10 LU below the loudness
+// of the rest
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Loudness ReplayGain 2.0 brings songs to, in LUFS
+pub const REPLAYGAIN_REFERENCE: f64 = -18.0;
+/// Loudness the R128 gains of Opus files bring songs to, in LUFS
+pub const R128_REFERENCE: f64 = -23.0;
+
+// True peaks are found between the samples at 4 times the sample rate, interpolating with a
+// windowed sinc of this many taps
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 16;
+
+/// How loud a song is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, `None` when the song is silent
+    pub integrated: Option<f64>,
+    /// The highest level, including between samples, in dBTP. `None` when the song is silent
+    pub true_peak: Option<f64>,
+}
+
+impl Loudness {
+    /// Gain in dB that brings the song to `reference` LUFS, `None` when it's silent
+    pub fn gain(&self, reference: f64) -> Option<f64> {
+        self.integrated.map(|integrated| reference - integrated)
+    }
+
+    /// Vorbis comments telling players how much to turn the song up or down, as ReplayGain
+    /// 2.0 writes them
+    pub fn replaygain_tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(gain) = self.gain(REPLAYGAIN_REFERENCE) {
+            tags.push(("REPLAYGAIN_TRACK_GAIN", format!("{gain:.2} dB")));
+        }
+        if let Some(true_peak) = self.true_peak {
+            let peak = 10f64.powf(true_peak / 20.0);
+            tags.push(("REPLAYGAIN_TRACK_PEAK", format!("{peak:.6}")));
+        }
+        tags
+    }
+
+    /// The comment Opus players read the gain from instead (RFC 7845), in 1/256 dB
+    pub fn r128_tags(&self) -> Vec<(&'static str, String)> {
+        let gain = self.gain(R128_REFERENCE).map(|gain| {
+            let gain = (gain * 256.0)
+                .round()
+                .clamp(i16::MIN as f64, i16::MAX as f64);
+            ("R128_TRACK_GAIN", (gain as i16).to_string())
+        });
+        gain.into_iter().collect()
+    }
+}
+
+impl fmt::Display for Loudness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.integrated {
+            Some(integrated) => write!(f, "{integrated:.1} LUFS")?,
+            None => write!(f, "silent")?,
+        }
+        if let Some(true_peak) = self.true_peak {
+            write!(f, ", true peak {true_peak:.1} dBTP")?;
+        }
+        Ok(())
+    }
+}
+
+/// A filter with two poles and two zeros, the K-weighting is two of them
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    // The coefficients are those BS.1770 gives for 48 kHz, recalculated for `sample_rate`
+
+    // Boosts the highs by 4 dB, like the head does
+    fn high_shelf(sample_rate: u32) -> Biquad {
+        const FREQUENCY: f64 = 1681.974450955533;
+        const GAIN: f64 = 3.999843853973347;
+        const Q: f64 = 0.7071752369554196;
+
+        let k = (PI * FREQUENCY / sample_rate as f64).tan();
+        let vh = 10f64.powf(GAIN / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / Q + k * k;
+
+        Biquad {
+            b: [
+                (vh + vb * k / Q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / Q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / Q + k * k) / a0],
+        }
+    }
+
+    // Cuts the lows, which are heard as quieter than they measure
+    fn high_pass(sample_rate: u32) -> Biquad {
+        const FREQUENCY: f64 = 38.13547087602444;
+        const Q: f64 = 0.5003270373238773;
+
+        let k = (PI * FREQUENCY / sample_rate as f64).tan();
+        let a0 = 1.0 + k / Q + k * k;
+
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / Q + k * k) / a0],
+        }
+    }
+
+    // Filters one sample, `state` holds the filter's memory for the channel
+    fn process(&self, state: &mut [f64; 2], input: f64) -> f64 {
+        // Transposed direct form II
+        let output = self.b[0] * input + state[0];
+        state[0] = self.b[1] * input - self.a[0] * output + state[1];
+        state[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// Measures the loudness of interleaved audio as it's added. Also an [`AudioSink`], so a song
+/// can be measured with [`super::render`], or see [`measure`].
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: [Biquad; 2],
+    // The memory of both filters, for every channel
+    states: Vec<[[f64; 2]; 2]>,
+
+    // Frames in 100 ms, and how many of them the current step has
+    step_frames: usize,
+    frames: usize,
+    // Sum of the squared, weighted samples of the current step, and of the steps before it
+    // that are in the current block
+    energy: f64,
+    steps: Vec<f64>,
+    // Mean square of every block
+    blocks: Vec<f64>,
+
+    // The latest samples of every channel, newest first, to interpolate true peaks from
+    history: Vec<Vec<f32>>,
+    interpolation: Vec<[f32; TRUE_PEAK_TAPS]>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    /// Measures audio with `channels` interleaved channels at `sample_rate`
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+
+        LoudnessMeter {
+            channels,
+            filters: [
+                Biquad::high_shelf(sample_rate),
+                Biquad::high_pass(sample_rate),
+            ],
+            states: vec![[[0.0; 2]; 2]; channels],
+            step_frames: ((sample_rate as f64 * STEP_SECONDS).round() as usize).max(1),
+            frames: 0,
+            energy: 0.0,
+            steps: Vec::with_capacity(STEPS_PER_BLOCK),
+            blocks: Vec::new(),
+            history: vec![vec![0.0; TRUE_PEAK_TAPS]; channels],
+            interpolation: interpolation_filters(),
+            peak: 0.0,
+        }
+    }
+
+    /// Adds interleaved samples
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let [shelf, high_pass] = &mut self.states[channel];
+                let weighted = self.filters[0].process(shelf, sample as f64);
+                let weighted = self.filters[1].process(high_pass, weighted);
+                // Left, right and center count the same, surround channels aren't rendered
+                self.energy += weighted * weighted;
+
+                let history = &mut self.history[channel];
+                history.rotate_right(1);
+                history[0] = sample;
+                for taps in &self.interpolation {
+                    let value: f32 = taps.iter().zip(history.iter()).map(|(t, s)| t * s).sum();
+                    self.peak = self.peak.max(value.abs());
+                }
+            }
+
+            self.frames += 1;
+            if self.frames == self.step_frames {
+                self.end_step();
+            }
+        }
+    }
+
+    /// The loudness of everything added so far
+    pub fn loudness(&self) -> Loudness {
+        let loudness = |mean_square: f64| -0.691 + 10.0 * mean_square.log10();
+        let gated_mean = |gate: f64| {
+            let gated: Vec<f64> = (self.blocks.iter().copied())
+                .filter(|&block| loudness(block) > gate)
+                .collect();
+            (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+        };
+
+        let integrated = gated_mean(ABSOLUTE_GATE)
+            .and_then(|mean| gated_mean(loudness(mean) + RELATIVE_GATE))
+            .map(loudness);
+        let true_peak = (self.peak > 0.0).then(|| 20.0 * (self.peak as f64).log10());
+
+        Loudness {
+            integrated,
+            true_peak,
+        }
+    }
+
+    // Finishes 100 ms, and the block that ends with them once there are enough
+    fn end_step(&mut self) {
+        if self.steps.len() == STEPS_PER_BLOCK {
+            self.steps.remove(0);
+        }
+        self.steps.push(self.energy / self.frames as f64);
+        self.energy = 0.0;
+        self.frames = 0;
+
+        if self.steps.len() == STEPS_PER_BLOCK {
+            self.blocks
+                .push(self.steps.iter().sum::<f64>() / STEPS_PER_BLOCK as f64);
+        }
+    }
+}
+
+impl AudioSink for LoudnessMeter {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.add(samples);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Renders the rest of the song `engine` plays and measures how loud it is
+pub fn measure(engine: &mut Engine) -> Loudness {
+    let channels = engine.channel_count();
+    let mut meter = LoudnessMeter::new(engine.sample_rate(), channels);
+
+    let mut buffer = vec![0.0f32; RENDER_CHUNK_FRAMES * channels as usize];
+    while !engine.is_finished() {
+        let frames = engine.render(&mut buffer);
+        meter.add(&buffer[..frames * channels as usize]);
+    }
+
+    meter.loudness()
+}
+
+// The taps that interpolate every point between two samples, for the samples newest first.
+// The first point is the sample itself
+fn interpolation_filters() -> Vec<[f32; TRUE_PEAK_TAPS]> {
+    let half = (TRUE_PEAK_TAPS / 2) as f64;
+
+    (0..TRUE_PEAK_OVERSAMPLING)
+        .map(|phase| {
+            std::array::from_fn(|tap| {
+                let x = tap as f64 - half + phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = 0.5 + 0.5 * (PI * x / half).cos();
+                (sinc * window) as f32
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn stereo_sine() {
+    // EBU Tech 3341, case 1: a 1 kHz sine at -23 dBFS in both channels is -23 LUFS
+    let sample_rate = 48000;
+    let amplitude = 10f32.powf(-23.0 / 20.0);
+    let samples: Vec<f32> = (0..sample_rate * 5)
+        .flat_map(|frame| {
+            let phase = 2.0 * PI * 1000.0 * frame as f64 / sample_rate as f64;
+            [amplitude * phase.sin() as f32; 2]
+        })
+        .collect();
+
+    let mut meter = LoudnessMeter::new(sample_rate, 2);
+    meter.add(&samples);
+    let loudness = meter.loudness();
+
+    assert!((loudness.integrated.unwrap() + 23.0).abs() < 0.1);
+    assert!((loudness.true_peak.unwrap() + 23.0).abs() < 0.1);
+
+    // 5 dB quieter than ReplayGain's reference, 0 dB for R128
+    let tags = loudness.replaygain_tags();
+    assert_eq!(tags[0].0, "REPLAYGAIN_TRACK_GAIN");
+    assert!(tags[0].1.starts_with("5.0") || tags[0].1.starts_with("4.9"));
+    assert!(
+        matches!(&loudness.r128_tags()[..], [("R128_TRACK_GAIN", gain)] if gain.parse::<i16>().unwrap().abs() < 26)
+    );
+
+    let silence = LoudnessMeter::new(sample_rate, 2);
+    assert_eq!(silence.loudness().integrated, None);
+    assert!(silence.loudness().replaygain_tags().is_empty());
+}
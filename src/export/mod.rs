@@ -0,0 +1,333 @@
+//! Writing rendered audio and samples to files
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+
+use crate::engine::{Engine, TrackerEngine};
+use raw::{RawFormat, RawSink};
+use tags::Tags;
+use wav::{WavFormat, WavStream, WavWriter};
+
+pub mod cue;
+pub mod loudness;
+#[cfg(feature = "mp3")]
+pub mod mp3;
+#[cfg(feature = "opus")]
+pub mod opus;
+pub mod raw;
+pub mod replay_log;
+pub mod samples;
+pub mod tags;
+#[cfg(feature = "ogg")]
+pub mod vorbis;
+pub mod wav;
+pub mod waveform;
+
+// Frames rendered per iteration of the offline render loop
+const RENDER_CHUNK_FRAMES: usize = 4096;
+
+/// A destination for rendered, interleaved audio
+pub trait AudioSink {
+    /// Appends interleaved samples in the range [-1.0, 1.0]
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Flushes any buffered audio and finalizes the container
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        WavWriter::write_samples(self, samples)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        WavWriter::finish(*self).map(|_| ())
+    }
+}
+
+/// Throws the audio away, only counting how much there was, for measuring how fast songs render
+#[derive(Debug, Default)]
+pub struct NullSink {
+    samples: usize,
+}
+
+impl NullSink {
+    /// Interleaved samples written so far
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+}
+
+impl AudioSink for NullSink {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.samples += samples.len();
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Container/codec of an exported file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Wav,
+    #[cfg(feature = "ogg")]
+    Vorbis,
+    #[cfg(feature = "opus")]
+    Opus,
+    #[cfg(feature = "mp3")]
+    Mp3,
+}
+
+impl OutputFormat {
+    /// Picks the output format from the extension of `path`
+    ///
+    /// # Errors
+    /// When the extension is unknown, or the encoder for it wasn't compiled in
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+
+        Self::from_extension(ext)
+    }
+
+    /// Picks the output format by its file extension, e.g. `wav`
+    ///
+    /// # Errors
+    /// When the extension is unknown, or the encoder for it wasn't compiled in
+    pub fn from_extension(ext: &str) -> io::Result<Self> {
+        let ext = ext.to_ascii_lowercase();
+
+        match ext.as_str() {
+            "wav" => Ok(OutputFormat::Wav),
+            #[cfg(feature = "ogg")]
+            "ogg" | "oga" => Ok(OutputFormat::Vorbis),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(OutputFormat::Opus),
+            #[cfg(feature = "mp3")]
+            "mp3" => Ok(OutputFormat::Mp3),
+            _ => {
+                let hint = match ext.as_str() {
+                    "ogg" | "oga" => " (rebuild with the `ogg` feature)",
+                    "opus" => " (rebuild with the `opus` feature)",
+                    "mp3" => " (rebuild with the `mp3` feature)",
+                    _ => "",
+                };
+
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Unsupported output format \"{ext}\"{hint}"),
+                ))
+            }
+        }
+    }
+
+    /// File extension for the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            #[cfg(feature = "ogg")]
+            OutputFormat::Vorbis => "ogg",
+            #[cfg(feature = "opus")]
+            OutputFormat::Opus => "opus",
+            #[cfg(feature = "mp3")]
+            OutputFormat::Mp3 => "mp3",
+        }
+    }
+
+    /// Media type of the format, for the Content-Type of HTTP streams
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "audio/wav",
+            #[cfg(feature = "ogg")]
+            OutputFormat::Vorbis => "audio/ogg",
+            #[cfg(feature = "opus")]
+            OutputFormat::Opus => "audio/ogg; codecs=opus",
+            #[cfg(feature = "mp3")]
+            OutputFormat::Mp3 => "audio/mpeg",
+        }
+    }
+
+    /// Whether the format has tags for the gain that normalizes its loudness, which
+    /// [`create_sink`] writes when the tags have the loudness
+    pub fn has_gain_tags(self) -> bool {
+        match self {
+            OutputFormat::Wav => false,
+            #[cfg(feature = "ogg")]
+            OutputFormat::Vorbis => true,
+            #[cfg(feature = "opus")]
+            OutputFormat::Opus => true,
+            #[cfg(feature = "mp3")]
+            OutputFormat::Mp3 => false,
+        }
+    }
+
+    /// Type CUE sheets give files of the format. They only know of a few formats, players
+    /// read the others as WAVE
+    pub fn cue_file_type(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "WAVE",
+            #[cfg(feature = "ogg")]
+            OutputFormat::Vorbis => "WAVE",
+            #[cfg(feature = "opus")]
+            OutputFormat::Opus => "WAVE",
+            #[cfg(feature = "mp3")]
+            OutputFormat::Mp3 => "MP3",
+        }
+    }
+
+    /// Sample rate the format has to be rendered at, some encoders only support specific rates
+    pub fn required_sample_rate(self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "opus")]
+            OutputFormat::Opus => Some(opus::OPUS_SAMPLE_RATE),
+            _ => None,
+        }
+    }
+}
+
+/// Creates an encoder writing to `path`, with `tags` in the file's metadata. Formats with
+/// gain tags get the ReplayGain or R128 gain for the loudness of the tags, which has to be
+/// measured before the audio is encoded
+pub fn create_sink(
+    path: &Path,
+    format: OutputFormat,
+    sample_rate: u32,
+    channels: u16,
+    wav_format: WavFormat,
+    tags: &Tags,
+) -> io::Result<Box<dyn AudioSink>> {
+    let file = BufWriter::new(File::create(path)?);
+
+    if format != OutputFormat::Wav {
+        return create_stream_sink(file, format, sample_rate, channels, wav_format, tags);
+    }
+
+    // Files can be seeked, to write the sizes and the tags after the audio
+    let mut wav = WavWriter::new(file, sample_rate, channels, wav_format)?;
+    for (id, text) in tags.riff_info() {
+        wav.add_info(id, &text);
+    }
+    Ok(Box::new(wav))
+}
+
+/// Creates an encoder writing to `writer` as it goes, e.g. to stream the audio over the
+/// network. WAV is written without tags, with a header for a stream of unknown length, see
+/// [`wav::WavStream`]. Otherwise like [`create_sink`]
+#[cfg_attr(not(any(feature = "ogg", feature = "opus")), allow(unused_variables))]
+pub fn create_stream_sink<W: Write + 'static>(
+    writer: W,
+    format: OutputFormat,
+    sample_rate: u32,
+    channels: u16,
+    wav_format: WavFormat,
+    tags: &Tags,
+) -> io::Result<Box<dyn AudioSink>> {
+    let sink: Box<dyn AudioSink> = match format {
+        OutputFormat::Wav => Box::new(WavStream::new(writer, sample_rate, channels, wav_format)?),
+        #[cfg(feature = "ogg")]
+        OutputFormat::Vorbis => Box::new(vorbis::VorbisSink::new(
+            writer,
+            sample_rate,
+            channels,
+            tags,
+        )?),
+        #[cfg(feature = "opus")]
+        OutputFormat::Opus => Box::new(opus::OpusSink::new(writer, sample_rate, channels, tags)?),
+        #[cfg(feature = "mp3")]
+        OutputFormat::Mp3 => Box::new(mp3::Mp3Sink::new(writer, sample_rate, channels)?),
+    };
+
+    Ok(sink)
+}
+
+/// Renders the whole song into `sink`, without touching the audio device. Returns how many
+/// frames were rendered
+pub fn render(engine: &mut Engine, sink: &mut dyn AudioSink) -> io::Result<u64> {
+    let channels = engine.channel_count() as usize;
+
+    let mut buffer = vec![0.0f32; RENDER_CHUNK_FRAMES * channels];
+    let mut rendered = 0;
+    while !engine.is_finished() {
+        let frames = engine.render(&mut buffer);
+        sink.write_samples(&buffer[..frames * channels])?;
+        rendered += frames as u64;
+    }
+
+    Ok(rendered)
+}
+
+/// Where things happen in a render, in frames from its start
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Markers {
+    /// The first frame of every order played, and the order
+    pub orders: Vec<(u32, usize)>,
+    /// The first and last frame of the part that repeats when the song loops, if the song
+    /// got to where it loops
+    pub song_loop: Option<(u32, u32)>,
+    /// Frames rendered
+    pub frames: u32,
+}
+
+/// Renders the whole song into `sink` like [`render`], noting the frames every order starts
+/// at and the loop of the song.
+///
+/// The song loops from the first time it reaches its [loop point](TrackerEngine::loop_point)
+/// up to where it ends or jumps back there.
+pub fn render_with_markers(engine: &mut Engine, sink: &mut dyn AudioSink) -> io::Result<Markers> {
+    let channels = engine.channel_count() as usize;
+    let loop_point = engine.loop_point();
+    let order_count = engine.song().metadata().orders().len();
+
+    let mut buffer = vec![0.0f32; RENDER_CHUNK_FRAMES * channels];
+    let mut markers = Markers::default();
+    let mut frame = 0u32;
+    let mut order = None;
+    let mut loop_start = None;
+
+    while !engine.is_finished() {
+        // Positions only change between ticks, a new row starts at this frame
+        if engine.samples_since_tick() == 0 {
+            let position = engine.position();
+            // Past the last order once the song ended
+            if order != Some(position.0) && position.0 < order_count {
+                order = Some(position.0);
+                markers.orders.push((frame, position.0));
+            }
+
+            if engine.times_played() == 0 && position == loop_point {
+                loop_start.get_or_insert(frame);
+            } else if engine.times_played() > 0 && markers.song_loop.is_none() {
+                markers.song_loop = loop_start.map(|start| (start, frame.saturating_sub(1)));
+            }
+        }
+
+        // Rendering the first frame of a tick processes it, after which its length is known
+        let frames = match engine.samples_since_tick() {
+            0 => 1,
+            done => engine.samples_per_tick().saturating_sub(done),
+        };
+        let frames = frames.clamp(1, RENDER_CHUNK_FRAMES);
+        let rendered = engine.render(&mut buffer[..frames * channels]);
+        sink.write_samples(&buffer[..rendered * channels])?;
+        frame += rendered as u32;
+    }
+
+    // The song ended without jumping back
+    if markers.song_loop.is_none() {
+        markers.song_loop = loop_start.map(|start| (start, frame.saturating_sub(1)));
+    }
+    markers.frames = frame;
+
+    Ok(markers)
+}
+
+/// Creates a sink writing headerless PCM to stdout
+pub fn create_stdout_sink(format: RawFormat) -> Box<dyn AudioSink> {
+    Box::new(RawSink::new(BufWriter::new(io::stdout()), format))
+}
@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+use mp3lame_encoder::{Builder, Encoder, FlushNoGap, InterleavedPcm, Quality};
+
+use super::AudioSink;
+
+/// Encodes interleaved stereo audio into an MP3 stream using LAME
+pub struct Mp3Sink<W: Write> {
+    writer: W,
+    encoder: Encoder,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Mp3Sink<W> {
+    pub fn new(writer: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        if channels != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "MP3 export only supports stereo output",
+            ));
+        }
+
+        let mut builder = Builder::new()
+            .ok_or_else(|| io::Error::other("Failed to initialize the LAME encoder"))?;
+
+        builder.set_num_channels(2).map_err(io::Error::other)?;
+        builder
+            .set_sample_rate(sample_rate)
+            .map_err(io::Error::other)?;
+        builder
+            .set_quality(Quality::NearBest)
+            .map_err(io::Error::other)?;
+
+        Ok(Mp3Sink {
+            writer,
+            encoder: builder.build().map_err(io::Error::other)?,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> AudioSink for Mp3Sink<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.buffer.clear();
+        self.buffer
+            .reserve(mp3lame_encoder::max_required_buffer_size(samples.len() / 2));
+
+        self.encoder
+            .encode_to_vec(InterleavedPcm(samples), &mut self.buffer)
+            .map_err(io::Error::other)?;
+
+        self.writer.write_all(&self.buffer)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        // LAME needs atleast 7200 bytes to flush its internal buffers
+        self.buffer.clear();
+        self.buffer.reserve(7200);
+
+        self.encoder
+            .flush_to_vec::<FlushNoGap>(&mut self.buffer)
+            .map_err(io::Error::other)?;
+
+        self.writer.write_all(&self.buffer)?;
+        self.writer.flush()
+    }
+}
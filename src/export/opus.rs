@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Channels, Encoder};
+
+use super::tags::{Tags, SOFTWARE};
+use super::AudioSink;
+
+/// Opus always runs at 48 kHz internally, and only accepts this rate for fullband audio
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+
+// 20ms frames, the default frame size for music
+const FRAME_SIZE: usize = 960;
+
+// Arbitrary, a file only contains a single logical stream
+const STREAM_SERIAL: u32 = 0x5275_7374;
+
+/// Encodes interleaved stereo audio into an Ogg Opus stream
+pub struct OpusSink<W: Write> {
+    writer: PacketWriter<'static, W>,
+    encoder: Encoder,
+
+    // Samples waiting for a full frame
+    pending: Vec<f32>,
+    // Granule position of the last packet, in 48 kHz samples
+    granule: u64,
+}
+
+impl<W: Write> OpusSink<W> {
+    /// `tags` are written as comments, with the R128 gain for their loudness if it's given
+    pub fn new(writer: W, sample_rate: u32, channels: u16, tags: &Tags) -> io::Result<Self> {
+        if sample_rate != OPUS_SAMPLE_RATE || channels != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Opus export requires 48 kHz stereo output",
+            ));
+        }
+
+        let mut encoder = Encoder::new(OPUS_SAMPLE_RATE, Channels::Stereo, Application::Audio)
+            .map_err(io::Error::other)?;
+        let pre_skip = encoder.get_lookahead().map_err(io::Error::other)? as u16;
+
+        let mut writer = PacketWriter::new(writer);
+
+        // Identification header, see RFC 7845 section 5.1
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // Version
+        head.push(channels as u8);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+        head.push(0); // Channel mapping family
+        writer.write_packet(head, STREAM_SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        // Comment header, see RFC 7845 section 5.2
+        let mut header = Vec::new();
+        header.extend_from_slice(b"OpusTags");
+        header.extend_from_slice(&(SOFTWARE.len() as u32).to_le_bytes());
+        header.extend_from_slice(SOFTWARE.as_bytes());
+        let mut comments = tags.vorbis_comments();
+        comments.extend((tags.loudness.iter()).flat_map(|loudness| loudness.r128_tags()));
+        header.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for (key, value) in comments {
+            let comment = format!("{key}={value}");
+            header.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            header.extend_from_slice(comment.as_bytes());
+        }
+        writer.write_packet(header, STREAM_SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(OpusSink {
+            writer,
+            encoder,
+            pending: Vec::new(),
+            granule: pre_skip as u64,
+        })
+    }
+
+    // `frames` is the amount of real (non-padding) frames in `frame`
+    fn encode_frame(
+        &mut self,
+        frame: &[f32],
+        frames: usize,
+        end: PacketWriteEndInfo,
+    ) -> io::Result<()> {
+        let mut packet = vec![0u8; 4000];
+        let size = self
+            .encoder
+            .encode_float(frame, &mut packet)
+            .map_err(io::Error::other)?;
+        packet.truncate(size);
+
+        self.granule += frames as u64;
+        self.writer
+            .write_packet(packet, STREAM_SERIAL, end, self.granule)
+    }
+}
+
+impl<W: Write> AudioSink for OpusSink<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.pending.extend_from_slice(samples);
+
+        let frame_len = FRAME_SIZE * 2;
+        while self.pending.len() > frame_len {
+            let frame: Vec<f32> = self.pending.drain(..frame_len).collect();
+            self.encode_frame(&frame, FRAME_SIZE, PacketWriteEndInfo::NormalPacket)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        // Pad the last frame with silence, the granule position marks where the audio ends
+        let remaining = self.pending.len() / 2;
+        let mut frame = std::mem::take(&mut self.pending);
+        frame.resize(FRAME_SIZE * 2, 0.0);
+
+        self.encode_frame(&frame, remaining, PacketWriteEndInfo::EndStream)?;
+
+        self.writer.inner_mut().flush()
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use super::AudioSink;
+
+/// Sample encoding for headerless PCM output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    /// Unsigned 8-bit
+    U8,
+    /// Signed 16-bit little endian
+    S16Le,
+    /// Signed 16-bit big endian
+    S16Be,
+    /// 32-bit float little endian
+    F32Le,
+}
+
+impl FromStr for RawFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // Names match the ones used by sox/ffmpeg/aplay
+        match value.to_ascii_lowercase().as_str() {
+            "u8" => Ok(RawFormat::U8),
+            "s16le" | "s16" => Ok(RawFormat::S16Le),
+            "s16be" => Ok(RawFormat::S16Be),
+            "f32le" | "f32" => Ok(RawFormat::F32Le),
+            _ => Err(format!(
+                "Unknown sample format {value}, expected u8, s16le, s16be or f32le"
+            )),
+        }
+    }
+}
+
+/// Writes interleaved samples without any header, e.g. to pipe into other tools
+pub struct RawSink<W: Write> {
+    writer: W,
+    format: RawFormat,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> RawSink<W> {
+    pub fn new(writer: W, format: RawFormat) -> Self {
+        RawSink {
+            writer,
+            format,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> AudioSink for RawSink<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.buffer.clear();
+
+        for &sample in samples {
+            let sample = sample.clamp(-1.0, 1.0);
+
+            match self.format {
+                RawFormat::U8 => self
+                    .buffer
+                    .push((sample * 127.0).round() as i8 as u8 ^ 0x80),
+                RawFormat::S16Le => {
+                    let value = (sample * i16::MAX as f32).round() as i16;
+                    self.buffer.extend_from_slice(&value.to_le_bytes());
+                }
+                RawFormat::S16Be => {
+                    let value = (sample * i16::MAX as f32).round() as i16;
+                    self.buffer.extend_from_slice(&value.to_be_bytes());
+                }
+                RawFormat::F32Le => self.buffer.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+
+        self.writer.write_all(&self.buffer)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
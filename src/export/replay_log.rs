@@ -0,0 +1,108 @@
+//! Logs of what every channel does on every tick, to compare how a song plays with how other
+//! players play it
+
+use std::io::{self, Write};
+
+use super::RENDER_CHUNK_FRAMES;
+use crate::engine::{Engine, TrackerEngine};
+
+/// Plays the rest of the song `engine` plays, writing a line for every tick with the state
+/// every channel is mixed in.
+///
+/// Lines are tab separated: order, row and tick, then period, volume, sample number and
+/// position in the sample for each channel. Positions depend on the sample rate and mixer,
+/// logs meant to be compared are written with the same ones. Lines starting with `#` describe
+/// the log.
+pub fn write_replay_log(engine: &mut Engine, mut writer: impl Write) -> io::Result<()> {
+    let channels = engine.channel_count().max(1) as usize;
+    let tracker_channels = engine.song().metadata().channels();
+
+    writeln!(writer, "# {}", engine.song().metadata().title().trim_end())?;
+    writeln!(
+        writer,
+        "# {tracker_channels} channels, positions at {} Hz",
+        engine.sample_rate()
+    )?;
+    write!(writer, "# order\trow\ttick")?;
+    for channel in 1..=tracker_channels {
+        write!(
+            writer,
+            "\tperiod {channel}\tvolume {channel}\tsample {channel}\tposition {channel}"
+        )?;
+    }
+    writeln!(writer)?;
+
+    let mut buffer = vec![0.0f32; RENDER_CHUNK_FRAMES * channels];
+    loop {
+        // The position and tick move on while the tick is processed
+        let (order, row) = engine.position();
+        let tick = engine.tick();
+        engine.next_tick();
+        if engine.is_finished() {
+            break;
+        }
+
+        write!(writer, "{order}\t{row}\t{tick}")?;
+        for channel in 0..tracker_channels {
+            let snapshot = engine.channel_snapshot(channel);
+            write!(
+                writer,
+                "\t{}\t{}\t{}\t{}",
+                snapshot.period, snapshot.volume, snapshot.sample, snapshot.position
+            )?;
+        }
+        writeln!(writer)?;
+
+        // Mixing the tick moves the positions on to where the next one starts
+        let mut frames = engine.samples_per_tick().max(1);
+        while frames > 0 {
+            let chunk = frames.min(RENDER_CHUNK_FRAMES);
+            engine.get_audio_buffer(&mut buffer[..chunk * channels]);
+            frames -= chunk;
+        }
+    }
+
+    writer.flush()
+}
+
+#[test]
+fn ticks_are_logged() {
+    use crate::song::{Note, PCMData, SongBuilder};
+
+    let song = SongBuilder::new(4)
+        .title("log")
+        .sample("square", PCMData::I8(vec![64; 1000]))
+        .empty_pattern()
+        .note(
+            0,
+            0,
+            1,
+            Note {
+                sample: 1,
+                period: 428,
+                effect: 0xF,
+                argument: 2,
+            },
+        )
+        .build()
+        .unwrap();
+    let mut engine = Engine::new(song).unwrap();
+    engine.set_channel_count(2);
+    engine.set_sample_rate(8000);
+
+    let mut log = Vec::new();
+    write_replay_log(&mut engine, &mut log).unwrap();
+    let log = String::from_utf8(log).unwrap();
+    let lines: Vec<&str> = log.lines().filter(|line| !line.starts_with('#')).collect();
+
+    // 64 rows of 2 ticks
+    assert_eq!(lines.len(), 128);
+    assert_eq!(
+        lines[0],
+        "0\t0\t0\t0\t64\t0\t0\t428\t64\t1\t0\t0\t64\t0\t0\t0\t64\t0\t0"
+    );
+    // A tick lasts 160 frames, C-2 plays 8287 Hz at PAL
+    let second: Vec<&str> = lines[1].split('\t').collect();
+    assert_eq!(&second[..3], ["0", "0", "1"]);
+    assert!((160..=170).contains(&second[10].parse::<u32>().unwrap()));
+}
@@ -0,0 +1,80 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use super::wav::{WavFormat, WavWriter};
+use crate::song::{PCMData, Song};
+
+// Playback rate of a sample at C-2 with finetune 0, the usual tracker convention
+const C2_SAMPLE_RATE: f64 = 8363.0;
+
+/// Writes every non-empty sample of `song` to `dir` as a mono WAV file.
+///
+/// Finetune is applied to the sample rate of the file, and looped samples get a
+/// `smpl` chunk with their loop points. Returns the paths of the written files.
+pub fn export_samples(song: &Song, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+
+    let mut written = Vec::new();
+
+    for (index, (sample, pcm)) in song.metadata.samples.iter().zip(&song.samples).enumerate() {
+        let (data, format): (Vec<f32>, _) = match pcm.as_ref() {
+            PCMData::I8(data) => (
+                data.iter().map(|&s| s as f32 / 128.0).collect(),
+                WavFormat::Int8,
+            ),
+            PCMData::I16(data) => (
+                data.iter().map(|&s| s as f32 / 32768.0).collect(),
+                WavFormat::Int16,
+            ),
+        };
+
+        if data.is_empty() {
+            continue;
+        }
+
+        // Finetune is in 1/8th semitones
+        let sample_rate = C2_SAMPLE_RATE * 2f64.powf(sample.finetune as f64 / 96.0);
+
+        let path = dir.join(sample_file_name(index + 1, &sample.name));
+        let mut wav = WavWriter::new(
+            BufWriter::new(File::create(&path)?),
+            sample_rate.round() as u32,
+            1,
+            format,
+        )?;
+        wav.write_samples(&data)?;
+
+        let loop_start = sample.repeat_offset as usize;
+        let loop_end = (loop_start + sample.repeat_length as usize).min(data.len());
+        if sample.repeat_length > 0 && loop_start < loop_end {
+            wav.add_loop(loop_start as u32, loop_end as u32 - 1);
+        }
+
+        wav.finish()?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+// "01 name.wav", with characters that aren't safe in file names replaced
+fn sample_file_name(number: usize, name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || " -_.()".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if name.is_empty() {
+        format!("{number:02}.wav")
+    } else {
+        format!("{number:02} {name}.wav")
+    }
+}
@@ -0,0 +1,107 @@
+//! Metadata written into exported files, so they can be told apart and traced back to the
+//! module they were rendered from
+
+use super::loudness::Loudness;
+use crate::song::SongMetadata;
+
+/// The program exported files are written by
+pub const SOFTWARE: &str = concat!("Rustune ", env!("CARGO_PKG_VERSION"));
+
+/// What's known about the audio of an exported file. Every encoder writes it in the form its
+/// format has for it, a [`Tags::default`] only names the software.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tags {
+    /// Title of the song
+    pub title: Option<String>,
+    /// The tracker the song was made with
+    pub tracker: Option<String>,
+    /// Names of the samples, musicians used them for messages and greetings
+    pub sample_names: Vec<String>,
+    /// How loud the audio is, for the formats with tags for the gain that normalizes it
+    pub loudness: Option<Loudness>,
+}
+
+impl Tags {
+    /// The title, tracker and sample names of a song. Samples without a name are left out
+    pub fn from_song(metadata: &SongMetadata) -> Self {
+        let title = metadata.title().trim();
+
+        Tags {
+            title: (!title.is_empty()).then(|| title.to_owned()),
+            tracker: Some(metadata.tracker().to_string()),
+            sample_names: (metadata.instruments().iter())
+                .map(|sample| sample.name.trim_end().to_owned())
+                .filter(|name| !name.trim().is_empty())
+                .collect(),
+            loudness: None,
+        }
+    }
+
+    /// Vorbis comments for Ogg Vorbis and Opus files. Every sample name is a comment of its own,
+    /// the gain tags are up to the encoder
+    pub fn vorbis_comments(&self) -> Vec<(&'static str, String)> {
+        let mut comments = Vec::new();
+        if let Some(title) = &self.title {
+            comments.push(("TITLE", title.clone()));
+        }
+        if let Some(tracker) = &self.tracker {
+            comments.push(("TRACKER", tracker.clone()));
+        }
+        comments.extend((self.sample_names.iter()).map(|name| ("COMMENT", name.clone())));
+        comments.push(("ENCODER", SOFTWARE.to_owned()));
+        comments
+    }
+
+    /// Entries of the `INFO` list of WAV files. The tracker and sample names share the
+    /// comment, a line each
+    pub fn riff_info(&self) -> Vec<(&'static [u8; 4], String)> {
+        let mut info = Vec::new();
+        if let Some(title) = &self.title {
+            info.push((b"INAM", title.clone()));
+        }
+
+        let comment: Vec<String> = (self.tracker.iter())
+            .map(|tracker| format!("Made with {tracker}"))
+            .chain(self.sample_names.iter().cloned())
+            .collect();
+        if !comment.is_empty() {
+            info.push((b"ICMT", comment.join("\n")));
+        }
+
+        info.push((b"ISFT", SOFTWARE.to_owned()));
+        info
+    }
+}
+
+#[test]
+fn song_tags() {
+    use crate::song::{PCMData, SongBuilder};
+
+    let song = SongBuilder::new(4)
+        .title("tagged  ")
+        .sample("bass", PCMData::I8(vec![0; 2]))
+        .sample("", PCMData::I8(vec![0; 2]))
+        .sample("greets to all", PCMData::I8(vec![0; 2]))
+        .empty_pattern()
+        .build()
+        .unwrap();
+    let tags = Tags::from_song(song.metadata());
+
+    assert_eq!(tags.title.as_deref(), Some("tagged"));
+    assert_eq!(tags.sample_names, ["bass", "greets to all"]);
+
+    let comments = tags.vorbis_comments();
+    assert_eq!(comments[0], ("TITLE", "tagged".to_owned()));
+    assert_eq!(
+        comments.iter().filter(|(key, _)| *key == "COMMENT").count(),
+        2
+    );
+
+    let info = tags.riff_info();
+    assert_eq!(info[0], (b"INAM", "tagged".to_owned()));
+    assert!(info[1].1.ends_with("\nbass\ngreets to all"));
+    assert_eq!(
+        Tags::default().riff_info(),
+        [(b"ISFT", SOFTWARE.to_owned())]
+    );
+}
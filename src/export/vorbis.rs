@@ -0,0 +1,59 @@
+use std::io::{self, Write};
+use std::num::{NonZeroU32, NonZeroU8};
+
+use vorbis_rs::{VorbisEncoder, VorbisEncoderBuilder};
+
+use super::tags::Tags;
+use super::AudioSink;
+
+/// Encodes interleaved audio into an Ogg Vorbis stream
+pub struct VorbisSink<W: Write> {
+    encoder: VorbisEncoder<W>,
+    channels: usize,
+}
+
+impl<W: Write> VorbisSink<W> {
+    /// `tags` are written as Vorbis comments, with the ReplayGain tags for their loudness if
+    /// it's given
+    pub fn new(writer: W, sample_rate: u32, channels: u16, tags: &Tags) -> io::Result<Self> {
+        let rate = NonZeroU32::new(sample_rate).ok_or(io::ErrorKind::InvalidInput)?;
+        let channel_count = NonZeroU8::new(channels as u8).ok_or(io::ErrorKind::InvalidInput)?;
+
+        let mut builder =
+            VorbisEncoderBuilder::new(rate, channel_count, writer).map_err(io::Error::other)?;
+        let gain = (tags.loudness.iter()).flat_map(|loudness| loudness.replaygain_tags());
+        builder
+            .comment_tags(tags.vorbis_comments().into_iter().chain(gain))
+            .map_err(io::Error::other)?;
+        let encoder = builder.build().map_err(io::Error::other)?;
+
+        Ok(VorbisSink {
+            encoder,
+            channels: channels as usize,
+        })
+    }
+}
+
+impl<W: Write> AudioSink for VorbisSink<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        // libvorbis takes planar audio
+        let mut planar = vec![Vec::with_capacity(samples.len() / self.channels); self.channels];
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in planar.iter_mut().zip(frame) {
+                channel.push(sample);
+            }
+        }
+
+        self.encoder
+            .encode_audio_block(planar)
+            .map_err(io::Error::other)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.encoder.finish().map_err(io::Error::other)?.flush()
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::AudioSink;
+
+/// Sample encoding used for the data chunk of a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavFormat {
+    /// Unsigned 8-bit PCM
+    Int8,
+    /// Signed 16-bit PCM
+    Int16,
+    /// 32-bit IEEE float
+    Float32,
+}
+
+impl WavFormat {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavFormat::Int8 => 1,
+            WavFormat::Int16 => 2,
+            WavFormat::Float32 => 4,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            WavFormat::Int8 | WavFormat::Int16 => 1, // WAVE_FORMAT_PCM
+            WavFormat::Float32 => 3,                 // WAVE_FORMAT_IEEE_FLOAT
+        }
+    }
+}
+
+/// Streams interleaved `f32` audio into a RIFF/WAVE file.
+///
+/// The chunk sizes are unknown until all audio has been written, so they are
+/// patched in by [`WavWriter::finish`].
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: WavFormat,
+    sample_rate: u32,
+    data_size: u32,
+
+    // Forward loops as (first frame, last frame), written to a `smpl` chunk
+    loops: Vec<(u32, u32)>,
+    // Markers as (frame, label), written to `cue ` and `LIST` chunks
+    cues: Vec<(u32, String)>,
+    // Text about the file as (chunk id, text), written to an `INFO` list
+    info: Vec<([u8; 4], String)>,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the WAV header and returns a writer ready to accept audio.
+    ///
+    /// # Arguments
+    /// * `writer` - The destination, usually a [`std::fs::File`]
+    /// * `sample_rate` - Frames per second
+    /// * `channels` - The amount of interleaved channels per frame
+    /// * `format` - The sample encoding, see [`WavFormat`]
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        channels: u16,
+        format: WavFormat,
+    ) -> io::Result<Self> {
+        // The sizes are patched in `finish`
+        write_header(&mut writer, sample_rate, channels, format, 0)?;
+
+        Ok(WavWriter {
+            writer,
+            format,
+            sample_rate,
+            data_size: 0,
+            loops: Vec::new(),
+            cues: Vec::new(),
+            info: Vec::new(),
+        })
+    }
+
+    /// Appends interleaved samples in the range [-1.0, 1.0]
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bytes = encode(self.format, samples);
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+
+        Ok(())
+    }
+
+    /// Adds a forward loop from `start` up to and including `end`, both in frames.
+    ///
+    /// Loops are stored in a `smpl` chunk after the audio, which samplers and
+    /// most audio editors use as the sustain loop.
+    pub fn add_loop(&mut self, start: u32, end: u32) {
+        self.loops.push((start, end));
+    }
+
+    /// Adds a marker at `frame` named `label`.
+    ///
+    /// Markers are stored as cue points in a `cue ` chunk, with their labels in an
+    /// associated data list, which audio editors and game engines show as regions.
+    pub fn add_cue(&mut self, frame: u32, label: &str) {
+        self.cues.push((frame, label.to_owned()));
+    }
+
+    /// Adds text about the file, e.g. `INAM` for its title or `ICMT` for a comment.
+    ///
+    /// The text is stored in a `LIST` chunk of type `INFO` after the audio.
+    pub fn add_info(&mut self, id: &[u8; 4], text: &str) {
+        self.info.push((*id, text.to_owned()));
+    }
+
+    /// Patches the chunk sizes in the header and flushes the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        // The data chunk has to be padded to an even size
+        if self.data_size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+
+        let smpl_size = self.write_smpl_chunk()?;
+        let cue_size = self.write_cue_chunks()?;
+        let info_size = self.write_info_chunk()?;
+        let riff_size = 4
+            + (8 + 16)
+            + (8 + self.data_size + self.data_size % 2)
+            + smpl_size
+            + cue_size
+            + info_size;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&riff_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    // Writes the `smpl` chunk if there are any loops, returns the amount of bytes written
+    fn write_smpl_chunk(&mut self) -> io::Result<u32> {
+        if self.loops.is_empty() {
+            return Ok(0);
+        }
+
+        let size = 36 + 24 * self.loops.len() as u32;
+        let sample_period = 1_000_000_000 / self.sample_rate.max(1);
+
+        let mut chunk = Vec::with_capacity(8 + size as usize);
+        chunk.extend_from_slice(b"smpl");
+        chunk.extend_from_slice(&size.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // Manufacturer
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // Product
+        chunk.extend_from_slice(&sample_period.to_le_bytes()); // Nanoseconds per frame
+        chunk.extend_from_slice(&60u32.to_le_bytes()); // MIDI unity note, middle C
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // MIDI pitch fraction
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // SMPTE format
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // SMPTE offset
+        chunk.extend_from_slice(&(self.loops.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // Sampler data size
+
+        for (id, &(start, end)) in self.loops.iter().enumerate() {
+            chunk.extend_from_slice(&(id as u32).to_le_bytes()); // Cue point id
+            chunk.extend_from_slice(&0u32.to_le_bytes()); // Forward loop
+            chunk.extend_from_slice(&start.to_le_bytes());
+            chunk.extend_from_slice(&end.to_le_bytes());
+            chunk.extend_from_slice(&0u32.to_le_bytes()); // Fraction
+            chunk.extend_from_slice(&0u32.to_le_bytes()); // Play count, 0 is infinite
+        }
+
+        self.writer.write_all(&chunk)?;
+        Ok(chunk.len() as u32)
+    }
+
+    // Writes the `cue ` chunk and the `LIST` chunk with its labels if there are any markers,
+    // returns the amount of bytes written
+    fn write_cue_chunks(&mut self) -> io::Result<u32> {
+        if self.cues.is_empty() {
+            return Ok(0);
+        }
+
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(b"cue ");
+        chunk.extend_from_slice(&(4 + 24 * self.cues.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&(self.cues.len() as u32).to_le_bytes());
+
+        // Cue point ids start at 1, the labels refer to them
+        for (id, &(frame, _)) in (1u32..).zip(&self.cues) {
+            chunk.extend_from_slice(&id.to_le_bytes());
+            chunk.extend_from_slice(&frame.to_le_bytes()); // Position in the playlist
+            chunk.extend_from_slice(b"data"); // Chunk the cue is in
+            chunk.extend_from_slice(&0u32.to_le_bytes()); // Chunk start
+            chunk.extend_from_slice(&0u32.to_le_bytes()); // Block start
+            chunk.extend_from_slice(&frame.to_le_bytes()); // Sample offset
+        }
+
+        let mut labels = Vec::new();
+        labels.extend_from_slice(b"adtl");
+        for (id, (_, label)) in (1u32..).zip(&self.cues) {
+            // The text ends with a null byte, and is padded to an even size
+            let size = 4 + label.len() as u32 + 1;
+            labels.extend_from_slice(b"labl");
+            labels.extend_from_slice(&size.to_le_bytes());
+            labels.extend_from_slice(&id.to_le_bytes());
+            labels.extend_from_slice(label.as_bytes());
+            labels.push(0);
+            if size % 2 == 1 {
+                labels.push(0);
+            }
+        }
+        chunk.extend_from_slice(b"LIST");
+        chunk.extend_from_slice(&(labels.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&labels);
+
+        self.writer.write_all(&chunk)?;
+        Ok(chunk.len() as u32)
+    }
+
+    // Writes the `INFO` list if there's any text, returns the amount of bytes written
+    fn write_info_chunk(&mut self) -> io::Result<u32> {
+        if self.info.is_empty() {
+            return Ok(0);
+        }
+
+        let mut list = Vec::new();
+        list.extend_from_slice(b"INFO");
+        for (id, text) in &self.info {
+            // Like the labels, the text ends with a null byte and is padded to an even size
+            let size = text.len() as u32 + 1;
+            list.extend_from_slice(id);
+            list.extend_from_slice(&size.to_le_bytes());
+            list.extend_from_slice(text.as_bytes());
+            list.push(0);
+            if size % 2 == 1 {
+                list.push(0);
+            }
+        }
+
+        let mut chunk = Vec::with_capacity(8 + list.len());
+        chunk.extend_from_slice(b"LIST");
+        chunk.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&list);
+
+        self.writer.write_all(&chunk)?;
+        Ok(chunk.len() as u32)
+    }
+}
+
+/// Streams interleaved `f32` audio as WAV to a writer that can't seek, like a network
+/// connection.
+///
+/// The header can't be patched once the audio is written, so it claims the largest sizes
+/// there are, which players take as a stream of unknown length.
+pub struct WavStream<W: Write> {
+    writer: W,
+    format: WavFormat,
+}
+
+impl<W: Write> WavStream<W> {
+    /// Writes the WAV header and returns a stream ready to accept audio, see
+    /// [`WavWriter::new`] for the arguments
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        channels: u16,
+        format: WavFormat,
+    ) -> io::Result<Self> {
+        write_header(&mut writer, sample_rate, channels, format, u32::MAX)?;
+        Ok(WavStream { writer, format })
+    }
+}
+
+impl<W: Write> AudioSink for WavStream<W> {
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.writer.write_all(&encode(self.format, samples))
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Writes the RIFF header with the `fmt ` chunk and the start of the `data` chunk, which is
+// `data_size` bytes. The RIFF chunk is as large as that allows
+fn write_header<W: Write>(
+    writer: &mut W,
+    sample_rate: u32,
+    channels: u16,
+    format: WavFormat,
+    data_size: u32,
+) -> io::Result<()> {
+    let block_align = channels * format.bytes_per_sample();
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&data_size.saturating_add(36).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&format.format_tag().to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&(format.bytes_per_sample() * 8).to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
+
+// Interleaved samples in the range [-1.0, 1.0] as the bytes of the data chunk
+fn encode(format: WavFormat, samples: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * format.bytes_per_sample() as usize);
+
+    for &sample in samples {
+        match format {
+            WavFormat::Int8 => {
+                // 8-bit WAV data is unsigned, centered around 128
+                let value = (sample.clamp(-1.0, 1.0) * 128.0).round().min(127.0) as i8;
+                bytes.push(value as u8 ^ 0x80);
+            }
+            WavFormat::Int16 => {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            WavFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+
+    bytes
+}
@@ -0,0 +1,288 @@
+//! Overview images of the amplitude of rendered songs, for thumbnails and indexes
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use super::RENDER_CHUNK_FRAMES;
+use crate::archive::crc32;
+use crate::engine::{Engine, ScopeBuffer, TrackerEngine};
+
+// Frames every peak is measured over, before they're narrowed down to the width of the image
+const BLOCK_FRAMES: usize = 256;
+
+// The image has 4 colors: the background, the zero line, the peaks and the loudness (RMS)
+// inside them
+const PALETTE: [[u8; 3]; 4] = [
+    [0x10, 0x12, 0x1A],
+    [0x3A, 0x3F, 0x4F],
+    [0x2E, 0x7D, 0xB8],
+    [0x8F, 0xD0, 0xFF],
+];
+const BACKGROUND: u8 = 0;
+const ZERO_LINE: u8 = 1;
+const PEAK: u8 = 2;
+const LOUDNESS: u8 = 3;
+
+/// Amplitude of a block of frames
+#[derive(Debug, Clone, Copy, Default)]
+struct Block {
+    min: f32,
+    max: f32,
+    squares: f32,
+    frames: u32,
+}
+
+impl Block {
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.squares += value * value;
+        self.frames += 1;
+    }
+
+    fn merge(self, other: Block) -> Block {
+        Block {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            squares: self.squares + other.squares,
+            frames: self.frames + other.frames,
+        }
+    }
+
+    fn rms(&self) -> f32 {
+        (self.squares / self.frames.max(1) as f32).sqrt()
+    }
+}
+
+/// The amplitude of songs over time, drawn as an image by [`Waveform::write_png`].
+///
+/// Songs rendered one after another are appended, like a playlist rendered to one file.
+/// There's a single lane with the mix, or one lane per tracker channel.
+///
+/// ```no_run
+/// use rustune::export::waveform::Waveform;
+/// use rustune::{Engine, Song, TrackerEngine};
+///
+/// let mut engine = Engine::new(Song::new("song.mod".as_ref())?)?;
+///
+/// let mut waveform = Waveform::new(false);
+/// waveform.render(&mut engine);
+/// waveform.write_png(std::fs::File::create("song.png")?, 800, 160)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    per_channel: bool,
+    // Every lane, with the blocks measured so far
+    lanes: Vec<Vec<Block>>,
+    // Frames of all the songs rendered so far
+    frames: usize,
+}
+
+impl Waveform {
+    /// An empty waveform, with a lane for every channel when `per_channel` is set
+    pub fn new(per_channel: bool) -> Self {
+        Waveform {
+            per_channel,
+            lanes: Vec::new(),
+            frames: 0,
+        }
+    }
+
+    /// Renders the rest of the song `engine` plays and adds it to the waveform
+    pub fn render(&mut self, engine: &mut Engine) {
+        let channels = engine.channel_count() as usize;
+        let oversampling = engine.oversampling();
+
+        // The channels are recorded at the mixing rate, every chunk rendered fits
+        let scope = self.per_channel.then(|| {
+            let scope = Arc::new(ScopeBuffer::new(
+                engine.song().metadata().channels(),
+                RENDER_CHUNK_FRAMES * oversampling,
+            ));
+            engine.set_scope(Some(scope.clone()));
+            scope
+        });
+
+        let mut buffer = vec![0.0f32; RENDER_CHUNK_FRAMES * channels];
+        while !engine.is_finished() {
+            let frames = engine.render(&mut buffer);
+
+            match &scope {
+                Some(scope) => {
+                    for (lane, history) in scope.snapshot().iter().enumerate() {
+                        let recorded = &history[history.len() - frames * oversampling..];
+                        self.record(lane, recorded, oversampling);
+                    }
+                }
+                None => self.record(0, &buffer[..frames * channels], channels),
+            }
+            self.frames += frames;
+        }
+
+        if scope.is_some() {
+            engine.set_scope(None);
+        }
+    }
+
+    /// Draws the waveform into a `width` by `height` PNG image, with the lanes stacked
+    /// top to bottom
+    pub fn write_png<W: Write>(&self, mut writer: W, width: u32, height: u32) -> io::Result<()> {
+        let blocks = self.frames.div_ceil(BLOCK_FRAMES);
+        let lanes = self.lanes.len().max(1);
+        let lane_height = (height as usize / lanes).max(1);
+
+        let mut pixels = vec![BACKGROUND; width as usize * height as usize];
+        for (lane, measured) in self.lanes.iter().enumerate() {
+            let top = lane * lane_height;
+            let middle = top + lane_height / 2;
+            // Where a value from -1 to 1 is drawn, louder values are cut off
+            let y = |value: f32| {
+                let offset = (value.clamp(-1.0, 1.0) * (lane_height / 2) as f32).round() as isize;
+                (middle as isize - offset).clamp(top as isize, (top + lane_height - 1) as isize)
+                    as usize
+            };
+
+            for x in 0..width as usize {
+                // Every column covers at least one block, there may be fewer than columns
+                let first = x * blocks / width as usize;
+                let last = ((x + 1) * blocks / width as usize).max(first + 1);
+                let block = (measured.get(first..last.min(measured.len())))
+                    .unwrap_or_default()
+                    .iter()
+                    .fold(Block::default(), |total, &block| total.merge(block));
+
+                // Silence leaves the zero line as it is
+                pixels[middle * width as usize + x] = ZERO_LINE;
+                if block.min == block.max {
+                    continue;
+                }
+
+                let rms = block.rms();
+                let column = (y(block.max)..=y(block.min)).map(|row| {
+                    let inside = (y(rms)..=y(-rms)).contains(&row);
+                    (row, if inside { LOUDNESS } else { PEAK })
+                });
+                for (row, color) in column {
+                    pixels[row * width as usize + x] = color;
+                }
+            }
+        }
+
+        write_indexed_png(&mut writer, width, height, &pixels)
+    }
+
+    // Adds `values` to the blocks of `lane`, `per_frame` of them to every frame after the
+    // frames rendered before
+    fn record(&mut self, lane: usize, values: &[f32], per_frame: usize) {
+        if self.lanes.len() <= lane {
+            self.lanes.resize(lane + 1, Vec::new());
+        }
+        let blocks = &mut self.lanes[lane];
+
+        for (frame, values) in values.chunks(per_frame).enumerate() {
+            let index = (self.frames + frame) / BLOCK_FRAMES;
+            // Channels the songs before didn't have were silent until here
+            if blocks.len() <= index {
+                blocks.resize(index + 1, Block::default());
+            }
+            values.iter().for_each(|&value| blocks[index].add(value));
+        }
+    }
+}
+
+// Writes a PNG with 2 bits a pixel, which index `PALETTE`. The image data is stored without
+// compression, which is small enough at 4 pixels a byte
+fn write_indexed_png<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 2 bits deep, color type 3 (palette), the default compression, filter and no interlacing
+    header.extend_from_slice(&[2, 3, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)?;
+    write_chunk(writer, b"PLTE", PALETTE.as_flattened())?;
+
+    // Every row starts with its filter type, 0 for none
+    let mut rows = Vec::new();
+    for row in pixels.chunks(width as usize) {
+        rows.push(0);
+        rows.extend(row.chunks(4).map(|pixels| {
+            (pixels.iter().enumerate()).fold(0, |byte, (i, &pixel)| byte | pixel << (6 - 2 * i))
+        }));
+    }
+    write_chunk(writer, b"IDAT", &zlib_stored(&rows))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+// A chunk is its length, type, data and the CRC of the type and data
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32(kind.iter().chain(data));
+    writer.write_all(&crc.to_be_bytes())
+}
+
+// A zlib stream of deflate blocks that store the data as it is
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32 KiB window, and no preset dictionary
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        stream.push(last as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MODULO: u32 = 65521;
+    let (a, b) = bytes.iter().fold((1, 0), |(a, b), &byte| {
+        let a = (a + byte as u32) % MODULO;
+        (a, (b + a) % MODULO)
+    });
+    (b << 16) | a
+}
+
+#[test]
+fn png_structure() {
+    let mut png = Vec::new();
+    write_indexed_png(&mut png, 5, 2, &[0, 1, 2, 3, 0, 3, 2, 1, 0, 3]).unwrap();
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    // The CRC of an empty IEND chunk is always the same
+    assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+
+    // Both rows hold 5 pixels in 2 bytes, after their filter type
+    let rows = [
+        0,
+        0b00_01_10_11,
+        0b00_00_00_00,
+        0,
+        0b11_10_01_00,
+        0b11_00_00_00,
+    ];
+    let mut expected = vec![0x78, 0x01, 1, 6, 0, 0xF9, 0xFF];
+    expected.extend_from_slice(&rows);
+    expected.extend_from_slice(&adler32(&rows).to_be_bytes());
+    let idat = png.windows(4).position(|kind| kind == b"IDAT").unwrap();
+    assert_eq!(&png[idat + 4..idat + 4 + expected.len()], expected);
+}
@@ -0,0 +1,152 @@
+//! Telling module formats apart from the start of a file, without loading it
+
+use core::fmt;
+
+use super::mod_loader::{self, SAMPLE_HEADER_SIZE, TITLE_SIZE};
+use crate::song::SongMetadata;
+use crate::tracker::Tracker;
+
+/// A module format the library can load
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Format {
+    /// ProTracker style MOD files, with 15 or 31 samples
+    Mod,
+    /// Nothing the library can load
+    Unknown,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Format::Mod => "MOD",
+            Format::Unknown => "Unknown",
+        })
+    }
+}
+
+/// What a file looks like, see [`detect`]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Detection {
+    pub format: Format,
+    pub tracker: Tracker,
+    pub channels: u8,
+    /// How sure the detection is, from 0.0 (not a module) to 1.0 (a known format tag and a
+    /// header that makes sense)
+    pub confidence: f32,
+}
+
+impl Detection {
+    const UNKNOWN: Detection = Detection {
+        format: Format::Unknown,
+        tracker: Tracker::Generic,
+        channels: 0,
+        confidence: 0.0,
+    };
+}
+
+/// Classifies a file from its header, reading only the first 1084 bytes of `data`.
+///
+/// `data` should be the whole file, its size is used to guess the channels of modules
+/// without a format tag. Those have no magic number at all, so every file is checked for a
+/// header that makes sense: volumes, finetunes, loops and the pattern table must be in
+/// range, and the patterns and samples must fit in the file. Every check that fails halves
+/// the confidence.
+///
+/// ```
+/// use rustune::formats::detect::{detect, Format};
+///
+/// let detection = detect(b"Not a module");
+/// assert_eq!(detection.format, Format::Unknown);
+/// assert_eq!(detection.confidence, 0.0);
+/// ```
+pub fn detect(data: &[u8]) -> Detection {
+    let Ok((metadata, patterns_offset)) = mod_loader::parse_header(data, data.len()) else {
+        return Detection::UNKNOWN;
+    };
+    if metadata.channel_count == 0 {
+        return Detection::UNKNOWN;
+    }
+
+    let tagged = metadata.samples.len() == 31;
+    let base: f32 = match metadata.tracker {
+        Tracker::Generic if tagged => 0.5,
+        // Modules with 15 samples have no tag, anything long enough parses as one
+        Tracker::Generic => 0.25,
+        _ => 1.0,
+    };
+
+    let failures = failed_checks(data, &metadata, patterns_offset);
+    // Without a tag the header is all there is to go by
+    if !tagged && failures > 1 {
+        return Detection::UNKNOWN;
+    }
+
+    Detection {
+        format: Format::Mod,
+        tracker: metadata.tracker,
+        channels: metadata.channel_count,
+        confidence: base / (1 << failures.min(16)) as f32,
+    }
+}
+
+// Counts how many parts of the header hold values no tracker writes
+fn failed_checks(data: &[u8], metadata: &SongMetadata, patterns_offset: usize) -> u32 {
+    let sample_count = metadata.samples.len();
+    let mut failures = 0;
+
+    for index in 0..sample_count {
+        let offset = TITLE_SIZE + SAMPLE_HEADER_SIZE * index;
+        let finetune = data[offset + 24];
+        let volume = data[offset + 25];
+        failures += (finetune & 0xF0 != 0) as u32;
+        failures += (volume > 64) as u32;
+
+        let sample = &metadata.samples[index];
+        let loop_end = sample.repeat_offset as usize + sample.repeat_length as usize;
+        failures += (sample.repeat_length > 0 && loop_end > sample.length as usize) as u32;
+    }
+
+    // Names are padded with nulls, other control characters don't belong there
+    let text_end = TITLE_SIZE + SAMPLE_HEADER_SIZE * sample_count;
+    let control = (0..text_end)
+        .filter(|&offset| offset < TITLE_SIZE || (offset - TITLE_SIZE) % SAMPLE_HEADER_SIZE < 22)
+        .any(|offset| (1..32).contains(&data[offset]));
+    failures += control as u32;
+
+    let song_length = data[text_end];
+    failures += !(1..=128).contains(&song_length) as u32;
+    // The original trackers only had room for 64 patterns
+    let pattern_limit = if sample_count == 31 { 128 } else { 64 };
+    failures += (metadata.pattern_count as usize > pattern_limit) as u32;
+
+    let samples_size: usize = metadata.samples.iter().map(|s| s.length as usize).sum();
+    failures += (samples_size == 0) as u32;
+    let size = patterns_offset
+        + metadata.pattern_count as usize * mod_loader::pattern_size(metadata.channel_count)
+        + samples_size;
+    failures += (size > data.len()) as u32;
+
+    failures
+}
+
+#[test]
+fn detect_written_module() {
+    use crate::formats::mod_writer;
+    use crate::song::square_song;
+
+    let song = square_song(6).empty_pattern().build().unwrap();
+    let mut data = mod_writer::write(&song).unwrap();
+
+    let detection = detect(&data);
+    assert_eq!(detection.format, Format::Mod);
+    assert_eq!(detection.channels, 6);
+    assert_eq!(detection.confidence, 1.0);
+
+    // Cutting off the sample data makes it less likely to be a module
+    data.truncate(data.len() - 8);
+    assert_eq!(detect(&data).confidence, 0.5);
+
+    assert_eq!(detect(&[0; 2048]).format, Format::Unknown);
+}
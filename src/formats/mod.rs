@@ -1 +1,12 @@
+//! Parsers for the supported module formats
+
+pub mod detect;
+pub mod mod_editor;
 pub mod mod_loader;
+pub mod mod_optimizer;
+#[cfg(feature = "std")]
+pub mod mod_reader;
+pub mod mod_repair;
+pub mod mod_validator;
+pub mod mod_writer;
+pub mod xm_writer;
@@ -0,0 +1,149 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::mod_loader::{self, SAMPLE_HEADER_SIZE, TITLE_SIZE};
+use crate::bytereader::{Codepage, Encoding};
+use crate::bytewriter::ByteWriter;
+use crate::song::SongError;
+
+// The fields of a sample header that can be edited are at these offsets in it
+const SAMPLE_NAME_SIZE: usize = 22;
+const FINETUNE_OFFSET: usize = 24;
+const VOLUME_OFFSET: usize = 25;
+
+/// Changes to the title and sample headers of a MOD file. Samples count from 0
+#[derive(Debug, Clone, Default)]
+pub struct MetadataEdit {
+    /// New title of the song, up to 20 characters
+    pub title: Option<String>,
+    /// New names of samples, up to 22 characters
+    pub sample_names: Vec<(usize, String)>,
+    /// New default volumes of samples, up to 64
+    pub sample_volumes: Vec<(usize, u8)>,
+    /// New finetunes of samples in eighths of a semitone, -8 to 7
+    pub sample_finetunes: Vec<(usize, i8)>,
+}
+
+impl MetadataEdit {
+    /// Whether the edit changes nothing
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.sample_names.is_empty()
+            && self.sample_volumes.is_empty()
+            && self.sample_finetunes.is_empty()
+    }
+}
+
+/// Applies `edit` to a MOD file, changing only the bytes of the edited fields. Patterns and
+/// sample data stay as they are, so a damaged or unusual module isn't rewritten the way the
+/// loader understands it. Names are written in Latin-1, which the Amiga used.
+///
+/// # Errors
+/// When `data` isn't a MOD file, a sample doesn't exist, or a value doesn't fit its field
+pub fn edit(data: &[u8], edit: &MetadataEdit) -> Result<Vec<u8>, SongError> {
+    let (metadata, _) = mod_loader::parse_header(data, data.len())?;
+    let sample_count = metadata.samples.len();
+    let sample_header = |index: usize| {
+        if index < sample_count {
+            Ok(TITLE_SIZE + SAMPLE_HEADER_SIZE * index)
+        } else {
+            Err(SongError::NoSuchSample { index })
+        }
+    };
+
+    let mut data = data.to_vec();
+
+    if let Some(title) = &edit.title {
+        let mut writer = ByteWriter::new(Encoding::BigEndian);
+        writer.write_str_lossy(title, TITLE_SIZE, Codepage::Latin1)?;
+        overwrite(&mut data, 0, writer);
+    }
+
+    for (index, name) in &edit.sample_names {
+        let mut writer = ByteWriter::new(Encoding::BigEndian);
+        writer.write_str_lossy(name, SAMPLE_NAME_SIZE, Codepage::Latin1)?;
+        overwrite(&mut data, sample_header(*index)?, writer);
+    }
+
+    for &(index, volume) in &edit.sample_volumes {
+        if volume > 64 {
+            return Err(SongError::LimitExceeded {
+                what: "Volume",
+                found: volume as usize,
+                limit: 64,
+            });
+        }
+        let mut writer = ByteWriter::new(Encoding::BigEndian);
+        writer.write_u8(volume);
+        overwrite(&mut data, sample_header(index)? + VOLUME_OFFSET, writer);
+    }
+
+    for &(index, finetune) in &edit.sample_finetunes {
+        if !(-8..=7).contains(&finetune) {
+            return Err(SongError::LimitExceeded {
+                what: "Finetune",
+                found: finetune.unsigned_abs() as usize,
+                limit: if finetune < 0 { 8 } else { 7 },
+            });
+        }
+        // A signed nibble, as `mod_loader::decode_finetune` reads it
+        let mut writer = ByteWriter::new(Encoding::BigEndian);
+        writer.write_u8(finetune as u8 & 0x0F);
+        overwrite(&mut data, sample_header(index)? + FINETUNE_OFFSET, writer);
+    }
+
+    Ok(data)
+}
+
+// Replaces the bytes at `offset` with what was written
+fn overwrite(data: &mut [u8], offset: usize, writer: ByteWriter) {
+    let bytes = writer.into_inner();
+    data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+}
+
+#[test]
+fn edited_fields() {
+    use super::mod_writer;
+    use crate::song::{PCMData, SongBuilder};
+
+    let song = SongBuilder::new(4)
+        .title("untitled")
+        .sample("", PCMData::I8(alloc::vec![1; 8]))
+        .sample("bass", PCMData::I8(alloc::vec![2; 8]))
+        .empty_pattern()
+        .build()
+        .unwrap();
+    let data = mod_writer::write(&song).unwrap();
+
+    let changes = MetadataEdit {
+        title: Some("Café".into()),
+        sample_names: alloc::vec![(0, "(c) 1992".into())],
+        sample_volumes: alloc::vec![(1, 32)],
+        sample_finetunes: alloc::vec![(1, -3)],
+    };
+    let edited = edit(&data, &changes).unwrap();
+    assert_eq!(&edited[..5], b"Caf\xe9\0");
+    // Nothing after the header changed
+    assert_eq!(
+        edited[mod_loader::HEADER_SIZE..],
+        data[mod_loader::HEADER_SIZE..]
+    );
+
+    let metadata = mod_loader::parse(edited).unwrap().metadata;
+    assert_eq!(metadata.title(), "Café");
+    let (first, second) = (&metadata.samples[0], &metadata.samples[1]);
+    assert_eq!(first.name, "(c) 1992");
+    assert_eq!(
+        (second.name.as_str(), second.volume, second.finetune),
+        ("bass", 32, -3)
+    );
+
+    let missing = MetadataEdit {
+        sample_volumes: alloc::vec![(31, 10)],
+        ..Default::default()
+    };
+    assert!(matches!(
+        edit(&data, &missing),
+        Err(SongError::NoSuchSample { index: 31 })
+    ));
+}
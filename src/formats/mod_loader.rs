@@ -1,20 +1,26 @@
-use crate::bytereader::{ByteReader, Encoding};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::bytereader::{ByteReader, Codepage, Encoding};
 use crate::song::{self, Sample, Song, SongError};
-use crate::tracker::Tracker;
+use crate::tracker::fingerprint::{self, Evidence};
+use crate::tracker::{self, Tracker};
 
-fn read_sample(reader: &mut ByteReader) -> Result<Sample, SongError> {
-    let name = reader.read_str(22)?;
-    let length = reader.read_u16()? * 2;
+/// Samples are at most this long, their lengths are stored in words
+pub(crate) const MAX_SAMPLE_LENGTH: usize = u16::MAX as usize * 2;
 
-    let raw_finetune = reader.read_u8()? & 0x0F;
-    let value = (raw_finetune & 0b0111) as i8;
-    let sign = (raw_finetune & 0b1000) != 0;
+fn read_sample(reader: &mut ByteReader) -> Result<Sample, SongError> {
+    // The Amiga wrote names in Latin-1
+    let name = reader.read_str_lossy(22, Codepage::Latin1)?;
+    // Lengths are stored in words
+    let length = reader.read_u16()? as u32 * 2;
 
-    let finetune = if sign { -(value as i8) } else { value as i8 };
+    let finetune = decode_finetune(reader.read_u8()?);
     let volume = reader.read_u8()?;
 
-    let repeat_offset = reader.read_u16()? * 2;
-    let mut repeat_length = reader.read_u16()? * 2;
+    let repeat_offset = reader.read_u16()? as u32 * 2;
+    let mut repeat_length = reader.read_u16()? as u32 * 2;
 
     // No idea why its saved as a 1 when not repeated
     if repeat_length == 2 {
@@ -31,7 +37,13 @@ fn read_sample(reader: &mut ByteReader) -> Result<Sample, SongError> {
     })
 }
 
-fn detect_sample_count(format_tag: &str) -> usize {
+/// Finetune in eighths of a semitone, from the byte in the sample header.
+/// It's a signed nibble: 0-7 tune up, 8-15 are -8 to -1
+pub(super) fn decode_finetune(raw: u8) -> i8 {
+    ((raw << 4) as i8) >> 4
+}
+
+pub(crate) fn detect_sample_count(format_tag: &str) -> usize {
     // List of known 31-sample MOD format tags
     const KNOWN_TAGS: [&str; 30] = [
         "M.K.", "M!K!", "FLT4", "FLT8", "CD81", "2CHN", "4CHN", "6CHN", "8CHN", "10CH", "12CH",
@@ -43,10 +55,12 @@ fn detect_sample_count(format_tag: &str) -> usize {
     if KNOWN_TAGS.contains(&format_tag) {
         31
     } else {
-        // Fallback: check if all characters are printable ASCII
-        let printable_ascii = format_tag
-            .chars()
-            .all(|c| c != '\0' && (32..=126).contains(&(c as u8)));
+        // Fallback: check if all characters are printable ASCII. Trailing zeros are trimmed
+        // when reading the tag, so pattern data of 15 sample modules can come out shorter
+        let printable_ascii = format_tag.len() == 4
+            && format_tag
+                .chars()
+                .all(|c| c != '\0' && (32..=126).contains(&(c as u8)));
 
         if printable_ascii {
             31
@@ -56,7 +70,7 @@ fn detect_sample_count(format_tag: &str) -> usize {
     }
 }
 
-fn guess_channel_count(file_size: usize, sample_meta: &Vec<Sample>, pattern_count: u8) -> u8 {
+fn guess_channel_count(file_size: usize, sample_meta: &[Sample], pattern_count: u8) -> u8 {
     // Guess channel count from calculating the size of the pattern data
     // To get the amount of patterns, we find the highest pattern played + 1 from the pattern table
     // A pattern consists of 64 lines, and each note is 4 bytes, each line has nr_channels of bytes
@@ -76,25 +90,27 @@ fn guess_channel_count(file_size: usize, sample_meta: &Vec<Sample>, pattern_coun
     // Older 15 sample mods don't have a format tag
     let format_size = if sample_count == 31 { 4 } else { 0 };
 
-    let mut sample_pcm_size: u32 = 0;
-    for index in 0..sample_count {
-        sample_pcm_size += sample_meta[index].length as u32;
-    }
+    let sample_pcm_size: u32 = sample_meta.iter().map(|s| s.length).sum();
 
-    let pattern_data_left: u32 = file_size as u32
-        - song_metadata_size as u32
-        - sample_meta_size as u32
-        - format_size
-        - sample_pcm_size;
+    // Truncated files may not even have room for the samples, leaving no channels
+    let pattern_data_left: u32 = (file_size as u32)
+        .saturating_sub(song_metadata_size as u32)
+        .saturating_sub(sample_meta_size as u32)
+        .saturating_sub(format_size)
+        .saturating_sub(sample_pcm_size);
 
-    return ((pattern_data_left / pattern_count as u32) / (64 * 4)) as u8;
+    // Without any patterns there's nothing to fit channels into
+    let pattern_size = pattern_data_left
+        .checked_div(pattern_count as u32)
+        .unwrap_or(0);
+    (pattern_size / (64 * 4)).min(u8::MAX as u32) as u8
 }
 
 // This takes in 4 parameters because we may need to "guess" the amount of channels if we can't derive it from the tag
-fn identify_format_and_channels(
+pub(crate) fn identify_format_and_channels(
     tag: &str,
     file_size: usize,
-    sample_metadata: &Vec<Sample>,
+    sample_metadata: &[Sample],
     pattern_count: u8,
 ) -> (u8, Tracker) {
     match tag {
@@ -127,23 +143,61 @@ fn identify_format_and_channels(
         "15CH" => (15, Tracker::TakeTracker),
 
         _ => {
-            // Detect yyCH FastTracker mods
+            // Detect yyCH FastTracker mods, odd counts are written by later trackers
             if tag.ends_with("CH") {
                 if let Ok(yy) = tag[0..2].parse::<u8>() {
-                    if (10..=32).contains(&yy) && yy % 2 == 0 {
+                    if (10..=32).contains(&yy) {
                         return (yy, Tracker::FastTracker);
                     }
                 }
             }
 
-            return (
+            (
                 guess_channel_count(file_size, sample_metadata, pattern_count),
                 Tracker::Generic,
-            );
+            )
         }
     }
 }
 
+/// Tells apart the trackers that write the same tag by what the song uses, once its patterns
+/// are known. Only `FastOrNoiseTracker` is resolved, any other tracker is returned as it is.
+///
+/// Panning (8xx, E8x) is only found in PC trackers. NoiseTracker has no finetune, tempo
+/// (Fxx from 20) or the effects ProTracker added later, so a song without any of those is
+/// taken to be from NoiseTracker. See [`fingerprint::fingerprint`] for the other tags.
+pub(crate) fn resolve_tracker(
+    tracker: Tracker,
+    samples: &[Sample],
+    patterns: &[song::Pattern],
+) -> Tracker {
+    if tracker != Tracker::FastOrNoiseTracker {
+        return tracker;
+    }
+
+    let evidence = fingerprint::pattern_evidence(samples, patterns);
+    if evidence.contains(&Evidence::Panning) {
+        Tracker::FastTracker
+    } else if evidence.is_empty() {
+        Tracker::NoiseTracker
+    } else {
+        Tracker::ProTracker
+    }
+}
+
+fn initial_tempo(sample_count: usize, end_jump: u8) -> u16 {
+    // Later Soundtracker versions stored the CIA timer speed where ProTracker keeps the restart
+    // position, with 0x78 meaning the usual 50 Hz. Converted the same way OpenMPT does
+    if sample_count == 15 && end_jump != 0 && end_jump != 0x78 && end_jump < 240 {
+        // 709379 * 125 / 50 / ((240 - end_jump) * 122), rounded in integers so it works without std
+        let divisor = 50 * (240 - end_jump as u64) * 122;
+        let tempo = (709379 * 125 + divisor / 2) / divisor;
+        return (tempo as u16).clamp(32, 255);
+    }
+
+    tracker::PROTRACKER_DEFAULT_TEMPO
+}
+
 fn read_note(reader: &mut ByteReader) -> Result<song::Note, SongError> {
     //              Byte  1   Byte  2   Byte  3   Byte 4
     //              --------- --------- --------- ---------
@@ -169,11 +223,34 @@ fn read_note(reader: &mut ByteReader) -> Result<song::Note, SongError> {
     })
 }
 
-fn read_pattern(reader: &mut ByteReader, channel_count: u8) -> Result<song::Pattern, SongError> {
-    let mut pattern: song::Pattern = Vec::with_capacity(64);
+// Layout of the header, the pattern table follows the sample headers
+pub(crate) const TITLE_SIZE: usize = 20;
+pub(crate) const SAMPLE_HEADER_SIZE: usize = 30;
+pub(crate) const PATTERN_TABLE_SIZE: usize = 128;
+pub(crate) const FORMAT_TAG_OFFSET: usize = 1080;
+
+// Notes are stored row by row, a pattern always has 64 rows
+pub(crate) const ROWS_PER_PATTERN: usize = 64;
+pub(crate) const NOTE_SIZE: usize = 4;
+
+/// Size of the header of a module with 31 samples, it ends with the format tag.
+/// This much of the start of a file tells the formats apart, modules with 15 samples have a
+/// shorter header
+pub const HEADER_SIZE: usize = 1084;
+
+/// Size in bytes of a single pattern with `channel_count` channels
+pub fn pattern_size(channel_count: u8) -> usize {
+    ROWS_PER_PATTERN * channel_count as usize * NOTE_SIZE
+}
+
+pub(super) fn read_pattern(
+    reader: &mut ByteReader,
+    channel_count: u8,
+) -> Result<song::Pattern, SongError> {
+    let mut pattern: song::Pattern = Vec::with_capacity(ROWS_PER_PATTERN);
 
     // Read all the lines
-    for _ in 1..=64 {
+    for _ in 0..ROWS_PER_PATTERN {
         let mut line: song::Line = Vec::with_capacity(channel_count as usize);
 
         // Read all the notes
@@ -187,12 +264,30 @@ fn read_pattern(reader: &mut ByteReader, channel_count: u8) -> Result<song::Patt
     Ok(pattern)
 }
 
-pub fn parse(data: Vec<u8>) -> Result<Song, SongError> {
-    let mut reader = ByteReader::new(&data, Encoding::BigEndian);
+// Sample data is stored as signed 8-bit PCM
+pub(super) fn decode_sample(bytes: &[u8]) -> song::PCMData {
+    song::PCMData::I8(bytes.iter().map(|b| *b as i8).collect())
+}
+
+/// Parses everything up to the pattern data: title, sample headers, pattern table and
+/// format tag.
+///
+/// `data` starts at the beginning of the file and holds at least the header,
+/// `file_size` is the size of the whole file, used to guess the channels of modules without
+/// a format tag.
+///
+/// # Returns
+/// The metadata, and the offset the pattern data starts at
+pub fn parse_header(
+    data: &[u8],
+    file_size: usize,
+) -> Result<(song::SongMetadata, usize), SongError> {
+    let mut reader = ByteReader::new(data, Encoding::BigEndian);
 
-    // Ensure there's atleast 1080 bytes before hand, this isn't enough, but doesn't hurt to check prematurely
-    if reader.seek(1080).is_err() {
-        return Err(SongError::Read("Invalid or corrupted module".into()));
+    // Ensure there's atleast 1080 bytes before hand, this isn't enough, but doesn't hurt to check prematurely.
+    // Anything shorter is too small to be a module at all
+    if reader.seek(FORMAT_TAG_OFFSET).is_err() {
+        return Err(SongError::UnknownFormat);
     }
 
     let format = reader
@@ -205,69 +300,184 @@ pub fn parse(data: Vec<u8>) -> Result<Song, SongError> {
     // Unwrap because we know we can safely jump to offset 0
     reader.seek(0).unwrap();
 
-    let title = reader.read_str(20)?;
+    let title = reader.read_str_lossy(20, Codepage::Latin1)?;
     let mut sample_metadata: Vec<Sample> = Vec::with_capacity(sample_count);
 
-    for _ in 0..sample_count {
-        sample_metadata.push(read_sample(&mut reader)?);
+    for i in 0..sample_count {
+        let metadata = read_sample(&mut reader)?;
+        log::trace!(
+            "Sample {}: {:?}, {} bytes, volume {}, finetune {}, loop {}+{}",
+            i + 1,
+            metadata.name,
+            metadata.length,
+            metadata.volume,
+            metadata.finetune,
+            metadata.repeat_offset,
+            metadata.repeat_length
+        );
+
+        sample_metadata.push(metadata);
     }
 
-    // Patterns played, we can skip this (I think?)
-    reader.read_u8()?;
+    // Amount of entries in the pattern table that are actually played
+    let song_length = reader.read_u8()?.clamp(1, 128);
     let end_jmp_pos = reader.read_i8()?;
 
-    let pattern_table = reader
-        .read_bytes(128)
-        .map_err(|_| SongError::Read("Failed to read pattern table".into()))?
-        .to_vec();
+    let pattern_table = reader.read_bytes(128)?.to_vec();
 
-    let pattern_count = pattern_table.iter().max().unwrap_or(&0) + 1;
+    // Every pattern up to the highest one in the table is stored, 255 would make it 256
+    let highest_pattern = pattern_table.iter().copied().max().unwrap_or(0);
+    let pattern_count = highest_pattern
+        .checked_add(1)
+        .ok_or(SongError::LimitExceeded {
+            what: "Pattern count",
+            found: highest_pattern as usize + 1,
+            limit: u8::MAX as usize,
+        })?;
 
     // Skip reading the format tag, we've already read it above
     if sample_count == 31 {
-        reader.seek(reader.position() + 4)?;
+        reader.seek_relative(4)?;
     }
 
     let (channel_count, tracker) =
-        identify_format_and_channels(&format, data.len(), &sample_metadata, pattern_count);
-
-    let mut patterns: Vec<song::Pattern> = Vec::with_capacity(pattern_count as usize);
-    for _ in 0..pattern_count {
-        patterns.push(read_pattern(&mut reader, channel_count)?);
-    }
-
-    let mut samples: Vec<song::PCMData> = Vec::with_capacity(sample_count);
-    for i in 0..sample_count {
-        let length = sample_metadata.get(i).unwrap().length as usize;
-
-        let sample = reader
-            .read_bytes(length)
-            .map_err(|_| SongError::Read("Failed to read sample data".into()))?
-            .into_iter()
-            .map(|b| *b as i8)
-            .collect();
-
-        samples.push(song::PCMData::I8(sample));
-    }
+        identify_format_and_channels(&format, file_size, &sample_metadata, pattern_count);
+    log::debug!(
+        "Format {:?}: {tracker}, {sample_count} samples, {channel_count} channels, {pattern_count} patterns",
+        format
+    );
 
     let metadata = song::SongMetadata {
         name: title,
         samples: sample_metadata,
 
         pattern_table,
+        song_length,
 
-        pattern_count: pattern_count,
+        pattern_count,
         channel_count,
 
         end_jump: end_jmp_pos,
         format,
 
+        initial_speed: tracker::PROTRACKER_DEFAULT_SPEED,
+        initial_tempo: initial_tempo(sample_count, end_jmp_pos as u8),
+
         tracker,
     };
 
+    Ok((metadata, reader.position()))
+}
+
+/// Parses a MOD file, with 15 or 31 samples and any amount of channels
+pub fn parse(data: Vec<u8>) -> Result<Song, SongError> {
+    let (metadata, patterns_offset) = parse_header(&data, data.len())?;
+
+    let mut reader = ByteReader::new(&data, Encoding::BigEndian);
+    reader.seek(patterns_offset)?;
+
+    // Checked up front, so nothing is allocated for a file that's cut short
+    let patterns_size = metadata.pattern_count as usize * pattern_size(metadata.channel_count);
+    let samples_size: usize = metadata.samples.iter().map(|s| s.length as usize).sum();
+    if reader.remaining() < patterns_size + samples_size {
+        return Err(SongError::UnexpectedEof {
+            offset: patterns_offset,
+            wanted: patterns_size + samples_size,
+        });
+    }
+
+    let mut patterns: Vec<song::Pattern> = Vec::with_capacity(metadata.pattern_count as usize);
+    for _ in 0..metadata.pattern_count {
+        patterns.push(read_pattern(&mut reader, metadata.channel_count)?);
+    }
+
+    let mut samples: Vec<Arc<song::PCMData>> = Vec::with_capacity(metadata.samples.len());
+    for sample in &metadata.samples {
+        let data = reader.read_bytes(sample.length as usize)?;
+        samples.push(Arc::new(decode_sample(data)));
+    }
+
+    let mut metadata = metadata;
+    metadata.tracker = resolve_tracker(metadata.tracker, &metadata.samples, &patterns);
+
     Ok(Song {
         metadata,
         patterns,
         samples,
     })
 }
+
+#[test]
+fn truncated_module_fails() {
+    use crate::formats::mod_writer;
+    use crate::song::square_song;
+
+    let song = square_song(4).empty_pattern().build().unwrap();
+    let mut data = mod_writer::write(&song).unwrap();
+    data.pop();
+
+    let wanted = pattern_size(4) + song.metadata().instrument(0).unwrap().length as usize;
+    assert!(matches!(
+        parse(data),
+        Err(SongError::UnexpectedEof { offset: HEADER_SIZE, wanted: w }) if w == wanted
+    ));
+}
+
+#[test]
+fn samples_longer_than_64_kib() {
+    use crate::formats::mod_writer;
+    use crate::song::{PCMData, SongBuilder};
+
+    let long: Vec<i8> = (0..MAX_SAMPLE_LENGTH).map(|i| (i % 251) as i8).collect();
+    let song = SongBuilder::new(4)
+        .sample("Long", PCMData::I8(long.clone()))
+        .sample_loop(0, 100_000, 31_070)
+        .sample("Square", PCMData::I8([64, 64, -64, -64].repeat(8)))
+        .empty_pattern()
+        .build()
+        .unwrap();
+    let song = parse(mod_writer::write(&song).unwrap()).unwrap();
+
+    let sample = song.metadata().instrument(0).unwrap();
+    assert_eq!(sample.length as usize, MAX_SAMPLE_LENGTH);
+    assert_eq!(
+        (sample.repeat_offset, sample.repeat_length),
+        (100_000, 31_070)
+    );
+    assert!(matches!(song.sample_data(0), Some(PCMData::I8(data)) if *data == long));
+
+    // The samples after it are read from where they are
+    let square = [64, 64, -64, -64].repeat(8);
+    assert!(matches!(song.sample_data(1), Some(PCMData::I8(data)) if *data == square));
+}
+
+#[test]
+fn resolve_4chn_tracker() {
+    use crate::formats::mod_writer;
+    use crate::song::{square_song, Note};
+
+    let tracker = |effect: u8, argument: u8| {
+        let note = Note {
+            sample: 1,
+            period: 428,
+            effect,
+            argument,
+        };
+        let song = square_song(4)
+            .empty_pattern()
+            .note(0, 0, 0, note)
+            .build()
+            .unwrap();
+        let mut data = mod_writer::write(&song).unwrap();
+        data[1080..1084].copy_from_slice(b"4CHN");
+
+        parse(data).unwrap().metadata.tracker
+    };
+
+    assert_eq!(tracker(0xA, 0x01), Tracker::NoiseTracker);
+    assert_eq!(tracker(0xE, 0x01), Tracker::NoiseTracker);
+    assert_eq!(tracker(0xF, 0x7D), Tracker::ProTracker);
+    assert_eq!(tracker(0x9, 0x10), Tracker::ProTracker);
+    assert_eq!(tracker(0x8, 0x80), Tracker::FastTracker);
+    assert_eq!(tracker(0xE, 0x84), Tracker::FastTracker);
+}
@@ -0,0 +1,196 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::mod_writer;
+use crate::song::{Note, PCMData, Pattern, Song};
+
+/// What [`optimize`] took out of a song
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Optimization {
+    /// Patterns that weren't played, or played the same as another pattern
+    pub patterns_removed: usize,
+    /// Samples no note used, whose data was dropped
+    pub samples_removed: usize,
+    /// Bytes of sample data after the end of a loop, which are never heard
+    pub bytes_truncated: usize,
+}
+
+/// Makes a song as small as it can be saved, without changing how it plays.
+///
+/// Patterns the order list doesn't play are removed, and patterns that are the same are
+/// stored once. The rest are numbered in the order they're first played, the order list is
+/// rewritten to match. Samples that no note uses lose their data but keep their slot and
+/// name, which musicians used for messages. Looped samples end with their loop.
+pub fn optimize(song: &Song) -> (Song, Optimization) {
+    let mut optimized = song.clone();
+    let mut optimization = Optimization::default();
+
+    // Played patterns are numbered by their first appearance, identical ones share a number
+    let mut patterns: Vec<Pattern> = Vec::new();
+    let mut pattern_table = Vec::with_capacity(song.metadata().orders().len());
+    for &order in song.metadata().orders() {
+        let pattern = song.pattern(order as usize).cloned().unwrap_or_default();
+        let index = match (patterns.iter()).position(|kept| same_pattern(kept, &pattern)) {
+            Some(index) => index,
+            None => {
+                patterns.push(pattern);
+                patterns.len() - 1
+            }
+        };
+        pattern_table.push(index as u8);
+    }
+    optimization.patterns_removed = song.patterns().len().saturating_sub(patterns.len());
+
+    let mut used = Vec::new();
+    for note in patterns.iter().flatten().flatten() {
+        if note.sample > 0 {
+            let index = note.sample as usize - 1;
+            used.resize(used.len().max(index + 1), false);
+            used[index] = true;
+        }
+    }
+
+    for (index, sample) in optimized.metadata.samples.iter_mut().enumerate() {
+        let length = if !used.get(index).copied().unwrap_or(false) {
+            if sample.length > 0 {
+                optimization.samples_removed += 1;
+            }
+            sample.repeat_offset = 0;
+            sample.repeat_length = 0;
+            0
+        } else if sample.repeat_length > 2 {
+            // A loop of one word means the sample doesn't loop
+            let end = sample.repeat_offset as usize + sample.repeat_length as usize;
+            let length = end.min(sample.length as usize) as u32;
+            optimization.bytes_truncated += (sample.length - length) as usize;
+            length
+        } else {
+            sample.length
+        };
+
+        if length == sample.length {
+            continue;
+        }
+        sample.length = length;
+
+        if let Some(data) = optimized.samples.get_mut(index) {
+            *data = Arc::new(match data.as_ref() {
+                PCMData::I8(data) => PCMData::I8(data[..data.len().min(length as usize)].to_vec()),
+                PCMData::I16(data) => {
+                    PCMData::I16(data[..data.len().min(length as usize)].to_vec())
+                }
+            });
+        }
+    }
+
+    let metadata = &mut optimized.metadata;
+    metadata.pattern_count = patterns.len() as u8;
+    metadata.format = mod_writer::format_tag(metadata.channel_count, patterns.len());
+    metadata.pattern_table = pattern_table;
+    optimized.patterns = patterns;
+
+    (optimized, optimization)
+}
+
+// Whether two patterns play the same. Missing rows and cells are empty
+fn same_pattern(a: &Pattern, b: &Pattern) -> bool {
+    let rows = a.len().max(b.len());
+    (0..rows).all(|row| {
+        let (a, b) = (a.get(row), b.get(row));
+        let channels = a.map_or(0, Vec::len).max(b.map_or(0, Vec::len));
+        (0..channels).all(|channel| {
+            let a = a.and_then(|line| line.get(channel));
+            let b = b.and_then(|line| line.get(channel));
+            let fields = |note: Option<&Note>| {
+                note.map_or((0, 0, 0, 0), |note| {
+                    (note.sample, note.period, note.effect, note.argument)
+                })
+            };
+            fields(a) == fields(b)
+        })
+    })
+}
+
+#[test]
+fn unused_parts_are_removed() {
+    use crate::song::SongBuilder;
+
+    let note = |sample| Note {
+        sample,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    let song = SongBuilder::new(4)
+        .sample("Looped", PCMData::I8(alloc::vec![1; 16]))
+        .sample_loop(0, 4, 4)
+        .sample("Unused", PCMData::I8(alloc::vec![2; 8]))
+        .sample("One shot", PCMData::I8(alloc::vec![3; 8]))
+        .empty_pattern()
+        .empty_pattern()
+        .empty_pattern()
+        .empty_pattern()
+        .note(0, 0, 0, note(1))
+        .note(1, 0, 0, note(3))
+        // The same as pattern 1, and pattern 3 isn't played
+        .note(2, 0, 0, note(3))
+        .note(3, 0, 0, note(2))
+        .orders(&[1, 0, 2, 1])
+        .build()
+        .unwrap();
+
+    let (optimized, optimization) = optimize(&song);
+    assert_eq!(
+        optimization,
+        Optimization {
+            patterns_removed: 2,
+            samples_removed: 1,
+            bytes_truncated: 8,
+        }
+    );
+
+    let metadata = optimized.metadata();
+    assert_eq!(metadata.orders(), [0, 1, 0, 0]);
+    assert_eq!(optimized.patterns().len(), 2);
+    assert_eq!(optimized.patterns()[0][0][0].sample, 3);
+
+    let lengths: Vec<u32> = (metadata.instruments().iter()).map(|s| s.length).collect();
+    assert_eq!(lengths, [8, 0, 8]);
+    assert_eq!(metadata.instrument(1).unwrap().name, "Unused");
+    assert!(matches!(optimized.sample_data(0), Some(PCMData::I8(data)) if data.len() == 8));
+
+    // What's left still saves and loads
+    let saved = mod_writer::write(&optimized).unwrap();
+    assert!(saved.len() < mod_writer::write(&song).unwrap().len());
+    super::mod_loader::parse(saved).unwrap();
+}
+
+#[test]
+fn loops_past_64_kib_are_kept() {
+    use crate::song::SongBuilder;
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    let song = SongBuilder::new(4)
+        .sample("Long", PCMData::I8(alloc::vec![1; 100_000]))
+        .sample_loop(0, 40_000, 30_000)
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .build()
+        .unwrap();
+
+    // Only what comes after the loop is cut off
+    let (optimized, optimization) = optimize(&song);
+    assert_eq!(optimization.bytes_truncated, 30_000);
+    let sample = optimized.metadata().instrument(0).unwrap();
+    assert_eq!(sample.length, 70_000);
+    assert_eq!(
+        (sample.repeat_offset, sample.repeat_length),
+        (40_000, 30_000)
+    );
+    assert!(matches!(optimized.sample_data(0), Some(PCMData::I8(data)) if data.len() == 70_000));
+}
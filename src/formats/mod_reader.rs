@@ -0,0 +1,209 @@
+//! Loading MOD files from a stream, a part at a time
+
+use std::io::{Read, Seek};
+use std::sync::Arc;
+
+use super::mod_loader::{self, HEADER_SIZE};
+use crate::bytereader::{ByteReader, Encoding};
+use crate::song::{PCMData, Pattern, Song, SongError, SongMetadata};
+use crate::streamreader::StreamReader;
+
+/// Reads a MOD file from a stream, loading only its header up front.
+///
+/// Patterns and samples are read when they're asked for, so the file never has to be in
+/// memory as a whole, and whatever only needs the metadata doesn't read the rest.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use rustune::formats::mod_reader::ModReader;
+///
+/// let mut reader = ModReader::new(BufReader::new(File::open("song.mod")?))?;
+/// println!("{}", reader.metadata().title());
+///
+/// let first = reader.metadata().orders()[0] as usize;
+/// let pattern = reader.read_pattern(first)?;
+/// # let _ = pattern;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ModReader<R> {
+    reader: StreamReader<R>,
+    metadata: SongMetadata,
+
+    patterns_offset: u64,
+    // Where the data of every sample starts, they follow the patterns in order
+    sample_offsets: Vec<u64>,
+}
+
+impl<R: Read + Seek> ModReader<R> {
+    /// Reads the header of the module that starts at the beginning of `reader`
+    pub fn new(reader: R) -> Result<Self, SongError> {
+        let mut reader = StreamReader::new(reader, Encoding::BigEndian)?;
+        let file_size = reader.size();
+
+        let header = reader.peek_bytes(HEADER_SIZE.min(file_size))?;
+        let (metadata, patterns_offset) = mod_loader::parse_header(header, file_size)?;
+
+        let patterns_size =
+            metadata.pattern_count as usize * mod_loader::pattern_size(metadata.channel_count);
+        let mut offset = (patterns_offset + patterns_size) as u64;
+        let sample_offsets = (metadata.samples.iter())
+            .map(|sample| {
+                let start = offset;
+                offset += sample.length as u64;
+                start
+            })
+            .collect();
+
+        Ok(ModReader {
+            reader,
+            metadata,
+            patterns_offset: patterns_offset as u64,
+            sample_offsets,
+        })
+    }
+
+    pub fn metadata(&self) -> &SongMetadata {
+        &self.metadata
+    }
+
+    /// Reads a pattern, numbered as in the pattern table
+    pub fn read_pattern(&mut self, index: usize) -> Result<Pattern, SongError> {
+        let channel_count = self.metadata.channel_count;
+        if index >= self.metadata.pattern_count as usize {
+            return Err(SongError::NoSuchPattern { index });
+        }
+
+        let size = mod_loader::pattern_size(channel_count);
+        let data = self.read_at(self.patterns_offset + (index * size) as u64, size)?;
+
+        mod_loader::read_pattern(
+            &mut ByteReader::new(data, Encoding::BigEndian),
+            channel_count,
+        )
+    }
+
+    /// Reads the data of a sample. Counts from 0, unlike sample numbers in the patterns
+    pub fn read_sample(&mut self, index: usize) -> Result<PCMData, SongError> {
+        let (Some(&offset), Some(sample)) = (
+            self.sample_offsets.get(index),
+            self.metadata.samples.get(index),
+        ) else {
+            return Err(SongError::NoSuchSample { index });
+        };
+
+        let length = sample.length as usize;
+        let data = self.read_at(offset, length)?;
+        Ok(mod_loader::decode_sample(data))
+    }
+
+    /// Reads every pattern and sample, giving the same song as parsing the whole file at once
+    pub fn into_song(mut self) -> Result<Song, SongError> {
+        let patterns: Vec<Pattern> = (0..self.metadata.pattern_count as usize)
+            .map(|index| self.read_pattern(index))
+            .collect::<Result<_, _>>()?;
+        let samples = (0..self.metadata.samples.len())
+            .map(|index| self.read_sample(index).map(Arc::new))
+            .collect::<Result<_, _>>()?;
+
+        let mut metadata = self.metadata;
+        metadata.tracker =
+            mod_loader::resolve_tracker(metadata.tracker, &metadata.samples, &patterns);
+
+        Ok(Song {
+            metadata,
+            patterns,
+            samples,
+        })
+    }
+
+    /// Gives back the stream, e.g. to close it early once the metadata was read
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> Result<&[u8], SongError> {
+        self.reader.seek(offset as usize)?;
+        self.reader.read_bytes(length)
+    }
+}
+
+#[cfg(test)]
+fn assert_same_song(data: Vec<u8>) {
+    use std::io::Cursor;
+
+    let streamed = ModReader::new(Cursor::new(data.clone())).and_then(ModReader::into_song);
+    let parsed = mod_loader::parse(data);
+    // Songs don't compare, but every part of them is shown in debug output
+    assert_eq!(format!("{streamed:?}"), format!("{parsed:?}"));
+}
+
+#[cfg(test)]
+fn fixture(channel_count: u8) -> Vec<u8> {
+    use super::mod_writer;
+    use crate::song::{square_song, Note};
+
+    let note = |sample: u8, effect: u8, argument: u8| Note {
+        sample,
+        period: 428,
+        effect,
+        argument,
+    };
+    let song = square_song(channel_count)
+        .title("Fixture")
+        .sample("Empty", PCMData::I8(Vec::new()))
+        .sample("Ramp", PCMData::I8((-64..64).collect()))
+        .sample_loop(2, 32, 64)
+        .sample_volume(2, 40)
+        .sample_finetune(2, -3)
+        .empty_pattern()
+        .empty_pattern()
+        .note(0, 0, 0, note(1, 0xC, 32))
+        .note(0, 16, 1, note(3, 0xA, 0x04))
+        .note(1, 63, channel_count as usize - 1, note(3, 0xF, 0x7D))
+        .orders(&[1, 0, 1])
+        .build()
+        .unwrap();
+    mod_writer::write(&song).unwrap()
+}
+
+#[test]
+fn same_song_as_parsing() {
+    assert_same_song(fixture(4));
+}
+
+#[test]
+fn same_song_with_more_channels() {
+    assert_same_song(fixture(8));
+}
+
+#[test]
+fn same_song_with_15_samples() {
+    // The same module without the format tag and the last 16 sample headers
+    let data = fixture(4);
+    let samples_end = 20 + 15 * 30;
+    let table = 20 + 31 * 30;
+    let data = [
+        &data[..samples_end],
+        &data[table..table + 2 + 128],
+        &data[HEADER_SIZE..],
+    ]
+    .concat();
+    assert_same_song(data);
+}
+
+#[test]
+fn truncated_module_fails_like_parsing() {
+    use std::io::Cursor;
+
+    let mut data = fixture(4);
+    data.pop();
+
+    // Parsing checks the size up front, the reader finds out when it gets to the sample
+    let streamed = ModReader::new(Cursor::new(data.clone())).and_then(ModReader::into_song);
+    assert!(matches!(streamed, Err(SongError::UnexpectedEof { .. })));
+    assert!(matches!(
+        mod_loader::parse(data),
+        Err(SongError::UnexpectedEof { .. })
+    ));
+}
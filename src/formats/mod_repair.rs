@@ -0,0 +1,248 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+use super::mod_loader::{self, PATTERN_TABLE_SIZE, SAMPLE_HEADER_SIZE, TITLE_SIZE};
+use crate::song::{Song, SongError};
+
+/// A change [`repair`] made to a module
+#[derive(Debug, Clone)]
+pub struct Fix {
+    /// Position in the damaged file of the data that was changed
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#08x}  {}", self.offset, self.message)
+    }
+}
+
+/// Loads a MOD file the loader refuses because of damage [`super::mod_validator`] finds,
+/// fixing what it has to on the way.
+///
+/// Samples that go past the end of the file are shortened to the data that's there, loops
+/// past the end of their sample are shortened or removed. Orders that play patterns missing
+/// from the file are removed from the song, and the table entries after the song that refer
+/// to them are cleared. A file cut off inside its patterns keeps the whole patterns, its
+/// samples are empty.
+///
+/// # Returns
+/// The song, and what was changed in the order it was done. No fixes means the loader
+/// would have loaded the file as it is
+///
+/// # Errors
+/// When the header itself is damaged, e.g. the file isn't a module, ends inside the header or
+/// its pattern table refers to pattern 255
+pub fn repair(data: &[u8]) -> Result<(Song, Vec<Fix>), SongError> {
+    let (metadata, patterns_offset) = mod_loader::parse_header(data, data.len())?;
+    if metadata.channel_count == 0 {
+        return Err(SongError::UnknownFormat);
+    }
+
+    let mut data = data.to_vec();
+    let mut fixes = Vec::new();
+
+    let sample_count = metadata.samples.len();
+    let song_length_offset = TITLE_SIZE + SAMPLE_HEADER_SIZE * sample_count;
+    let table_offset = song_length_offset + 2;
+    let pattern_size = mod_loader::pattern_size(metadata.channel_count);
+
+    let song_length = metadata.song_length as usize;
+    if data[song_length_offset] as usize != song_length {
+        fixes.push(Fix {
+            offset: song_length_offset,
+            message: format!(
+                "Song length {} is outside 1-128, set to {song_length}",
+                data[song_length_offset]
+            ),
+        });
+    }
+
+    // Patterns come first, so they're only missing when the file is cut off inside them, or
+    // the table refers to patterns after the song that were never stored
+    let available = data.len() - patterns_offset;
+    let samples_size: usize = (metadata.samples.iter())
+        .map(|sample| sample.length as usize)
+        .sum();
+    let stored = metadata.pattern_count as usize;
+    let played = (metadata.pattern_table[..song_length].iter())
+        .max()
+        .map_or(0, |&max| max as usize + 1);
+    let mut present = if stored * pattern_size + samples_size <= available {
+        stored
+    } else if played * pattern_size + samples_size <= available {
+        played
+    } else if stored * pattern_size <= available {
+        stored
+    } else if played * pattern_size <= available {
+        played
+    } else {
+        // Cut off inside the played patterns, what follows the last whole one isn't samples
+        let present = available / pattern_size;
+        data.truncate(patterns_offset + present * pattern_size);
+        present
+    };
+
+    if present == 0 {
+        // Without any pattern it's no song, a silent one stands in for those missing
+        data.splice(
+            patterns_offset..patterns_offset,
+            alloc::vec![0; pattern_size],
+        );
+        fixes.push(Fix {
+            offset: patterns_offset,
+            message: String::from("No pattern is left, added an empty one"),
+        });
+        present = 1;
+    }
+
+    let mut orders = Vec::with_capacity(song_length);
+    let mut unplayed = Vec::with_capacity(PATTERN_TABLE_SIZE - song_length);
+    for (order, &pattern) in metadata.pattern_table.iter().enumerate() {
+        let offset = table_offset + order;
+        if (pattern as usize) < present {
+            if order < song_length {
+                orders.push(pattern);
+            } else {
+                unplayed.push(pattern);
+            }
+        } else if order < song_length {
+            fixes.push(Fix {
+                offset,
+                message: format!(
+                    "Order {order} plays pattern {pattern}, which is missing from the file, removed"
+                ),
+            });
+        } else {
+            unplayed.push(0);
+            fixes.push(Fix {
+                offset,
+                message: format!(
+                    "Order {order} after the end of the song refers to pattern {pattern}, \
+                     which is missing from the file, cleared"
+                ),
+            });
+        }
+    }
+
+    if orders.is_empty() {
+        // Nothing that's played is left, the first pattern is
+        orders.push(0);
+    }
+
+    // Orders that were removed move the ones after them forward, the table ends with zeroes
+    let song_length = orders.len();
+    let mut table = orders;
+    table.extend(unplayed);
+    table.resize(PATTERN_TABLE_SIZE, 0);
+    data[song_length_offset] = song_length as u8;
+    data[table_offset..table_offset + PATTERN_TABLE_SIZE].copy_from_slice(&table);
+
+    let mut offset = patterns_offset + present * pattern_size;
+    for index in 0..sample_count {
+        let header = TITLE_SIZE + SAMPLE_HEADER_SIZE * index;
+        let number = index + 1;
+
+        let mut length = u16_at(&data, header + 22) as usize * 2;
+        let left = data.len().saturating_sub(offset);
+        if length > left {
+            fixes.push(Fix {
+                offset: header + 22,
+                message: format!(
+                    "Sample {number} is {length} bytes, but only {left} are left in the file, \
+                     shortened"
+                ),
+            });
+            length = left & !1;
+            set_u16(&mut data, header + 22, (length / 2) as u16);
+        }
+        offset += length;
+
+        // A loop of one word means the sample doesn't loop
+        let repeat_offset = u16_at(&data, header + 26) as usize * 2;
+        let repeat_length = u16_at(&data, header + 28) as usize * 2;
+        if repeat_length > 2 && repeat_offset + repeat_length > length {
+            let end = repeat_offset + repeat_length;
+            let (repeat_offset, repeat_length) = if repeat_offset + 2 < length {
+                fixes.push(Fix {
+                    offset: header + 26,
+                    message: format!(
+                        "Loop of sample {number} ends at {end}, past the end of the sample at \
+                         {length}, shortened"
+                    ),
+                });
+                (repeat_offset, length - repeat_offset)
+            } else {
+                fixes.push(Fix {
+                    offset: header + 26,
+                    message: format!(
+                        "Loop of sample {number} starts at {repeat_offset}, past the end of the \
+                         sample at {length}, removed"
+                    ),
+                });
+                (0, 2)
+            };
+            set_u16(&mut data, header + 26, (repeat_offset / 2) as u16);
+            set_u16(&mut data, header + 28, (repeat_length / 2) as u16);
+        }
+    }
+
+    let song = mod_loader::parse(data)?;
+    Ok((song, fixes))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn set_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+#[test]
+fn damaged_module_is_repaired() {
+    use super::mod_writer;
+    use crate::song::{PCMData, SongBuilder};
+
+    let song = SongBuilder::new(4)
+        .sample("Looped", PCMData::I8(alloc::vec![1; 16]))
+        .sample_loop(0, 4, 8)
+        .sample("Cut off", PCMData::I8(alloc::vec![2; 64]))
+        .empty_pattern()
+        .empty_pattern()
+        .orders(&[0, 1, 0])
+        .build()
+        .unwrap();
+    let saved = mod_writer::write(&song).unwrap();
+    let (_, fixes) = repair(&saved).unwrap();
+    assert!(fixes.is_empty());
+
+    // The loop of the first sample ends past it, the second one lacks 24 bytes, and an order
+    // after the song refers to a pattern that isn't there
+    let mut damaged = saved.clone();
+    damaged.truncate(saved.len() - 24);
+    set_u16(&mut damaged, TITLE_SIZE + 28, 8);
+    damaged[TITLE_SIZE + SAMPLE_HEADER_SIZE * 31 + 2 + 5] = 7;
+    assert!(mod_loader::parse(damaged.clone()).is_err());
+
+    let (repaired, fixes) = repair(&damaged).unwrap();
+    assert_eq!(fixes.len(), 3);
+    let metadata = repaired.metadata();
+    assert_eq!(metadata.orders(), [0, 1, 0]);
+    assert_eq!(metadata.pattern_table()[5], 0);
+    let looped = metadata.instrument(0).unwrap();
+    assert_eq!((looped.repeat_offset, looped.repeat_length), (4, 12));
+    assert_eq!(metadata.instrument(1).unwrap().length, 40);
+
+    // Cut off inside the second pattern, it's removed from the orders and the samples are empty
+    let patterns_offset = mod_loader::HEADER_SIZE;
+    damaged.truncate(patterns_offset + mod_loader::pattern_size(4) + 100);
+    let (repaired, fixes) = repair(&damaged).unwrap();
+    assert!(fixes.iter().any(|fix| fix.message.contains("pattern 1")));
+    assert_eq!(repaired.metadata().orders(), [0, 0]);
+    assert_eq!(repaired.patterns().len(), 1);
+    assert_eq!(repaired.metadata().instrument(1).unwrap().length, 0);
+}
@@ -0,0 +1,702 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+use super::mod_loader::{
+    self, FORMAT_TAG_OFFSET, NOTE_SIZE, PATTERN_TABLE_SIZE, ROWS_PER_PATTERN, SAMPLE_HEADER_SIZE,
+    TITLE_SIZE,
+};
+use crate::bytereader::{ByteReader, Encoding};
+#[cfg(feature = "std")]
+use crate::engine;
+use crate::song::Sample;
+use crate::tracker::Tracker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Players disagree on how to handle it, or it's suspicious but harmless
+    Warning,
+    /// Data is missing or the file can't be loaded
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Something wrong with the structure of a module
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// Position in the file of the data that's wrong, or where missing data should have been
+    pub offset: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#08x}  {:<7}  {}",
+            self.offset, self.severity, self.message
+        )
+    }
+}
+
+// The first note using an effect or sample number that's reported, and how often it's used
+struct Occurrence {
+    offset: usize,
+    pattern: usize,
+    row: usize,
+    channel: usize,
+    count: usize,
+}
+
+struct Validator<'a> {
+    data: &'a [u8],
+    problems: Vec<Problem>,
+}
+
+impl Validator<'_> {
+    fn error(&mut self, offset: usize, message: String) {
+        self.report(offset, Severity::Error, message);
+    }
+
+    fn warning(&mut self, offset: usize, message: String) {
+        self.report(offset, Severity::Warning, message);
+    }
+
+    fn report(&mut self, offset: usize, severity: Severity, message: String) {
+        self.problems.push(Problem {
+            offset,
+            severity,
+            message,
+        });
+    }
+
+    // Only called on offsets that are known to be inside the header
+    fn u8_at(&self, offset: usize) -> u8 {
+        self.data[offset]
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.data[offset], self.data[offset + 1]])
+    }
+}
+
+/// Checks a MOD file for structural problems without loading it, reporting every one
+/// found rather than stopping at the first like the loader does.
+///
+/// The problems are in the order of their offsets in the file, as far as they're known.
+pub fn validate(data: &[u8]) -> Vec<Problem> {
+    let mut validator = Validator {
+        data,
+        problems: Vec::new(),
+    };
+
+    check(&mut validator);
+
+    validator.problems.sort_by_key(|problem| problem.offset);
+    validator.problems
+}
+
+fn check(v: &mut Validator) {
+    let data = v.data;
+
+    // Tag and sample count are detected the same way the loader does
+    let mut reader = ByteReader::new(data, Encoding::BigEndian);
+    let tag = reader
+        .seek(FORMAT_TAG_OFFSET)
+        .and_then(|_| reader.read_str(4))
+        .unwrap_or_else(|_| String::from("\0\0\0\0"));
+    let sample_count = mod_loader::detect_sample_count(&tag);
+
+    let tag_size = if sample_count == 31 { 4 } else { 0 };
+    let table_offset = TITLE_SIZE + SAMPLE_HEADER_SIZE * sample_count + 2;
+    let header_size = table_offset + PATTERN_TABLE_SIZE + tag_size;
+
+    if data.len() < header_size {
+        v.error(
+            data.len(),
+            format!(
+                "File ends after {} bytes, inside the {header_size} byte header",
+                data.len()
+            ),
+        );
+        return;
+    }
+
+    if core::str::from_utf8(&data[..TITLE_SIZE]).is_err() {
+        v.error(0, "Title isn't valid UTF-8, the loader refuses it".into());
+    }
+
+    let samples: Vec<Sample> = (0..sample_count)
+        .map(|index| check_sample_header(v, index))
+        .collect();
+
+    let song_length_offset = table_offset - 2;
+    let song_length = v.u8_at(song_length_offset);
+    if !(1..=128).contains(&song_length) {
+        v.warning(
+            song_length_offset,
+            format!(
+                "Song length {song_length} is outside 1-128, it's played as {}",
+                song_length.clamp(1, 128)
+            ),
+        );
+    }
+    let song_length = song_length.clamp(1, 128) as usize;
+
+    let pattern_table = &data[table_offset..table_offset + PATTERN_TABLE_SIZE];
+    // Every pattern up to the highest one in the table is stored, played or not
+    let pattern_count = pattern_table
+        .iter()
+        .max()
+        .map_or(0, |&max| max as usize + 1);
+
+    if pattern_count > u8::MAX as usize {
+        let position = pattern_table
+            .iter()
+            .position(|&p| p == u8::MAX)
+            .unwrap_or(0);
+        v.error(
+            table_offset + position,
+            "Pattern table refers to pattern 255, the loader supports at most 255 patterns".into(),
+        );
+        return;
+    }
+
+    let (channel_count, tracker) =
+        mod_loader::identify_format_and_channels(&tag, data.len(), &samples, pattern_count as u8);
+
+    if let Tracker::Generic = tracker {
+        let (offset, found) = if sample_count == 31 {
+            (FORMAT_TAG_OFFSET, format!("Unknown format tag {tag:?}"))
+        } else {
+            (0, String::from("No format tag"))
+        };
+        v.warning(
+            offset,
+            format!("{found}, guessed {channel_count} channels from the file size"),
+        );
+    }
+
+    if channel_count == 0 {
+        v.error(
+            header_size,
+            "The patterns don't fit in the file with any amount of channels".into(),
+        );
+        return;
+    }
+
+    let pattern_size = mod_loader::pattern_size(channel_count);
+    check_patterns(
+        v,
+        header_size,
+        pattern_size,
+        pattern_count,
+        &pattern_table[..song_length],
+        table_offset,
+        sample_count,
+    );
+
+    let mut offset = header_size + pattern_count * pattern_size;
+    for index in 0..sample_count {
+        let length = v.u16_at(sample_header_offset(index) + 22) as usize * 2;
+        let available = data.len().saturating_sub(offset);
+
+        if length > available {
+            v.error(
+                sample_header_offset(index) + 22,
+                format!(
+                    "Sample {} is {length} bytes, but only {available} are left in the file \
+                     for it at {offset:#x}",
+                    index + 1
+                ),
+            );
+        }
+
+        offset += length;
+    }
+
+    if offset < data.len() {
+        v.warning(
+            offset,
+            format!(
+                "{} bytes of unused data after the last sample",
+                data.len() - offset
+            ),
+        );
+    }
+}
+
+fn sample_header_offset(index: usize) -> usize {
+    TITLE_SIZE + SAMPLE_HEADER_SIZE * index
+}
+
+// Checks the header of a sample, returning it as the loader would read it
+fn check_sample_header(v: &mut Validator, index: usize) -> Sample {
+    let offset = sample_header_offset(index);
+    let number = index + 1;
+
+    let name = &v.data[offset..offset + 22];
+    if core::str::from_utf8(name).is_err() {
+        v.error(
+            offset,
+            format!("Name of sample {number} isn't valid UTF-8, the loader refuses it"),
+        );
+    }
+
+    let length = v.u16_at(offset + 22) as usize * 2;
+
+    let raw_finetune = v.u8_at(offset + 24);
+    if raw_finetune & 0xF0 != 0 {
+        v.warning(
+            offset + 24,
+            format!(
+                "Finetune {raw_finetune:#04x} of sample {number} has bits set above the lowest 4"
+            ),
+        );
+    }
+
+    let volume = v.u8_at(offset + 25);
+    if volume > 64 {
+        v.warning(
+            offset + 25,
+            format!("Volume {volume} of sample {number} is above the maximum of 64"),
+        );
+    }
+
+    let repeat_offset = v.u16_at(offset + 26) as usize * 2;
+    let repeat_length = v.u16_at(offset + 28) as usize * 2;
+
+    // A length of 2 (one word) means the sample doesn't loop
+    if repeat_length > 2 && repeat_offset + repeat_length > length {
+        v.warning(
+            offset + 26,
+            format!(
+                "Loop of sample {number} ends at {}, past the end of the sample at {length}",
+                repeat_offset + repeat_length
+            ),
+        );
+    }
+
+    Sample {
+        name: String::from_utf8_lossy(name)
+            .trim_end_matches('\0')
+            .to_string(),
+        length: length as u32,
+        finetune: mod_loader::decode_finetune(raw_finetune),
+        volume,
+        repeat_offset: repeat_offset as u32,
+        repeat_length: repeat_length as u32,
+    }
+}
+
+fn check_patterns(
+    v: &mut Validator,
+    patterns_offset: usize,
+    pattern_size: usize,
+    pattern_count: usize,
+    orders: &[u8],
+    table_offset: usize,
+    sample_count: usize,
+) {
+    let data = v.data;
+    let row_size = pattern_size / ROWS_PER_PATTERN;
+
+    // Reported once each with the first place they're used, a song may use them thousands of times
+    let mut unsupported: BTreeMap<(u8, u8), Occurrence> = BTreeMap::new();
+    let mut invalid_samples: BTreeMap<u8, Occurrence> = BTreeMap::new();
+
+    for pattern in 0..pattern_count {
+        let start = patterns_offset + pattern * pattern_size;
+        let end = start + pattern_size;
+
+        if end > data.len() {
+            let played: Vec<String> = (orders.iter().enumerate())
+                .filter(|(_, &p)| p as usize == pattern)
+                .map(|(order, _)| order.to_string())
+                .collect();
+
+            let present = data.len().saturating_sub(start);
+            let mut message = if present == 0 {
+                format!("Pattern {pattern} is missing from the file")
+            } else {
+                format!("Pattern {pattern} is cut off after {present} of {pattern_size} bytes")
+            };
+
+            if played.is_empty() {
+                message += ", the loader refuses the file even though it isn't played";
+                v.error(start, message);
+            } else {
+                message += &format!(" (played at order {})", played.join(", "));
+                let order: usize = played[0].parse().unwrap_or_default();
+                v.error(table_offset + order, message);
+            }
+
+            continue;
+        }
+
+        for (row, line) in data[start..end].chunks_exact(row_size).enumerate() {
+            for (channel, note) in line.chunks_exact(NOTE_SIZE).enumerate() {
+                let offset = start + row * row_size + channel * NOTE_SIZE;
+                let occurrence = || Occurrence {
+                    offset,
+                    pattern,
+                    row,
+                    channel,
+                    count: 0,
+                };
+
+                let sample = (note[0] & 0xF0) | (note[2] >> 4);
+                if sample as usize > sample_count {
+                    invalid_samples
+                        .entry(sample)
+                        .or_insert_with(occurrence)
+                        .count += 1;
+                }
+
+                if let Some(key) = unsupported_effect(note[2] & 0x0F, note[3]) {
+                    unsupported.entry(key).or_insert_with(occurrence).count += 1;
+                }
+            }
+        }
+    }
+
+    for (sample, first) in invalid_samples {
+        v.warning(
+            first.offset,
+            format!(
+                "Sample {sample} doesn't exist, the module has {sample_count} samples ({})",
+                first.location()
+            ),
+        );
+    }
+
+    for ((effect, sub), first) in unsupported {
+        let name = if effect == 0xE {
+            format!("E{sub:X}x")
+        } else {
+            format!("{effect:X}xx")
+        };
+        v.warning(
+            first.offset,
+            format!(
+                "Effect {name} isn't supported by the player ({})",
+                first.location()
+            ),
+        );
+    }
+}
+
+// What an effect the player doesn't support is reported as, `None` if it's supported.
+// Extended effects are told apart by their first argument digit
+#[cfg(feature = "std")]
+fn unsupported_effect(effect: u8, argument: u8) -> Option<(u8, u8)> {
+    if engine::supports_effect(effect, argument) {
+        None
+    } else if effect == 0xE {
+        Some((effect, argument >> 4))
+    } else {
+        Some((effect, 0))
+    }
+}
+
+// Without std there's no player to check the effects against
+#[cfg(not(feature = "std"))]
+fn unsupported_effect(_effect: u8, _argument: u8) -> Option<(u8, u8)> {
+    None
+}
+
+impl Occurrence {
+    fn location(&self) -> String {
+        let times = match self.count {
+            1 => String::from("once"),
+            count => format!("{count} times"),
+        };
+
+        format!(
+            "first at pattern {}, row {}, channel {}, used {times}",
+            self.pattern,
+            self.row,
+            self.channel + 1
+        )
+    }
+}
+
+// A valid 4 channel module with one pattern and a 16 byte sample, which the tests damage
+#[cfg(test)]
+fn fixture() -> Vec<u8> {
+    use super::mod_writer;
+    use crate::song::{Note, PCMData, SongBuilder};
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0xC,
+        argument: 32,
+    };
+    let song = SongBuilder::new(4)
+        .sample("Sample", PCMData::I8(alloc::vec![1; 16]))
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .build()
+        .unwrap();
+    mod_writer::write(&song).unwrap()
+}
+
+// Offsets in the fixture
+#[cfg(test)]
+const TABLE_OFFSET: usize = TITLE_SIZE + SAMPLE_HEADER_SIZE * 31 + 2;
+#[cfg(test)]
+const PATTERNS_OFFSET: usize = TABLE_OFFSET + PATTERN_TABLE_SIZE + 4;
+#[cfg(test)]
+const FIXTURE_SIZE: usize = PATTERNS_OFFSET + ROWS_PER_PATTERN * 4 * NOTE_SIZE + 16;
+
+#[cfg(test)]
+fn assert_problem(data: &[u8], offset: usize, severity: Severity, text: &str) {
+    let problems = validate(data);
+    assert!(
+        problems.iter().any(|problem| problem.offset == offset
+            && problem.severity == severity
+            && problem.message.contains(text)),
+        "No {severity} {text:?} at {offset:#x}: {problems:?}"
+    );
+}
+
+#[test]
+fn valid_module_has_no_problems() {
+    let data = fixture();
+    assert_eq!(data.len(), FIXTURE_SIZE);
+    assert!(validate(&data).is_empty(), "{:?}", validate(&data));
+}
+
+#[test]
+fn truncated_header() {
+    let mut data = fixture();
+    // Without the format tag it's taken for a module with 15 samples
+    data.truncate(500);
+    assert_problem(&data, 500, Severity::Error, "inside the 600 byte header");
+    assert_eq!(validate(&data).len(), 1);
+}
+
+#[test]
+fn title_not_utf8() {
+    let mut data = fixture();
+    data[3] = 0xFF;
+    assert_problem(&data, 0, Severity::Error, "Title isn't valid UTF-8");
+}
+
+#[test]
+fn sample_name_not_utf8() {
+    let mut data = fixture();
+    data[sample_header_offset(1) + 2] = 0xFF;
+    assert_problem(
+        &data,
+        sample_header_offset(1),
+        Severity::Error,
+        "Name of sample 2",
+    );
+}
+
+#[test]
+fn samples_longer_than_64_kib() {
+    // Samples go up to 128 KiB, it's only a problem when the data isn't there
+    let mut data = fixture();
+    data[sample_header_offset(1) + 22] = 0x80;
+    assert_problem(
+        &data,
+        sample_header_offset(1) + 22,
+        Severity::Error,
+        "Sample 2 is 65536 bytes, but only 0 are left",
+    );
+    assert_eq!(validate(&data).len(), 1, "{:?}", validate(&data));
+}
+
+#[test]
+fn finetune_high_bits() {
+    let mut data = fixture();
+    data[sample_header_offset(0) + 24] = 0x17;
+    assert_problem(
+        &data,
+        sample_header_offset(0) + 24,
+        Severity::Warning,
+        "Finetune 0x17 of sample 1",
+    );
+}
+
+#[test]
+fn volume_above_maximum() {
+    let mut data = fixture();
+    data[sample_header_offset(0) + 25] = 65;
+    assert_problem(
+        &data,
+        sample_header_offset(0) + 25,
+        Severity::Warning,
+        "Volume 65 of sample 1",
+    );
+}
+
+#[test]
+fn loop_past_sample_end() {
+    let mut data = fixture();
+    // From word 4 for 8 words, the sample is 8 words long
+    let offset = sample_header_offset(0) + 26;
+    data[offset..offset + 4].copy_from_slice(&[0, 4, 0, 8]);
+    assert_problem(
+        &data,
+        offset,
+        Severity::Warning,
+        "ends at 24, past the end of the sample at 16",
+    );
+}
+
+#[test]
+fn song_length_out_of_range() {
+    let mut data = fixture();
+    data[TABLE_OFFSET - 2] = 0;
+    assert_problem(
+        &data,
+        TABLE_OFFSET - 2,
+        Severity::Warning,
+        "Song length 0 is outside 1-128, it's played as 1",
+    );
+}
+
+#[test]
+fn pattern_255_in_table() {
+    let mut data = fixture();
+    data[TABLE_OFFSET + 5] = 255;
+    assert_problem(
+        &data,
+        TABLE_OFFSET + 5,
+        Severity::Error,
+        "refers to pattern 255",
+    );
+}
+
+#[test]
+fn unknown_format_tag() {
+    let mut data = fixture();
+    data[FORMAT_TAG_OFFSET..FORMAT_TAG_OFFSET + 4].copy_from_slice(b"ABCD");
+    assert_problem(
+        &data,
+        FORMAT_TAG_OFFSET,
+        Severity::Warning,
+        "Unknown format tag \"ABCD\", guessed 4 channels",
+    );
+    assert_eq!(validate(&data).len(), 1);
+}
+
+#[test]
+fn no_room_for_channels() {
+    let mut data = fixture();
+    data[FORMAT_TAG_OFFSET..FORMAT_TAG_OFFSET + 4].copy_from_slice(b"ABCD");
+    data.truncate(PATTERNS_OFFSET + 100);
+    assert_problem(
+        &data,
+        PATTERNS_OFFSET,
+        Severity::Error,
+        "don't fit in the file",
+    );
+}
+
+#[test]
+fn played_pattern_cut_off() {
+    let mut data = fixture();
+    data[TABLE_OFFSET - 2] = 2;
+    data[TABLE_OFFSET + 1] = 1;
+    assert_problem(
+        &data,
+        TABLE_OFFSET + 1,
+        Severity::Error,
+        "Pattern 1 is cut off after 16 of 1024 bytes (played at order 1)",
+    );
+}
+
+#[test]
+fn unplayed_pattern_missing() {
+    let mut data = fixture();
+    data.truncate(FIXTURE_SIZE - 16);
+    data[TABLE_OFFSET + 5] = 1;
+    assert_problem(
+        &data,
+        PATTERNS_OFFSET + 1024,
+        Severity::Error,
+        "Pattern 1 is missing from the file, the loader refuses the file",
+    );
+}
+
+#[test]
+fn sample_data_cut_off() {
+    let mut data = fixture();
+    data.truncate(FIXTURE_SIZE - 6);
+    assert_problem(
+        &data,
+        sample_header_offset(0) + 22,
+        Severity::Error,
+        "Sample 1 is 16 bytes, but only 10 are left",
+    );
+}
+
+#[test]
+fn unused_data_after_samples() {
+    let mut data = fixture();
+    data.extend_from_slice(&[0; 10]);
+    assert_problem(
+        &data,
+        FIXTURE_SIZE,
+        Severity::Warning,
+        "10 bytes of unused data",
+    );
+}
+
+#[test]
+fn missing_sample_used() {
+    let mut data = fixture();
+    // Sample 32 on row 1, channel 3
+    let offset = PATTERNS_OFFSET + 16 + 2 * NOTE_SIZE;
+    data[offset] = 0x20;
+    assert_problem(
+        &data,
+        offset,
+        Severity::Warning,
+        "Sample 32 doesn't exist, the module has 31 samples (first at pattern 0, row 1, \
+         channel 3, used once)",
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn unsupported_effects() {
+    let mut data = fixture();
+    // 7xx on row 2 and E5x on row 3 and 4, all on channel 1
+    let tremolo = PATTERNS_OFFSET + 2 * 16;
+    data[tremolo + 2..tremolo + 4].copy_from_slice(&[0x07, 0x11]);
+    let finetune = PATTERNS_OFFSET + 3 * 16;
+    data[finetune + 2..finetune + 4].copy_from_slice(&[0x0E, 0x52]);
+    data[finetune + 18..finetune + 20].copy_from_slice(&[0x0E, 0x53]);
+
+    assert_problem(
+        &data,
+        tremolo,
+        Severity::Warning,
+        "Effect 7xx isn't supported by the player (first at pattern 0, row 2, channel 1, \
+         used once)",
+    );
+    assert_problem(
+        &data,
+        finetune,
+        Severity::Warning,
+        "Effect E5x isn't supported by the player (first at pattern 0, row 3, channel 1, \
+         used 2 times)",
+    );
+}
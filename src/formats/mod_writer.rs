@@ -0,0 +1,203 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::mod_loader::{PATTERN_TABLE_SIZE, ROWS_PER_PATTERN};
+use crate::bytereader::Encoding;
+use crate::bytewriter::ByteWriter;
+use crate::song::{PCMData, Song, SongError};
+
+const SAMPLE_COUNT: usize = 31;
+
+/// The tag a module with `channel_count` channels is saved with, the way the trackers that
+/// introduced them name them
+pub fn format_tag(channel_count: u8, pattern_count: usize) -> String {
+    match channel_count {
+        1..=3 => format!("TDZ{channel_count}"),
+        // ProTracker switches tags once there are more patterns than it originally supported
+        4 if pattern_count > 64 => String::from("M!K!"),
+        4 => String::from("M.K."),
+        5..=9 => format!("{channel_count}CHN"),
+        _ => format!("{channel_count}CH"),
+    }
+}
+
+/// Saves a song as a MOD file with 31 samples, which every ProTracker compatible player loads.
+///
+/// Songs with 15 samples are saved with 31, the rest left empty. Only the patterns the
+/// pattern table reaches are saved, MOD files have no room for others.
+///
+/// # Errors
+/// When the song doesn't fit in a MOD file, e.g. with more than 31 samples or a longer title,
+/// or the pattern table refers to a pattern that doesn't exist
+pub fn write(song: &Song) -> Result<Vec<u8>, SongError> {
+    let metadata = song.metadata();
+
+    if metadata.channels() > 32 {
+        return Err(SongError::LimitExceeded {
+            what: "Channel count",
+            found: metadata.channels(),
+            limit: 32,
+        });
+    }
+    if metadata.instruments().len() > SAMPLE_COUNT {
+        return Err(SongError::LimitExceeded {
+            what: "Sample count",
+            found: metadata.instruments().len(),
+            limit: SAMPLE_COUNT,
+        });
+    }
+    if metadata.orders().len() > PATTERN_TABLE_SIZE {
+        return Err(SongError::LimitExceeded {
+            what: "Order count",
+            found: metadata.orders().len(),
+            limit: PATTERN_TABLE_SIZE,
+        });
+    }
+
+    // Every pattern up to the highest one in the table is stored, played or not
+    let mut pattern_table = metadata.pattern_table().to_vec();
+    pattern_table.resize(PATTERN_TABLE_SIZE, 0);
+    let pattern_count = pattern_table
+        .iter()
+        .max()
+        .map_or(0, |&max| max as usize + 1);
+
+    let mut writer = ByteWriter::new(Encoding::BigEndian);
+    writer.write_str(metadata.title(), 20)?;
+
+    for index in 0..SAMPLE_COUNT {
+        let Some(sample) = metadata.instrument(index) else {
+            writer.write_bytes(&[0; 22]);
+            writer.write_bytes(&[0, 0, 0, 0, 0, 0, 0, 1]);
+            continue;
+        };
+
+        writer.write_str(&sample.name, 22)?;
+        // Lengths and loops are stored in words, an odd last byte is padded. Songs are
+        // built and loaded with samples no longer than the words can hold
+        writer.write_u16(sample.length.div_ceil(2) as u16);
+        writer.write_u8((sample.finetune as u8) & 0x0F);
+        writer.write_u8(sample.volume);
+        writer.write_u16((sample.repeat_offset / 2) as u16);
+        // A loop of one word means the sample doesn't loop
+        writer.write_u16((sample.repeat_length / 2).max(1) as u16);
+    }
+
+    writer.write_u8(metadata.orders().len() as u8);
+    writer.write_i8(metadata.restart_position());
+    writer.write_bytes(&pattern_table);
+    writer.write_str(&format_tag(metadata.channels() as u8, pattern_count), 4)?;
+
+    for index in 0..pattern_count {
+        let pattern = song
+            .pattern(index)
+            .ok_or(SongError::NoSuchPattern { index })?;
+
+        for row in 0..ROWS_PER_PATTERN {
+            for channel in 0..metadata.channels() {
+                let Some(note) = pattern.get(row).and_then(|line| line.get(channel)) else {
+                    writer.write_bytes(&[0; 4]);
+                    continue;
+                };
+
+                if note.period > 0x0FFF || note.effect > 0x0F {
+                    return Err(SongError::InvalidNote {
+                        pattern: index,
+                        row,
+                        channel,
+                    });
+                }
+
+                // The inverse of the layout `mod_loader` reads
+                writer.write_bytes(&[
+                    (note.sample & 0xF0) | (note.period >> 8) as u8,
+                    note.period as u8,
+                    (note.sample << 4) | note.effect,
+                    note.argument,
+                ]);
+            }
+        }
+    }
+
+    for (index, sample) in metadata.instruments().iter().enumerate() {
+        let length = sample.length as usize;
+        let start = writer.position();
+
+        match song.sample_data(index) {
+            Some(PCMData::I8(data)) => {
+                let data = &data[..length.min(data.len())];
+                writer.write_bytes(&data.iter().map(|&s| s as u8).collect::<Vec<_>>());
+            }
+            // MOD samples are 8-bit, only the high byte is kept
+            Some(PCMData::I16(data)) => {
+                for &s in data.iter().take(length) {
+                    writer.write_i8((s >> 8) as i8);
+                }
+            }
+            None => {}
+        }
+
+        // Silence for data that's missing, and the padding to a whole word
+        let written = writer.position() - start;
+        writer.write_bytes(&alloc::vec![0; length.next_multiple_of(2) - written]);
+    }
+
+    Ok(writer.into_inner())
+}
+
+#[test]
+fn written_song_loads_the_same() {
+    use crate::song::{Note, SongBuilder};
+
+    let song = SongBuilder::new(4)
+        .title("Round trip")
+        .sample("Square", PCMData::I8(alloc::vec![64, 64, -64, -64, 64]))
+        .sample_volume(0, 48)
+        .sample_finetune(0, -3)
+        .sample_loop(0, 0, 4)
+        .empty_pattern()
+        .empty_pattern()
+        .note(
+            1,
+            63,
+            3,
+            Note {
+                sample: 1,
+                period: 428,
+                effect: 0xC,
+                argument: 0x20,
+            },
+        )
+        .orders(&[1, 0, 1])
+        .build()
+        .unwrap();
+
+    let loaded = crate::formats::mod_loader::parse(write(&song).unwrap()).unwrap();
+
+    let (metadata, loaded_metadata) = (song.metadata(), loaded.metadata());
+    assert_eq!(loaded_metadata.title(), metadata.title());
+    assert_eq!(loaded_metadata.orders(), metadata.orders());
+    assert_eq!(loaded_metadata.pattern_table(), metadata.pattern_table());
+    assert_eq!(loaded_metadata.format_tag(), metadata.format_tag());
+    assert_eq!(
+        format!("{:?}", loaded_metadata.tracker()),
+        format!("{:?}", metadata.tracker())
+    );
+    // The samples the song doesn't have are saved empty
+    assert_eq!(loaded_metadata.instruments().len(), 31);
+    assert_eq!(
+        format!("{:?}", loaded_metadata.instrument(0)),
+        format!("{:?}", metadata.instrument(0))
+    );
+    assert_eq!(
+        format!("{:?}", loaded.patterns()),
+        format!("{:?}", song.patterns())
+    );
+    assert_eq!(
+        format!("{:?}", loaded.sample_data(0)),
+        format!("{:?}", song.sample_data(0))
+    );
+    // The builder pads samples to whole words, the way they're stored
+    assert_eq!(song.metadata().instrument(0).unwrap().length, 6);
+}
@@ -0,0 +1,290 @@
+use alloc::vec::Vec;
+
+use crate::bytereader::Encoding;
+use crate::bytewriter::ByteWriter;
+use crate::song::{Note, PCMData, Song, SongError};
+use crate::tracker::{self, fingerprint};
+
+const ID_TEXT: &str = "Extended Module: ";
+const TRACKER_NAME: &str = "Rustune";
+const VERSION: u16 = 0x0104;
+// The header size counts itself and the pattern order table after it
+const HEADER_SIZE: u32 = 20 + 256;
+const ORDER_TABLE_SIZE: usize = 256;
+const MAX_CHANNELS: usize = 32;
+const ROWS_PER_PATTERN: u16 = 64;
+
+// Instrument headers with samples take this much, the rest is reserved
+const INSTRUMENT_HEADER_SIZE: u32 = 263;
+// Without samples they end after the sample count
+const EMPTY_INSTRUMENT_HEADER_SIZE: u32 = 29;
+const SAMPLE_HEADER_SIZE: u32 = 40;
+
+// XM notes count from C-0 as 1, ProTracker's from the bottom of its extended range as 0.
+// C-2 of ProTracker (period 428) plays at the rate of C-4 in XM
+const NOTE_OFFSET: usize = 13;
+const LAST_NOTE: usize = 96;
+
+// Flags of a packed pattern cell, telling which of the fields follow
+const PACKED: u8 = 0x80;
+const HAS_NOTE: u8 = 0x01;
+const HAS_INSTRUMENT: u8 = 0x02;
+const HAS_EFFECT: u8 = 0x08;
+const HAS_ARGUMENT: u8 = 0x10;
+
+// Sample types
+const FORWARD_LOOP: u8 = 0x01;
+const SIXTEEN_BIT: u8 = 0x10;
+const CENTER: u8 = 0x80;
+
+/// Saves a song as an XM module, which modern trackers open for editing.
+///
+/// Periods become the notes they play, every sample an instrument of its own and effects
+/// their XM equivalents. XM effects repeat their last argument when it's 0 where MOD
+/// effects do nothing, those are dropped or continue without sliding. The song uses Amiga
+/// frequencies, so slides sound the same. How the tracker that made the song plays it is
+/// kept where XM can tell, e.g. Fxx from 20 stays a speed for NoiseTracker songs.
+///
+/// # Errors
+/// When the song doesn't fit in an XM file, e.g. with more than 32 channels, or the pattern
+/// table refers to a pattern that doesn't exist
+pub fn write(song: &Song) -> Result<Vec<u8>, SongError> {
+    let metadata = song.metadata();
+    let quirks = fingerprint::fingerprint(song, None).quirks();
+
+    if metadata.channels() > MAX_CHANNELS {
+        return Err(SongError::LimitExceeded {
+            what: "Channel count",
+            found: metadata.channels(),
+            limit: MAX_CHANNELS,
+        });
+    }
+    // Trackers only take an even amount of channels, odd ones get a silent one
+    let channels = metadata.channels().next_multiple_of(2);
+
+    let pattern_count = (metadata.pattern_table().iter())
+        .max()
+        .map_or(0, |&max| max as usize + 1);
+    let song_length = metadata.orders().len();
+    let restart = match metadata.restart_position() as usize {
+        restart if quirks.restart && restart < song_length => restart,
+        _ => 0,
+    };
+
+    let mut writer = ByteWriter::new(Encoding::LittleEndian);
+    writer.write_str(ID_TEXT, ID_TEXT.len())?;
+    writer.write_str(metadata.title(), 20)?;
+    writer.write_u8(0x1A);
+    writer.write_str(TRACKER_NAME, 20)?;
+    writer.write_u16(VERSION);
+
+    writer.write_u32(HEADER_SIZE);
+    writer.write_u16(song_length as u16);
+    writer.write_u16(restart as u16);
+    writer.write_u16(channels as u16);
+    writer.write_u16(pattern_count as u16);
+    writer.write_u16(metadata.instruments().len() as u16);
+    // Amiga frequencies rather than linear ones
+    writer.write_u16(0);
+    writer.write_u16(metadata.initial_speed() as u16);
+    writer.write_u16(metadata.initial_tempo());
+    let mut order_table = metadata.orders().to_vec();
+    order_table.resize(ORDER_TABLE_SIZE, 0);
+    writer.write_bytes(&order_table);
+
+    for index in 0..pattern_count {
+        let pattern = song
+            .pattern(index)
+            .ok_or(SongError::NoSuchPattern { index })?;
+
+        let mut packed = Vec::new();
+        for row in 0..ROWS_PER_PATTERN as usize {
+            for channel in 0..channels {
+                let note = pattern.get(row).and_then(|line| line.get(channel));
+                pack_cell(&mut packed, note.map(|note| convert_note(note, quirks)));
+            }
+        }
+
+        writer.write_u32(9); // Pattern header size
+        writer.write_u8(0); // Packing type
+        writer.write_u16(ROWS_PER_PATTERN);
+        writer.write_u16(packed.len() as u16);
+        writer.write_bytes(&packed);
+    }
+
+    for (index, sample) in metadata.instruments().iter().enumerate() {
+        let data = song.sample_data(index);
+        let length = sample.length as usize;
+
+        if length == 0 || data.is_none() {
+            writer.write_u32(EMPTY_INSTRUMENT_HEADER_SIZE);
+            writer.write_str(&sample.name, 22)?;
+            writer.write_u8(0); // Type
+            writer.write_u16(0); // Samples
+            continue;
+        }
+
+        let header_start = writer.position();
+        writer.write_u32(INSTRUMENT_HEADER_SIZE);
+        writer.write_str(&sample.name, 22)?;
+        writer.write_u8(0); // Type
+        writer.write_u16(1); // Samples
+        writer.write_u32(SAMPLE_HEADER_SIZE);
+        // Every note plays the one sample, without envelopes, vibrato or fade out
+        writer.write_bytes(&[0; 96 + 48 + 48 + 2 + 6 + 2 + 4 + 2]);
+        let written = writer.position() - header_start;
+        writer.write_bytes(&alloc::vec![0; INSTRUMENT_HEADER_SIZE as usize - written]);
+
+        let sixteen_bit = matches!(data, Some(PCMData::I16(_)));
+        let bytes_per_sample = if sixteen_bit { 2 } else { 1 };
+        // A loop of one word or less means the sample doesn't loop
+        let looped = sample.repeat_length > 2;
+        let (loop_start, loop_length) = if looped {
+            (sample.repeat_offset, sample.repeat_length)
+        } else {
+            (0, 0)
+        };
+        let finetune = if quirks.finetune { sample.finetune } else { 0 };
+
+        writer.write_u32(length as u32 * bytes_per_sample);
+        writer.write_u32(loop_start * bytes_per_sample);
+        writer.write_u32(loop_length * bytes_per_sample);
+        writer.write_u8(sample.volume.min(64));
+        // MOD finetunes are eighths of a semitone, XM ones 128ths
+        writer.write_i8(finetune.clamp(-8, 7) * 16);
+        let mut kind = if looped { FORWARD_LOOP } else { 0 };
+        if sixteen_bit {
+            kind |= SIXTEEN_BIT;
+        }
+        writer.write_u8(kind);
+        writer.write_u8(CENTER);
+        writer.write_i8(0); // Relative note
+        writer.write_u8(0); // Reserved
+        writer.write_str(&sample.name, 22)?;
+
+        // Samples are stored as the difference to the sample before
+        match data {
+            Some(PCMData::I8(data)) => {
+                let mut previous = 0i8;
+                for &value in data.iter().take(length) {
+                    writer.write_i8(value.wrapping_sub(previous));
+                    previous = value;
+                }
+                writer.write_bytes(&alloc::vec![0; length.saturating_sub(data.len())]);
+            }
+            Some(PCMData::I16(data)) => {
+                let mut previous = 0i16;
+                for &value in data.iter().take(length) {
+                    writer.write_i16(value.wrapping_sub(previous));
+                    previous = value;
+                }
+                writer.write_bytes(&alloc::vec![0; 2 * length.saturating_sub(data.len())]);
+            }
+            None => {}
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+// A cell as XM stores it: note, instrument, effect and argument, 0 when there's none. The
+// volume column is left empty, MOD sets volumes with Cxx
+fn convert_note(note: &Note, quirks: fingerprint::Quirks) -> [u8; 4] {
+    let number = tracker::protracker_period_to_semitone(note.period, 0)
+        .map_or(0, |semitone| (semitone + NOTE_OFFSET).min(LAST_NOTE) as u8);
+
+    let (effect, argument) = match (note.effect, note.argument) {
+        // Slides and the continued slides of 5 and 6 would repeat the last argument
+        (0x1 | 0x2 | 0xA, 0) => (0, 0),
+        (0x5, 0) => (0x3, 0),
+        (0x6, 0) => (0x4, 0),
+        (0xE, 0x10 | 0x20 | 0xA0 | 0xB0) => (0, 0),
+        // Trackers that didn't pan used these for other things, or nothing
+        (0x8, _) if !quirks.panning => (0, 0),
+        (0xE, argument) if argument >> 4 == 0x8 && !quirks.panning => (0, 0),
+        // Coarse panning only exists as 8xx
+        (0xE, argument) if argument >> 4 == 0x8 => (0x8, (argument & 0x0F) * 17),
+        // Trackers without tempo set the fastest speed from 20 on
+        (0xF, argument) if argument >= 0x20 && !quirks.tempo => (0xF, 0x1F),
+        (effect, argument) => (effect, argument),
+    };
+
+    [number, note.sample, effect, argument]
+}
+
+// Appends a cell, leaving out the fields that are 0
+fn pack_cell(packed: &mut Vec<u8>, cell: Option<[u8; 4]>) {
+    let [note, instrument, effect, argument] = cell.unwrap_or_default();
+    let fields = [
+        (HAS_NOTE, note),
+        (HAS_INSTRUMENT, instrument),
+        (HAS_EFFECT, effect),
+        (HAS_ARGUMENT, argument),
+    ];
+
+    let flags = (fields.iter())
+        .filter(|(_, value)| *value != 0)
+        .fold(PACKED, |flags, (flag, _)| flags | flag);
+    packed.push(flags);
+    packed.extend(
+        (fields.iter())
+            .filter(|(_, value)| *value != 0)
+            .map(|(_, value)| value),
+    );
+}
+
+#[test]
+fn written_song_has_xm_layout() {
+    use crate::song::SongBuilder;
+
+    let song = SongBuilder::new(4)
+        .title("Converted")
+        .sample("Saw", PCMData::I8(alloc::vec![0, 10, 20, -20]))
+        .sample_volume(0, 48)
+        .sample_finetune(0, -3)
+        .sample_loop(0, 0, 4)
+        .empty_pattern()
+        .note(
+            0,
+            0,
+            1,
+            Note {
+                sample: 1,
+                period: 428,
+                effect: 0xA,
+                argument: 0,
+            },
+        )
+        .orders(&[0, 0])
+        .build()
+        .unwrap();
+
+    let xm = write(&song).unwrap();
+    assert_eq!(&xm[..17], b"Extended Module: ");
+    assert_eq!(&xm[17..26], b"Converted");
+    assert_eq!(xm[37], 0x1A);
+    let header = |offset: usize| u16::from_le_bytes([xm[offset], xm[offset + 1]]);
+    assert_eq!(header(58), VERSION);
+    // Song length, restart, channels, patterns and instruments
+    assert_eq!([64, 66, 68, 70, 72].map(header), [2, 0, 4, 1, 1]);
+
+    // C-2 is C-4 in XM, with the sample, and A00 is dropped. The other cells are empty
+    let pattern = 60 + HEADER_SIZE as usize;
+    let packed_size = header(pattern + 7) as usize;
+    let packed = &xm[pattern + 9..pattern + 9 + packed_size];
+    assert_eq!(
+        &packed[..4],
+        [PACKED, PACKED | HAS_NOTE | HAS_INSTRUMENT, 49, 1]
+    );
+    assert_eq!(packed_size, 64 * 4 + 2);
+
+    // The sample follows its headers, as differences
+    let instrument = pattern + 9 + packed_size;
+    let sample = instrument + INSTRUMENT_HEADER_SIZE as usize;
+    assert_eq!(&xm[sample..sample + 4], 4u32.to_le_bytes());
+    assert_eq!(xm[sample + 12], 48);
+    assert_eq!(xm[sample + 13] as i8, -48);
+    assert_eq!(xm[sample + 14], FORWARD_LOOP);
+    let data = sample + SAMPLE_HEADER_SIZE as usize;
+    assert_eq!(xm[data..], [0, 10, 10, (-40i8) as u8]);
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Expands a wildcard pattern into the paths matching it, sorted.
+///
+/// `*` matches any part of a file or directory name and `?` a single character, neither
+/// matches a leading dot. `**` matches any amount of nested directories. A pattern without
+/// wildcards is returned as is, so missing files are still reported by whoever opens them.
+pub fn expand(pattern: &str) -> Vec<PathBuf> {
+    if !has_wildcards(pattern) {
+        return vec![PathBuf::from(pattern)];
+    }
+
+    let mut base = PathBuf::new();
+    let mut components = Path::new(pattern).components().peekable();
+
+    // Everything up to the first wildcard is taken literally
+    while let Some(component) =
+        components.next_if(|c| !has_wildcards(&c.as_os_str().to_string_lossy()))
+    {
+        base.push(component);
+    }
+
+    let rest: Vec<String> = components
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+
+    let mut paths = Vec::new();
+    walk(&base, &rest, &mut paths);
+
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+fn has_wildcards(text: &str) -> bool {
+    text.contains(['*', '?'])
+}
+
+fn walk(dir: &Path, rest: &[String], paths: &mut Vec<PathBuf>) {
+    let Some((component, rest)) = rest.split_first() else {
+        paths.push(dir.to_path_buf());
+        return;
+    };
+
+    if !has_wildcards(component) {
+        let path = dir.join(component);
+        if path.exists() {
+            walk(&path, rest, paths);
+        }
+        return;
+    }
+
+    // The current directory when nothing came before the wildcards
+    let listed = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(listed) else {
+        return;
+    };
+
+    if component == "**" {
+        // No directories at all, then every directory below this one
+        walk(dir, rest, paths);
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') && entry.path().is_dir() {
+                walk(&dir.join(name), &rest_with_self(component, rest), paths);
+            }
+        }
+        return;
+    }
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if matches(component, &name) {
+            walk(&dir.join(name), rest, paths);
+        }
+    }
+}
+
+// The remaining components for descending further while still inside `**`
+fn rest_with_self(component: &str, rest: &[String]) -> Vec<String> {
+    let mut components = vec![component.to_string()];
+    components.extend_from_slice(rest);
+    components
+}
+
+// Whether `name` matches a pattern of `*` and `?` wildcards
+fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and the name position it's currently matched up to
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the `*` take one more character and try again
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
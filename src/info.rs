@@ -0,0 +1,452 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::json::{self, JsonObject};
+use crate::playlist::Playlist;
+use crate::{create_engine, Args, DEFAULT_RENDER_RATE};
+use rustune::engine::TrackerEngine;
+use rustune::export::loudness::{self, Loudness};
+use rustune::formats::mod_validator::Severity;
+use rustune::song::{self, Song, SongError};
+use rustune::tracker::fingerprint::{self, Fingerprint};
+
+/// Which patterns `--dump-patterns` prints
+#[derive(Debug, Clone, Copy)]
+pub enum PatternSelection {
+    All,
+    Single(usize),
+}
+
+impl FromStr for PatternSelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(PatternSelection::All),
+            _ => value
+                .parse()
+                .map(PatternSelection::Single)
+                .map_err(|_| format!("Invalid pattern \"{value}\", expected a number or all")),
+        }
+    }
+}
+
+/// How `--dump-patterns` prints patterns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternFormat {
+    /// Tracker notation with line numbers, one block per pattern
+    Text,
+    /// OpenMPT's clipboard format, one block per pattern that can be pasted as is
+    OpenMpt,
+}
+
+impl FromStr for PatternFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(PatternFormat::Text),
+            "openmpt" | "modplug" => Ok(PatternFormat::OpenMpt),
+            _ => Err(format!(
+                "Unknown pattern format {value}, expected text or openmpt"
+            )),
+        }
+    }
+}
+
+/// Human readable summary of a module's metadata and sample table
+pub struct SongInfo<'a> {
+    pub song: &'a Song,
+    pub path: &'a Path,
+    /// Of the song rendered the way it would play, if it ends
+    pub loudness: Option<Loudness>,
+}
+
+impl Display for SongInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metadata = self.song.metadata();
+        let fingerprint = fingerprint_file(self.song, self.path);
+
+        writeln!(f, "File:      {}", self.path.display())?;
+        writeln!(f, "Title:     {}", metadata.title())?;
+        write!(f, "Tracker:   {fingerprint}")?;
+        if !fingerprint.evidence.is_empty() {
+            let evidence: Vec<String> =
+                fingerprint.evidence.iter().map(|e| e.to_string()).collect();
+            write!(f, " ({})", evidence.join(", "))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Format:    {}", metadata.format_tag().escape_default())?;
+        writeln!(f, "Channels:  {}", metadata.channels())?;
+        writeln!(f, "Patterns:  {}", metadata.pattern_count())?;
+        writeln!(
+            f,
+            "Orders:    {} (restart at {})",
+            metadata.orders().len(),
+            metadata.restart_position()
+        )?;
+        writeln!(f, "Speed:     {}", metadata.initial_speed())?;
+        writeln!(f, "Tempo:     {}", metadata.initial_tempo())?;
+        if let Some(loudness) = self.loudness {
+            writeln!(f, "Loudness:  {loudness}")?;
+        }
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            " #  {:<22}  {:>6}  {:>6}  {:>8}  Loop",
+            "Name", "Length", "Volume", "Finetune"
+        )?;
+
+        for (index, sample) in metadata.instruments().iter().enumerate() {
+            // Unused slots, there's nothing to show
+            if sample.name.trim().is_empty() && sample.length == 0 {
+                continue;
+            }
+
+            let repeat = if sample.repeat_length == 0 {
+                String::from("-")
+            } else {
+                format!(
+                    "{}-{}",
+                    sample.repeat_offset,
+                    sample.repeat_offset + sample.repeat_length
+                )
+            };
+
+            writeln!(
+                f,
+                "{:>2}  {:<22}  {:>6}  {:>6}  {:>8}  {}",
+                index + 1,
+                sample.name,
+                sample.length,
+                sample.volume,
+                sample.finetune,
+                repeat
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The name of the song, or its file name if the song doesn't have one
+pub fn title(song: &Song, path: &Path) -> String {
+    let name = song.metadata().title();
+    if name.trim().is_empty() {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        name.to_string()
+    }
+}
+
+// The tracker that saved the song, checking it against the size of the file it's loaded from
+fn fingerprint_file(song: &Song, path: &Path) -> Fingerprint {
+    let file_size = std::fs::metadata(path).ok().map(|file| file.len() as usize);
+    fingerprint::fingerprint(song, file_size)
+}
+
+/// Appends the same information as [`SongInfo`] to a JSON object
+pub fn metadata_json(object: JsonObject, song: &Song, path: &Path) -> JsonObject {
+    let metadata = song.metadata();
+    let fingerprint = fingerprint_file(song, path);
+    let evidence = (fingerprint.evidence.iter()).map(|e| json::string(&e.to_string()));
+
+    let samples = metadata
+        .instruments()
+        .iter()
+        .enumerate()
+        .map(|(index, sample)| {
+            JsonObject::new()
+                .value("index", index + 1)
+                .string("name", &sample.name)
+                .value("length", sample.length)
+                .value("volume", sample.volume)
+                .value("finetune", sample.finetune)
+                .value("repeat_offset", sample.repeat_offset)
+                .value("repeat_length", sample.repeat_length)
+                .finish()
+        });
+
+    object
+        .string("path", &path.to_string_lossy())
+        .string("title", metadata.title())
+        .string("tracker", &fingerprint.tracker.to_string())
+        .raw(
+            "version",
+            &fingerprint
+                .version
+                .map_or(String::from("null"), json::string),
+        )
+        .raw("evidence", &json::array(evidence))
+        .string("format", metadata.format_tag())
+        .value("channels", metadata.channels())
+        .value("patterns", metadata.pattern_count())
+        .value("orders", metadata.orders().len())
+        .value("restart", metadata.restart_position())
+        .value("speed", metadata.initial_speed())
+        .value("tempo", metadata.initial_tempo())
+        .raw("samples", &json::array(samples))
+}
+
+/// Adds the integrated loudness and the true peak to a JSON object, null if they weren't
+/// measured or the song is silent
+pub fn loudness_json(object: JsonObject, loudness: Option<Loudness>) -> JsonObject {
+    object
+        .raw(
+            "loudness_lufs",
+            &json::number(loudness.and_then(|l| l.integrated)),
+        )
+        .raw(
+            "true_peak_dbtp",
+            &json::number(loudness.and_then(|l| l.true_peak)),
+        )
+}
+
+/// One line summary of the playback position, e.g. `01:23 / 04:05  ord 07/24  row 32`
+pub struct Progress {
+    /// Seconds into the song, `None` if that isn't known
+    pub elapsed: Option<f64>,
+    /// Length of the song in seconds
+    pub duration: f64,
+    pub order: usize,
+    pub song_length: usize,
+    pub row: usize,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.elapsed {
+            Some(elapsed) => write_timecode(f, elapsed)?,
+            None => write!(f, "--:--")?,
+        }
+        write!(f, " / ")?;
+        write_timecode(f, self.duration)?;
+
+        write!(
+            f,
+            "  ord {:02}/{:02}  row {:02}",
+            self.order,
+            self.song_length.saturating_sub(1),
+            self.row
+        )
+    }
+}
+
+/// Compact header for a song that starts playing, e.g.
+/// `Title — ProTracker (M.K.), 4 channels, 04:05`
+pub struct NowPlaying<'a> {
+    pub song: &'a Song,
+    pub path: &'a Path,
+    /// Length of the song in seconds
+    pub duration: f64,
+}
+
+impl Display for NowPlaying<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metadata = self.song.metadata();
+        write!(
+            f,
+            "{} — {} ({}), {} channels, ",
+            title(self.song, self.path),
+            metadata.tracker(),
+            metadata.format_tag(),
+            metadata.channels()
+        )?;
+        write_timecode(f, self.duration)
+    }
+}
+
+// Minutes and seconds, e.g. 04:05
+fn write_timecode(f: &mut fmt::Formatter<'_>, seconds: f64) -> fmt::Result {
+    let seconds = seconds.max(0.0) as u64;
+    write!(f, "{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Prints the metadata and loudness of every file, for --info
+pub fn print_info(args: &Args, playlist: &Playlist) -> Result<(), Box<dyn Error>> {
+    for path in playlist.entries() {
+        let song = playlist.load(path)?;
+        let loudness = measure_loudness(&song, args)?;
+
+        if args.json {
+            let object = JsonObject::new().string("type", "info");
+            let object = metadata_json(object, &song, path);
+            println!("{}", loudness_json(object, loudness).finish());
+        } else {
+            let info = SongInfo {
+                song: &song,
+                path,
+                loudness,
+            };
+            println!("{info}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the problems found in every file, for --validate
+///
+/// # Errors
+/// When a file can't be read, or any of them has errors
+pub fn validate(args: &Args, playlist: &Playlist) -> Result<(), Box<dyn Error>> {
+    let mut failed = 0;
+
+    for path in playlist.entries() {
+        let problems = playlist.validate(path)?;
+        let errors = (problems.iter())
+            .filter(|problem| problem.severity == Severity::Error)
+            .count();
+        let warnings = problems.len() - errors;
+
+        if errors > 0 {
+            failed += 1;
+        }
+
+        if args.json {
+            let path = path.to_string_lossy();
+            for problem in &problems {
+                let object = JsonObject::new()
+                    .string("type", "problem")
+                    .string("path", &path)
+                    .value("offset", problem.offset)
+                    .string("severity", &problem.severity.to_string())
+                    .string("message", &problem.message);
+                println!("{}", object.finish());
+            }
+
+            let object = JsonObject::new()
+                .string("type", "validated")
+                .string("path", &path)
+                .value("errors", errors)
+                .value("warnings", warnings);
+            println!("{}", object.finish());
+        } else if problems.is_empty() {
+            println!("{}: OK", path.display());
+        } else {
+            println!("{}", path.display());
+            for problem in &problems {
+                println!("  {problem}");
+            }
+            println!("  {errors} errors, {warnings} warnings\n");
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{failed} of {} files have errors", playlist.len()).into());
+    }
+
+    Ok(())
+}
+
+/// Prints the patterns of every file, for --dump-patterns
+pub fn print_patterns(
+    playlist: &Playlist,
+    selection: PatternSelection,
+    format: PatternFormat,
+) -> Result<(), Box<dyn Error>> {
+    let mut out = io::stdout().lock();
+
+    for path in playlist.entries() {
+        let song = playlist.load(path)?;
+
+        match dump_patterns(&song, path, selection, format, &mut out) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            result => result.map_err(|e| e.to_string())?,
+        }
+    }
+
+    Ok(())
+}
+
+// Renders the song the way it would play to measure how loud it is, songs that repeat
+// forever can't be
+fn measure_loudness(song: &Song, args: &Args) -> Result<Option<Loudness>, SongError> {
+    let endless = args.loop_count == 0 || args.loop_region.is_some();
+    if endless && args.max_time.is_none() {
+        return Ok(None);
+    }
+
+    let mut engine = create_engine(song.clone(), args)?;
+    engine.set_channel_count(2);
+    engine.set_sample_rate(args.sample_rate.unwrap_or(DEFAULT_RENDER_RATE));
+
+    Ok(Some(loudness::measure(&mut engine)))
+}
+
+fn dump_patterns(
+    song: &Song,
+    path: &Path,
+    selection: PatternSelection,
+    format: PatternFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let patterns = match selection {
+        PatternSelection::All => 0..song.patterns().len(),
+        PatternSelection::Single(index) if index < song.patterns().len() => index..index + 1,
+        PatternSelection::Single(index) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} has no pattern {index}, it has {} patterns",
+                    path.display(),
+                    song.patterns().len()
+                ),
+            ))
+        }
+    };
+
+    if format == PatternFormat::OpenMpt {
+        // Only the rows themselves, anything else would end up in the pasted pattern
+        for index in patterns {
+            let pattern = &song.patterns()[index];
+            writeln!(out, "{}", song::OpenMptLineDisplay::HEADER)?;
+
+            for lineno in 0..pattern.len() {
+                writeln!(
+                    out,
+                    "{}",
+                    song::OpenMptLineDisplay {
+                        pattern,
+                        sample_metadata: song.metadata().instruments(),
+                        lineno,
+                    }
+                )?;
+            }
+
+            writeln!(out)?;
+        }
+
+        return Ok(());
+    }
+
+    writeln!(out, "{}", path.display())?;
+
+    for index in patterns {
+        let pattern = &song.patterns()[index];
+        writeln!(out, "Pattern {index}:")?;
+
+        for lineno in 0..pattern.len() {
+            writeln!(
+                out,
+                "{}",
+                song::SongLineDisplay {
+                    pattern,
+                    sample_metadata: song.metadata().instruments(),
+                    lineno,
+                }
+            )?;
+        }
+
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use std::io;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::player::Handle;
+use rustune::song::{Note, PCMData, Song, SongBuilder, SongError};
+use rustune::tracker;
+
+// How long to wait for key presses before drawing again
+const INPUT_POLL: Duration = Duration::from_millis(50);
+
+// The keys of two piano octaves on a QWERTY keyboard, as trackers lay them out: the bottom
+// row and the one above it from C, and the top two rows from the octave above. Semitones
+// count from C of the selected octave
+pub const KEYS: [(char, u8); 29] = [
+    ('z', 0),
+    ('s', 1),
+    ('x', 2),
+    ('d', 3),
+    ('c', 4),
+    ('v', 5),
+    ('g', 6),
+    ('b', 7),
+    ('h', 8),
+    ('n', 9),
+    ('j', 10),
+    ('m', 11),
+    (',', 12),
+    ('l', 13),
+    ('.', 14),
+    ('q', 12),
+    ('2', 13),
+    ('w', 14),
+    ('3', 15),
+    ('e', 16),
+    ('r', 17),
+    ('5', 18),
+    ('t', 19),
+    ('6', 20),
+    ('y', 21),
+    ('7', 22),
+    ('u', 23),
+    ('i', 24),
+    ('9', 25),
+];
+
+// ProTracker's three octaves, the keys reach one octave above the selected one
+pub const OCTAVES: std::ops::RangeInclusive<u8> = 1..=3;
+
+/// The samples of `song` with a single empty pattern, which keeps playing without notes of
+/// its own when it loops. Notes played on it use the song's mixer and channels
+pub fn jam_song(song: &Song) -> Result<Song, SongError> {
+    let metadata = song.metadata();
+
+    let mut builder = SongBuilder::new(metadata.channels() as u8).title(metadata.title());
+    for (index, sample) in metadata.instruments().iter().enumerate() {
+        let data = song
+            .sample_data(index)
+            .cloned()
+            .unwrap_or(PCMData::I8(Vec::new()));
+        builder = builder
+            .sample(&sample.name, data)
+            .sample_volume(index, sample.volume)
+            .sample_finetune(index, sample.finetune)
+            .sample_loop(index, sample.repeat_offset, sample.repeat_length);
+    }
+
+    builder.empty_pattern().build()
+}
+
+/// Full screen interface that plays the samples of a song from the keyboard.
+///
+/// Like [`crate::tui::Tui`], the terminal is in raw mode on the alternate screen while this is
+/// alive.
+pub struct Jam {
+    terminal: DefaultTerminal,
+    keyboard: Keyboard,
+}
+
+// What's played and shown, apart from the terminal it's drawn on
+struct Keyboard {
+    title: String,
+    // Names of the samples that have data, with their numbers counting from 1
+    samples: Vec<(u8, String)>,
+    channels: usize,
+
+    // Index into `samples`
+    selected: usize,
+    octave: u8,
+    // Notes go to the channels in turn, so the ones before them ring on
+    next_channel: usize,
+    // The note played last, for the status line
+    last_note: Option<u8>,
+}
+
+impl Jam {
+    pub fn new(song: &Song, title: String) -> io::Result<Self> {
+        let metadata = song.metadata();
+        let samples = (metadata.instruments().iter().enumerate())
+            .filter(|(_, sample)| sample.length > 0)
+            .map(|(index, sample)| (index as u8 + 1, sample.name.trim_end().to_owned()))
+            .collect();
+
+        Ok(Jam {
+            terminal: ratatui::try_init()?,
+            keyboard: Keyboard {
+                title,
+                samples,
+                channels: metadata.channels(),
+                selected: 0,
+                octave: 2,
+                next_channel: 0,
+                last_note: None,
+            },
+        })
+    }
+
+    /// Plays notes on the channels of the jam song through `player` until the user quits
+    pub fn run(&mut self, player: &Handle) -> io::Result<()> {
+        loop {
+            let keyboard = &mut self.keyboard;
+            self.terminal.draw(|frame| keyboard.draw(frame))?;
+
+            if event::poll(INPUT_POLL)? {
+                if let Event::Key(key) = event::read()? {
+                    if !keyboard.handle_key(key, player) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Keyboard {
+    // Returns false once the user quits
+    fn handle_key(&mut self, key: KeyEvent, player: &Handle) -> bool {
+        if key.kind != KeyEventKind::Press {
+            return true;
+        }
+
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Esc => return false,
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.samples.len().saturating_sub(1))
+            }
+            KeyCode::Left => self.octave = (self.octave - 1).max(*OCTAVES.start()),
+            KeyCode::Right => self.octave = (self.octave + 1).min(*OCTAVES.end()),
+            // Volume 0 on every channel silences looped samples too
+            KeyCode::Char(' ') => {
+                for channel in 0..self.channels {
+                    let silence = Note {
+                        sample: 0,
+                        period: 0,
+                        effect: 0xC,
+                        argument: 0,
+                    };
+                    player.play_note(channel, silence);
+                }
+                self.last_note = None;
+            }
+            KeyCode::Char(key) => self.play(key.to_ascii_lowercase(), player),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn play(&mut self, key: char, player: &Handle) {
+        let Some(&(_, semitone)) = KEYS.iter().find(|&&(mapped, _)| mapped == key) else {
+            return;
+        };
+        let Some(&(sample, _)) = self.samples.get(self.selected) else {
+            return;
+        };
+
+        // C-1 is note 24
+        let number = (self.octave + 1) * 12 + semitone;
+        let Some(period) = tracker::protracker_note_to_period(number as usize, 0) else {
+            return;
+        };
+
+        let note = Note {
+            sample,
+            period,
+            effect: 0,
+            argument: 0,
+        };
+        player.play_note(self.next_channel, note);
+        self.next_channel = (self.next_channel + 1) % self.channels.max(1);
+        self.last_note = Some(number);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, body] =
+            Layout::vertical([Constraint::Length(5), Constraint::Min(0)]).areas(frame.area());
+
+        let note = self.last_note.map_or(String::from("---"), |number| {
+            let name = tracker::NOTE_NAMES[number as usize % 12];
+            format!("{name}{}", number / 12 - 1)
+        });
+        let lines = vec![
+            Line::raw(format!("Octave {}   Last note {note}", self.octave)),
+            Line::raw("Z-M and Q-I play notes, the rows above them sharps"),
+            Line::raw("Up/Down sample, Left/Right octave, Space silences, Esc quits"),
+        ];
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(format!("Jam: {}", self.title))),
+            header,
+        );
+
+        // Keep the selected sample in view
+        let height = body.height.saturating_sub(2) as usize;
+        let first = self.selected.saturating_sub(height.saturating_sub(1));
+        let samples: Vec<Line> = (self.samples.iter().enumerate())
+            .skip(first)
+            .take(height)
+            .map(|(index, (number, name))| {
+                let line = format!("{number:02} {name}");
+                if index == self.selected {
+                    Line::styled(line, Style::new().add_modifier(Modifier::REVERSED))
+                } else {
+                    Line::raw(line)
+                }
+            })
+            .collect();
+        frame.render_widget(
+            Paragraph::new(samples).block(Block::bordered().title("Samples")),
+            body,
+        );
+    }
+}
+
+impl Drop for Jam {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
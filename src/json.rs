@@ -0,0 +1,404 @@
+use std::fmt::{Display, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Deepest nesting of arrays and objects that's parsed, deeper documents would overflow the
+// stack of the parser
+const MAX_DEPTH: usize = 64;
+
+/// Builds a single-line JSON object, for machine readable output
+pub struct JsonObject {
+    buffer: String,
+    empty: bool,
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        JsonObject {
+            buffer: String::from("{"),
+            empty: true,
+        }
+    }
+
+    fn key(&mut self, key: &str) {
+        if !self.empty {
+            self.buffer.push(',');
+        }
+
+        self.empty = false;
+        write_str(&mut self.buffer, key);
+        self.buffer.push(':');
+    }
+
+    /// Adds a string field, escaping it as needed
+    pub fn string(mut self, key: &str, value: &str) -> Self {
+        self.key(key);
+        write_str(&mut self.buffer, value);
+        self
+    }
+
+    /// Adds a number or boolean field, anything whose `Display` output is valid JSON
+    pub fn value(mut self, key: &str, value: impl Display) -> Self {
+        self.key(key);
+        let _ = write!(self.buffer, "{value}");
+        self
+    }
+
+    /// Adds a field containing already serialized JSON
+    pub fn raw(mut self, key: &str, json: &str) -> Self {
+        self.key(key);
+        self.buffer.push_str(json);
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.buffer.push('}');
+        self.buffer
+    }
+}
+
+impl Default for JsonObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Joins already serialized JSON values into an array
+pub fn array(values: impl IntoIterator<Item = String>) -> String {
+    let values: Vec<String> = values.into_iter().collect();
+    format!("[{}]", values.join(","))
+}
+
+/// Serializes a string value, escaping it as needed
+pub fn string(value: &str) -> String {
+    let mut buffer = String::new();
+    write_str(&mut buffer, value);
+    buffer
+}
+
+/// Serializes a number rounded to 2 decimals, or null without one
+pub fn number(value: Option<f64>) -> String {
+    value.map_or(String::from("null"), |value| format!("{value:.2}"))
+}
+
+fn write_str(buffer: &mut String, value: &str) {
+    buffer.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\r' => buffer.push_str("\\r"),
+            '\t' => buffer.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buffer, "\\u{:04x}", c as u32);
+            }
+            c => buffer.push(c),
+        }
+    }
+
+    buffer.push('"');
+}
+
+/// A parsed JSON value, for reading requests
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Fields in the order they were written
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The field `key` of an object, `None` for other values or a missing field
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// A number without a fractional part
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_f64()
+            .filter(|value| value.fract() == 0.0 && value.abs() < i64::MAX as f64)
+            .map(|value| value as i64)
+    }
+}
+
+/// Serializes strings, numbers, booleans and null, e.g. to echo a request's id. Arrays and
+/// objects are written as null
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Number(value) => write!(f, "{value}"),
+            Value::String(value) => f.write_str(&string(value)),
+            _ => f.write_str("null"),
+        }
+    }
+}
+
+/// Parses a JSON document, which mustn't be followed by anything but whitespace
+///
+/// # Errors
+/// A message saying what's wrong where, when `text` isn't valid JSON
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut chars = text.chars().peekable();
+    let value = parse_value(&mut chars, 0)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("Unexpected '{c}' after the value")),
+    }
+}
+
+// Parses the value that comes next, inside `depth` arrays and objects
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<Value, String> {
+    skip_whitespace(chars);
+
+    match chars.peek().copied() {
+        None => Err(String::from("Unexpected end")),
+        Some('{' | '[') if depth == MAX_DEPTH => {
+            Err(format!("Nested deeper than {MAX_DEPTH} levels"))
+        }
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            if next_is(chars, '}') {
+                return Ok(Value::Object(fields));
+            }
+            loop {
+                skip_whitespace(chars);
+                if chars.next() != Some('"') {
+                    return Err(String::from("Expected a field name"));
+                }
+                let key = parse_string(chars)?;
+                if !next_is(chars, ':') {
+                    return Err(format!("Expected ':' after \"{key}\""));
+                }
+                fields.push((key, parse_value(chars, depth + 1)?));
+                if next_is(chars, '}') {
+                    return Ok(Value::Object(fields));
+                }
+                if !next_is(chars, ',') {
+                    return Err(String::from("Expected ',' or '}' in an object"));
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut values = Vec::new();
+            if next_is(chars, ']') {
+                return Ok(Value::Array(values));
+            }
+            loop {
+                values.push(parse_value(chars, depth + 1)?);
+                if next_is(chars, ']') {
+                    return Ok(Value::Array(values));
+                }
+                if !next_is(chars, ',') {
+                    return Err(String::from("Expected ',' or ']' in an array"));
+                }
+            }
+        }
+        Some('"') => {
+            chars.next();
+            parse_string(chars).map(Value::String)
+        }
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            (number.parse().map(Value::Number)).map_err(|_| format!("Invalid number {number}"))
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                word.push(c);
+                chars.next();
+            }
+            match word.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "null" => Ok(Value::Null),
+                _ => Err(format!(
+                    "Unexpected '{}'",
+                    chars.peek().map_or(word, |c| c.to_string())
+                )),
+            }
+        }
+    }
+}
+
+// The rest of a string after its opening quote, with escapes resolved
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut value = String::new();
+
+    loop {
+        match chars.next() {
+            None => return Err(String::from("Unterminated string")),
+            Some('"') => return Ok(value),
+            Some('\\') => {
+                let c = match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let code = hex_escape(chars)?;
+                        // Characters outside the basic plane are escaped as surrogate pairs
+                        let code = if (0xD800..0xDC00).contains(&code) {
+                            if chars.next() != Some('\\') || chars.next() != Some('u') {
+                                return Err(String::from("Unpaired surrogate"));
+                            }
+                            let low = hex_escape(chars)?;
+                            0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                        } else {
+                            code
+                        };
+                        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    }
+                    Some(c @ ('"' | '\\' | '/')) => c,
+                    _ => return Err(String::from("Invalid escape")),
+                };
+                value.push(c);
+            }
+            Some(c) => value.push(c),
+        }
+    }
+}
+
+// The 4 hex digits of a \u escape
+fn hex_escape(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let digits: String = chars.take(4).collect();
+    u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid escape \\u{digits}"))
+}
+
+// Skips whitespace, then consumes `expected` if it comes next
+fn next_is(chars: &mut Peekable<Chars>, expected: char) -> bool {
+    skip_whitespace(chars);
+    chars.next_if_eq(&expected).is_some()
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+#[test]
+fn parses_values() {
+    let value = parse(r#" {"a": [1, -2.5, 3e2], "b": {"c": null}, "d": true, "e": false} "#);
+    assert_eq!(
+        value,
+        Ok(Value::Object(vec![
+            (
+                String::from("a"),
+                Value::Array(vec![
+                    Value::Number(1.0),
+                    Value::Number(-2.5),
+                    Value::Number(300.0)
+                ])
+            ),
+            (
+                String::from("b"),
+                Value::Object(vec![(String::from("c"), Value::Null)])
+            ),
+            (String::from("d"), Value::Bool(true)),
+            (String::from("e"), Value::Bool(false)),
+        ]))
+    );
+    assert_eq!(parse("[]"), Ok(Value::Array(Vec::new())));
+    assert_eq!(parse("{ }"), Ok(Value::Object(Vec::new())));
+}
+
+#[test]
+fn parses_escapes() {
+    let value = parse(r#""\"\\\/\n\r\t\b\f\u00e9\ud83c\udfb5""#);
+    assert_eq!(
+        value,
+        Ok(Value::String(String::from("\"\\/\n\r\t\u{8}\u{c}é🎵")))
+    );
+    assert!(parse(r#""\ud83c""#).is_err());
+    assert!(parse(r#""\x""#).is_err());
+    assert!(parse(r#""\u12""#).is_err());
+}
+
+#[test]
+fn rejects_invalid_documents() {
+    for text in [
+        "",
+        "{",
+        "[1,",
+        "[1 2]",
+        "{\"a\" 1}",
+        "{1: 2}",
+        "\"open",
+        "nul",
+        "1.2.3",
+        "{} x",
+        "[,]",
+    ] {
+        assert!(parse(text).is_err(), "{text:?} parsed");
+    }
+}
+
+#[test]
+fn limits_nesting() {
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(parse(&nested(MAX_DEPTH)).is_ok());
+    assert_eq!(
+        parse(&nested(MAX_DEPTH + 1)),
+        Err(format!("Nested deeper than {MAX_DEPTH} levels"))
+    );
+    // Far deeper than the stack would allow without the limit
+    assert!(parse(&"{\"a\":".repeat(1_000_000)).is_err());
+}
+
+#[test]
+fn value_accessors() {
+    let value = parse(r#"{"n": 4, "f": 1.5, "s": "x"}"#).unwrap();
+    assert_eq!(value.get("n").and_then(Value::as_i64), Some(4));
+    assert_eq!(value.get("f").and_then(Value::as_i64), None);
+    assert_eq!(value.get("f").and_then(Value::as_f64), Some(1.5));
+    assert_eq!(value.get("s").and_then(Value::as_str), Some("x"));
+    assert_eq!(value.get("missing"), None);
+    assert_eq!(Value::Number(1.0).get("n"), None);
+}
+
+#[test]
+fn writes_objects() {
+    let object = JsonObject::new()
+        .string("text", "a \"quote\"\n\u{1}")
+        .value("number", 1.5)
+        .raw("list", &array([string("x"), number(None)]))
+        .finish();
+    assert_eq!(
+        object,
+        r#"{"text":"a \"quote\"\n\u0001","number":1.5,"list":["x",null]}"#
+    );
+    assert_eq!(JsonObject::new().finish(), "{}");
+}
@@ -0,0 +1,74 @@
+//! Tracker module playback.
+//!
+//! Rustune loads tracker modules (currently ProTracker style MOD files) into a [`Song`],
+//! and plays them with an [`Engine`], which renders interleaved audio into any buffer
+//! through the [`TrackerEngine`] trait. It doesn't open an audio device itself, so it can
+//! be fed into whatever audio library the application already uses.
+//!
+//! ```no_run
+//! # #[cfg(feature = "std")] {
+//! use rustune::{Engine, Song, TrackerEngine};
+//!
+//! let song = Song::new("song.mod".as_ref())?;
+//! let mut engine = Engine::new(song)?;
+//! engine.set_sample_rate(48000);
+//!
+//! let mut buffer = vec![0.0; 1024 * engine.channel_count() as usize];
+//! while !engine.is_finished() {
+//!     let frames = engine.render(&mut buffer);
+//!     // Send `buffer[..frames * channels]` to the audio output
+//!     # let _ = frames;
+//! }
+//! # }
+//! # Ok::<(), rustune::SongError>(())
+//! ```
+//!
+//! - [`song`] holds the parsed module: metadata, patterns and sample data, and the
+//!   [`SongBuilder`] for putting songs together in code.
+//! - [`formats`] has the parsers for every supported format, a validator, readers that
+//!   load modules from a stream a part at a time, and a MOD writer. [`detect`] tells
+//!   formats apart from the header alone, e.g. to sort through a collection quickly.
+//! - [`engine`] plays songs back, and has a `Player` that's controlled from other threads
+//!   while it renders on the audio thread.
+//! - [`export`] writes rendered audio to WAV, raw PCM and (with features) compressed formats.
+//!
+//! With the `rodio` feature, `RodioSource` plays a song through a rodio `Sink`. The `serde`
+//! feature makes songs and their parts serializable, e.g. to dump them as JSON.
+//!
+//! The library doesn't need an audio device or the file system, so it also builds for
+//! `wasm32-unknown-unknown`. The `wasm` feature adds JavaScript bindings for playing songs
+//! in the browser, see `wasm::WasmPlayer`.
+//!
+//! Parsing only needs `alloc`: without the default `std` feature the crate is `no_std`
+//! and has [`bytereader`], [`bitreader`], [`archive`], [`formats`], [`song`] and [`tracker`],
+//! so modules can be loaded on embedded players with [`Song::from_bytes`]. Playback, export
+//! and loading from files need `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod archive;
+pub mod bitreader;
+pub mod bytereader;
+pub mod bytewriter;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod export;
+pub mod formats;
+#[cfg(feature = "rodio")]
+pub mod rodio_source;
+pub mod song;
+#[cfg(feature = "std")]
+pub mod streamreader;
+pub mod tracker;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "std")]
+pub use engine::{Engine, TrackerEngine};
+pub use formats::detect::{detect, Detection};
+#[cfg(feature = "rodio")]
+pub use rodio_source::RodioSource;
+pub use song::{Song, SongBuilder, SongError};
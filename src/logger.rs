@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rustune::engine::TRACE_EFFECTS_TARGET;
+
+/// Writes log records to stderr, so they don't end up in piped output such as `--raw`
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// The level asked for, the max level of the log crate is raised to trace effects
+static LEVEL: AtomicUsize = AtomicUsize::new(0);
+static TRACE_EFFECTS: AtomicBool = AtomicBool::new(false);
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.target() == TRACE_EFFECTS_TARGET && TRACE_EFFECTS.load(Ordering::Relaxed) {
+            return true;
+        }
+        metadata.level() as usize <= LEVEL.load(Ordering::Relaxed)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Traced effects are the output asked for, not diagnostics
+        if record.target() == TRACE_EFFECTS_TARGET {
+            eprintln!("{}", record.args());
+            return;
+        }
+
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+
+        eprintln!("{level}: {}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, call once at startup
+pub fn init(level: LevelFilter) {
+    // Only fails if a logger was already installed
+    let _ = log::set_logger(&LOGGER);
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    log::set_max_level(level);
+}
+
+/// Shows the effects engines trace with `--trace-effects`, whatever the level
+pub fn set_trace_effects(enabled: bool) {
+    TRACE_EFFECTS.store(enabled, Ordering::Relaxed);
+    if enabled {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+/// Maps the amount of `-v` and `-q` flags to a level, warnings are shown by default
+pub fn level_from_flags(verbose: u8, quiet: bool) -> LevelFilter {
+    if quiet {
+        return LevelFilter::Error;
+    }
+
+    match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
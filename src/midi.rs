@@ -0,0 +1,201 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use midir::{MidiOutput, MidiOutputConnection};
+use rustune::engine::{Engine, TrackerEngine};
+use rustune::tracker;
+
+// Tracker note numbers start 2 octaves below C-1, which plays as C3 (MIDI note 48). C-2,
+// the note samples are usually recorded at, is middle C
+const NOTE_OFFSET: usize = 24;
+
+// MIDI clock counts 24 pulses a beat, trackers usually put 4 rows in a beat
+const CLOCKS_PER_ROW: u32 = 6;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const STOP: u8 = 0xFC;
+
+/// Sent from the player to the thread writing to the MIDI port
+enum Message {
+    /// The messages a row starts with, and how long it lasts
+    Row(Vec<[u8; 3]>, Duration),
+    /// Playback paused or the song ended, every note is let go
+    Stop(Vec<[u8; 3]>),
+}
+
+/// Turns the rows of the playing song into MIDI messages on the audio thread, and hands them
+/// to a thread that sends them to the port.
+///
+/// Tracker channels play on MIDI channels 1-16, wrapping around for more. Notes start with
+/// the velocity of their volume and end when the next note on the channel starts. The tempo
+/// goes out as MIDI clock, 6 pulses a row, so synths and sequencers can follow along.
+/// Messages go out as the player renders audio, ahead of what's heard by the length of the
+/// audio buffer.
+pub struct MidiOut {
+    messages: Sender<Message>,
+    // The MIDI note every tracker channel plays, if any
+    playing: Vec<Option<u8>>,
+}
+
+impl MidiOut {
+    /// Connects to the first output port whose name contains `port`, or to the port with
+    /// that number as [`port_names`] lists them
+    pub fn connect(port: &str) -> Result<MidiOut, Box<dyn std::error::Error>> {
+        let output = MidiOutput::new("rustune")?;
+        let ports = output.ports();
+
+        let index = match port.parse::<usize>() {
+            Ok(index) if index < ports.len() => Some(index),
+            _ => ports.iter().position(|candidate| {
+                output
+                    .port_name(candidate)
+                    .is_ok_and(|name| name.contains(port))
+            }),
+        };
+        let Some(index) = index else {
+            let names = port_names(&output).join(", ");
+            return Err(format!("No MIDI output port \"{port}\", available: {names}").into());
+        };
+
+        let name = output.port_name(&ports[index])?;
+        let connection = output
+            .connect(&ports[index], "rustune")
+            .map_err(|e| e.to_string())?;
+        log::info!("Sending MIDI to {name}");
+
+        let (messages, receiver) = mpsc::channel();
+        thread::spawn(move || send_messages(connection, receiver));
+
+        Ok(MidiOut {
+            messages,
+            playing: Vec::new(),
+        })
+    }
+
+    /// Sends the notes of the row `engine` is about to play
+    pub fn row(&mut self, engine: &Engine) {
+        let song = engine.song();
+        let metadata = song.metadata();
+        let (order, row) = engine.position();
+        let Some(line) = (metadata.orders().get(order))
+            .and_then(|&pattern| song.patterns().get(pattern as usize))
+            .and_then(|pattern| pattern.get(row))
+        else {
+            return;
+        };
+
+        self.playing.resize(line.len(), None);
+        let mut messages = Vec::new();
+        for (channel, note) in line.iter().enumerate() {
+            let Some(number) = tracker::protracker_period_to_semitone(note.period, 0) else {
+                continue;
+            };
+            let midi_channel = (channel % 16) as u8;
+
+            if let Some(playing) = self.playing[channel].take() {
+                messages.push([NOTE_OFF | midi_channel, playing, 0]);
+            }
+            if engine.is_channel_muted(channel) {
+                continue;
+            }
+
+            // Cxx on the same row sets the volume the note starts at
+            let volume = match note.effect {
+                0xC => note.argument,
+                _ => (note.sample as usize)
+                    .checked_sub(1)
+                    .and_then(|index| metadata.instruments().get(index))
+                    .map_or(64, |sample| sample.volume),
+            };
+            let velocity = (volume.min(64) as u32 * 127 / 64).max(1) as u8;
+
+            let key = (number + NOTE_OFFSET).min(127) as u8;
+            messages.push([NOTE_ON | midi_channel, key, velocity]);
+            self.playing[channel] = Some(key);
+        }
+
+        let duration = Duration::from_secs_f32(engine.tick_duration() * engine.speed() as f32);
+        let _ = self.messages.send(Message::Row(messages, duration));
+    }
+
+    /// Lets go of every playing note and stops the clock
+    pub fn stop(&mut self) {
+        let messages = (self.playing.iter_mut().enumerate())
+            .filter_map(|(channel, note)| Some([NOTE_OFF | (channel % 16) as u8, note.take()?, 0]))
+            .collect();
+        let _ = self.messages.send(Message::Stop(messages));
+    }
+}
+
+/// Names of the MIDI output ports, numbered the way `--midi-out` takes them
+pub fn port_names(output: &MidiOutput) -> Vec<String> {
+    (output.ports().iter().enumerate())
+        .map(|(index, port)| {
+            let name = output.port_name(port).unwrap_or_default();
+            format!("{index}: {name}")
+        })
+        .collect()
+}
+
+// Writes messages to the port as they arrive, with the clock pulses of every row spread
+// over its length. Ends once the player goes away
+fn send_messages(mut connection: MidiOutputConnection, messages: Receiver<Message>) {
+    let mut send = |message: &[u8]| {
+        if let Err(e) = connection.send(message) {
+            log::warn!("Couldn't send MIDI: {e}");
+        }
+    };
+
+    let mut running = false;
+    // When the next clock pulse is due, and how many are left in the row
+    let mut next_clock = Instant::now();
+    let mut interval = Duration::ZERO;
+    let mut clocks = 0;
+
+    loop {
+        let message = if clocks > 0 {
+            match messages.recv_timeout(next_clock.saturating_duration_since(Instant::now())) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    send(&[CLOCK]);
+                    clocks -= 1;
+                    next_clock += interval;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match messages.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            }
+        };
+
+        match message {
+            Message::Row(notes, duration) => {
+                if !running {
+                    send(&[START]);
+                    running = true;
+                }
+                notes.iter().for_each(|note| send(note));
+
+                // Pulses the previous row didn't get to yet are dropped, the song sets the pace
+                interval = duration / CLOCKS_PER_ROW;
+                clocks = CLOCKS_PER_ROW;
+                next_clock = Instant::now();
+            }
+            Message::Stop(notes) => {
+                notes.iter().for_each(|note| send(note));
+                if running {
+                    send(&[STOP]);
+                    running = false;
+                }
+                clocks = 0;
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+
+use crate::player::{Handle, NowPlaying, Status};
+use crate::PlaybackEvent;
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.rustune";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+// How often the player status is checked for changes to signal to clients
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// MPRIS times are in microseconds
+const MICROSECONDS: f64 = 1_000_000.0;
+
+/// State of the D-Bus object, shared by all method calls and property reads
+struct Server {
+    status: Arc<Mutex<Status>>,
+    player: Handle,
+    events: Sender<PlaybackEvent>,
+}
+
+impl Server {
+    fn status(&self) -> (Option<NowPlaying>, bool) {
+        let status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        (status.song.clone(), status.paused)
+    }
+
+    fn set_paused(&self, paused: bool) {
+        if paused {
+            self.player.pause();
+        } else {
+            self.player.play();
+        }
+    }
+}
+
+/// Publishes the player on the session bus as an MPRIS media player, so desktop media keys
+/// and status bars can show and control playback.
+///
+/// Runs on a background thread. Play and pause go straight to the player through
+/// its handle, next, previous and quit go to the playback loop through `events`.
+/// When there's no session bus a warning is logged and playback continues without it.
+pub fn serve(status: Arc<Mutex<Status>>, player: Handle, events: Sender<PlaybackEvent>) {
+    thread::spawn(move || {
+        let server = Server {
+            status,
+            player,
+            events,
+        };
+
+        if let Err(e) = run(server) {
+            log::warn!("Media player controls (MPRIS) unavailable: {e}");
+        }
+    });
+}
+
+fn run(server: Server) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+    connection.request_name(BUS_NAME, false, true, false)?;
+
+    let status = server.status.clone();
+
+    let mut crossroads = Crossroads::new();
+    let root = crossroads.register("org.mpris.MediaPlayer2", register_root);
+    let player = crossroads.register(PLAYER_INTERFACE, register_player);
+    crossroads.insert(OBJECT_PATH, &[root, player], server);
+
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = crossroads.handle_message(message, connection);
+            true
+        }),
+    );
+
+    // Clients are told about changes instead of polling for them, except for the position
+    let mut last_status = None;
+    let mut last_song = None;
+    loop {
+        connection.process(POLL_INTERVAL)?;
+
+        let (song, paused) = {
+            let status = status.lock().unwrap_or_else(|e| e.into_inner());
+            (status.song.clone(), status.paused)
+        };
+
+        let mut changed = PropMap::new();
+
+        let playback_status = playback_status(song.as_ref(), paused);
+        if last_status != Some(playback_status) {
+            last_status = Some(playback_status);
+            changed.insert(
+                "PlaybackStatus".into(),
+                Variant(Box::new(playback_status.to_string())),
+            );
+        }
+
+        let identity = song
+            .as_ref()
+            .map(|s| (s.index, s.title.clone(), s.duration));
+        if last_song != Some(identity.clone()) {
+            last_song = Some(identity);
+            changed.insert(
+                "Metadata".into(),
+                Variant(Box::new(metadata(song.as_ref()))),
+            );
+        }
+
+        if !changed.is_empty() {
+            let signal = PropertiesPropertiesChanged {
+                interface_name: PLAYER_INTERFACE.into(),
+                changed_properties: changed,
+                invalidated_properties: Vec::new(),
+            };
+            let _ = connection.send(signal.to_emit_message(&OBJECT_PATH.into()));
+        }
+    }
+}
+
+// The org.mpris.MediaPlayer2 interface, about the application itself
+fn register_root(builder: &mut IfaceBuilder<Server>) {
+    builder.method("Raise", (), (), |_, _, _: ()| Ok(()));
+    builder.method("Quit", (), (), |_, server: &mut Server, _: ()| {
+        let _ = server.events.send(PlaybackEvent::Quit);
+        Ok(())
+    });
+
+    builder.property("CanQuit").get(|_, _| Ok(true));
+    builder.property("CanRaise").get(|_, _| Ok(false));
+    builder.property("HasTrackList").get(|_, _| Ok(false));
+    builder
+        .property("Identity")
+        .get(|_, _| Ok(String::from("Rustune")));
+    builder
+        .property("SupportedUriSchemes")
+        .get(|_, _| Ok(Vec::<String>::new()));
+    builder
+        .property("SupportedMimeTypes")
+        .get(|_, _| Ok(Vec::<String>::new()));
+}
+
+// The org.mpris.MediaPlayer2.Player interface, controlling playback
+fn register_player(builder: &mut IfaceBuilder<Server>) {
+    builder.method("Next", (), (), |_, server: &mut Server, _: ()| {
+        let _ = server.events.send(PlaybackEvent::Next);
+        Ok(())
+    });
+    builder.method("Previous", (), (), |_, server: &mut Server, _: ()| {
+        let _ = server.events.send(PlaybackEvent::Previous);
+        Ok(())
+    });
+    builder.method("Play", (), (), |_, server: &mut Server, _: ()| {
+        server.set_paused(false);
+        Ok(())
+    });
+    // There's nothing to stop to without ending the song, so stopping pauses
+    builder.method("Pause", (), (), |_, server: &mut Server, _: ()| {
+        server.set_paused(true);
+        Ok(())
+    });
+    builder.method("Stop", (), (), |_, server: &mut Server, _: ()| {
+        server.set_paused(true);
+        Ok(())
+    });
+    builder.method("PlayPause", (), (), |_, server: &mut Server, _: ()| {
+        let (_, paused) = server.status();
+        server.set_paused(!paused);
+        Ok(())
+    });
+
+    // Seeking by time isn't supported, which CanSeek tells clients
+    builder.method("Seek", ("Offset",), (), |_, _, _: (i64,)| Ok(()));
+    builder.method(
+        "SetPosition",
+        ("TrackId", "Position"),
+        (),
+        |_, _, _: (dbus::Path<'static>, i64)| Ok(()),
+    );
+    builder.method("OpenUri", ("Uri",), (), |_, _, _: (String,)| Ok(()));
+
+    builder
+        .property("PlaybackStatus")
+        .get(|_, server: &mut Server| {
+            let (song, paused) = server.status();
+            Ok(playback_status(song.as_ref(), paused).to_string())
+        });
+    builder.property("Metadata").get(|_, server: &mut Server| {
+        let (song, _) = server.status();
+        Ok(metadata(song.as_ref()))
+    });
+    builder
+        .property("Position")
+        .emits_changed_false()
+        .get(|_, server: &mut Server| {
+            let (song, _) = server.status();
+            let elapsed = song.and_then(|song| song.elapsed).unwrap_or(0.0);
+            Ok((elapsed * MICROSECONDS) as i64)
+        });
+
+    builder.property("Rate").get(|_, _| Ok(1.0));
+    builder.property("MinimumRate").get(|_, _| Ok(1.0));
+    builder.property("MaximumRate").get(|_, _| Ok(1.0));
+    builder.property("Volume").get(|_, _| Ok(1.0));
+
+    builder.property("CanGoNext").get(|_, _| Ok(true));
+    builder.property("CanGoPrevious").get(|_, _| Ok(true));
+    builder.property("CanPlay").get(|_, _| Ok(true));
+    builder.property("CanPause").get(|_, _| Ok(true));
+    builder.property("CanSeek").get(|_, _| Ok(false));
+    builder.property("CanControl").get(|_, _| Ok(true));
+}
+
+fn playback_status(song: Option<&NowPlaying>, paused: bool) -> &'static str {
+    match (song, paused) {
+        (None, _) => "Stopped",
+        (Some(_), true) => "Paused",
+        (Some(_), false) => "Playing",
+    }
+}
+
+fn metadata(song: Option<&NowPlaying>) -> PropMap {
+    let mut metadata = PropMap::new();
+    let Some(song) = song else {
+        return metadata;
+    };
+
+    let mut insert = |key: &str, value: Box<dyn RefArg>| {
+        metadata.insert(key.into(), Variant(value));
+    };
+
+    let track_id = format!("/org/mpris/MediaPlayer2/Track/{}", song.index);
+    insert("mpris:trackid", Box::new(dbus::Path::from(track_id)));
+    insert(
+        "mpris:length",
+        Box::new((song.duration * MICROSECONDS) as i64),
+    );
+    insert("xesam:title", Box::new(song.title.clone()));
+    if let Some(url) = file_url(&song.path) {
+        insert("xesam:url", Box::new(url));
+    }
+
+    metadata
+}
+
+// file:// URL of a local file, with the characters that aren't allowed in URLs escaped
+fn file_url(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+
+    let mut url = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url += &format!("%{byte:02X}");
+        }
+    }
+
+    Some(url)
+}
@@ -0,0 +1,143 @@
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use rustune::engine::{Engine, TrackerEngine};
+use rustune::tracker;
+
+// OSC time tag meaning "as soon as it arrives"
+const IMMEDIATELY: u64 = 1;
+
+/// A value in an OSC message
+enum Argument<'a> {
+    Int(i32),
+    Str(&'a str),
+}
+
+/// Broadcasts playback as OSC messages over UDP, for visuals that follow the music.
+///
+/// Every row the player starts sends a bundle with:
+/// - `/rustune/position order pattern row`
+/// - `/rustune/channel channel note name sample volume` for every channel, numbered from 1.
+///   `note` counts semitones like `tracker::parse_note` (C-2 is 36) and is -1 without a new
+///   note, `name` is e.g. `C-2` or `---`, `volume` is 0-64
+///
+/// and every song that starts sends `/rustune/track index title`. Messages that can't be
+/// sent are dropped, playback never waits for the network.
+pub struct OscOut {
+    socket: UdpSocket,
+}
+
+impl OscOut {
+    /// Sends to `address`, a host and port like `127.0.0.1:9000`
+    pub fn connect(address: &str) -> io::Result<OscOut> {
+        let target = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("No address for {address}"))
+        })?;
+
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_broadcast(true)?;
+        socket.connect(target)?;
+        // Sent from the audio thread, which mustn't block
+        socket.set_nonblocking(true)?;
+        log::info!("Sending OSC to {target}");
+
+        Ok(OscOut { socket })
+    }
+
+    /// Announces the song with the given playlist index
+    pub fn track(&self, index: usize, title: &str) {
+        let index = Argument::Int(index as i32);
+        self.send(&message("/rustune/track", &[index, Argument::Str(title)]));
+    }
+
+    /// Sends the position and channels of the row `engine` is about to play
+    pub fn row(&self, engine: &Engine) {
+        let song = engine.song();
+        let (order, row) = engine.position();
+        let Some(&pattern) = song.metadata().orders().get(order) else {
+            return;
+        };
+        let line = song
+            .patterns()
+            .get(pattern as usize)
+            .and_then(|pattern| pattern.get(row));
+
+        let position = [order, pattern as usize, row].map(|value| Argument::Int(value as i32));
+        let mut messages = vec![message("/rustune/position", &position)];
+
+        for channel in 0..song.metadata().channels() {
+            let note = line.and_then(|line| line.get(channel));
+            let pitch = note.and_then(|note| tracker::protracker_period_to_note(note.period, 0));
+            let name = pitch.map_or_else(|| String::from("---"), |pitch| pitch.to_string());
+
+            let arguments = [
+                Argument::Int(channel as i32 + 1),
+                Argument::Int(pitch.map_or(-1, |pitch| pitch.number() as i32)),
+                Argument::Str(&name),
+                Argument::Int(note.map_or(0, |note| note.sample as i32)),
+                Argument::Int(engine.channel_volume(channel) as i32),
+            ];
+            messages.push(message("/rustune/channel", &arguments));
+        }
+
+        self.send(&bundle(&messages));
+    }
+
+    fn send(&self, packet: &[u8]) {
+        if let Err(e) = self.socket.send(packet) {
+            log::debug!("Couldn't send OSC: {e}");
+        }
+    }
+}
+
+// Encodes a message: the address, the type tags and the arguments, each padded to 4 bytes
+fn message(address: &str, arguments: &[Argument]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    write_str(&mut packet, address);
+
+    let tags: String = std::iter::once(',')
+        .chain(arguments.iter().map(|argument| match argument {
+            Argument::Int(_) => 'i',
+            Argument::Str(_) => 's',
+        }))
+        .collect();
+    write_str(&mut packet, &tags);
+
+    for argument in arguments {
+        match argument {
+            Argument::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Argument::Str(value) => write_str(&mut packet, value),
+        }
+    }
+
+    packet
+}
+
+// Encodes messages that are handled together, every one prefixed by its size
+fn bundle(messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    write_str(&mut packet, "#bundle");
+    packet.extend_from_slice(&IMMEDIATELY.to_be_bytes());
+
+    for message in messages {
+        packet.extend_from_slice(&(message.len() as i32).to_be_bytes());
+        packet.extend_from_slice(message);
+    }
+
+    packet
+}
+
+// Strings end with a null byte, and are padded with more to a multiple of 4 bytes
+fn write_str(packet: &mut Vec<u8>, value: &str) {
+    // OSC strings can't hold null bytes, they'd end it early
+    packet.extend(value.bytes().filter(|&byte| byte != 0));
+    packet.push(0);
+    while !packet.len().is_multiple_of(4) {
+        packet.push(0);
+    }
+}
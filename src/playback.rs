@@ -0,0 +1,863 @@
+use std::error::Error;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+#[cfg(feature = "tui")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+#[cfg(feature = "icecast")]
+use crate::icecast;
+use crate::json::{self, JsonObject};
+#[cfg(feature = "midi")]
+use crate::midi;
+#[cfg(feature = "mpris")]
+use crate::mpris;
+use crate::player::{Details, Entry, Handle, Player, Status};
+use crate::playlist::Playlist;
+#[cfg(feature = "scrobble")]
+use crate::scrobble;
+use crate::{control, DEFAULT_RENDER_RATE};
+use crate::{create_engine, info, osc, serve, watch, Args, JamArgs, PlaybackEvent};
+#[cfg(feature = "tui")]
+use crate::{jam, spectrum, tui};
+use rustune::engine::{Engine, TrackerEngine};
+use rustune::export::wav::WavFormat;
+#[cfg(feature = "tui")]
+use rustune::song::Song;
+use rustune::song::SongError;
+
+// Frames rendered at once for the listeners of --serve and --icecast
+const STREAMED_CHUNK_FRAMES: usize = 1024;
+
+/// A loaded playlist entry, with everything the main thread needs once its engine is handed
+/// to the player
+struct Track {
+    index: usize,
+    // For the terminal window
+    title: String,
+    // Only the orders that are part of the song
+    pattern_table: Vec<u8>,
+    // Printed when the track starts playing, unless the player prints a header instead
+    announcement: Option<String>,
+    // In seconds, for the progress line
+    duration: f64,
+
+    #[cfg(feature = "tui")]
+    view: Option<tui::PatternView>,
+    #[cfg(feature = "tui")]
+    scope: Option<Arc<rustune::engine::ScopeBuffer>>,
+    #[cfg(feature = "tui")]
+    state: tui::TrackState,
+}
+
+/// Plays the playlist on the audio device, or streams it, until it ends or the user quits
+pub fn play(args: &Args, mut playlist: Playlist) -> Result<(), Box<dyn Error>> {
+    #[cfg(not(feature = "tui"))]
+    if args.tui {
+        return Err("The terminal interface requires building with `--features tui`".into());
+    }
+    #[cfg(not(feature = "midi"))]
+    if args.midi_out.is_some() {
+        return Err("MIDI output requires building with `--features midi`".into());
+    }
+    #[cfg(not(feature = "icecast"))]
+    if args.icecast.is_some() {
+        return Err("Icecast streaming requires building with `--features icecast`".into());
+    }
+    // Streams are rendered in real time without the audio device
+    let streamed = args.serve.is_some() || args.icecast.is_some();
+
+    let output = open_output(args, streamed)?;
+
+    // channel is used as a simple concurrency primitive: basic lock and key
+    // common usage pattern for channels
+    let (events, blocker) = channel();
+    if args.watch {
+        watch::watch(playlist.entries().to_vec(), events.clone());
+    }
+    let (mut player, handle) = Player::new(events.clone(), args.crossfade > 0.0);
+    player.set_print_rows(!args.json && !args.tui && !args.progress);
+    if let Some(address) = &args.osc {
+        let osc = osc::OscOut::connect(address).map_err(|e| format!("OSC to {address}: {e}"))?;
+        player.set_osc(osc);
+    }
+    #[cfg(feature = "midi")]
+    if let Some(port) = &args.midi_out {
+        player.set_midi(midi::MidiOut::connect(port)?);
+    }
+    if let Some(port) = args.control_port {
+        let host = &args.control_host;
+        control::serve(host, port, player.status(), handle.clone(), events.clone())
+            .map_err(|e| format!("Control port {host}:{port}: {e}"))?;
+    }
+    // Only what's heard counts as listened to
+    #[cfg(feature = "scrobble")]
+    if output.is_some() {
+        scrobble::start(
+            args.lastfm.clone(),
+            args.listenbrainz.clone(),
+            player.status(),
+        );
+    }
+    #[cfg(feature = "mpris")]
+    mpris::serve(player.status(), handle.clone(), events);
+
+    // Streams are rendered at the rate of files, in stereo
+    let streamed_config = streamed.then(|| cpal::StreamConfig {
+        channels: 2,
+        sample_rate: cpal::SampleRate(args.sample_rate.unwrap_or(DEFAULT_RENDER_RATE)),
+        buffer_size: cpal::BufferSize::Default,
+    });
+    let streams = open_streams(args, &player, streamed_config.as_ref())?;
+
+    let stream_config = (output.as_ref().map(|(_, config)| config))
+        .or(streamed_config.as_ref())
+        .cloned();
+    let config = stream_config.as_ref();
+
+    #[cfg(feature = "tui")]
+    let mut tui = if args.tui {
+        // Without audio there's no output to analyze
+        let spectrum = config.filter(|_| args.spectrum).map(|config| {
+            let mix = Arc::new(rustune::engine::ScopeBuffer::new(
+                1,
+                spectrum::SPECTRUM_SIZE,
+            ));
+            player.set_mix(mix.clone(), config.channels as usize);
+            spectrum::Spectrum::new(mix, config.sample_rate.0, args.spectrum_bands as usize)
+        });
+        Some(tui::Tui::new(player.status(), spectrum, args.piano_roll)?)
+    } else {
+        None
+    };
+
+    let status = player.status();
+    // Dropping the stream stops it
+    let _stream = match (&output, &streamed_config) {
+        (Some((device, config)), _) => Some(play_stream(device, config, player)?),
+        (None, Some(config)) => {
+            play_streamed(player, streams, config);
+            None
+        }
+        (None, None) => {
+            // Without audio there's nothing to keep in time with, except for the terminal UI
+            // and MIDI or OSC output
+            let realtime = args.tui
+                || args.midi_out.is_some()
+                || args.osc.is_some()
+                || args.control_port.is_some();
+            play_silent(player, realtime);
+            None
+        }
+    };
+
+    play_tracks(
+        args,
+        &mut playlist,
+        config,
+        &handle,
+        &blocker,
+        #[cfg(feature = "tui")]
+        &mut tui,
+    )?;
+    print_load(args, &status);
+    Ok(())
+}
+
+// The audio device and its config, `None` if there's none or the output goes elsewhere
+fn open_output(args: &Args, streamed: bool) -> Result<Option<Output>, Box<dyn Error>> {
+    let output = if args.no_audio || streamed {
+        None
+    } else {
+        let host = cpal::default_host();
+
+        let device = match &args.device {
+            Some(name) => find_device(&host, name)?,
+            None => host
+                .default_output_device()
+                .ok_or("No output device available")?,
+        };
+
+        stream_config(&device, args.sample_rate, args.buffer_size).map(|config| (device, config))
+    };
+
+    if args.json {
+        let object = JsonObject::new()
+            .string("type", "audio")
+            .value("available", output.is_some());
+        println!("{}", object.finish());
+    } else if !args.no_audio && !streamed {
+        match &output {
+            Some((_, config)) => log::info!(
+                "Audio detected, {} channels at {} Hz",
+                config.channels,
+                config.sample_rate.0
+            ),
+            None => log::warn!("No audio detected, playing silently"),
+        }
+    }
+
+    Ok(output)
+}
+
+// Starts sending the output to where it's streamed to, for --serve and --icecast
+#[cfg_attr(not(feature = "icecast"), allow(unused_variables))]
+fn open_streams(
+    args: &Args,
+    player: &Player,
+    config: Option<&cpal::StreamConfig>,
+) -> Result<Vec<Stream>, Box<dyn Error>> {
+    let Some(config) = config else {
+        return Ok(Vec::new());
+    };
+
+    let mut streams: Vec<Stream> = Vec::new();
+    if let Some(address) = &args.serve {
+        let format = if args.float {
+            WavFormat::Float32
+        } else {
+            WavFormat::Int16
+        };
+        let server =
+            serve::StreamServer::bind(address, config.sample_rate.0, config.channels, format)
+                .map_err(|e| format!("Streaming on {address}: {e}"))?;
+        streams.push(Box::new(move |samples| server.send(samples)));
+    }
+    #[cfg(feature = "icecast")]
+    if let Some(url) = &args.icecast {
+        let status = player.status();
+        let mut source =
+            icecast::IcecastSource::connect(url, status, config.sample_rate.0, config.channels)?;
+        streams.push(Box::new(move |samples| source.send(samples)));
+    }
+    Ok(streams)
+}
+
+// Plays the playlist from its current entry until it ends or the user quits, loading every
+// entry as the one before it starts
+fn play_tracks(
+    args: &Args,
+    playlist: &mut Playlist,
+    config: Option<&cpal::StreamConfig>,
+    handle: &Handle,
+    blocker: &Receiver<PlaybackEvent>,
+    #[cfg(feature = "tui")] tui: &mut Option<tui::Tui>,
+) -> Result<(), Box<dyn Error>> {
+    let mut terminal_title = TerminalTitle::new(!args.json && io::stdout().is_terminal());
+    let mut pending = load_track(args, playlist, playlist.position(), config);
+
+    while let Some((track, entry)) = pending.take() {
+        let mut current = track;
+        let mut queued: Option<Track> = None;
+        handle.load(entry);
+
+        loop {
+            #[cfg(feature = "tui")]
+            let event = match (tui.as_mut(), &current.view) {
+                (Some(tui), Some(view)) => tui.run(
+                    view,
+                    current.scope.as_deref(),
+                    &mut current.state,
+                    handle,
+                    blocker,
+                )?,
+                _ => wait_for_event(blocker, args, &current),
+            };
+            #[cfg(not(feature = "tui"))]
+            let event = wait_for_event(blocker, args, &current);
+
+            // Modules opened by control clients are played from the playlist
+            let event = match event {
+                PlaybackEvent::Open(path) => PlaybackEvent::Select(playlist.add(path)),
+                event => event,
+            };
+
+            match event {
+                PlaybackEvent::Started(index) => {
+                    // The queued track took over, without the main thread asking for it
+                    if index != current.index {
+                        let Some(track) = queued.take().filter(|track| track.index == index) else {
+                            continue;
+                        };
+
+                        print_end(args, &current);
+                        current = track;
+                    }
+
+                    playlist.select(index);
+                    terminal_title.set(&current.title);
+                    if let Some(announcement) = &current.announcement {
+                        println!("{announcement}");
+                    }
+
+                    // Parse the next entry while this one plays, so it can start without a gap
+                    let next = playlist.position() + 1;
+                    if let Some((track, entry)) = load_track(args, playlist, next, config) {
+                        handle.queue(entry);
+                        queued = Some(track);
+                    }
+                }
+                // The queued track is about to start, it was sent just as the current one ended
+                PlaybackEvent::Finished if queued.is_some() => {}
+                PlaybackEvent::Finished => {
+                    print_end(args, &current);
+                    break;
+                }
+                PlaybackEvent::Next => {
+                    handle.skip();
+                }
+                PlaybackEvent::Previous => {
+                    print_end(args, &current);
+                    playlist.select(current.index);
+                    playlist.previous();
+                    pending = load_track(args, playlist, playlist.position(), config);
+                    break;
+                }
+                PlaybackEvent::Select(index) => {
+                    if index >= playlist.len() {
+                        log::warn!("No playlist entry {index} to play");
+                        continue;
+                    }
+
+                    print_end(args, &current);
+                    playlist.select(index);
+                    pending = load_track(args, playlist, playlist.position(), config);
+                    break;
+                }
+                PlaybackEvent::Changed(index) => {
+                    let playing = index == current.index;
+                    if !playing && queued.as_ref().is_none_or(|track| track.index != index) {
+                        continue;
+                    }
+
+                    let path = &playlist.entries()[index];
+                    let (track, entry) = match load_entry(args, playlist, index, config) {
+                        Ok(loaded) => loaded,
+                        Err(e) => {
+                            log::warn!("Not reloading {}: {e}", path.display());
+                            continue;
+                        }
+                    };
+                    log::info!("Reloading {}", path.display());
+
+                    #[cfg(feature = "tui")]
+                    let (track, entry) = if playing {
+                        keep_track_state(&current, track, entry)
+                    } else {
+                        (track, entry)
+                    };
+
+                    if playing {
+                        current = track;
+                    } else {
+                        queued = Some(track);
+                    }
+                    handle.reload(entry);
+                }
+                PlaybackEvent::Quit => {
+                    print_end(args, &current);
+                    return Ok(());
+                }
+                PlaybackEvent::Position { .. } | PlaybackEvent::Open(..) => unreachable!(),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Prints how long mixing took for --cpu-stats
+fn print_load(args: &Args, status: &Mutex<Status>) {
+    if !args.cpu_stats {
+        return;
+    }
+
+    let load = status
+        .lock()
+        .map_or_else(|e| e.into_inner().load, |status| status.load);
+    if args.json {
+        let object = JsonObject::new()
+            .string("type", "load")
+            .raw(
+                "average_percent",
+                &json::number(Some(load.average() * 100.0)),
+            )
+            .raw("peak_percent", &json::number(Some(load.peak * 100.0)))
+            .raw("realtime", &json::number(load.realtime()));
+        println!("{}", object.finish());
+    } else if let Some(realtime) = load.realtime() {
+        println!(
+            "CPU usage: {:.1}% average, {:.1}% peak, {realtime:.1}x realtime",
+            load.average() * 100.0,
+            load.peak * 100.0
+        );
+    } else {
+        println!("CPU usage: nothing was mixed");
+    }
+}
+
+// Names the terminal window after the song that's playing. The title from before is saved on
+// the terminal's title stack and restored once playback ends
+struct TerminalTitle {
+    enabled: bool,
+}
+
+impl TerminalTitle {
+    fn new(enabled: bool) -> Self {
+        if enabled {
+            print!("\x1b[22;0t");
+        }
+        TerminalTitle { enabled }
+    }
+
+    fn set(&mut self, title: &str) {
+        if self.enabled {
+            // Control characters in the title would end the escape sequence early
+            let title: String = title.chars().filter(|c| !c.is_control()).collect();
+            print!("\x1b]0;Rustune — {title}\x07");
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+impl Drop for TerminalTitle {
+    fn drop(&mut self) {
+        if self.enabled {
+            print!("\x1b[23;0t");
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+// Blocks until playback should move on, printing position events in JSON mode
+fn wait_for_event(blocker: &Receiver<PlaybackEvent>, args: &Args, track: &Track) -> PlaybackEvent {
+    loop {
+        match blocker.recv() {
+            Ok(PlaybackEvent::Position {
+                order,
+                row,
+                elapsed,
+                ..
+            }) => {
+                if args.progress {
+                    let progress = info::Progress {
+                        elapsed,
+                        duration: track.duration,
+                        order,
+                        song_length: track.pattern_table.len(),
+                        row,
+                    };
+                    print!("\r{progress}");
+                    let _ = io::stdout().flush();
+                } else if args.json {
+                    let object = JsonObject::new()
+                        .string("type", "position")
+                        .value("order", order)
+                        .value(
+                            "pattern",
+                            track.pattern_table.get(order).copied().unwrap_or(0),
+                        )
+                        .value("row", row);
+                    println!("{}", object.finish());
+                }
+            }
+            Ok(event) => return event,
+            Err(_) => return PlaybackEvent::Quit,
+        }
+    }
+}
+
+// Prints the JSON event for a track that stopped playing, or ends its progress line
+fn print_end(args: &Args, track: &Track) {
+    if args.progress {
+        println!();
+    } else if args.json {
+        let object = JsonObject::new()
+            .string("type", "end")
+            .value("index", track.index);
+        println!("{}", object.finish());
+    }
+}
+
+/// Loads the first entry from `index` onwards that can be played, skipping broken ones
+fn load_track(
+    args: &Args,
+    playlist: &Playlist,
+    index: usize,
+    config: Option<&cpal::StreamConfig>,
+) -> Option<(Track, Entry)> {
+    for (index, path) in playlist.entries().iter().enumerate().skip(index) {
+        match load_entry(args, playlist, index, config) {
+            Ok(loaded) => return Some(loaded),
+            Err(e) => log::warn!("Skipping {}: {e}", path.display()),
+        }
+    }
+
+    None
+}
+
+/// Loads the playlist entry at `index`, with its engine set up for playback
+fn load_entry(
+    args: &Args,
+    playlist: &Playlist,
+    index: usize,
+    config: Option<&cpal::StreamConfig>,
+) -> Result<(Track, Entry), SongError> {
+    let path = &playlist.entries()[index];
+    let song = playlist.load(path)?;
+    let title = info::title(&song, path);
+
+    #[cfg(feature = "tui")]
+    let view = args
+        .tui
+        .then(|| tui::PatternView::new(&song, path, index, playlist.len()));
+
+    let pattern_table = song.metadata().orders().to_vec();
+    let channel_count = song.metadata().channels();
+
+    let mut engine = create_engine(song, args)?;
+    engine.set_trace_effects(args.trace_effects);
+
+    if let Some(config) = config {
+        engine.set_channel_count(config.channels);
+        engine.set_sample_rate(config.sample_rate.0);
+    }
+
+    let (announcement, header) = if args.json {
+        let object = JsonObject::new()
+            .string("type", "track")
+            .value("index", index)
+            .value("total", playlist.len());
+        (
+            Some(info::metadata_json(object, engine.song(), path).finish()),
+            None,
+        )
+    } else if args.tui {
+        (None, None)
+    } else {
+        let info = track_info(&engine, path, index, playlist.len());
+
+        // Only the rows are printed by the player, the header has to come before them
+        if args.progress {
+            (Some(info), None)
+        } else {
+            (None, Some(info))
+        }
+    };
+
+    #[cfg(feature = "tui")]
+    let view = view.map(|view| view.with_duration(engine.duration()));
+
+    #[cfg(feature = "tui")]
+    let scope = args.scope.then(|| {
+        let scope = Arc::new(rustune::engine::ScopeBuffer::new(
+            channel_count,
+            tui::SCOPE_LENGTH,
+        ));
+        engine.set_scope(Some(scope.clone()));
+        scope
+    });
+
+    let track = Track {
+        index,
+        title: title.clone(),
+        pattern_table,
+        announcement,
+        duration: engine.duration(),
+
+        #[cfg(feature = "tui")]
+        view,
+        #[cfg(feature = "tui")]
+        scope,
+        #[cfg(feature = "tui")]
+        state: tui::TrackState::new(
+            (0..channel_count)
+                .map(|channel| engine.is_channel_muted(channel))
+                .collect(),
+            engine.loop_region(),
+        ),
+    };
+
+    #[cfg(not(feature = "tui"))]
+    let _ = channel_count;
+
+    Ok((
+        track,
+        Entry {
+            id: index,
+            engine,
+            data: Details {
+                title,
+                path: path.clone(),
+                header,
+            },
+        },
+    ))
+}
+
+// Carries the channels muted and the loop region set in the terminal interface over to a
+// reloaded version of the current track
+#[cfg(feature = "tui")]
+fn keep_track_state(current: &Track, mut track: Track, mut entry: Entry) -> (Track, Entry) {
+    let old = &current.state;
+
+    let muted: Vec<bool> = (track.state.muted().iter().enumerate())
+        .map(|(channel, &muted)| old.muted().get(channel).copied().unwrap_or(muted))
+        .collect();
+    for (channel, &muted) in muted.iter().enumerate() {
+        entry.engine.set_channel_muted(channel, muted);
+    }
+
+    // The region may not exist anymore, if the song got shorter
+    let loop_region = old.loop_region();
+    if !entry.engine.set_loop_region(loop_region) {
+        entry.engine.set_loop_region(None);
+    }
+
+    track.state = tui::TrackState::new(muted, entry.engine.loop_region());
+    (track, entry)
+}
+
+// Picks the stream config of the device, at the requested sample rate and buffer size if
+// it supports them
+fn stream_config(
+    device: &cpal::Device,
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+) -> Option<cpal::StreamConfig> {
+    let default = device.default_output_config().ok();
+
+    let supported = match sample_rate {
+        Some(rate) => {
+            // Prefer the default channel count and the f32 format the callback is written for
+            let channels = default.as_ref().map(|config| config.channels());
+            let range = device.supported_output_configs().ok().and_then(|configs| {
+                configs
+                    .filter(|range| {
+                        (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&rate)
+                    })
+                    .max_by_key(|range| {
+                        (
+                            range.sample_format() == cpal::SampleFormat::F32,
+                            Some(range.channels()) == channels,
+                        )
+                    })
+            });
+
+            match range {
+                Some(range) => Some(range.with_sample_rate(cpal::SampleRate(rate))),
+                None => {
+                    log::warn!(
+                        "The output device doesn't support {rate} Hz, using its default rate"
+                    );
+                    default
+                }
+            }
+        }
+        None => default,
+    }?;
+
+    let mut config = cpal::StreamConfig::from(supported.clone());
+
+    if let Some(frames) = buffer_size {
+        match supported.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } if !(*min..=*max).contains(&frames) => {
+                let clamped = frames.clamp(*min, *max);
+                log::warn!(
+                    "The output device supports buffers of {min}-{max} frames, using {clamped}"
+                );
+                config.buffer_size = cpal::BufferSize::Fixed(clamped);
+            }
+            _ => config.buffer_size = cpal::BufferSize::Fixed(frames),
+        }
+    }
+
+    Some(config)
+}
+
+pub fn list_devices(host: &cpal::Host) -> Result<(), Box<dyn Error>> {
+    let default = host.default_output_device().and_then(|d| d.name().ok());
+
+    for device in host.output_devices()? {
+        let name = device.name().unwrap_or_else(|_| String::from("<unknown>"));
+        let marker = if default.as_ref() == Some(&name) {
+            " (default)"
+        } else {
+            ""
+        };
+        println!("{name}{marker}");
+
+        let Ok(configs) = device.supported_output_configs() else {
+            println!("  No supported configurations");
+            continue;
+        };
+
+        for config in configs {
+            println!(
+                "  {} channels, {}-{} Hz, {}",
+                config.channels(),
+                config.min_sample_rate().0,
+                config.max_sample_rate().0,
+                config.sample_format()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Finds an output device by its exact name, or else a unique partial match
+fn find_device(host: &cpal::Host, name: &str) -> Result<cpal::Device, Box<dyn Error>> {
+    let mut matches: Vec<(String, cpal::Device)> = host
+        .output_devices()?
+        .filter_map(|device| Some((device.name().ok()?, device)))
+        .filter(|(device_name, _)| device_name.to_lowercase().contains(&name.to_lowercase()))
+        .collect();
+
+    if let Some(index) = matches
+        .iter()
+        .position(|(device_name, _)| device_name == name)
+    {
+        return Ok(matches.swap_remove(index).1);
+    }
+
+    match matches.len() {
+        0 => Err(format!("No output device matches {name}, see --list-devices").into()),
+        1 => Ok(matches.remove(0).1),
+        _ => {
+            let names: Vec<_> = matches.into_iter().map(|(name, _)| name).collect();
+            Err(format!(
+                "{name} matches several output devices: {}",
+                names.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+fn track_info(engine: &Engine, path: &Path, index: usize, total: usize) -> String {
+    let now_playing = info::NowPlaying {
+        song: engine.song(),
+        path,
+        duration: engine.duration(),
+    };
+    format!("[{}/{}] {now_playing}", index + 1, total)
+}
+
+#[cfg(feature = "tui")]
+pub fn jam_file(args: &Args, jam: &JamArgs) -> Result<(), Box<dyn Error>> {
+    let song = Song::new(&jam.input)?;
+    let title = info::title(&song, &jam.input);
+
+    let host = cpal::default_host();
+    let device = match &args.device {
+        Some(name) => find_device(&host, name)?,
+        None => host
+            .default_output_device()
+            .ok_or("No output device available")?,
+    };
+    let config = stream_config(&device, args.sample_rate, args.buffer_size)
+        .ok_or("The output device has no usable configuration")?;
+
+    // The jam song loops its empty pattern until the user quits
+    let mut engine = create_engine(jam::jam_song(&song)?, args)?;
+    engine.set_loop_count(0);
+    engine.set_loop_region(None);
+    engine.set_max_time(None);
+    engine.set_silence_limit(None);
+    engine.set_channel_count(config.channels);
+    engine.set_sample_rate(config.sample_rate.0);
+
+    let (events, _blocker) = channel();
+    let (player, handle) = Player::new(events, false);
+    let _stream = play_stream(&device, &config, player)?;
+    handle.load(Entry {
+        id: 0,
+        engine,
+        data: Details {
+            title: title.clone(),
+            path: jam.input.clone(),
+            header: None,
+        },
+    });
+
+    jam::Jam::new(&song, title)?.run(&handle)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "tui"))]
+pub fn jam_file(_: &Args, _: &JamArgs) -> Result<(), Box<dyn Error>> {
+    Err("Jam mode requires building with `--features tui`".into())
+}
+
+fn play_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut player: Player,
+) -> Result<cpal::Stream, Box<dyn Error>> {
+    // the player is only used by the audio thread, so no need to put it in
+    // an arc + mutex; we simply give ownership of it to the callback
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            // How long until the device plays what's rendered now
+            let timestamp = info.timestamp();
+            let latency = timestamp.playback.duration_since(&timestamp.callback);
+            player.render(data, latency.unwrap_or_default())
+        },
+        move |err| {
+            log::error!("Audio stream error: {}", err);
+        },
+        None,
+    )?;
+
+    stream.play()?;
+
+    Ok(stream)
+}
+
+// An audio device and the config to play on it with
+type Output = (cpal::Device, cpal::StreamConfig);
+
+// Sends rendered audio somewhere over the network, for --serve and --icecast
+type Stream = Box<dyn FnMut(&[f32]) + Send>;
+
+// Renders in real time for `streams`, whose listeners buffer the audio as they like, so the
+// rows are reported as they're rendered
+fn play_streamed(mut player: Player, mut streams: Vec<Stream>, config: &cpal::StreamConfig) {
+    let sample_rate = config.sample_rate.0;
+    let channels = config.channels as usize;
+    thread::spawn(move || {
+        let mut buffer = vec![0.0; STREAMED_CHUNK_FRAMES * channels];
+        let start = Instant::now();
+        let mut rendered = 0u64;
+
+        loop {
+            player.render(&mut buffer, Duration::ZERO);
+            for stream in &mut streams {
+                stream(&buffer);
+            }
+
+            rendered += STREAMED_CHUNK_FRAMES as u64;
+            let due = start + Duration::from_secs_f64(rendered as f64 / sample_rate as f64);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+    });
+}
+
+// Advances the player without producing audio, in real time or as fast as possible
+fn play_silent(mut player: Player, realtime: bool) {
+    thread::spawn(move || loop {
+        match player.tick() {
+            Some(duration) if realtime => thread::sleep(Duration::from_secs_f32(duration)),
+            Some(_) => {}
+            // Nothing to play until the main thread sends the next song
+            None => thread::sleep(Duration::from_millis(1)),
+        }
+    });
+}
@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "midi")]
+use crate::midi::MidiOut;
+use crate::osc::OscOut;
+use crate::PlaybackEvent;
+#[cfg(feature = "tui")]
+use rustune::engine::ScopeBuffer;
+use rustune::engine::{self, Engine, PlayerEntry, PlayerHandle, PlayerListener, TrackerEngine};
+use rustune::song::SongLineDisplay;
+
+/// What the command line keeps about a song it hands to the player
+pub struct Details {
+    /// Song name (or file name without one) and file, for reporting what's playing
+    pub title: String,
+    pub path: PathBuf,
+    /// Printed when the song starts, from the thread that prints its rows so it comes first
+    pub header: Option<String>,
+}
+
+/// A song handed to the player, identified by its index in the playlist
+pub type Entry = PlayerEntry<Details>;
+
+/// Controls the player from the main thread and user interfaces
+pub type Handle = PlayerHandle<Details>;
+
+/// What the player is doing, shared with the threads that show or report it
+#[derive(Debug, Default)]
+pub struct Status {
+    /// `None` before the first song starts and after the last one ended
+    pub song: Option<NowPlaying>,
+    pub paused: bool,
+    pub load: Load,
+}
+
+#[derive(Debug, Clone)]
+pub struct NowPlaying {
+    pub index: usize,
+    pub title: String,
+    pub path: PathBuf,
+    /// Length of the song in seconds
+    pub duration: f64,
+    /// Seconds into the song, `None` if that isn't known
+    pub elapsed: Option<f64>,
+}
+
+/// How long mixing takes compared to how long the mixed audio lasts. A load of 1.0 keeps a
+/// whole CPU core busy to play in real time, and above that playback drops out
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Load {
+    /// Seconds spent mixing, and seconds of audio mixed in that time
+    pub busy: f64,
+    pub audio: f64,
+    /// Highest load of a single buffer
+    pub peak: f64,
+    /// Load over about the last second of audio, to show it as it plays
+    pub recent: f64,
+    // Time spent and audio mixed since `recent` was last updated
+    window: (f64, f64),
+}
+
+impl Load {
+    /// Load over everything mixed so far
+    pub fn average(&self) -> f64 {
+        if self.audio > 0.0 {
+            self.busy / self.audio
+        } else {
+            0.0
+        }
+    }
+
+    /// How many times faster than real time the mixing is, `None` before anything was mixed
+    pub fn realtime(&self) -> Option<f64> {
+        (self.busy > 0.0).then(|| self.audio / self.busy)
+    }
+
+    fn add(&mut self, busy: f64, audio: f64) {
+        self.busy += busy;
+        self.audio += audio;
+        self.peak = self.peak.max(busy / audio);
+
+        self.window.0 += busy;
+        self.window.1 += audio;
+        if self.window.1 >= 1.0 || self.recent == 0.0 {
+            self.recent = self.window.0 / self.window.1;
+            self.window = (0.0, 0.0);
+        }
+    }
+}
+
+// Output and events that wait until the audio they belong to is heard
+enum Delayed {
+    Print(String),
+    Event(PlaybackEvent),
+}
+
+/// Plays songs with the library's player on the audio (or silent playback) thread, and
+/// reports what it plays: printing rows, sending events and notes, and keeping the status.
+///
+/// Switching songs happens in the player rather than by restarting the stream, so the next
+/// song can start in the same buffer the previous one ended in, or overlap with its fade out
+/// when crossfading.
+pub struct Player {
+    player: engine::Player<Details>,
+    reporter: Reporter,
+
+    // Receives the output downmixed to mono, with the output channels and the frames to push
+    #[cfg(feature = "tui")]
+    mix: Option<(Arc<ScopeBuffer>, usize, Vec<Vec<f32>>)>,
+}
+
+// Told by the player what it plays, on the thread it plays on
+struct Reporter {
+    events: Sender<PlaybackEvent>,
+    status: Arc<Mutex<Status>>,
+
+    // The order of the last row, to tell when the next pattern starts
+    last_order: Option<usize>,
+    // Print every row as it's heard, like the engine does as it's played
+    print_rows: bool,
+    // When the audio rendered next is heard, the audio device plays it after its latency
+    heard_at: Instant,
+    // Waiting for the audio they belong to, in the order they're due
+    delayed: VecDeque<(Instant, Delayed)>,
+
+    // Receives the notes of every row as it starts
+    #[cfg(feature = "midi")]
+    midi: Option<MidiOut>,
+    osc: Option<OscOut>,
+}
+
+impl Player {
+    pub fn new(events: Sender<PlaybackEvent>, crossfade: bool) -> (Self, Handle) {
+        let (mut player, handle) = engine::Player::idle();
+        player.set_crossfade(crossfade);
+
+        let reporter = Reporter {
+            events,
+            status: Arc::default(),
+            last_order: None,
+            print_rows: false,
+            heard_at: Instant::now(),
+            delayed: VecDeque::new(),
+            #[cfg(feature = "midi")]
+            midi: None,
+            osc: None,
+        };
+        let player = Player {
+            player,
+            reporter,
+            #[cfg(feature = "tui")]
+            mix: None,
+        };
+
+        (player, handle)
+    }
+
+    /// Records the output into `mix` as a single channel, averaging the `channels` of the
+    /// output together
+    #[cfg(feature = "tui")]
+    pub fn set_mix(&mut self, mix: Arc<ScopeBuffer>, channels: usize) {
+        self.mix = Some((mix, channels.max(1), vec![Vec::new()]));
+    }
+
+    /// Broadcasts the rows and songs that start as OSC messages
+    pub fn set_osc(&mut self, osc: OscOut) {
+        self.reporter.osc = Some(osc);
+    }
+
+    /// Sends the notes of the playing songs to a MIDI port
+    #[cfg(feature = "midi")]
+    pub fn set_midi(&mut self, midi: MidiOut) {
+        self.reporter.midi = Some(midi);
+    }
+
+    /// Prints the rows of the playing songs as they're heard, the engines shouldn't print them
+    /// too
+    pub fn set_print_rows(&mut self, enabled: bool) {
+        self.reporter.print_rows = enabled;
+    }
+
+    /// What the player is doing, kept up to date as it plays
+    pub fn status(&self) -> Arc<Mutex<Status>> {
+        self.reporter.status.clone()
+    }
+
+    /// Fills `data` with interleaved audio, for the audio callback. The device plays it after
+    /// `latency`, rows and events are reported then
+    pub fn render(&mut self, data: &mut [f32], latency: Duration) {
+        self.reporter.heard_at = Instant::now() + latency;
+        let sample_rate = self.player.engine().map(|engine| engine.sample_rate());
+
+        // Only mixing songs is measured, silence takes no time to speak of
+        let started = Instant::now();
+        let frames = self.player.render_with(data, &mut self.reporter);
+        if let Some(sample_rate) = sample_rate.filter(|_| frames > 0) {
+            let busy = started.elapsed().as_secs_f64();
+            let audio = frames as f64 / sample_rate.max(1) as f64;
+            self.reporter
+                .update_status(|status| status.load.add(busy, audio));
+        }
+
+        #[cfg(feature = "tui")]
+        self.record_mix(data);
+        self.reporter.flush();
+    }
+
+    // Downmixes the output for the spectrum analyzer
+    #[cfg(feature = "tui")]
+    fn record_mix(&mut self, data: &[f32]) {
+        let Some((mix, channels, frames)) = &mut self.mix else {
+            return;
+        };
+
+        frames[0].clear();
+        frames[0].extend(
+            data.chunks_exact(*channels)
+                .map(|frame| frame.iter().sum::<f32>() / *channels as f32),
+        );
+        mix.push(frames);
+    }
+
+    /// Advances by a single tick without mixing, for playback without an audio device.
+    ///
+    /// # Returns
+    /// How long the tick lasts in seconds, or `None` if nothing is playing.
+    pub fn tick(&mut self) -> Option<f32> {
+        self.reporter.heard_at = Instant::now();
+        let duration = self.player.tick_with(&mut self.reporter);
+        self.reporter.flush();
+        duration
+    }
+}
+
+impl PlayerListener<Details> for Reporter {
+    // Tells the main thread which song is playing now
+    fn started(&mut self, mut entry: Option<&mut Entry>, frame: usize) {
+        self.last_order = None;
+        // Notes of the previous song don't carry over
+        #[cfg(feature = "midi")]
+        if let Some(midi) = &mut self.midi {
+            midi.stop();
+        }
+
+        let heard_at = match &entry {
+            Some(entry) => self.heard_at(&entry.engine, frame),
+            None => self.heard_at,
+        };
+        if let Some(header) = entry.as_mut().and_then(|entry| entry.data.header.take()) {
+            self.delayed.push_back((heard_at, Delayed::Print(header)));
+        }
+        if let (Some(osc), Some(entry)) = (&self.osc, &entry) {
+            osc.track(entry.id, &entry.data.title);
+        }
+
+        let song = entry.as_deref().map(now_playing);
+        self.update_status(|status| status.song = song);
+
+        let event = match entry {
+            Some(entry) => PlaybackEvent::Started(entry.id),
+            None => PlaybackEvent::Finished,
+        };
+        self.delayed.push_back((heard_at, Delayed::Event(event)));
+    }
+
+    fn row(&mut self, engine: &Engine, frame: usize) {
+        let heard_at = self.heard_at(engine, frame);
+        let (order, row) = engine.position();
+
+        if self.print_rows {
+            let metadata = engine.song().metadata();
+            let index = metadata.orders()[order] as usize;
+            if self.last_order.is_some_and(|last| last != order) {
+                let line = format!("Playing pattern: {index}");
+                self.delayed.push_back((heard_at, Delayed::Print(line)));
+            }
+            if let Some(pattern) = engine.song().pattern(index) {
+                let line = SongLineDisplay {
+                    pattern,
+                    sample_metadata: metadata.instruments(),
+                    lineno: row,
+                };
+                self.delayed
+                    .push_back((heard_at, Delayed::Print(line.to_string())));
+            }
+        }
+
+        self.last_order = Some(order);
+        #[cfg(feature = "midi")]
+        if let Some(midi) = &mut self.midi {
+            midi.row(engine);
+        }
+        if let Some(osc) = &self.osc {
+            osc.row(engine);
+        }
+        let elapsed = engine.elapsed();
+        let position = PlaybackEvent::Position {
+            order,
+            row,
+            elapsed,
+            #[cfg(feature = "tui")]
+            channels: (0..engine.song().metadata().channels())
+                .map(|channel| engine.channel_snapshot(channel))
+                .collect(),
+        };
+        self.delayed.push_back((heard_at, Delayed::Event(position)));
+
+        self.update_status(|status| {
+            if let Some(song) = &mut status.song {
+                song.elapsed = elapsed;
+            }
+        });
+    }
+
+    fn reloaded(&mut self, entry: &Entry) {
+        self.last_order = None;
+        self.update_status(|status| status.song = Some(now_playing(entry)));
+    }
+
+    fn paused(&mut self, paused: bool) {
+        #[cfg(feature = "midi")]
+        if let Some(midi) = self.midi.as_mut().filter(|_| paused) {
+            midi.stop();
+        }
+        self.update_status(|status| status.paused = paused);
+    }
+}
+
+impl Reporter {
+    // When audio `frame` frames into the buffer being rendered is heard
+    fn heard_at(&self, engine: &Engine, frame: usize) -> Instant {
+        self.heard_at + Duration::from_secs_f64(frame as f64 / engine.sample_rate().max(1) as f64)
+    }
+
+    // Prints and sends what is heard by now
+    fn flush(&mut self) {
+        let now = Instant::now();
+        while self.delayed.front().is_some_and(|(due, _)| *due <= now) {
+            let Some((_, delayed)) = self.delayed.pop_front() else {
+                break;
+            };
+            match delayed {
+                Delayed::Print(text) => println!("{text}"),
+                Delayed::Event(event) => {
+                    let _ = self.events.send(event);
+                }
+            }
+        }
+    }
+
+    fn update_status(&self, update: impl FnOnce(&mut Status)) {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+        update(&mut status);
+    }
+}
+
+fn now_playing(entry: &Entry) -> NowPlaying {
+    NowPlaying {
+        index: entry.id,
+        title: entry.data.title.clone(),
+        path: entry.data.path.clone(),
+        duration: entry.engine.duration(),
+        elapsed: entry.engine.elapsed(),
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rustune::archive::Archive;
+use rustune::formats::mod_validator::{self, Problem};
+use rustune::{Song, SongError};
+
+/// An ordered list of modules to play, built from file arguments, M3U playlists and ZIP
+/// archives.
+///
+/// The modules in an archive are entries of their own, with the archive's path followed by
+/// theirs in it, e.g. `mods.zip/4mat/eternity.mod`. [`Playlist::load`] reads them without
+/// extracting anything to disk.
+#[derive(Debug, Default)]
+pub struct Playlist {
+    entries: Vec<PathBuf>,
+    current: usize,
+    // The archives the entries were found in, read once for every module in them
+    archives: HashMap<PathBuf, Archive<Vec<u8>>>,
+}
+
+impl Playlist {
+    /// Builds a playlist from the given paths, expanding `.m3u`/`.m3u8` files and `.zip`
+    /// archives into their entries
+    ///
+    /// # Errors
+    /// When a playlist file or archive can't be read
+    pub fn from_paths(paths: &[PathBuf]) -> Result<Playlist, String> {
+        let mut entries = Vec::new();
+        let mut archives = HashMap::new();
+
+        for path in paths {
+            if is_m3u(path) {
+                entries.extend(read_m3u(path)?);
+            } else if is_zip(path) {
+                let (modules, archive) = read_zip(path)?;
+                entries.extend(modules);
+                archives.insert(path.clone(), archive);
+            } else {
+                entries.push(path.clone());
+            }
+        }
+
+        Ok(Playlist {
+            entries,
+            current: 0,
+            archives,
+        })
+    }
+
+    /// Loads the module of an entry, see [`load`]. Modules in the archives the playlist was
+    /// built from are extracted from its copy of the archive
+    ///
+    /// # Errors
+    /// When the module or its archive can't be read, or the module isn't valid
+    pub fn load(&self, path: &Path) -> Result<Song, SongError> {
+        match self.opened_member(path) {
+            Some(data) => load_member(data?),
+            None => load(path),
+        }
+    }
+
+    /// Checks the module of an entry for structural problems, see [`validate`]
+    ///
+    /// # Errors
+    /// When the module or its archive can't be read
+    pub fn validate(&self, path: &Path) -> Result<Vec<Problem>, SongError> {
+        match self.opened_member(path) {
+            Some(data) => Ok(mod_validator::validate(&data?)),
+            None => validate(path),
+        }
+    }
+
+    // The data of a module in one of the playlist's archives, `None` for any other path
+    fn opened_member(&self, path: &Path) -> Option<Result<Vec<u8>, SongError>> {
+        let (archive, name) = (path.ancestors().skip(1)).find_map(|ancestor| {
+            let archive = self.archives.get(ancestor)?;
+            Some((archive, member_name(path, ancestor)?))
+        })?;
+        Some(extract(archive, &name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[PathBuf] {
+        &self.entries
+    }
+
+    /// Index of the current entry
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    /// The entry that is currently playing, `None` once the end has been passed
+    pub fn current(&self) -> Option<&Path> {
+        self.entries.get(self.current).map(PathBuf::as_path)
+    }
+
+    /// Adds `path` to the end, unless it's already an entry
+    ///
+    /// # Returns
+    /// The index of the entry
+    pub fn add(&mut self, path: PathBuf) -> usize {
+        if let Some(index) = self.entries.iter().position(|entry| *entry == path) {
+            return index;
+        }
+
+        self.entries.push(path);
+        self.entries.len() - 1
+    }
+
+    /// Moves to the next entry, returning `None` at the end of the playlist
+    #[allow(dead_code)]
+    pub fn next(&mut self) -> Option<&Path> {
+        self.current = (self.current + 1).min(self.entries.len());
+        self.current()
+    }
+
+    /// Moves to the entry at `index`, or past the end if there's no such entry
+    pub fn select(&mut self, index: usize) -> Option<&Path> {
+        self.current = index.min(self.entries.len());
+        self.current()
+    }
+
+    /// Moves to the previous entry, staying on the first one
+    pub fn previous(&mut self) -> Option<&Path> {
+        self.current = self.current.saturating_sub(1);
+        self.current()
+    }
+}
+
+/// Loads the module of a playlist entry, from its file or the archive it's in
+///
+/// # Errors
+/// When the module or its archive can't be read, or the module isn't valid
+pub fn load(path: &Path) -> Result<Song, SongError> {
+    if let Some((archive, name)) = archive_member(path) {
+        return load_member(read_member(archive, &name)?);
+    }
+
+    // Songs are found in the cache by the contents of their file
+    #[cfg(feature = "cache")]
+    if crate::cache::is_enabled() {
+        return crate::cache::load(&fs::read(path)?, || Song::new(path));
+    }
+    Song::new(path)
+}
+
+/// Checks the module of a playlist entry for structural problems, see [`Song::validate`]
+///
+/// # Errors
+/// When the module or its archive can't be read
+pub fn validate(path: &Path) -> Result<Vec<Problem>, SongError> {
+    match archive_member(path) {
+        Some((archive, name)) => Ok(mod_validator::validate(&read_member(archive, &name)?)),
+        None => Song::validate(path),
+    }
+}
+
+fn load_member(data: Vec<u8>) -> Result<Song, SongError> {
+    #[cfg(feature = "cache")]
+    if crate::cache::is_enabled() {
+        return crate::cache::load(&data, || Song::from_bytes(data.clone()));
+    }
+    Song::from_bytes(data)
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+fn is_m3u(path: &Path) -> bool {
+    matches!(extension(path).as_deref(), Some("m3u" | "m3u8"))
+}
+
+fn is_zip(path: &Path) -> bool {
+    extension(path).as_deref() == Some("zip")
+}
+
+// The modules in an archive, anything else that comes with them is left out
+fn read_zip(path: &Path) -> Result<(Vec<PathBuf>, Archive<Vec<u8>>), String> {
+    let data =
+        fs::read(path).map_err(|e| format!("Failed to read archive {}: {e}", path.display()))?;
+    let archive =
+        Archive::new(data).map_err(|e| format!("Invalid archive {}: {e}", path.display()))?;
+
+    let entries = (archive.entries().iter())
+        .map(|entry| path.join(&entry.name))
+        .filter(|entry| extension(entry).as_deref() == Some("mod"))
+        .collect();
+    Ok((entries, archive))
+}
+
+// Splits the path of a module in an archive into the archive's path and the module's name
+// in it
+fn archive_member(path: &Path) -> Option<(&Path, String)> {
+    let archive =
+        (path.ancestors().skip(1)).find(|ancestor| is_zip(ancestor) && ancestor.is_file())?;
+    Some((archive, member_name(path, archive)?))
+}
+
+// The name of the module at `path` in the archive at `archive`
+fn member_name(path: &Path, archive: &Path) -> Option<String> {
+    let name = path.strip_prefix(archive).ok()?;
+
+    // Archives separate directories with slashes on every system
+    let name: Vec<_> = (name.components())
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    Some(name.join("/"))
+}
+
+fn read_member(archive: &Path, name: &str) -> Result<Vec<u8>, SongError> {
+    extract(&Archive::new(fs::read(archive)?)?, name)
+}
+
+fn extract(archive: &Archive<Vec<u8>>, name: &str) -> Result<Vec<u8>, SongError> {
+    let entry = archive.find(name).ok_or_else(|| {
+        SongError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{name} isn't in the archive"),
+        ))
+    })?;
+    archive.read(entry)
+}
+
+fn read_m3u(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read playlist {}: {e}", path.display()))?;
+
+    // Relative entries are relative to the playlist itself
+    let base = path.parent().unwrap_or(Path::new(""));
+    Ok(parse_m3u(&contents, base))
+}
+
+fn parse_m3u(contents: &str, base: &Path) -> Vec<PathBuf> {
+    contents
+        .lines()
+        .map(str::trim)
+        // Skip blank lines and comments/extended M3U directives
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect()
+}
+
+#[test]
+fn m3u_entries_are_relative_to_the_playlist() {
+    let entries = parse_m3u(
+        "one.mod\nsub/two.mod\n/music/three.mod\n",
+        Path::new("lists"),
+    );
+    assert_eq!(
+        entries,
+        [
+            PathBuf::from("lists/one.mod"),
+            PathBuf::from("lists/sub/two.mod"),
+            PathBuf::from("/music/three.mod"),
+        ]
+    );
+}
+
+#[test]
+fn m3u_skips_directives_and_comments() {
+    let contents = "#EXTM3U\n#EXTINF:123,Artist - Title\none.mod\n# A comment\ntwo.mod\n";
+    assert_eq!(
+        parse_m3u(contents, Path::new("")),
+        [PathBuf::from("one.mod"), PathBuf::from("two.mod")]
+    );
+}
+
+#[test]
+fn m3u_skips_blank_lines() {
+    let contents = "\none.mod\n\n   \n\ttwo.mod  \n\n";
+    assert_eq!(
+        parse_m3u(contents, Path::new("")),
+        [PathBuf::from("one.mod"), PathBuf::from("two.mod")]
+    );
+}
+
+#[test]
+fn m3u_reads_crlf_line_endings() {
+    let contents = "#EXTM3U\r\n#EXTINF:-1,Title\r\none.mod\r\n\r\ntwo.mod\r\n";
+    assert_eq!(
+        parse_m3u(contents, Path::new("lists")),
+        [
+            PathBuf::from("lists/one.mod"),
+            PathBuf::from("lists/two.mod")
+        ]
+    );
+}
+
+#[test]
+fn archives_are_read_once() {
+    // An archive with an empty `song.mod`, stored as it is
+    let name = b"song.mod";
+    let header = |signature: u32, version: &[u8]| {
+        let mut header = signature.to_le_bytes().to_vec();
+        header.extend_from_slice(version);
+        // Flags, method, time, CRC and sizes are all zero for an empty stored file
+        header.extend_from_slice(&[0; 20]);
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&[0; 2]);
+        header
+    };
+    let mut data = header(0x0403_4B50, &[20, 0]);
+    data.extend_from_slice(name);
+    let directory = data.len() as u32;
+    data.extend(header(0x0201_4B50, &[20, 0, 20, 0]));
+    data.extend_from_slice(&[0; 14]);
+    data.extend_from_slice(name);
+    let directory_size = data.len() as u32 - directory;
+    data.extend_from_slice(&0x0605_4B50u32.to_le_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    data.extend_from_slice(&directory_size.to_le_bytes());
+    data.extend_from_slice(&directory.to_le_bytes());
+    data.extend_from_slice(&[0; 2]);
+
+    let path = std::env::temp_dir().join(format!("rustune-playlist-{}.zip", std::process::id()));
+    fs::write(&path, data).unwrap();
+    let playlist = Playlist::from_paths(std::slice::from_ref(&path));
+    fs::remove_file(&path).unwrap();
+    let playlist = playlist.unwrap();
+
+    // The module is extracted from the playlist's copy, with the file gone
+    let song = path.join("song.mod");
+    assert_eq!(playlist.entries(), std::slice::from_ref(&song));
+    assert_eq!(playlist.opened_member(&song).unwrap().unwrap(), b"");
+    assert!(playlist
+        .opened_member(&path.join("other.mod"))
+        .unwrap()
+        .is_err());
+    assert!(playlist.opened_member(Path::new("song.mod")).is_none());
+}
@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::json::JsonObject;
+use crate::playlist::Playlist;
+use crate::{bench, create_engine, Args, DEFAULT_RENDER_RATE};
+use rustune::engine::TrackerEngine;
+use rustune::export::cue::CueSheet;
+use rustune::export::loudness::LoudnessMeter;
+use rustune::export::replay_log;
+use rustune::export::tags::Tags;
+use rustune::export::wav::{WavFormat, WavWriter};
+use rustune::export::waveform::Waveform;
+use rustune::export::{self, OutputFormat};
+use rustune::song::{Song, SongError};
+
+/// Renders every file without keeping the audio and reports how long it took, for --bench
+pub fn bench(args: &Args, playlist: &Playlist) -> Result<(), Box<dyn Error>> {
+    let render_rate = sample_rate(args);
+    let mut total = bench::Timings::default();
+
+    for path in playlist.entries() {
+        let timings = bench::bench(playlist, path, render_rate, |song| {
+            create_engine(song, args)
+        })?;
+        total = total + timings;
+
+        if args.json {
+            let object = JsonObject::new()
+                .string("type", "bench")
+                .string("path", &path.to_string_lossy());
+            println!("{}", timings.json(object).finish());
+        } else {
+            println!("{}\n{timings}\n", path.display());
+        }
+    }
+
+    if playlist.len() > 1 {
+        if args.json {
+            let object = JsonObject::new().string("type", "bench_total");
+            println!("{}", total.json(object).finish());
+        } else {
+            println!("All {} files\n{total}", playlist.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders the playlist to stdout, for --raw and --render -
+pub fn raw(args: &Args, playlist: &Playlist) -> Result<(), Box<dyn Error>> {
+    let render_rate = sample_rate(args);
+    if args.cue_sheet {
+        return Err("--cue-sheet needs a file to render to".into());
+    }
+
+    let mut sink = export::create_stdout_sink(args.raw_format);
+    let rendered = render_playlist(args, playlist, sink.as_mut(), render_rate, args.channels);
+
+    // The reading end going away (e.g. `| head`) isn't an error worth reporting
+    match rendered.and_then(|_| sink.finish()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Draws the waveform of the playlist as a PNG, for --waveform
+pub fn waveform(args: &Args, playlist: &Playlist, out: &Path) -> Result<(), Box<dyn Error>> {
+    let render_rate = sample_rate(args);
+    let mut waveform = Waveform::new(args.waveform_channels);
+    for path in playlist.entries() {
+        let song = playlist.load(path)?;
+        let mut engine = create_engine(song, args)?;
+        engine.set_channel_count(2);
+        engine.set_sample_rate(render_rate);
+        waveform.render(&mut engine);
+    }
+
+    let (width, height) = args.waveform_size;
+    let file = fs::File::create(out).map_err(|e| format!("{}: {e}", out.display()))?;
+    waveform.write_png(BufWriter::new(file), width, height)?;
+    println!("Waveform written to {}", out.display());
+
+    Ok(())
+}
+
+/// Writes every tick and channel the engine plays to `out` (or stdout for -), for --replay-log
+pub fn replay_log(args: &Args, playlist: &Playlist, out: &Path) -> Result<(), Box<dyn Error>> {
+    let render_rate = sample_rate(args);
+    let mut writer: Box<dyn Write> = if out == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        let file = fs::File::create(out).map_err(|e| format!("{}: {e}", out.display()))?;
+        Box::new(BufWriter::new(file))
+    };
+
+    for path in playlist.entries() {
+        let song = playlist.load(path)?;
+        let mut engine = create_engine(song, args)?;
+        engine.set_trace_effects(args.trace_effects);
+        engine.set_channel_count(2);
+        engine.set_sample_rate(render_rate);
+
+        let written = writeln!(writer, "# {}", path.display())
+            .and_then(|_| replay_log::write_replay_log(&mut engine, &mut writer));
+        // The reading end going away (e.g. `| head`) isn't an error worth reporting
+        match written {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+
+    if out != Path::new("-") {
+        println!("Replay log written to {}", out.display());
+    }
+
+    Ok(())
+}
+
+/// Renders the playlist back to back into a single file, for --render
+pub fn render(args: &Args, playlist: &Playlist, out: &Path) -> Result<(), Box<dyn Error>> {
+    let render_rate = sample_rate(args);
+    let format = if args.float {
+        WavFormat::Float32
+    } else {
+        WavFormat::Int16
+    };
+
+    let output_format = OutputFormat::from_path(out)?;
+    let sample_rate = output_format.required_sample_rate().unwrap_or(render_rate);
+
+    if args.markers {
+        if output_format != OutputFormat::Wav {
+            return Err("--markers is only supported when rendering to WAV".into());
+        }
+
+        let file = BufWriter::new(fs::File::create(out)?);
+        let mut wav = WavWriter::new(file, sample_rate, 2, format)?;
+        for (id, text) in playlist_tags(playlist)?.riff_info() {
+            wav.add_info(id, &text);
+        }
+        let tracks = render_playlist_markers(args, playlist, &mut wav, sample_rate)?;
+        wav.finish()?;
+        println!("Rendered to {}", out.display());
+
+        if args.cue_sheet {
+            write_cue_sheet(out, output_format, sample_rate, &tracks)?;
+        }
+
+        return Ok(());
+    }
+
+    let mut tags = playlist_tags(playlist)?;
+    // The gain tags come before the audio, so the songs are rendered once to measure them
+    if output_format.has_gain_tags() {
+        let mut meter = LoudnessMeter::new(sample_rate, 2);
+        render_playlist(args, playlist, &mut meter, sample_rate, 2)?;
+        tags.loudness = Some(meter.loudness());
+    }
+
+    let mut sink = export::create_sink(out, output_format, sample_rate, 2, format, &tags)?;
+    let tracks = render_playlist(args, playlist, sink.as_mut(), sample_rate, 2)?;
+    sink.finish()?;
+    println!("Rendered to {}", out.display());
+
+    if args.cue_sheet {
+        write_cue_sheet(out, output_format, sample_rate, &tracks)?;
+    }
+
+    Ok(())
+}
+
+// Sample rate of rendered files
+fn sample_rate(args: &Args) -> u32 {
+    args.sample_rate.unwrap_or(DEFAULT_RENDER_RATE)
+}
+
+// Renders the songs one after another into `sink`, returns the title and length in frames
+// of every song
+fn render_playlist(
+    args: &Args,
+    playlist: &Playlist,
+    sink: &mut dyn export::AudioSink,
+    sample_rate: u32,
+    channels: u16,
+) -> io::Result<Vec<(String, u64)>> {
+    let mut tracks = Vec::new();
+    for path in playlist.entries() {
+        let song = playlist.load(path).map_err(io::Error::other)?;
+        let title = track_title(&song, path);
+
+        let mut engine = create_engine(song, args).map_err(io::Error::other)?;
+        engine.set_trace_effects(args.trace_effects);
+        engine.set_channel_count(channels);
+        engine.set_sample_rate(sample_rate);
+
+        let frames = export::render(&mut engine, sink)?;
+        tracks.push((title, frames));
+    }
+
+    Ok(tracks)
+}
+
+// The tags of a file the playlist is rendered to: those of its song when there's only one,
+// of the software for more
+fn playlist_tags(playlist: &Playlist) -> Result<Tags, SongError> {
+    match playlist.entries() {
+        [path] => Ok(Tags::from_song(playlist.load(path)?.metadata())),
+        _ => Ok(Tags::default()),
+    }
+}
+
+// The title of a song, or the name of its file when it has none
+fn track_title(song: &Song, path: &Path) -> String {
+    let title = song.metadata().title().trim();
+    if title.is_empty() {
+        path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        title.to_owned()
+    }
+}
+
+// Writes a CUE sheet next to `out` with a track for every song rendered into it, given by
+// their titles and lengths
+fn write_cue_sheet(
+    out: &Path,
+    format: OutputFormat,
+    sample_rate: u32,
+    tracks: &[(String, u64)],
+) -> io::Result<()> {
+    // The sheet refers to the audio by its name, they're kept in the same directory
+    let file = out.file_name().unwrap_or_default().to_string_lossy();
+    let mut sheet = CueSheet::new(&file, format, sample_rate);
+
+    let mut start = 0;
+    for (title, frames) in tracks {
+        sheet.add_track(title, start);
+        start += frames;
+    }
+
+    let path = out.with_extension("cue");
+    sheet.write(BufWriter::new(fs::File::create(&path)?))?;
+    println!("CUE sheet written to {}", path.display());
+
+    Ok(())
+}
+
+// Renders the songs one after another into `wav`, marking the orders and loop of every song.
+// Returns the songs like `render_playlist`
+fn render_playlist_markers<W: Write + io::Seek>(
+    args: &Args,
+    playlist: &Playlist,
+    wav: &mut WavWriter<W>,
+    sample_rate: u32,
+) -> io::Result<Vec<(String, u64)>> {
+    let mut tracks = Vec::new();
+    let mut offset = 0;
+    for path in playlist.entries() {
+        let song = playlist.load(path).map_err(io::Error::other)?;
+        let title = track_title(&song, path);
+        let orders = song.metadata().orders().to_vec();
+
+        let mut engine = create_engine(song, args).map_err(io::Error::other)?;
+        engine.set_trace_effects(args.trace_effects);
+        engine.set_channel_count(2);
+        engine.set_sample_rate(sample_rate);
+
+        let markers = export::render_with_markers(&mut engine, wav)?;
+        for &(frame, order) in &markers.orders {
+            let mut label = format!("Order {order} (pattern {})", orders[order]);
+            // Songs of a playlist are told apart by their titles
+            if playlist.len() > 1 {
+                label = format!("{title}: {label}");
+            }
+            wav.add_cue(offset + frame, &label);
+        }
+        if let Some((start, end)) = markers.song_loop {
+            wav.add_loop(offset + start, offset + end);
+        }
+
+        offset += markers.frames;
+        tracks.push((title, markers.frames as u64));
+    }
+
+    Ok(tracks)
+}
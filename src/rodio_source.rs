@@ -0,0 +1,153 @@
+//! Playing songs through [rodio](https://docs.rs/rodio)
+
+use std::time::Duration;
+
+use rodio::Source;
+
+use crate::engine::{Engine, TrackerEngine, DEFAULT_SAMPLE_RATE};
+use crate::song::{Song, SongError};
+
+// Frames rendered at once, rodio pulls samples one by one
+const CHUNK_FRAMES: usize = 1024;
+
+/// A [`rodio::Source`] playing a song, so it can be appended to a `rodio::Sink` like any
+/// other sound.
+///
+/// ```no_run
+/// use rustune::{RodioSource, Song};
+///
+/// let (_stream, handle) = rodio::OutputStream::try_default().unwrap();
+/// let sink = rodio::Sink::try_new(&handle).unwrap();
+///
+/// sink.append(RodioSource::new(Song::new("song.mod".as_ref())?)?);
+/// sink.sleep_until_end();
+/// # Ok::<(), rustune::SongError>(())
+/// ```
+pub struct RodioSource {
+    engine: Engine,
+    buffer: Vec<f32>,
+    // Next sample of `buffer` to hand out, and how much of it is filled
+    position: usize,
+    filled: usize,
+}
+
+impl RodioSource {
+    /// Plays `song` in stereo at 44100 Hz, once
+    ///
+    /// # Errors
+    /// When there's no engine for the song's tracker yet
+    pub fn new(song: Song) -> Result<Self, SongError> {
+        let mut engine = Engine::new(song)?;
+        engine.set_channel_count(2);
+        engine.set_sample_rate(DEFAULT_SAMPLE_RATE);
+
+        Ok(Self::from_engine(engine))
+    }
+
+    /// Plays an engine that's already been set up, e.g. with a sample rate, loop count or
+    /// muted channels. The sample rate and channels can't change once playback started
+    pub fn from_engine(mut engine: Engine) -> Self {
+        if engine.channel_count() == 0 {
+            engine.set_channel_count(2);
+        }
+        if engine.sample_rate() == 0 {
+            engine.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        }
+
+        RodioSource {
+            buffer: vec![0.0; CHUNK_FRAMES * engine.channel_count() as usize],
+            engine,
+            position: 0,
+            filled: 0,
+        }
+    }
+
+    /// The engine playing the song
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// The engine playing the song, e.g. to seek or mute channels while it plays
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+}
+
+impl Iterator for RodioSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.filled {
+            if self.engine.is_finished() {
+                return None;
+            }
+
+            let channels = self.engine.channel_count() as usize;
+            self.filled = self.engine.render(&mut self.buffer) * channels;
+            self.position = 0;
+
+            if self.filled == 0 {
+                return None;
+            }
+        }
+
+        let sample = self.buffer[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl Source for RodioSource {
+    // The format never changes, so the whole song is a single frame
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.engine.channel_count()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.engine.sample_rate()
+    }
+
+    // Loops, fades and jumps make the length of playback unknown up front
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[test]
+fn plays_engines_that_arent_set_up() {
+    use crate::song::{square_song, Note};
+
+    let note = Note {
+        sample: 1,
+        period: 428,
+        effect: 0,
+        argument: 0,
+    };
+    let song = square_song(4)
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .build()
+        .unwrap();
+
+    let source = RodioSource::from_engine(Engine::new(song.clone()).unwrap());
+    assert_eq!(
+        (source.channels(), source.sample_rate()),
+        (2, DEFAULT_SAMPLE_RATE)
+    );
+    assert!(source.take(4096).any(|sample| sample != 0.0));
+
+    // Nor ones that were left without channels or a sample rate
+    let mut engine = Engine::new(song).unwrap();
+    engine.set_channel_count(0);
+    engine.set_sample_rate(0);
+    let source = RodioSource::from_engine(engine);
+    assert_eq!(
+        (source.channels(), source.sample_rate()),
+        (2, DEFAULT_SAMPLE_RATE)
+    );
+    assert!(source.take(4096).any(|sample| sample != 0.0));
+}
@@ -0,0 +1,605 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::json::{self, JsonObject, Value};
+use crate::player::Status;
+
+// How often the player status is checked for new songs and how long they've been heard
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long to wait before sending scrobbles again after a service couldn't take them
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// Scrobbles kept while a service can't be reached, the oldest are dropped beyond that
+const MAX_PENDING: usize = 100;
+
+// What Last.fm asks of scrobblers: songs shorter than this aren't scrobbled, the others once
+// they've been heard for half their length or this long, whichever comes first
+const MIN_DURATION: f64 = 30.0;
+const MAX_HEARD: f64 = 240.0;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+const USER_AGENT: &str = concat!("Rustune/", env!("CARGO_PKG_VERSION"));
+
+// MusicBrainz's name for an artist that isn't known, which both services understand
+const UNKNOWN_ARTIST: &str = "[unknown]";
+
+const LASTFM_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+const LASTFM_AUTH_URL: &str = "https://www.last.fm/api/auth/";
+const LISTENBRAINZ_URL: &str = "https://api.listenbrainz.org";
+
+/// A song the way the services know it
+#[derive(Debug, Clone)]
+struct Track {
+    artist: String,
+    title: String,
+    // In seconds
+    duration: f64,
+}
+
+impl Track {
+    // Modules rarely say who made them, but their files are often named "Artist - Title.mod".
+    // Otherwise the file is taken to be in a folder named after the artist, the way
+    // collections like Modland sort them
+    fn guess(path: &Path, title: &str, duration: f64) -> Track {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = name.replace('_', " ");
+
+        if let Some((artist, title)) = name.split_once(" - ") {
+            let (artist, title) = (artist.trim(), title.trim());
+            if !artist.is_empty() && !title.is_empty() {
+                return Track {
+                    artist: artist.to_owned(),
+                    title: title.to_owned(),
+                    duration,
+                };
+            }
+        }
+
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let artist = (path.parent().and_then(Path::file_name))
+            .map(|folder| folder.to_string_lossy().trim().to_owned())
+            .filter(|folder| !folder.is_empty())
+            .unwrap_or_else(|| UNKNOWN_ARTIST.to_owned());
+        // The player shows the file name for modules without a title
+        let title = match title.trim() {
+            "" => name.trim(),
+            title if title == file_name => name.trim(),
+            title => title,
+        };
+
+        Track {
+            artist,
+            title: title.to_owned(),
+            duration,
+        }
+    }
+}
+
+/// A song that was heard long enough to be scrobbled
+#[derive(Debug, Clone)]
+struct Listen {
+    track: Track,
+    // When it started, in seconds since the Unix epoch
+    started_at: u64,
+}
+
+/// Why a service didn't take a request
+enum Failure {
+    // Worth trying again later, e.g. the service couldn't be reached or was busy
+    Temporary(String),
+    // The service won't ever take it, e.g. for wrong credentials
+    Rejected(String),
+}
+
+trait Service: Send {
+    fn name(&self) -> &'static str;
+    fn now_playing(&mut self, track: &Track) -> Result<(), Failure>;
+    fn scrobble(&mut self, listen: &Listen) -> Result<(), Failure>;
+}
+
+/// Sends the songs played to Last.fm and ListenBrainz, for the accounts set up in the config
+/// file.
+///
+/// Runs on a background thread that watches the player's status. A song is announced as
+/// playing when it starts, and scrobbled once it has been heard, without the time it spent
+/// paused, for half its length or four minutes. Songs under 30 seconds aren't scrobbled.
+/// Scrobbles a service can't take right now are sent again every minute while the player runs.
+pub fn start(
+    lastfm: Option<config::LastFm>,
+    listenbrainz: Option<config::ListenBrainz>,
+    status: Arc<Mutex<Status>>,
+) {
+    let agent = agent();
+
+    let mut services: Vec<Box<dyn Service>> = Vec::new();
+    if let Some(account) = lastfm {
+        match account.session_key.clone() {
+            Some(session) => services.push(Box::new(LastFm {
+                account,
+                session,
+                agent: agent.clone(),
+            })),
+            None => log::warn!(
+                "Not scrobbling to Last.fm without a session-key, get one with --lastfm-login"
+            ),
+        }
+    }
+    if let Some(account) = listenbrainz {
+        let url = account.url.as_deref().unwrap_or(LISTENBRAINZ_URL);
+        services.push(Box::new(ListenBrainz {
+            url: url.trim_end_matches('/').to_owned(),
+            token: account.token,
+            agent,
+        }));
+    }
+    if services.is_empty() {
+        return;
+    }
+
+    let scrobblers = services.into_iter().map(Scrobbler::new).collect();
+    thread::spawn(move || run(scrobblers, &status));
+}
+
+// The song that's playing, and how long it has been heard
+struct Playing {
+    index: usize,
+    path: PathBuf,
+    listen: Listen,
+    heard: f64,
+    scrobbled: bool,
+}
+
+fn run(mut scrobblers: Vec<Scrobbler>, status: &Mutex<Status>) {
+    let mut playing: Option<Playing> = None;
+    let mut last_poll = Instant::now();
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let now = Instant::now();
+        let since_poll = now.duration_since(last_poll).as_secs_f64();
+        last_poll = now;
+
+        let (song, paused) = {
+            let status = status.lock().unwrap_or_else(|e| e.into_inner());
+            (status.song.clone(), status.paused)
+        };
+
+        match (&mut playing, song) {
+            (_, None) => playing = None,
+            (Some(playing), Some(song))
+                if playing.index == song.index && playing.path == song.path =>
+            {
+                if !paused {
+                    playing.heard += since_poll;
+                }
+            }
+            (_, Some(song)) => {
+                let track = Track::guess(&song.path, &song.title, song.duration);
+                for scrobbler in &mut scrobblers {
+                    scrobbler.now_playing(&track);
+                }
+
+                let started_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                playing = Some(Playing {
+                    index: song.index,
+                    path: song.path,
+                    listen: Listen { track, started_at },
+                    heard: 0.0,
+                    scrobbled: false,
+                });
+            }
+        }
+
+        if let Some(playing) = &mut playing {
+            let duration = playing.listen.track.duration;
+            if !playing.scrobbled
+                && duration >= MIN_DURATION
+                && playing.heard >= (duration / 2.0).min(MAX_HEARD)
+            {
+                playing.scrobbled = true;
+                for scrobbler in &mut scrobblers {
+                    scrobbler.scrobble(playing.listen.clone());
+                }
+            }
+        }
+
+        for scrobbler in &mut scrobblers {
+            scrobbler.retry(now);
+        }
+    }
+}
+
+// A service, and the scrobbles it hasn't taken yet
+struct Scrobbler {
+    service: Box<dyn Service>,
+    pending: VecDeque<Listen>,
+    // When to send the pending scrobbles again, after the service failed to take them
+    retry_at: Option<Instant>,
+}
+
+impl Scrobbler {
+    fn new(service: Box<dyn Service>) -> Self {
+        Scrobbler {
+            service,
+            pending: VecDeque::new(),
+            retry_at: None,
+        }
+    }
+
+    fn now_playing(&mut self, track: &Track) {
+        let name = self.service.name();
+        match self.service.now_playing(track) {
+            Ok(()) => log::debug!("Sent {} - {} to {name}", track.artist, track.title),
+            Err(Failure::Temporary(e) | Failure::Rejected(e)) => {
+                log::warn!("Couldn't tell {name} what's playing: {e}")
+            }
+        }
+    }
+
+    fn scrobble(&mut self, listen: Listen) {
+        self.pending.push_back(listen);
+        if self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+            log::warn!(
+                "Too many scrobbles for {}, dropped the oldest",
+                self.service.name()
+            );
+        }
+
+        // The pending ones go first, in order
+        if self.retry_at.is_none() {
+            self.send_pending();
+        }
+    }
+
+    fn retry(&mut self, now: Instant) {
+        if self.retry_at.is_some_and(|at| now >= at) {
+            self.send_pending();
+        }
+    }
+
+    fn send_pending(&mut self) {
+        let name = self.service.name();
+        while let Some(listen) = self.pending.front() {
+            match self.service.scrobble(listen) {
+                Ok(()) => log::info!("Scrobbled {} to {name}", listen.track.title),
+                Err(Failure::Rejected(e)) => {
+                    log::warn!(
+                        "{name} didn't take the scrobble of {}: {e}",
+                        listen.track.title
+                    )
+                }
+                Err(Failure::Temporary(e)) => {
+                    log::warn!("Couldn't scrobble to {name}, trying again in a minute: {e}");
+                    self.retry_at = Some(Instant::now() + RETRY_INTERVAL);
+                    return;
+                }
+            }
+            self.pending.pop_front();
+        }
+        self.retry_at = None;
+    }
+}
+
+// The status and body of a response, including those of errors
+fn response(result: Result<ureq::Response, ureq::Error>) -> Result<(u16, String), Failure> {
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => return Err(Failure::Temporary(e.to_string())),
+    };
+    let status = response.status();
+    let body = (response.into_string()).map_err(|e| Failure::Temporary(e.to_string()))?;
+    Ok((status, body))
+}
+
+// Servers that are busy or broken may take it later, anything else is refused for good
+fn http_failure(status: u16, message: String) -> Failure {
+    if status == 429 || status >= 500 {
+        Failure::Temporary(message)
+    } else {
+        Failure::Rejected(message)
+    }
+}
+
+/// Lets the user allow Rustune to scrobble to their Last.fm account, the way desktop
+/// applications log in, and prints the session key to put in the config file. The user's
+/// password never passes through the player.
+///
+/// # Errors
+/// When Last.fm can't be reached, or the user didn't allow it
+pub fn login(account: &config::LastFm) -> Result<(), Box<dyn Error>> {
+    let agent = agent();
+    let failed = |(Failure::Temporary(e) | Failure::Rejected(e))| format!("Last.fm: {e}");
+
+    let params = vec![("method", String::from("auth.getToken"))];
+    let reply = lastfm_request(&agent, account, params).map_err(failed)?;
+    let token = (reply.get("token").and_then(Value::as_str)).ok_or("Last.fm sent no token")?;
+
+    println!(
+        "Allow Rustune to scrobble to your account at {LASTFM_AUTH_URL}?api_key={}&token={token}",
+        account.api_key
+    );
+    println!("Then press Enter");
+    io::stdin().read_line(&mut String::new())?;
+
+    let params = vec![
+        ("method", String::from("auth.getSession")),
+        ("token", token.to_owned()),
+    ];
+    let reply = lastfm_request(&agent, account, params).map_err(failed)?;
+    let session = reply.get("session");
+    let key = (session.and_then(|session| session.get("key")))
+        .and_then(Value::as_str)
+        .ok_or("Last.fm sent no session")?;
+    let name = (session.and_then(|session| session.get("name")))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    println!("Logged in as {name}, add this to the [lastfm] table of the config file:");
+    println!("session-key = \"{key}\"");
+    Ok(())
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(USER_AGENT)
+        .build()
+}
+
+// The api_sig of a Last.fm request: the MD5 of every parameter and its value, sorted by name,
+// followed by the secret
+fn signature(params: &[(&str, String)], secret: &str) -> String {
+    let mut params = params.to_vec();
+    params.sort_by_key(|&(name, _)| name);
+
+    let mut signed: String = (params.iter())
+        .map(|(name, value)| format!("{name}{value}"))
+        .collect();
+    signed += secret;
+    format!("{:x}", md5::compute(signed))
+}
+
+// Sends a signed request to Last.fm, errors in the response are failures
+fn lastfm_request(
+    agent: &ureq::Agent,
+    account: &config::LastFm,
+    mut params: Vec<(&'static str, String)>,
+) -> Result<Value, Failure> {
+    params.push(("api_key", account.api_key.clone()));
+    params.push(("api_sig", signature(&params, &account.api_secret)));
+    params.push(("format", String::from("json")));
+
+    let form: Vec<(&str, &str)> = (params.iter())
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let (status, body) = response(agent.post(LASTFM_URL).send_form(&form))?;
+    let reply = json::parse(&body).map_err(|_| http_failure(status, format!("HTTP {status}")))?;
+
+    if let Some(code) = reply.get("error").and_then(Value::as_i64) {
+        let message = reply
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let message = format!("{message} (error {code})");
+        // Failed at the backend, offline, unavailable and rate limited. A session that's no
+        // longer valid takes logging in again
+        return Err(match code {
+            8 | 11 | 16 | 29 => Failure::Temporary(message),
+            9 => Failure::Rejected(format!("{message}, log in again with --lastfm-login")),
+            _ => Failure::Rejected(message),
+        });
+    }
+    if status != 200 {
+        return Err(http_failure(status, format!("HTTP {status}")));
+    }
+
+    Ok(reply)
+}
+
+/// Scrobbles with the Last.fm API, with the session key of a user that allowed it
+struct LastFm {
+    account: config::LastFm,
+    session: String,
+    agent: ureq::Agent,
+}
+
+impl LastFm {
+    // Calls a method on behalf of the user
+    fn call(&mut self, mut params: Vec<(&'static str, String)>) -> Result<Value, Failure> {
+        params.push(("sk", self.session.clone()));
+        lastfm_request(&self.agent, &self.account, params)
+    }
+
+    fn track_params(method: &str, track: &Track) -> Vec<(&'static str, String)> {
+        vec![
+            ("method", method.to_owned()),
+            ("artist", track.artist.clone()),
+            ("track", track.title.clone()),
+            ("duration", format!("{}", track.duration.round() as u64)),
+        ]
+    }
+}
+
+impl Service for LastFm {
+    fn name(&self) -> &'static str {
+        "Last.fm"
+    }
+
+    fn now_playing(&mut self, track: &Track) -> Result<(), Failure> {
+        self.call(Self::track_params("track.updateNowPlaying", track))
+            .map(|_| ())
+    }
+
+    fn scrobble(&mut self, listen: &Listen) -> Result<(), Failure> {
+        let mut params = Self::track_params("track.scrobble", &listen.track);
+        params.push(("timestamp", listen.started_at.to_string()));
+        let reply = self.call(params)?;
+
+        let ignored = (reply.get("scrobbles"))
+            .and_then(|scrobbles| scrobbles.get("@attr"))
+            .and_then(|attributes| attributes.get("ignored"))
+            .and_then(Value::as_i64);
+        match ignored {
+            Some(1..) => Err(Failure::Rejected(String::from("Ignored by Last.fm"))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Submits listens to ListenBrainz, or a server running it
+struct ListenBrainz {
+    // Without the API path
+    url: String,
+    token: String,
+    agent: ureq::Agent,
+}
+
+impl ListenBrainz {
+    fn submit(&self, listen_type: &str, listen: String) -> Result<(), Failure> {
+        let body = JsonObject::new()
+            .string("listen_type", listen_type)
+            .raw("payload", &json::array([listen]))
+            .finish();
+
+        let request = (self.agent.post(&format!("{}/1/submit-listens", self.url)))
+            .set("Authorization", &format!("Token {}", self.token))
+            .set("Content-Type", "application/json");
+        let (status, body) = response(request.send_string(&body))?;
+        if status == 200 {
+            return Ok(());
+        }
+
+        let message = (json::parse(&body).ok())
+            .and_then(|reply| {
+                reply
+                    .get("error")
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+            })
+            .unwrap_or_else(|| format!("HTTP {status}"));
+        Err(http_failure(status, message))
+    }
+
+    fn listen(track: &Track, listened_at: Option<u64>) -> String {
+        let info = JsonObject::new()
+            .string("media_player", "Rustune")
+            .string("submission_client", "Rustune")
+            .string("submission_client_version", env!("CARGO_PKG_VERSION"))
+            .value("duration_ms", (track.duration * 1000.0).round() as u64)
+            .finish();
+        let metadata = JsonObject::new()
+            .string("artist_name", &track.artist)
+            .string("track_name", &track.title)
+            .raw("additional_info", &info)
+            .finish();
+
+        let listen = JsonObject::new();
+        let listen = match listened_at {
+            Some(time) => listen.value("listened_at", time),
+            None => listen,
+        };
+        listen.raw("track_metadata", &metadata).finish()
+    }
+}
+
+impl Service for ListenBrainz {
+    fn name(&self) -> &'static str {
+        "ListenBrainz"
+    }
+
+    fn now_playing(&mut self, track: &Track) -> Result<(), Failure> {
+        self.submit("playing_now", Self::listen(track, None))
+    }
+
+    fn scrobble(&mut self, listen: &Listen) -> Result<(), Failure> {
+        self.submit(
+            "single",
+            Self::listen(&listen.track, Some(listen.started_at)),
+        )
+    }
+}
+
+#[test]
+fn guesses_artist_from_file_name() {
+    let track = Track::guess(Path::new("/mods/Purple_Motion - Sundance.mod"), "", 200.0);
+    assert_eq!(track.artist, "Purple Motion");
+    assert_eq!(track.title, "Sundance");
+    assert_eq!(track.duration, 200.0);
+
+    // The title in the module doesn't replace the one in the file name
+    let track = Track::guess(Path::new("Skaven -  Catch that goblin .xm"), "goblin", 60.0);
+    assert_eq!(
+        (track.artist.as_str(), track.title.as_str()),
+        ("Skaven", "Catch that goblin")
+    );
+}
+
+#[test]
+fn guesses_artist_from_folder() {
+    let path = Path::new("/nonexistent/Lizardking/l-ishar3.mod");
+    let track = Track::guess(path, "Ishar 3 ", 60.0);
+    assert_eq!(track.artist, "Lizardking");
+    assert_eq!(track.title, "Ishar 3");
+
+    // Without a title, or with the file name the player shows in its place
+    for title in ["", " ", "l-ishar3.mod"] {
+        assert_eq!(Track::guess(path, title, 60.0).title, "l-ishar3");
+    }
+
+    // A name with an empty artist or title isn't split
+    let track = Track::guess(Path::new("/nonexistent/Jogeir/ - Title.mod"), "T", 60.0);
+    assert_eq!(
+        (track.artist.as_str(), track.title.as_str()),
+        ("Jogeir", "T")
+    );
+}
+
+#[test]
+fn unknown_artist_without_folder() {
+    let track = Track::guess(Path::new("song.mod"), "Song", 60.0);
+    assert_eq!(track.artist, UNKNOWN_ARTIST);
+    assert_eq!(track.title, "Song");
+}
+
+#[test]
+fn signs_requests() {
+    let params = [
+        ("method", String::from("auth.getToken")),
+        ("api_key", String::from("xxxx")),
+    ];
+    assert_eq!(
+        signature(&params, "secret"),
+        "886c60f9b26c75e27466a022384370dd"
+    );
+
+    // Sorted by name, whatever order they're in
+    let mut params = vec![
+        ("track", String::from("Xtal")),
+        ("timestamp", String::from("10")),
+        ("method", String::from("track.scrobble")),
+        ("artist", String::from("Aphex")),
+        ("sk", String::from("SESSION")),
+        ("api_key", String::from("key")),
+    ];
+    assert_eq!(
+        signature(&params, "secret"),
+        "61ba48a2212d2f8ebb0551259da079ae"
+    );
+    params.reverse();
+    assert_eq!(
+        signature(&params, "secret"),
+        "61ba48a2212d2f8ebb0551259da079ae"
+    );
+}
@@ -0,0 +1,251 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rustune::export::tags::Tags;
+use rustune::export::wav::WavFormat;
+use rustune::export::{self, OutputFormat};
+
+// Chunks of audio waiting for a listener before it's let go for being too slow, a few
+// seconds at the size the player renders them in
+const QUEUE_CHUNKS: usize = 256;
+
+// Where the audio for a listener's thread is sent
+type Queue = SyncSender<Arc<[f32]>>;
+
+// Longest request line or header that's read, anything longer isn't a listener
+const MAX_HEADER_LINE: usize = 8192;
+
+// How long a listener has to send its request, so connections that stay silent don't keep
+// their threads forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the output as an HTTP audio stream to every listener that connects, like a
+/// network radio.
+///
+/// The format is picked by the extension of the requested path, e.g. `/stream.ogg`, which
+/// can be any format the encoders for were compiled in. Paths without one get WAV. Every listener
+/// has its own encoder and starts hearing the output from when it connects.
+pub struct StreamServer {
+    // The queues of the listeners' threads
+    listeners: Arc<Mutex<Vec<Queue>>>,
+}
+
+// How the audio sent to listeners is encoded
+#[derive(Clone, Copy)]
+struct StreamFormat {
+    sample_rate: u32,
+    channels: u16,
+    wav_format: WavFormat,
+}
+
+impl StreamServer {
+    /// Listens on `address`, a host and port like `0.0.0.0:8000`, for audio with the given
+    /// rate and channels. `wav_format` is the encoding of WAV streams
+    ///
+    /// # Errors
+    /// When `address` can't be listened on
+    pub fn bind(
+        address: &str,
+        sample_rate: u32,
+        channels: u16,
+        wav_format: WavFormat,
+    ) -> io::Result<StreamServer> {
+        let listener = TcpListener::bind(address)?;
+        log::info!("Streaming on http://{}/", listener.local_addr()?);
+
+        let listeners = Arc::new(Mutex::new(Vec::new()));
+        let format = StreamFormat {
+            sample_rate,
+            channels,
+            wav_format,
+        };
+
+        let queues = listeners.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::debug!("Stream connection failed: {e}");
+                        continue;
+                    }
+                };
+
+                if let Err(e) = stream.set_read_timeout(Some(REQUEST_TIMEOUT)) {
+                    log::debug!("Stream connection failed: {e}");
+                    continue;
+                }
+
+                let queues = queues.clone();
+                thread::spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    if let Err(e) = serve_listener(stream, format, &queues) {
+                        log::debug!("Stream to {peer:?} ended: {e}");
+                    }
+                });
+            }
+        });
+
+        Ok(StreamServer { listeners })
+    }
+
+    /// Sends interleaved audio to every listener. Listeners that fell too far behind are
+    /// disconnected, the output doesn't wait for them
+    pub fn send(&self, samples: &[f32]) {
+        let mut listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        if listeners.is_empty() {
+            return;
+        }
+
+        let chunk: Arc<[f32]> = samples.into();
+        listeners.retain(|listener| match listener.try_send(chunk.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                log::warn!("A listener can't keep up with the stream, disconnecting it");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+// Answers a request, and encodes the output for it until the listener disconnects
+fn serve_listener(
+    stream: TcpStream,
+    format: StreamFormat,
+    queues: &Mutex<Vec<Queue>>,
+) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut writer = stream.try_clone()?;
+    let (method, path) = read_request(stream)?;
+
+    if method != "GET" && method != "HEAD" {
+        return respond_error(
+            &mut writer,
+            "405 Method Not Allowed",
+            "Allow: GET, HEAD\r\n",
+            "Only GET and HEAD are supported",
+        );
+    }
+
+    let output_format = match stream_format(&path, format.sample_rate) {
+        Ok(output_format) => output_format,
+        Err(e) => return respond_error(&mut writer, "404 Not Found", "", &e.to_string()),
+    };
+
+    write!(
+        writer,
+        "HTTP/1.0 200 OK\r\n\
+         Content-Type: {}\r\n\
+         Cache-Control: no-cache, no-store\r\n\
+         Connection: close\r\n\r\n",
+        output_format.mime_type()
+    )?;
+    if method == "HEAD" {
+        return Ok(());
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_CHUNKS);
+    queues
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(sender);
+    log::info!("Streaming {} to {peer}", output_format.extension());
+
+    let mut sink = export::create_stream_sink(
+        writer,
+        output_format,
+        format.sample_rate,
+        format.channels,
+        format.wav_format,
+        &Tags {
+            title: Some(String::from("Rustune")),
+            ..Tags::default()
+        },
+    )?;
+    while let Ok(chunk) = receiver.recv() {
+        if let Err(e) = sink.write_samples(&chunk) {
+            log::info!("{peer} stopped listening");
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+// The method and path of an HTTP request, whose headers are skipped
+fn read_request(stream: TcpStream) -> io::Result<(String, String)> {
+    let mut reader = BufReader::new(stream).take(MAX_HEADER_LINE as u64 * 64);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not an HTTP request",
+        ));
+    };
+    let request = (method.to_owned(), path.to_owned());
+
+    // The headers end with an empty line
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || line.trim().is_empty() {
+            break;
+        }
+        if read > MAX_HEADER_LINE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Header too long",
+            ));
+        }
+    }
+
+    Ok(request)
+}
+
+// The format of the stream at `path`, by its extension and WAV without one
+fn stream_format(path: &str, sample_rate: u32) -> io::Result<OutputFormat> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let format = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => OutputFormat::from_extension(ext)?,
+        None => OutputFormat::Wav,
+    };
+
+    match format.required_sample_rate() {
+        Some(rate) if rate != sample_rate => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "The output is {sample_rate} Hz, {} needs {rate} Hz (set it with --sample-rate)",
+                format.extension()
+            ),
+        )),
+        _ => Ok(format),
+    }
+}
+
+// `headers` are any headers to send besides the usual ones, each ending in CRLF
+fn respond_error(
+    writer: &mut TcpStream,
+    status: &str,
+    headers: &str,
+    message: &str,
+) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.0 {status}\r\n\
+         {headers}\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n\
+         {message}\n",
+        message.len() + 1
+    )
+}
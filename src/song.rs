@@ -1,15 +1,85 @@
+//! A parsed module: its metadata, patterns and sample data
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+use core::str::Utf8Error;
+#[cfg(feature = "std")]
+use std::ffi::OsStr;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::{BufReader, Read, Seek};
+#[cfg(feature = "std")]
+use std::path::Path;
+
 use thiserror::Error;
 
-use crate::formats::mod_loader;
-use crate::tracker::Tracker;
-use std::{ffi::OsStr, fs, path::Path};
+use crate::formats::mod_loader::{self, MAX_SAMPLE_LENGTH};
+#[cfg(feature = "std")]
+use crate::formats::mod_reader::ModReader;
+#[cfg(feature = "std")]
+use crate::formats::mod_validator::{self, Problem};
+use crate::formats::mod_writer;
+use crate::tracker::{self, Tracker};
 
+/// Why a module couldn't be loaded or played
 #[derive(Debug, Error)]
 pub enum SongError {
+    /// The file couldn't be read
+    #[cfg(feature = "std")]
     #[error("IO Error: {0}")]
-    Io(String),
-    #[error("Read Error: {0}")]
-    Read(String),
+    Io(#[from] std::io::Error),
+
+    /// The data isn't in any of the supported formats
+    #[error("Unrecognized format")]
+    UnknownFormat,
+
+    // Raised by the reader while parsing, these don't allocate
+    /// The data ends before a value that's read
+    #[error("Read Error: Not enough data to read {wanted} bytes at offset {offset}")]
+    UnexpectedEof { offset: usize, wanted: usize },
+    /// A seek to beyond the end of the data
+    #[error("Read Error: Out of bounds seek at {position}; EOF: {size}")]
+    OutOfBounds { position: usize, size: usize },
+    /// A name that isn't valid UTF-8
+    #[error("Read Error: Invalid UTF-8 in the string at offset {offset}: {source}")]
+    InvalidUtf8 { offset: usize, source: Utf8Error },
+
+    /// The loop of a sample (counting from 0) lies outside its data
+    #[error("Loop of sample {index} is outside the sample")]
+    InvalidSampleLoop { index: usize },
+    /// A pattern (as numbered in the pattern table) that isn't in the song
+    #[error("Pattern {index} doesn't exist")]
+    NoSuchPattern { index: usize },
+    /// A sample (counting from 0) that isn't in the song
+    #[error("Sample {index} doesn't exist")]
+    NoSuchSample { index: usize },
+
+    /// A note with a period or effect that doesn't fit in a MOD file
+    #[error("Note on row {row}, channel {channel} of pattern {pattern} can't be stored")]
+    InvalidNote {
+        pattern: usize,
+        row: usize,
+        channel: usize,
+    },
+    /// The song is bigger than the format it's saved in allows, e.g. in samples or channels
+    #[error("{what} is {found}, at most {limit} is supported")]
+    LimitExceeded {
+        what: &'static str,
+        found: usize,
+        limit: usize,
+    },
+
+    /// A ZIP archive that's damaged, or uses features that aren't supported
+    #[error("Invalid archive: {0}")]
+    InvalidArchive(&'static str),
+
+    /// The module loaded, but there's no engine that plays modules of its tracker yet
+    #[error("{0} modules can't be played yet")]
+    UnsupportedTracker(Tracker),
 }
 
 impl From<SongError> for String {
@@ -18,54 +88,136 @@ impl From<SongError> for String {
     }
 }
 
-#[derive(Debug)]
+/// Sample data as stored in the module
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PCMData {
     I8(Vec<i8>),
     I16(Vec<i16>),
 }
 
+/// Everything about a module besides its patterns and sample data
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SongMetadata {
-    pub name: String,
+    pub(crate) name: String,
+
+    pub(crate) pattern_count: u8,
+    pub(crate) channel_count: u8,
+
+    pub(crate) samples: Vec<Sample>,
+    pub(crate) pattern_table: Vec<u8>,
+    // How many entries of the pattern table are played
+    pub(crate) song_length: u8,
+
+    pub(crate) format: String,
+    pub(crate) end_jump: i8,
+
+    // Ticks per row and tempo (BPM) the song starts with, until an effect changes them
+    pub(crate) initial_speed: u8,
+    pub(crate) initial_tempo: u16,
+
+    pub(crate) tracker: Tracker,
+}
 
-    pub pattern_count: u8,
-    pub channel_count: u8,
+impl SongMetadata {
+    pub fn title(&self) -> &str {
+        &self.name
+    }
+
+    /// The patterns in the order they're played: the part of the pattern table that's used
+    pub fn orders(&self) -> &[u8] {
+        &self.pattern_table[..self.song_length as usize]
+    }
+
+    /// The whole pattern table, including the entries after the last order
+    pub fn pattern_table(&self) -> &[u8] {
+        &self.pattern_table
+    }
+
+    /// How many patterns are stored, played or not
+    pub fn pattern_count(&self) -> usize {
+        self.pattern_count as usize
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channel_count as usize
+    }
+
+    /// The headers of every sample
+    pub fn instruments(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// The header of a sample, counting from 0 unlike sample numbers in notes
+    pub fn instrument(&self, index: usize) -> Option<&Sample> {
+        self.samples.get(index)
+    }
+
+    /// The tag that identifies the format, e.g. `M.K.`. Modules with 15 samples don't have
+    /// one, for those it's whatever is stored where the tag would be
+    pub fn format_tag(&self) -> &str {
+        &self.format
+    }
+
+    /// The order playback continues at after the last one. Some trackers store other
+    /// things here, see [`initial_tempo`](SongMetadata::initial_tempo)
+    pub fn restart_position(&self) -> i8 {
+        self.end_jump
+    }
 
-    pub samples: Vec<Sample>,
-    pub pattern_table: Vec<u8>,
+    /// Ticks per row the song starts with, until an effect changes it
+    pub fn initial_speed(&self) -> u8 {
+        self.initial_speed
+    }
 
-    pub format: String,
-    pub end_jump: i8,
+    /// Tempo (BPM) the song starts with, until an effect changes it
+    pub fn initial_tempo(&self) -> u16 {
+        self.initial_tempo
+    }
 
-    pub tracker: Tracker,
+    /// The tracker the module was most likely made with
+    pub fn tracker(&self) -> Tracker {
+        self.tracker
+    }
 }
 
+/// A loaded module, ready to be played by an [`Engine`](crate::Engine).
+///
+/// Cloning a song is cheap on memory: the sample data is shared between the clones, so
+/// several engines can play the same song (e.g. a preview next to normal playback)
+/// without duplicating it.
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Song {
-    pub metadata: SongMetadata,
+    pub(crate) metadata: SongMetadata,
 
-    pub patterns: Vec<Pattern>,
-    pub samples: Vec<PCMData>,
+    pub(crate) patterns: Vec<Pattern>,
+    pub(crate) samples: Vec<Arc<PCMData>>,
 }
 
+/// Header of a sample (instrument): its name, length, loop and default volume
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sample {
     pub name: String,
-    pub length: u16,
+    pub length: u32,
 
     pub finetune: i8,
     pub volume: u8,
 
-    pub repeat_offset: u16,
-    pub repeat_length: u16,
+    pub repeat_offset: u32,
+    pub repeat_length: u32,
 }
 
+/// A single cell of a pattern: one channel on one row
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Note {
     pub sample: u8,
     pub period: u16,
@@ -77,16 +229,482 @@ pub struct Note {
 pub type Line = Vec<Note>;
 pub type Pattern = Vec<Line>;
 
+/// A note and where it is in the song, see [`Song::cells`]
+#[derive(Debug, Clone, Copy)]
+pub struct Cell<'a> {
+    pub pattern: usize,
+    pub row: usize,
+    pub channel: usize,
+    pub note: &'a Note,
+}
+
+// rewritten as a `Display` implementation for more flexibility, such as
+// ability to log the line to other output streams, ie. files, etc.
+/// A single pattern line in tracker notation: note, sample and effect per channel
+pub struct SongLineDisplay<'a> {
+    pub pattern: &'a Pattern,
+    pub sample_metadata: &'a [Sample],
+    pub lineno: usize,
+}
+
+impl Display for SongLineDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let SongLineDisplay {
+            pattern,
+            sample_metadata,
+            lineno,
+        } = self;
+
+        let Some(line) = pattern.get(*lineno) else {
+            return Ok(());
+        };
+
+        write!(f, "{:02}", lineno)?;
+
+        for note in line {
+            let finetune = finetune(note, sample_metadata);
+            match tracker::protracker_period_to_note(note.period, finetune) {
+                Some(pitch) => write!(f, " | {pitch}")?,
+                None => write!(f, " | ---")?,
+            }
+
+            match note.sample {
+                0 => write!(f, " ..")?,
+                sample => write!(f, " {:02}", sample)?,
+            }
+
+            match (note.effect, note.argument) {
+                (0, 0) => write!(f, " ...")?,
+                (effect, argument) => write!(f, " {:X}{:02X}", effect, argument)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A single pattern line as OpenMPT copies it to the clipboard, so it can be pasted into
+/// OpenMPT and the trackers that accept the same format.
+///
+/// Notes are in OpenMPT's octave numbering, which is three above ProTracker's.
+/// The volume column doesn't exist in MOD files and is always left empty.
+#[cfg(feature = "std")]
+pub struct OpenMptLineDisplay<'a> {
+    pub pattern: &'a Pattern,
+    pub sample_metadata: &'a [Sample],
+    pub lineno: usize,
+}
+
+#[cfg(feature = "std")]
+impl OpenMptLineDisplay<'_> {
+    /// First line of a clipboard block, telling OpenMPT the format of the rows after it
+    pub const HEADER: &'static str = "ModPlug Tracker MOD";
+}
+
+#[cfg(feature = "std")]
+impl Display for OpenMptLineDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(line) = self.pattern.get(self.lineno) else {
+            return Ok(());
+        };
+
+        for note in line {
+            let semitone = tracker::protracker_period_to_semitone(
+                note.period,
+                finetune(note, self.sample_metadata),
+            );
+            match semitone {
+                // The lowest ProTracker period is C-2 in OpenMPT
+                Some(semitone) => write!(
+                    f,
+                    "|{}{}",
+                    tracker::NOTE_NAMES[semitone % 12],
+                    semitone / 12 + 2
+                )?,
+                None => write!(f, "|...")?,
+            }
+
+            match note.sample {
+                0 => write!(f, "..")?,
+                sample => write!(f, "{:02}", sample)?,
+            }
+
+            write!(f, "...")?;
+
+            match (note.effect, note.argument) {
+                (0, 0) => write!(f, "...")?,
+                (effect, argument) => write!(f, "{:X}{:02X}", effect, argument)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Finetune of the sample a note sets, 0 if it doesn't set one.
+// Sample numbers are 1-based, 0 means the note doesn't set one
+fn finetune(note: &Note, sample_metadata: &[Sample]) -> i8 {
+    (note.sample as usize)
+        .checked_sub(1)
+        .and_then(|index| sample_metadata.get(index))
+        .map_or(0, |sample| sample.finetune)
+}
+
 impl Song {
+    /// Loads the module at `path`, picking the format by its extension
+    #[cfg(feature = "std")]
     pub fn new(path: &Path) -> Result<Song, SongError> {
         // TODO: Handle multiple formats
         if path.extension() != Some(OsStr::new("mod")) {
-            return Err(SongError::Io("Unrecognized format".into()));
+            return Err(SongError::UnknownFormat);
+        }
+
+        let file = File::open(path)?;
+        Song::from_reader(BufReader::new(file))
+    }
+
+    /// Loads a module that's already in memory, e.g. fetched by a browser
+    pub fn from_bytes(data: Vec<u8>) -> Result<Song, SongError> {
+        mod_loader::parse(data)
+    }
+
+    /// Loads a module from a stream, reading it a part at a time rather than all at once.
+    /// See [`ModReader`] to only load parts of it
+    #[cfg(feature = "std")]
+    pub fn from_reader(reader: impl Read + Seek) -> Result<Song, SongError> {
+        ModReader::new(reader)?.into_song()
+    }
+
+    /// Checks the file at `path` for structural problems, without loading it
+    #[cfg(feature = "std")]
+    pub fn validate(path: &Path) -> Result<Vec<Problem>, SongError> {
+        if path.extension() != Some(OsStr::new("mod")) {
+            return Err(SongError::UnknownFormat);
+        }
+
+        let data = fs::read(path)?;
+        Ok(mod_validator::validate(&data))
+    }
+
+    /// Saves the song to `path` in the format of its extension, MOD or XM
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> Result<(), SongError> {
+        let data = match path.extension().and_then(OsStr::to_str) {
+            Some("mod") => mod_writer::write(self)?,
+            Some("xm") => crate::formats::xm_writer::write(self)?,
+            _ => return Err(SongError::UnknownFormat),
+        };
+
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn metadata(&self) -> &SongMetadata {
+        &self.metadata
+    }
+
+    /// Every stored pattern, numbered as in the pattern table
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    /// A pattern, numbered as in the pattern table
+    pub fn pattern(&self, index: usize) -> Option<&Pattern> {
+        self.patterns.get(index)
+    }
+
+    /// The note on a row of a pattern, to edit it. Sample numbers should stay within the
+    /// song's samples, the engine plays others as no sample at all
+    pub fn note_mut(&mut self, pattern: usize, row: usize, channel: usize) -> Option<&mut Note> {
+        self.patterns
+            .get_mut(pattern)?
+            .get_mut(row)?
+            .get_mut(channel)
+    }
+
+    /// The data of a sample, counting from 0 unlike sample numbers in notes
+    pub fn sample_data(&self, index: usize) -> Option<&PCMData> {
+        self.samples.get(index).map(|data| data.as_ref())
+    }
+
+    /// Every note of every pattern, pattern by pattern and row by row, in the order
+    /// they're stored rather than played
+    pub fn cells(&self) -> impl Iterator<Item = Cell<'_>> {
+        self.patterns
+            .iter()
+            .enumerate()
+            .flat_map(|(pattern, lines)| {
+                lines.iter().enumerate().flat_map(move |(row, line)| {
+                    line.iter().enumerate().map(move |(channel, note)| Cell {
+                        pattern,
+                        row,
+                        channel,
+                        note,
+                    })
+                })
+            })
+    }
+}
+
+// Notes of empty rows, and of rows the builder hasn't been given anything for
+const EMPTY_NOTE: Note = Note {
+    sample: 0,
+    period: 0,
+    effect: 0,
+    argument: 0,
+};
+
+/// Puts a song together in code, e.g. to generate modules, or for tests.
+///
+/// Samples and patterns are numbered in the order they're added, counting from 0. Notes
+/// refer to samples by that number + 1, as 0 means a note doesn't set one.
+///
+/// ```
+/// use rustune::song::{Note, PCMData, SongBuilder};
+///
+/// let note = Note {
+///     sample: 1,
+///     period: 428,
+///     effect: 0,
+///     argument: 0,
+/// };
+///
+/// let song = SongBuilder::new(4)
+///     .title("Square")
+///     .sample("Square", PCMData::I8([64, 64, -64, -64].repeat(8)))
+///     .sample_loop(0, 0, 32)
+///     .empty_pattern()
+///     .note(0, 0, 0, note)
+///     .orders(&[0])
+///     .build()?;
+///
+/// # #[cfg(feature = "std")]
+/// song.save("square.mod".as_ref())?;
+/// # #[cfg(feature = "std")]
+/// # std::fs::remove_file("square.mod").unwrap();
+/// # Ok::<(), rustune::SongError>(())
+/// ```
+#[derive(Debug)]
+pub struct SongBuilder {
+    channel_count: u8,
+    title: String,
+    samples: Vec<Sample>,
+    data: Vec<PCMData>,
+    patterns: Vec<Pattern>,
+    orders: Vec<u8>,
+    restart_position: i8,
+}
+
+impl SongBuilder {
+    /// Starts an empty song with `channel_count` channels
+    pub fn new(channel_count: u8) -> Self {
+        SongBuilder {
+            channel_count,
+            title: String::new(),
+            samples: Vec::new(),
+            data: Vec::new(),
+            patterns: Vec::new(),
+            orders: Vec::new(),
+            restart_position: 0,
         }
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Adds a sample that plays at full volume and doesn't loop.
+    ///
+    /// The data is padded with silence to an even length, as MOD files store samples in words
+    pub fn sample(mut self, name: &str, mut data: PCMData) -> Self {
+        let length = match &mut data {
+            PCMData::I8(data) => {
+                data.resize(data.len().next_multiple_of(2), 0);
+                data.len()
+            }
+            PCMData::I16(data) => {
+                data.resize(data.len().next_multiple_of(2), 0);
+                data.len()
+            }
+        };
+
+        self.samples.push(Sample {
+            name: name.into(),
+            // Checked when building
+            length: length as u32,
+            finetune: 0,
+            volume: 64,
+            repeat_offset: 0,
+            repeat_length: 0,
+        });
+        self.data.push(data);
+        self
+    }
+
+    /// Sets the volume a sample plays at, up to 64
+    ///
+    /// # Panics
+    /// When the sample hasn't been added
+    pub fn sample_volume(mut self, index: usize, volume: u8) -> Self {
+        self.samples[index].volume = volume.min(64);
+        self
+    }
+
+    /// Tunes a sample in eighths of a semitone, from -8 to 7
+    ///
+    /// # Panics
+    /// When the sample hasn't been added
+    pub fn sample_finetune(mut self, index: usize, finetune: i8) -> Self {
+        self.samples[index].finetune = finetune.clamp(-8, 7);
+        self
+    }
+
+    /// Loops `length` bytes of a sample, starting `offset` bytes into it. Both are rounded
+    /// down to whole words, a loop of a single word means no loop in MOD files
+    ///
+    /// # Panics
+    /// When the sample hasn't been added
+    pub fn sample_loop(mut self, index: usize, offset: u32, length: u32) -> Self {
+        let sample = &mut self.samples[index];
+        let length = length & !1;
+
+        (sample.repeat_offset, sample.repeat_length) = match length {
+            0..=2 => (0, 0),
+            _ => (offset & !1, length),
+        };
+        self
+    }
+
+    /// Adds a pattern of 64 empty rows
+    pub fn empty_pattern(mut self) -> Self {
+        let line = alloc::vec![EMPTY_NOTE; self.channel_count as usize];
+        self.patterns.push(alloc::vec![line; 64]);
+        self
+    }
+
+    /// Puts a note on a row of a pattern
+    ///
+    /// # Panics
+    /// When the pattern hasn't been added, or the row or channel is outside it
+    pub fn note(mut self, pattern: usize, row: usize, channel: usize, note: Note) -> Self {
+        self.patterns[pattern][row][channel] = note;
+        self
+    }
 
-        let data = fs::read(path).map_err(|e| SongError::Io(format!("Failed to read: {e}")))?;
-        let result = mod_loader::parse(data);
+    /// The patterns to play, in order. The first pattern plays once when there are none
+    pub fn orders(mut self, orders: &[u8]) -> Self {
+        self.orders = orders.to_vec();
+        self
+    }
 
-        result
+    /// The order playback continues at after the last one
+    pub fn restart_position(mut self, order: i8) -> Self {
+        self.restart_position = order;
+        self
     }
+
+    /// Checks that everything fits together and builds the song
+    ///
+    /// # Errors
+    /// When an order refers to a pattern or a note to a sample that doesn't exist, the loop
+    /// of a sample lies outside it, or there's more data than MOD files can hold
+    pub fn build(self) -> Result<Song, SongError> {
+        let orders = match self.orders.is_empty() {
+            true => alloc::vec![0],
+            false => self.orders,
+        };
+
+        if orders.len() > 128 {
+            return Err(SongError::LimitExceeded {
+                what: "Order count",
+                found: orders.len(),
+                limit: 128,
+            });
+        }
+        if let Some(&index) = orders.iter().find(|&&p| p as usize >= self.patterns.len()) {
+            return Err(SongError::NoSuchPattern {
+                index: index as usize,
+            });
+        }
+
+        for (index, (sample, data)) in self.samples.iter().zip(&self.data).enumerate() {
+            let length = match data {
+                PCMData::I8(data) => data.len(),
+                PCMData::I16(data) => data.len(),
+            };
+            if length > MAX_SAMPLE_LENGTH {
+                return Err(SongError::LimitExceeded {
+                    what: "Sample length",
+                    found: length,
+                    limit: MAX_SAMPLE_LENGTH,
+                });
+            }
+
+            let loop_end = sample.repeat_offset as usize + sample.repeat_length as usize;
+            if loop_end > length {
+                return Err(SongError::InvalidSampleLoop { index });
+            }
+        }
+
+        for (index, pattern) in self.patterns.iter().enumerate() {
+            for (row, line) in pattern.iter().enumerate() {
+                for (channel, note) in line.iter().enumerate() {
+                    if note.sample as usize > self.samples.len() {
+                        return Err(SongError::NoSuchSample {
+                            index: note.sample as usize - 1,
+                        });
+                    }
+                    if note.period > 0x0FFF || note.effect > 0x0F {
+                        return Err(SongError::InvalidNote {
+                            pattern: index,
+                            row,
+                            channel,
+                        });
+                    }
+                }
+            }
+        }
+
+        // The rest of the table is left empty, like trackers do
+        let mut pattern_table = orders.clone();
+        pattern_table.resize(128, 0);
+
+        // Identified the same way as when the song is saved and loaded again
+        let stored_patterns = orders.iter().max().map_or(0, |&max| max as usize + 1);
+        let format = mod_writer::format_tag(self.channel_count, stored_patterns);
+        let (_, tracker) = mod_loader::identify_format_and_channels(
+            &format,
+            0,
+            &self.samples,
+            stored_patterns as u8,
+        );
+
+        let metadata = SongMetadata {
+            name: self.title,
+            pattern_count: self.patterns.len() as u8,
+            channel_count: self.channel_count,
+            samples: self.samples,
+            pattern_table,
+            song_length: orders.len() as u8,
+            format,
+            end_jump: self.restart_position,
+            initial_speed: tracker::PROTRACKER_DEFAULT_SPEED,
+            initial_tempo: tracker::PROTRACKER_DEFAULT_TEMPO,
+            tracker,
+        };
+
+        Ok(Song {
+            metadata,
+            patterns: self.patterns,
+            samples: self.data.into_iter().map(Arc::new).collect(),
+        })
+    }
+}
+
+/// A song with a square wave as its first sample, for tests to add patterns and notes to.
+/// The wave is long enough to be heard for a while, as sample loops aren't played yet
+#[cfg(test)]
+pub(crate) fn square_song(channel_count: u8) -> SongBuilder {
+    SongBuilder::new(channel_count).sample("Square", PCMData::I8([64, 64, -64, -64].repeat(4096)))
 }
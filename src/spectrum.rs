@@ -0,0 +1,131 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rustune::engine::ScopeBuffer;
+
+/// Frames of the master mix every analysis looks at, a power of two for the FFT
+pub const SPECTRUM_SIZE: usize = 2048;
+
+// Lowest frequency shown, and the highest unless the sample rate doesn't go as high
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+// Levels from this far below full scale up to full scale fill the bars
+const RANGE_DB: f32 = 60.0;
+// How much of their height bars lose every time they're drawn, so peaks linger a moment
+const FALL: f32 = 0.04;
+
+/// Levels of the master mix in bands of frequencies, spaced evenly in pitch like the bars
+/// of a graphic equalizer
+pub struct Spectrum {
+    mix: Arc<ScopeBuffer>,
+    sample_rate: u32,
+    // Height of every bar from 0 to 1, as last drawn
+    levels: Vec<f32>,
+}
+
+impl Spectrum {
+    /// Analyzes the mono mix the player records into `mix`, played at `sample_rate`
+    pub fn new(mix: Arc<ScopeBuffer>, sample_rate: u32, bands: usize) -> Self {
+        Spectrum {
+            mix,
+            sample_rate,
+            levels: vec![0.0; bands],
+        }
+    }
+
+    /// Analyzes the latest frames of the mix, returns the height of every band from 0 to 1
+    pub fn update(&mut self) -> &[f32] {
+        let snapshot = self.mix.snapshot();
+        let Some(samples) = snapshot
+            .first()
+            .filter(|samples| samples.len() == SPECTRUM_SIZE)
+        else {
+            return &self.levels;
+        };
+
+        let magnitudes = magnitudes(samples);
+        let bin_width = self.sample_rate as f32 / SPECTRUM_SIZE as f32;
+        let highest = MAX_FREQUENCY.min(self.sample_rate as f32 / 2.0);
+        let bands = self.levels.len();
+
+        for (band, level) in self.levels.iter_mut().enumerate() {
+            let edge = |band: usize| {
+                MIN_FREQUENCY * (highest / MIN_FREQUENCY).powf(band as f32 / bands as f32)
+            };
+            let first = (edge(band) / bin_width) as usize;
+            // Low bands are narrower than a bin, they get at least the one they start in
+            let last = ((edge(band + 1) / bin_width) as usize).max(first + 1);
+
+            let magnitude = magnitudes[first.min(magnitudes.len() - 1)..last.min(magnitudes.len())]
+                .iter()
+                .fold(0.0f32, |loudest, &magnitude| loudest.max(magnitude));
+            let measured =
+                ((20.0 * magnitude.max(1e-9).log10() + RANGE_DB) / RANGE_DB).clamp(0.0, 1.0);
+
+            *level = measured.max(*level - FALL);
+        }
+
+        &self.levels
+    }
+}
+
+// Amplitude of every frequency up to half the sample rate, 1.0 for a full scale sine
+fn magnitudes(samples: &[f32]) -> Vec<f32> {
+    let size = samples.len();
+
+    // A Hann window, so frequencies between bins don't smear over the whole spectrum
+    let mut real: Vec<f32> = (samples.iter().enumerate())
+        .map(|(i, sample)| sample * (0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos()))
+        .collect();
+    let mut imaginary = vec![0.0; size];
+    fft(&mut real, &mut imaginary);
+
+    // A sine comes out at a quarter of the size: half is in the negative frequencies, and
+    // the window halves it again
+    let scale = 4.0 / size as f32;
+    (real.iter().zip(&imaginary))
+        .take(size / 2)
+        .map(|(re, im)| (re * re + im * im).sqrt() * scale)
+        .collect()
+}
+
+// In place radix-2 FFT, the length must be a power of two
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let size = real.len();
+    debug_assert!(size.is_power_of_two());
+
+    // Reorder by bit reversed index, so the butterflies can work on neighbours
+    let mut j = 0;
+    for i in 1..size {
+        let mut bit = size >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= size {
+        let angle = -2.0 * PI / length as f32;
+        for start in (0..size).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + length / 2);
+
+                let re = real[b] * cos - imaginary[b] * sin;
+                let im = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - re;
+                imaginary[b] = imaginary[a] - im;
+                real[a] += re;
+                imaginary[a] += im;
+            }
+        }
+        length <<= 1;
+    }
+}
@@ -0,0 +1,291 @@
+//! Reading binary module files from a stream, with the methods of a `ByteReader`
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::bytereader::{ByteReader, Codepage, Encoding};
+use crate::song::SongError;
+
+// Read from the stream at once, unless a read asks for more
+const BUFFER_SIZE: usize = 8192;
+
+/// Reads values one after another from any [`Read`] + [`Seek`] stream, like [`ByteReader`]
+/// does from a slice. Reads go through a buffer, so reading small values doesn't mean a call
+/// to the stream every time.
+///
+/// Positions count from the start of the stream, its size is taken when the reader is
+/// created.
+///
+/// ```
+/// # use std::io::Cursor;
+/// # use rustune::bytereader::Encoding;
+/// # use rustune::streamreader::StreamReader;
+/// let mut reader = StreamReader::new(Cursor::new(b"M.K.\x01\x02"), Encoding::BigEndian)?;
+///
+/// assert_eq!(reader.read_str(4)?, "M.K.");
+/// assert_eq!(reader.read_u16()?, 0x0102);
+/// # Ok::<(), rustune::SongError>(())
+/// ```
+#[derive(Debug)]
+pub struct StreamReader<R> {
+    inner: R,
+    encoding: Encoding,
+    size: usize,
+
+    buffer: Vec<u8>,
+    // Position in the stream of the first byte in `buffer`, and of the next byte to read
+    buffer_start: usize,
+    position: usize,
+}
+
+impl<R: Read + Seek> StreamReader<R> {
+    /// Reads from the start of `inner`, with the given byte order
+    ///
+    /// # Errors
+    /// When the stream can't seek to find its size
+    pub fn new(mut inner: R, encoding: Encoding) -> Result<Self, SongError> {
+        let size = inner.seek(SeekFrom::End(0))? as usize;
+        inner.rewind()?;
+
+        Ok(StreamReader {
+            inner,
+            encoding,
+            size,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            buffer_start: 0,
+            position: 0,
+        })
+    }
+
+    /// Returns the byte order the reader was instantiated with
+    pub fn encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    /// Returns the current position the reader is at
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Size of the stream when the reader was created
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the amount of bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.size - self.position
+    }
+
+    /// Gives back the stream, its position is wherever the buffer was last filled from
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Seeks to a specific position in the stream, see [`ByteReader::seek`]
+    ///
+    /// # Errors
+    /// When the `position` is out of bounds
+    pub fn seek(&mut self, position: usize) -> Result<usize, SongError> {
+        if position > self.size {
+            return Err(SongError::OutOfBounds {
+                position,
+                size: self.size,
+            });
+        }
+
+        // Only moves in the stream once a read needs data outside the buffer
+        Ok(core::mem::replace(&mut self.position, position))
+    }
+
+    /// Moves the position forwards, or backwards for negative offsets, see
+    /// [`ByteReader::seek_relative`]
+    ///
+    /// # Errors
+    /// When the new position is before the start or past the end of the stream
+    pub fn seek_relative(&mut self, offset: i64) -> Result<usize, SongError> {
+        let position = (self.position as i64).saturating_add(offset);
+        let position = usize::try_from(position).map_err(|_| SongError::OutOfBounds {
+            position: 0,
+            size: self.size,
+        })?;
+
+        self.seek(position)
+    }
+
+    /// Returns the next bytes of the stream without moving past them
+    ///
+    /// # Errors
+    /// When there are less than `count` bytes left, or the stream fails
+    pub fn peek_bytes(&mut self, count: usize) -> Result<&[u8], SongError> {
+        self.fill(count)?;
+
+        let start = self.position - self.buffer_start;
+        Ok(&self.buffer[start..start + count])
+    }
+
+    /// Reads a chunk of bytes from the stream, see [`ByteReader::read_bytes`]
+    ///
+    /// # Errors
+    /// When there are less than `count` bytes left, or the stream fails
+    pub fn read_bytes(&mut self, count: usize) -> Result<&[u8], SongError> {
+        self.fill(count)?;
+
+        let start = self.position - self.buffer_start;
+        self.position += count;
+        Ok(&self.buffer[start..start + count])
+    }
+
+    /// Reads a string with the given length, trimming any null characters
+    ///
+    /// # Errors
+    /// When the reader reads out of bounds, or the string isn't valid UTF-8
+    pub fn read_str(&mut self, length: usize) -> Result<String, SongError> {
+        self.read_with(length, |reader| reader.read_str(length))
+    }
+
+    /// Reads a string like [`ByteReader::read_str_lossy`], decoding text that isn't UTF-8
+    /// from `codepage`
+    ///
+    /// # Errors
+    /// When the reader reads out of bounds
+    pub fn read_str_lossy(
+        &mut self,
+        length: usize,
+        codepage: Codepage,
+    ) -> Result<String, SongError> {
+        self.read_with(length, |reader| reader.read_str_lossy(length, codepage))
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SongError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8, SongError> {
+        Ok(self.read_bytes(1)?[0] as i8)
+    }
+
+    /// Read a unsigned 16-bit integer, accounting for the byteorder automatically
+    pub fn read_u16(&mut self) -> Result<u16, SongError> {
+        self.read_with(2, |reader| reader.read_u16())
+    }
+
+    /// Read a signed 16-bit integer, accounting for the byteorder automatically
+    pub fn read_i16(&mut self) -> Result<i16, SongError> {
+        self.read_with(2, |reader| reader.read_i16())
+    }
+
+    /// Read a unsigned 24-bit integer, accounting for the byteorder automatically
+    pub fn read_u24(&mut self) -> Result<u32, SongError> {
+        self.read_with(3, |reader| reader.read_u24())
+    }
+
+    /// Read a unsigned 32-bit integer, accounting for the byteorder automatically
+    pub fn read_u32(&mut self) -> Result<u32, SongError> {
+        self.read_with(4, |reader| reader.read_u32())
+    }
+
+    /// Read a signed 32-bit integer, accounting for the byteorder automatically
+    pub fn read_i32(&mut self) -> Result<i32, SongError> {
+        self.read_with(4, |reader| reader.read_i32())
+    }
+
+    /// Read a 32-bit IEEE 754 float, accounting for the byteorder automatically
+    pub fn read_f32(&mut self) -> Result<f32, SongError> {
+        self.read_with(4, |reader| reader.read_f32())
+    }
+
+    // Decodes the next `count` bytes with a `ByteReader`, so values are read the same way
+    // from streams and slices. The position only moves when `read` succeeds
+    fn read_with<T>(
+        &mut self,
+        count: usize,
+        read: impl FnOnce(&mut ByteReader) -> Result<T, SongError>,
+    ) -> Result<T, SongError> {
+        let offset = self.position;
+        let encoding = self.encoding;
+        let bytes = self.peek_bytes(count)?;
+
+        let value = read(&mut ByteReader::new(bytes, encoding)).map_err(|error| match error {
+            SongError::InvalidUtf8 { offset: at, source } => SongError::InvalidUtf8 {
+                offset: offset + at,
+                source,
+            },
+            error => error,
+        })?;
+        self.position += count;
+
+        Ok(value)
+    }
+
+    // Makes sure the buffer holds `count` bytes from the position on
+    fn fill(&mut self, count: usize) -> Result<(), SongError> {
+        if count > self.remaining() {
+            return Err(SongError::UnexpectedEof {
+                offset: self.position,
+                wanted: count,
+            });
+        }
+
+        let buffer_end = self.buffer_start + self.buffer.len();
+        if self.position >= self.buffer_start && self.position + count <= buffer_end {
+            return Ok(());
+        }
+
+        // Refills from the position rather than keeping any of the old buffer, reads mostly
+        // go forwards and are smaller than it
+        let length = count.max(BUFFER_SIZE).min(self.remaining());
+        self.inner.seek(SeekFrom::Start(self.position as u64))?;
+        self.buffer.resize(length, 0);
+        self.buffer_start = self.position;
+
+        let result = self.inner.read_exact(&mut self.buffer);
+        if let Err(error) = result {
+            self.buffer.clear();
+            return Err(match error.kind() {
+                // The stream got shorter since the reader was created
+                io::ErrorKind::UnexpectedEof => SongError::UnexpectedEof {
+                    offset: self.position,
+                    wanted: count,
+                },
+                _ => SongError::Io(error),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn reads_like_byte_reader() {
+    use std::io::Cursor;
+
+    // Larger than the buffer, so reads cross from one fill to the next
+    let data: Vec<u8> = (0..BUFFER_SIZE * 3).map(|i| (i * 7) as u8).collect();
+    let mut stream = StreamReader::new(Cursor::new(&data), Encoding::LittleEndian).unwrap();
+    let mut slice = ByteReader::new(&data, Encoding::LittleEndian);
+
+    for _ in 0..BUFFER_SIZE / 4 {
+        assert_eq!(stream.read_u8().unwrap(), slice.read_u8().unwrap());
+        assert_eq!(stream.read_u24().unwrap(), slice.read_u24().unwrap());
+        assert_eq!(stream.read_i32().unwrap(), slice.read_i32().unwrap());
+    }
+    assert_eq!(stream.position(), slice.position());
+
+    stream.seek(10).unwrap();
+    slice.seek(10).unwrap();
+    assert_eq!(
+        stream.read_bytes(BUFFER_SIZE * 2).unwrap(),
+        slice.read_bytes(BUFFER_SIZE * 2).unwrap()
+    );
+
+    stream.seek_relative(-4).unwrap();
+    assert_eq!(
+        stream.peek_bytes(4).unwrap(),
+        &data[BUFFER_SIZE * 2 + 6..][..4]
+    );
+
+    stream.seek(data.len() - 1).unwrap();
+    assert!(stream.read_u16().is_err());
+    assert_eq!(stream.position(), data.len() - 1);
+    assert!(stream.seek(data.len() + 1).is_err());
+}
@@ -1,7 +1,12 @@
-use std::fmt;
+//! Tracker specific constants and notation, such as period tables and note names
 
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
+use core::fmt;
+
+pub mod fingerprint;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code, clippy::enum_variant_names)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Tracker {
     Generic,
     ProTracker,
@@ -13,7 +18,7 @@ pub enum Tracker {
     Oktalyzer,
     UltimateSoundTracker,
 
-    // Used for further heuristics later
+    // Written by all three with the 4CHN tag, resolved from the patterns once they're loaded
     FastOrNoiseTracker,
 }
 
@@ -35,7 +40,103 @@ impl fmt::Display for Tracker {
     }
 }
 
-// Flattened period tables for ProTracker and finetuned ProTracker
+// Speed (ticks per row) and tempo (BPM) every ProTracker song starts with
+pub const PROTRACKER_DEFAULT_SPEED: u8 = 6;
+pub const PROTRACKER_DEFAULT_TEMPO: u16 = 125;
+
+/// The clock periods count in, which sets the pitch every period plays at
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Clock {
+    /// European Amiga, the one most modules were written on
+    Pal,
+    /// American Amiga, which plays every note about a sixth of a semitone higher
+    Ntsc,
+    /// Any other rate in Hz, for formats that define their own
+    Custom(f64),
+}
+
+impl Clock {
+    /// Rate of the clock in Hz
+    pub fn hz(self) -> f64 {
+        match self {
+            Clock::Pal => 7_093_789.2,
+            Clock::Ntsc => 7_159_090.5,
+            Clock::Custom(hz) => hz,
+        }
+    }
+}
+
+/// Rate in Hz a sample plays at with the given Amiga period, 0.0 for period 0 (no note)
+///
+/// ```
+/// use rustune::tracker::{period_to_frequency, Clock};
+///
+/// // C-2, the note samples are usually recorded at
+/// assert_eq!(period_to_frequency(428, Clock::Pal).round(), 8287.0);
+/// ```
+pub fn period_to_frequency(period: u16, clock: Clock) -> f64 {
+    if period == 0 {
+        return 0.0;
+    }
+
+    // The sound chip counts at half the clock rate
+    clock.hz() / (period as f64 * 2.0)
+}
+
+/// Note names within an octave, padded to two characters as trackers show them
+pub const NOTE_NAMES: [&str; 12] = [
+    "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
+];
+
+/// Parses a note name like `C-2`, `A#3` or `Db1` into a note number. The letter can be in
+/// either case and `-` or nothing stands for neither sharp nor flat.
+///
+/// Note numbers count semitones from the lowest note of the extended ProTracker range
+/// (period 3424), the same as [`protracker_period_to_semitone`] and
+/// [`protracker_note_to_period`]. Octaves are ProTracker's, so C-1 (period 856) is 24 and
+/// every octave is 12 higher.
+///
+/// ```
+/// use rustune::tracker::parse_note;
+///
+/// assert_eq!(parse_note("C-1"), Some(24));
+/// assert_eq!(parse_note("a#3"), Some(58));
+/// assert_eq!(parse_note("H-2"), None);
+/// ```
+pub fn parse_note(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+
+    let semitone: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.as_bytes().first()? {
+        b'#' => (1, &rest[1..]),
+        b'b' => (-1, &rest[1..]),
+        b'-' => (0, &rest[1..]),
+        _ => (0, rest),
+    };
+
+    // Signs aren't octaves, and would make `C--1` parse
+    if !octave.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let octave: i32 = octave.parse().ok()?;
+
+    u8::try_from((octave + 1) * 12 + semitone + accidental).ok()
+}
+
+// Periods ProTracker stores notes as, from C-(-1) to B-5. Finetuned periods are worked out
+// from these, see `protracker_note_to_period`
 const PROTRACKER_PERIODS: [u16; 7 * 12] = [
     3424, 3232, 3048, 2880, 2712, 2560, 2416, 2280, 2152, 2032, 1920, 1812, 1712, 1616, 1524, 1440,
     1356, 1280, 1208, 1140, 1076, 1016, 960, 906, 856, 808, 762, 720, 678, 640, 604, 570, 538, 508,
@@ -44,49 +145,289 @@ const PROTRACKER_PERIODS: [u16; 7 * 12] = [
     47, 45, 42, 40, 37, 35, 33, 31, 30, 28,
 ];
 
-const PROTRACKER_TUNED_PERIODS: [u16; 16 * 12] = [
-    1712, 1616, 1524, 1440, 1356, 1280, 1208, 1140, 1076, 1016, 960, 907, 1700, 1604, 1514, 1430,
-    1348, 1274, 1202, 1134, 1070, 1010, 954, 900, 1688, 1592, 1504, 1418, 1340, 1264, 1194, 1126,
-    1064, 1004, 948, 894, 1676, 1582, 1492, 1408, 1330, 1256, 1184, 1118, 1056, 996, 940, 888,
-    1664, 1570, 1482, 1398, 1320, 1246, 1176, 1110, 1048, 990, 934, 882, 1652, 1558, 1472, 1388,
-    1310, 1238, 1168, 1102, 1040, 982, 926, 874, 1640, 1548, 1460, 1378, 1302, 1228, 1160, 1094,
-    1032, 974, 920, 868, 1628, 1536, 1450, 1368, 1292, 1220, 1150, 1086, 1026, 968, 914, 862, 1814,
-    1712, 1616, 1524, 1440, 1356, 1280, 1208, 1140, 1076, 1016, 960, 1800, 1700, 1604, 1514, 1430,
-    1350, 1272, 1202, 1134, 1070, 1010, 954, 1788, 1688, 1592, 1504, 1418, 1340, 1264, 1194, 1126,
-    1064, 1004, 948, 1774, 1676, 1582, 1492, 1408, 1330, 1256, 1184, 1118, 1056, 996, 940, 1762,
-    1664, 1570, 1482, 1398, 1320, 1246, 1176, 1110, 1048, 988, 934, 1750, 1652, 1558, 1472, 1388,
-    1310, 1238, 1168, 1102, 1040, 982, 926, 1736, 1640, 1548, 1460, 1378, 1302, 1228, 1160, 1094,
-    1032, 974, 920, 1724, 1628, 1536, 1450, 1368, 1292, 1220, 1150, 1086, 1026, 968, 914,
-];
+/// The note a period is closest to, as [`protracker_period_to_note`] finds it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pitch {
+    /// Semitone within the octave, 0 is C, see [`NOTE_NAMES`]
+    pub semitone: u8,
+    /// Octave as ProTracker numbers them, C-1 is period 856. Notes below the ProTracker
+    /// range are in octave -1
+    pub octave: i8,
+    /// How far the period is from the exact pitch of the note, in hundredths of a semitone
+    /// (-50 to 50)
+    pub cents: f32,
+}
+
+impl Pitch {
+    /// The note number, as [`parse_note`] gives it
+    pub fn number(&self) -> u8 {
+        ((self.octave + 1) as u8) * 12 + self.semitone
+    }
+}
+
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", NOTE_NAMES[self.semitone as usize], self.octave)
+    }
+}
 
-pub fn protracker_period_to_note(period: u16, finetune: i8) -> Option<String> {
+/// The note ProTracker plays closest to `period`, with how far off it is. `None` for
+/// period 0 (no note) and periods below the extended ProTracker range (above 3424).
+///
+/// The finetune the period was looked up with is undone, so this is the note that was
+/// entered in the tracker rather than the closest one to the pitch that plays.
+///
+/// ```
+/// use rustune::tracker::protracker_period_to_note;
+///
+/// let pitch = protracker_period_to_note(428, 0).unwrap();
+/// assert_eq!(pitch.to_string(), "C-2");
+/// assert_eq!(pitch.cents, 0.0);
+///
+/// // A period slid a little lower plays sharp
+/// assert!(protracker_period_to_note(425, 0).unwrap().cents > 10.0);
+/// ```
+pub fn protracker_period_to_note(period: u16, finetune: i8) -> Option<Pitch> {
     if period == 0 {
         return None;
     }
 
-    // Choose the correct period table based on the finetune value
-    let table: &[u16] = if finetune == 0 {
-        &PROTRACKER_PERIODS
+    // Every finetune step is an eighth of a semitone
+    let semitones =
+        12.0 * log2(PROTRACKER_PERIODS[0] as f64 / period as f64) - finetune as f64 / 8.0;
+    let note = round(semitones);
+    if note < 0.0 {
+        return None;
+    }
+
+    let note = note as u8;
+    Some(Pitch {
+        semitone: note % 12,
+        octave: (note / 12) as i8 - 1,
+        cents: ((semitones - note as f64) * 100.0) as f32,
+    })
+}
+
+// Neither is in core. The logarithm is split into the exponent of the float and the
+// logarithm of the mantissa between 1 and 2, from a series that converges fast there
+fn log2(x: f64) -> f64 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as i64 - 1023;
+    let mantissa = f64::from_bits((bits & ((1 << 52) - 1)) | (1023 << 52));
+
+    // ln(m) = 2 * (z + z^3 / 3 + z^5 / 5 + ...), z is at most 1/3
+    let z = (mantissa - 1.0) / (mantissa + 1.0);
+    let mut term = z;
+    let mut ln = 0.0;
+    for k in 0..20 {
+        ln += term / (2 * k + 1) as f64;
+        term *= z * z;
+    }
+
+    exponent as f64 + 2.0 * ln / core::f64::consts::LN_2
+}
+
+// 2 to the power of `x`, split into a whole power and the rest below 1, from the series of
+// the exponential function
+fn exp2(x: f64) -> f64 {
+    let whole = x as i64 - (x < (x as i64) as f64) as i64;
+    let y = (x - whole as f64) * core::f64::consts::LN_2;
+
+    let mut term = 1.0;
+    let mut exp = 0.0;
+    for k in 1..20 {
+        exp += term;
+        term *= y / k as f64;
+    }
+
+    exp * f64::from_bits(((whole + 1023) as u64) << 52)
+}
+
+fn round(x: f64) -> f64 {
+    let truncated = x as i64 as f64;
+    if (x - truncated).abs() >= 0.5 {
+        truncated + x.signum()
     } else {
-        &PROTRACKER_TUNED_PERIODS
-    };
+        truncated
+    }
+}
+
+/// The period ProTracker plays `note` at with a sample of the given finetune, the inverse of
+/// [`protracker_period_to_semitone`].
+///
+/// `note` counts semitones above the lowest note of the extended range (period 3424), so
+/// C-1 (period 856) is 24 and C-2 is 36, see [`parse_note`]. Notes without finetune have
+/// the periods ProTracker stores in patterns. Every finetune step is an eighth of a
+/// semitone, -8 to -1 go down from the note and 1 to 7 up.
+///
+/// ```
+/// use rustune::tracker::protracker_note_to_period;
+///
+/// assert_eq!(protracker_note_to_period(36, 0), Some(428)); // C-2
+/// assert_eq!(protracker_note_to_period(36, -8), Some(453));
+/// assert_eq!(protracker_note_to_period(84, 0), None);
+/// ```
+pub fn protracker_note_to_period(note: usize, finetune: i8) -> Option<u16> {
+    let &period = PROTRACKER_PERIODS.get(note)?;
+    if finetune == 0 {
+        return Some(period);
+    }
+
+    let semitones = note as f64 + finetune as f64 / 8.0;
+    Some(round(PROTRACKER_PERIODS[0] as f64 * exp2(-semitones / 12.0)) as u16)
+}
+
+/// Semitones above the lowest note of the extended ProTracker range (period 3424), the note
+/// number of [`protracker_period_to_note`].
+pub fn protracker_period_to_semitone(period: u16, finetune: i8) -> Option<usize> {
+    protracker_period_to_note(period, finetune).map(|pitch| pitch.number() as usize)
+}
+
+/// How XM modules turn notes into pitches, chosen by a flag in their header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrequencyMode {
+    /// Periods like the Amiga's, slides change the pitch less the higher the note
+    Amiga,
+    /// Periods that are linear in pitch, so slides sound the same on every note
+    Linear,
+}
+
+// Pitch of C-4 in XM modules, where an untuned sample plays at its own rate
+const XM_C4_FREQUENCY: f64 = 8363.0;
+#[cfg(feature = "std")]
+const XM_C4: u8 = 48;
+// Amiga period of C-4 in XM modules, four times as fine as ProTracker's
+const XM_C4_AMIGA_PERIOD: f64 = 1712.0;
+
+/// Linear period of an XM note, `note` counts semitones from C-0 and already includes the
+/// relative note of the sample. Each semitone is 64 steps, each step of `finetune` (-128 to
+/// 127, a semitone) half of one.
+///
+/// ```
+/// use rustune::tracker::xm_linear_period;
+///
+/// assert_eq!(xm_linear_period(48, 0), 4608); // C-4
+/// assert_eq!(xm_linear_period(49, 0), 4544);
+/// ```
+pub fn xm_linear_period(note: u8, finetune: i8) -> u16 {
+    (7680 - note as i32 * 64 - finetune as i32 / 2) as u16
+}
+
+/// Playback rate in Hz of a linear period, see [`xm_linear_period`]
+#[cfg(feature = "std")]
+pub fn xm_linear_frequency(period: u16) -> f64 {
+    let c4 = xm_linear_period(XM_C4, 0);
+    XM_C4_FREQUENCY * 2f64.powf((c4 as f64 - period as f64) / 768.0)
+}
+
+/// Amiga period of an XM note, with the same arguments as [`xm_linear_period`]. Not rounded,
+/// so finetunes and slides keep their precision.
+#[cfg(feature = "std")]
+pub fn xm_amiga_period(note: u8, finetune: i8) -> f64 {
+    let semitones = note as f64 - XM_C4 as f64 + finetune as f64 / 128.0;
+    XM_C4_AMIGA_PERIOD / 2f64.powf(semitones / 12.0)
+}
+
+/// Playback rate in Hz of an Amiga period, see [`xm_amiga_period`]
+pub fn xm_amiga_frequency(period: f64) -> f64 {
+    XM_C4_FREQUENCY * XM_C4_AMIGA_PERIOD / period
+}
 
-    let mut closest_period = u16::MAX;
-    let mut closest_index = 0;
+/// Playback rate in Hz of an XM note in either frequency mode
+#[cfg(feature = "std")]
+pub fn xm_frequency(note: u8, finetune: i8, mode: FrequencyMode) -> f64 {
+    match mode {
+        FrequencyMode::Amiga => xm_amiga_frequency(xm_amiga_period(note, finetune)),
+        FrequencyMode::Linear => xm_linear_frequency(xm_linear_period(note, finetune)),
+    }
+}
 
-    // Iterate over the table to find the closest period
-    for (i, &p) in table.iter().enumerate() {
-        if (p as i32 - period as i32).abs() < (closest_period as i32 - period as i32).abs() {
-            closest_period = p;
-            closest_index = i;
+#[test]
+fn note_to_period_round_trip() {
+    for finetune in -8..=7 {
+        for note in 0..PROTRACKER_PERIODS.len() {
+            let period = protracker_note_to_period(note, finetune).unwrap();
+            assert!(period > 0, "Note {note}, finetune {finetune}");
+            assert_eq!(
+                protracker_period_to_semitone(period, finetune),
+                Some(note),
+                "Note {note}, finetune {finetune}"
+            );
         }
     }
 
-    let note_names = [
-        "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
-    ];
-    let note_index = closest_index % 12;
-    let octave = closest_index / 12 + 2;
+    assert_eq!(protracker_note_to_period(24, 0), Some(856));
+    assert_eq!(protracker_note_to_period(12, 7), Some(1628));
+    assert_eq!(protracker_note_to_period(36, -1), Some(431));
+    // The same as in the finetune tables of ProTracker
+    assert_eq!(protracker_note_to_period(24, 1), Some(850));
+    assert_eq!(protracker_note_to_period(24, -8), Some(907));
+    assert_eq!(protracker_note_to_period(84, 3), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn xm_frequency_modes() {
+    for mode in [FrequencyMode::Amiga, FrequencyMode::Linear] {
+        assert!((xm_frequency(48, 0, mode) - 8363.0).abs() < 1e-6);
+        // An octave up doubles the rate, a finetune of 127 is almost a semitone
+        assert!((xm_frequency(60, 0, mode) - 16726.0).abs() < 1e-6);
+        let semitone = xm_frequency(49, 0, mode);
+        assert!(xm_frequency(48, 127, mode) < semitone);
+        assert!(xm_frequency(48, 127, mode) > xm_frequency(48, 0, mode));
+    }
+
+    assert_eq!(xm_amiga_period(48, 0), 1712.0);
+    assert_eq!(xm_linear_period(0, -128), 7744);
+}
 
-    Some(format!("{}{}", note_names[note_index], octave))
+#[test]
+fn parse_note_names() {
+    assert_eq!(parse_note("C-0"), Some(12));
+    assert_eq!(parse_note("C0"), Some(12));
+    assert_eq!(parse_note("Cb1"), Some(23));
+    assert_eq!(parse_note("B#1"), Some(36));
+    assert_eq!(parse_note("c#2"), Some(37));
+    assert_eq!(parse_note("D#20"), Some(255));
+
+    for name in ["", "C", "C-", "C#", "X-1", "C--1", "C-+1", "C-1 ", "E-20"] {
+        assert_eq!(parse_note(name), None, "{name:?}");
+    }
+
+    let period = protracker_note_to_period(parse_note("C-2").unwrap() as usize, 0);
+    assert_eq!(period, Some(428));
+}
+
+#[test]
+fn period_to_pitch() {
+    use alloc::string::ToString;
+
+    let pitch = protracker_period_to_note(856, 0).unwrap();
+    assert_eq!((pitch.semitone, pitch.octave, pitch.number()), (0, 1, 24));
+    assert!(pitch.cents.abs() < 0.01);
+
+    // Finetune -8 is half a semitone flat, so its C-1 is still a C-1
+    let pitch = protracker_period_to_note(907, -8).unwrap();
+    assert_eq!(pitch.to_string(), "C-1");
+    assert!(pitch.cents.abs() < 1.0);
+
+    assert_eq!(
+        protracker_period_to_note(3424, 0).unwrap().to_string(),
+        "C--1"
+    );
+    assert!(protracker_period_to_note(4000, 0).is_none());
+    assert!(protracker_period_to_note(0, 0).is_none());
+    assert!(protracker_period_to_note(1, 0).is_some());
+
+    let logarithms = [
+        (0.5, -1.0),
+        (1.0, 0.0),
+        (1.5, 0.584962500721156),
+        (1e9, 29.897352853986263),
+    ];
+    for (x, expected) in logarithms {
+        assert!((log2(x) - expected).abs() < 1e-12, "log2({x})");
+    }
 }
@@ -0,0 +1,313 @@
+//! Working out which tracker, and which version of it, saved a module. The format tag only
+//! goes so far: ProTracker, NoiseTracker and FastTracker all write `M.K.`, so the rest of
+//! the file is searched for things only some of them do.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::Tracker;
+use crate::formats::mod_loader;
+use crate::song::{Pattern, Sample, Song};
+
+// ProTracker always writes this where NoiseTracker keeps the restart position
+const PROTRACKER_RESTART: u8 = 0x7F;
+// Patterns ProTracker has room for before 2.3, which tags modules with more as M!K!
+const PROTRACKER_PATTERNS: usize = 64;
+
+/// Something in a module that points to the tracker that saved it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Evidence {
+    /// The restart byte is 0x7F, which ProTracker writes whatever the song is
+    ProTrackerRestart,
+    /// The restart byte is an order of the song, as NoiseTracker and FastTracker save it
+    RestartPosition(u8),
+    /// Samples have a finetune, NoiseTracker doesn't have one
+    Finetune,
+    /// Fxx from 20 sets the tempo, which NoiseTracker can't
+    Tempo,
+    /// Effects ProTracker added to NoiseTracker's: 5xy, 6xy, 7xy, 9xx and every Exy but E0x
+    ProTrackerEffects,
+    /// 8xx or E8x, panning is only found in PC trackers
+    Panning,
+    /// More patterns than ProTracker had room for before 2.3
+    ManyPatterns(u8),
+    /// The file ends this many bytes before the last sample does
+    Truncated(usize),
+    /// This many bytes follow the last sample
+    TrailingData(usize),
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evidence::ProTrackerRestart => write!(f, "restart byte 0x7F"),
+            Evidence::RestartPosition(order) => write!(f, "restarts at order {order}"),
+            Evidence::Finetune => write!(f, "finetuned samples"),
+            Evidence::Tempo => write!(f, "tempo changes"),
+            Evidence::ProTrackerEffects => write!(f, "ProTracker effects"),
+            Evidence::Panning => write!(f, "panning"),
+            Evidence::ManyPatterns(count) => write!(f, "{count} patterns"),
+            Evidence::Truncated(bytes) => write!(f, "{bytes} bytes missing"),
+            Evidence::TrailingData(bytes) => write!(f, "{bytes} bytes after the samples"),
+        }
+    }
+}
+
+/// How a tracker plays songs, where trackers differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// Fxx from 20 sets the tempo, rather than the speed
+    pub tempo: bool,
+    /// Samples play tuned by their finetune
+    pub finetune: bool,
+    /// 8xx and E8x pan the channel
+    pub panning: bool,
+    /// The song continues at the restart position after its last order, rather than at the
+    /// first. Ultimate SoundTracker keeps its tempo there instead
+    pub restart: bool,
+}
+
+impl Quirks {
+    /// The quirks of songs made with `tracker`, trackers not listed play like ProTracker
+    pub const fn of(tracker: Tracker) -> Quirks {
+        match tracker {
+            Tracker::NoiseTracker => Quirks {
+                tempo: false,
+                finetune: false,
+                panning: false,
+                restart: true,
+            },
+            Tracker::UltimateSoundTracker => Quirks {
+                tempo: false,
+                finetune: false,
+                panning: false,
+                restart: false,
+            },
+            Tracker::FastTracker => Quirks {
+                tempo: true,
+                finetune: true,
+                panning: true,
+                restart: true,
+            },
+            _ => Quirks {
+                tempo: true,
+                finetune: true,
+                panning: false,
+                restart: false,
+            },
+        }
+    }
+}
+
+/// The tracker, and as far as the module tells, the version that saved it, see
+/// [`fingerprint`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fingerprint {
+    pub tracker: Tracker,
+    /// A version or range of versions, e.g. `2.3 or later`, when the module narrows it down
+    pub version: Option<&'static str>,
+    /// Everything found that points to a tracker, in the order it was looked for
+    pub evidence: Vec<Evidence>,
+}
+
+impl Fingerprint {
+    /// How the tracker plays the song
+    pub fn quirks(&self) -> Quirks {
+        Quirks::of(self.tracker)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "{} {version}", self.tracker),
+            None => write!(f, "{}", self.tracker),
+        }
+    }
+}
+
+/// Works out the tracker and version that saved `song`, from its format tag, the bytes
+/// trackers fill in differently and the effects it uses. `file_size` is the size of the
+/// file it was loaded from, if it's known: files that are cut short or have data after the
+/// samples are reported too.
+///
+/// ```
+/// use rustune::song::{PCMData, SongBuilder};
+/// use rustune::tracker::fingerprint::{fingerprint, Evidence};
+/// use rustune::tracker::Tracker;
+///
+/// let song = SongBuilder::new(4)
+///     .sample("Square", PCMData::I8([64, 64, -64, -64].repeat(8)))
+///     .empty_pattern()
+///     .restart_position(0x7F)
+///     .build()?;
+///
+/// let fingerprint = fingerprint(&song, None);
+/// assert_eq!(fingerprint.tracker, Tracker::ProTracker);
+/// assert_eq!(fingerprint.evidence, [Evidence::ProTrackerRestart]);
+/// # Ok::<(), rustune::SongError>(())
+/// ```
+pub fn fingerprint(song: &Song, file_size: Option<usize>) -> Fingerprint {
+    let metadata = song.metadata();
+    let restart = metadata.restart_position() as u8;
+    let tag = metadata.format_tag();
+
+    let mut evidence = Vec::new();
+    // Soundtracker modules, with 15 samples, keep their tempo in the restart byte
+    if mod_loader::detect_sample_count(tag) == 31 {
+        if restart == PROTRACKER_RESTART {
+            evidence.push(Evidence::ProTrackerRestart);
+        } else if restart < metadata.song_length {
+            evidence.push(Evidence::RestartPosition(restart));
+        }
+    }
+    evidence.extend(pattern_evidence(&metadata.samples, &song.patterns));
+    if metadata.pattern_count() > PROTRACKER_PATTERNS {
+        evidence.push(Evidence::ManyPatterns(metadata.pattern_count));
+    }
+
+    if let Some(file_size) = file_size {
+        let expected = module_size(song);
+        if file_size < expected {
+            evidence.push(Evidence::Truncated(expected - file_size));
+        } else if file_size > expected {
+            evidence.push(Evidence::TrailingData(file_size - expected));
+        }
+    }
+
+    let found = |wanted: fn(&Evidence) -> bool| evidence.iter().any(wanted);
+    let protracker = found(|e| {
+        matches!(
+            e,
+            Evidence::Finetune | Evidence::Tempo | Evidence::ProTrackerEffects
+        )
+    });
+
+    let (tracker, version) = match metadata.tracker() {
+        // FastTracker 2 saves songs with 4 channels as M.K. too
+        Tracker::ProTracker if found(|e| *e == Evidence::Panning) => (Tracker::FastTracker, None),
+        // ProTracker took the tag from NoiseTracker, but not the restart position
+        Tracker::ProTracker
+            if tag == "M.K."
+                && !protracker
+                && found(|e| matches!(e, Evidence::RestartPosition(_))) =>
+        {
+            (Tracker::NoiseTracker, None)
+        }
+        Tracker::ProTracker
+            if tag == "M!K!" || found(|e| matches!(e, Evidence::ManyPatterns(_))) =>
+        {
+            (Tracker::ProTracker, Some("2.3 or later"))
+        }
+        // Two digit channel counts are from FastTracker 2, the first only had 6 and 8
+        Tracker::FastTracker if tag.ends_with("CH") => (Tracker::FastTracker, Some("2")),
+        tracker => (tracker, None),
+    };
+
+    Fingerprint {
+        tracker,
+        version,
+        evidence,
+    }
+}
+
+/// What the samples and patterns use that only some trackers have: finetune, tempo,
+/// panning and the effects ProTracker added. Every kind is reported once.
+pub(crate) fn pattern_evidence(samples: &[Sample], patterns: &[Pattern]) -> Vec<Evidence> {
+    let mut evidence = Vec::new();
+    if samples.iter().any(|sample| sample.finetune != 0) {
+        evidence.push(Evidence::Finetune);
+    }
+
+    for note in patterns.iter().flatten().flatten() {
+        let found = match (note.effect, note.argument >> 4) {
+            (0x8, _) | (0xE, 0x8) => Evidence::Panning,
+            // Tone portamento and vibrato with a volume slide, tremolo and sample offset
+            (0x5..=0x7 | 0x9, _) => Evidence::ProTrackerEffects,
+            // E0x (the filter) is the only extended effect NoiseTracker has
+            (0xE, 0x1..) => Evidence::ProTrackerEffects,
+            (0xF, _) if note.argument >= 0x20 => Evidence::Tempo,
+            _ => continue,
+        };
+
+        if !evidence.contains(&found) {
+            evidence.push(found);
+        }
+    }
+
+    evidence
+}
+
+// Size of the file the song was saved in: the header, the patterns and the samples
+fn module_size(song: &Song) -> usize {
+    let metadata = song.metadata();
+
+    // Modules with 15 samples have neither the last 16 sample headers nor the format tag
+    let header_size = match mod_loader::detect_sample_count(metadata.format_tag()) {
+        31 => mod_loader::HEADER_SIZE,
+        _ => mod_loader::HEADER_SIZE - 16 * 30 - 4,
+    };
+
+    let samples_size: usize = metadata.samples.iter().map(|s| s.length as usize).sum();
+    header_size
+        + metadata.pattern_count() * mod_loader::pattern_size(metadata.channel_count)
+        + samples_size
+}
+
+#[test]
+fn fingerprint_trackers() {
+    use crate::formats::mod_writer;
+    use crate::song::{square_song, Note};
+
+    let song = |tag: &[u8; 4], restart: u8, effect: u8, argument: u8| {
+        let note = Note {
+            sample: 1,
+            period: 428,
+            effect,
+            argument,
+        };
+        let song = square_song(4)
+            .empty_pattern()
+            .note(0, 0, 0, note)
+            .restart_position(restart as i8)
+            .build()
+            .unwrap();
+
+        let mut data = mod_writer::write(&song).unwrap();
+        data[1080..1084].copy_from_slice(tag);
+        mod_loader::parse(data).unwrap()
+    };
+    let tracker = |song: &Song| {
+        let fingerprint = fingerprint(song, None);
+        (fingerprint.tracker, fingerprint.version)
+    };
+
+    let protracker = song(b"M.K.", 0x7F, 0xA, 0x01);
+    assert_eq!(tracker(&protracker), (Tracker::ProTracker, None));
+    assert_eq!(
+        fingerprint(&protracker, Some(module_size(&protracker) + 3)).evidence,
+        [Evidence::ProTrackerRestart, Evidence::TrailingData(3)]
+    );
+
+    let noisetracker = song(b"M.K.", 0, 0xA, 0x01);
+    assert_eq!(tracker(&noisetracker), (Tracker::NoiseTracker, None));
+    assert!(!fingerprint(&noisetracker, None).quirks().finetune);
+    // NoiseTracker can't play tone portamento with a volume slide
+    assert_eq!(
+        tracker(&song(b"M.K.", 0, 0x5, 0x01)),
+        (Tracker::ProTracker, None)
+    );
+
+    assert_eq!(
+        tracker(&song(b"M!K!", 0x7F, 0, 0)),
+        (Tracker::ProTracker, Some("2.3 or later"))
+    );
+    assert_eq!(
+        tracker(&song(b"M.K.", 0, 0x8, 0x80)),
+        (Tracker::FastTracker, None)
+    );
+    assert!(Quirks::of(Tracker::FastTracker).panning);
+}
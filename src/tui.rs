@@ -0,0 +1,653 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::canvas::{self, Canvas, Points};
+use ratatui::widgets::{Bar, BarChart, BarGroup, Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::editor::{Action, Editor};
+use crate::info::{self, Progress};
+use crate::player::{Handle, Status};
+use crate::spectrum::Spectrum;
+use crate::PlaybackEvent;
+use rustune::engine::{ChannelSnapshot, LoopRegion, ScopeBuffer};
+use rustune::song::{Song, SongLineDisplay};
+use rustune::tracker;
+
+// How long to wait for key presses before checking the playback events again
+const INPUT_POLL: Duration = Duration::from_millis(20);
+
+/// Amount of frames shown by the oscilloscope of each channel
+pub const SCOPE_LENGTH: usize = 1024;
+
+// Height of the oscilloscope row, including borders
+const SCOPE_HEIGHT: u16 = 8;
+// Height of the spectrum analyzer, including borders
+const SPECTRUM_HEIGHT: u16 = 10;
+
+// Rows the piano roll shows, the newest on the right
+const ROLL_LENGTH: usize = 64;
+// Every sample is drawn in its own color in the piano roll, cycling through these
+const SAMPLE_COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::LightBlue,
+    Color::LightRed,
+];
+
+// How much +/- change the volume, and the loudest it goes
+const VOLUME_STEP: f32 = 0.1;
+const MAX_VOLUME: f32 = 2.0;
+
+/// Everything the pattern view needs from a song, since the song itself is owned by the engine
+pub struct PatternView {
+    title: String,
+    description: String,
+    pattern_table: Vec<u8>,
+    song_length: usize,
+    channels: usize,
+    // Lowest and highest note in the patterns, in semitones, the range of the piano roll
+    pitches: (f32, f32),
+    // In seconds, known once the engine has measured the song
+    duration: f64,
+
+    // Every line of every pattern, already in tracker notation
+    patterns: Vec<Vec<String>>,
+
+    // The playlist entry and its song as loaded, for the pattern editor
+    index: usize,
+    path: PathBuf,
+    song: Song,
+}
+
+impl PatternView {
+    pub fn new(song: &Song, path: &Path, index: usize, total: usize) -> Self {
+        let metadata = song.metadata();
+
+        let patterns = song
+            .patterns()
+            .iter()
+            .map(|pattern| {
+                (0..pattern.len())
+                    .map(|lineno| {
+                        SongLineDisplay {
+                            pattern,
+                            sample_metadata: metadata.instruments(),
+                            lineno,
+                        }
+                        .to_string()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let notes = (song.patterns().iter().flatten().flatten())
+            .filter_map(|note| tracker::protracker_period_to_semitone(note.period, 0));
+        let (lowest, highest) = notes.fold((usize::MAX, 0), |(lowest, highest), note| {
+            (lowest.min(note), highest.max(note))
+        });
+        // A semitone of room on either side, so slides and vibrato stay in view
+        let pitches = if lowest <= highest {
+            (lowest as f32 - 1.0, highest as f32 + 1.0)
+        } else {
+            (0.0, 84.0)
+        };
+
+        PatternView {
+            title: format!("[{}/{}] {}", index + 1, total, info::title(song, path)),
+            description: format!(
+                "{} ({}), {} channels, {} patterns",
+                metadata.tracker(),
+                metadata.format_tag(),
+                metadata.channels(),
+                metadata.pattern_count()
+            ),
+            pattern_table: metadata.pattern_table().to_vec(),
+            song_length: metadata.orders().len(),
+            channels: metadata.channels(),
+            pitches,
+            duration: 0.0,
+            patterns,
+            index,
+            path: path.to_path_buf(),
+            song: song.clone(),
+        }
+    }
+
+    pub fn with_duration(self, duration: f64) -> Self {
+        PatternView { duration, ..self }
+    }
+}
+
+/// What the terminal interface has changed about a song, mirroring what has been sent to
+/// the audio thread. Kept with the song, so it survives the song being reloaded
+pub struct TrackState {
+    muted: Vec<bool>,
+    loop_region: Option<LoopRegion>,
+    // Start of a loop region whose end hasn't been set yet
+    loop_start: Option<(usize, usize)>,
+}
+
+impl TrackState {
+    /// `muted` and `loop_region` are what the engine starts out with
+    pub fn new(muted: Vec<bool>, loop_region: Option<LoopRegion>) -> Self {
+        TrackState {
+            muted,
+            loop_region,
+            loop_start: None,
+        }
+    }
+
+    pub fn muted(&self) -> &[bool] {
+        &self.muted
+    }
+
+    pub fn loop_region(&self) -> Option<LoopRegion> {
+        self.loop_region
+    }
+
+    // Sets the start of a loop region, then its end, then clears it again. Returns the region
+    // to play once it changed
+    fn next_loop_point(&mut self, position: (usize, usize)) -> Option<Option<LoopRegion>> {
+        if self.loop_region.take().is_some() {
+            return Some(None);
+        }
+
+        let Some(start) = self.loop_start.take() else {
+            self.loop_start = Some(position);
+            return None;
+        };
+
+        // The points may be set in either order
+        self.loop_region = Some(LoopRegion {
+            start: start.min(position),
+            end: start.max(position),
+        });
+        Some(self.loop_region)
+    }
+}
+
+/// Full screen terminal interface showing the playing pattern.
+///
+/// The terminal is switched to the alternate screen in raw mode while this is
+/// alive, and restored when it's dropped.
+pub struct Tui {
+    terminal: DefaultTerminal,
+
+    // Whether playback is paused comes from the player, since others may pause it as well
+    status: Arc<Mutex<Status>>,
+    // Mirrors what has been sent to the audio thread, kept between songs
+    volume: f32,
+    // Shown between the oscilloscopes and the pattern, for every song
+    spectrum: Option<Spectrum>,
+    // Whether the piano roll is shown instead of the pattern
+    piano_roll: bool,
+    // Replaces the playing pattern while it's open, kept when the song is reloaded
+    editor: Option<Editor>,
+}
+
+impl Tui {
+    pub fn new(
+        status: Arc<Mutex<Status>>,
+        spectrum: Option<Spectrum>,
+        piano_roll: bool,
+    ) -> io::Result<Self> {
+        Ok(Tui {
+            terminal: ratatui::try_init()?,
+            status,
+            volume: 1.0,
+            spectrum,
+            piano_roll,
+            editor: None,
+        })
+    }
+
+    /// Draws `view` until playback of the song ends or the user quits.
+    ///
+    /// When `scope` is given, an oscilloscope of every channel is shown above the pattern, and
+    /// the spectrum analyzer below that if the interface has one.
+    /// Key presses control the `player`, or end the song early.
+    pub fn run(
+        &mut self,
+        view: &PatternView,
+        scope: Option<&ScopeBuffer>,
+        state: &mut TrackState,
+        player: &Handle,
+        events: &Receiver<PlaybackEvent>,
+    ) -> io::Result<PlaybackEvent> {
+        let mut position = (0, 0);
+        let mut elapsed = None;
+        // What the channels played on the latest rows, for the piano roll
+        let mut roll = VecDeque::with_capacity(ROLL_LENGTH);
+        // Another song is playing than the one that's edited
+        if self
+            .editor
+            .as_ref()
+            .is_some_and(|editor| editor.index() != view.index)
+        {
+            self.editor = None;
+        }
+
+        loop {
+            let waveforms = scope.map(ScopeBuffer::snapshot);
+            let levels = self
+                .spectrum
+                .as_mut()
+                .map(|spectrum| spectrum.update().to_vec());
+            let status = match &self.editor {
+                Some(editor) => editor.status(),
+                None => self.status(state),
+            };
+            self.terminal.draw(|frame| {
+                let progress = Progress {
+                    elapsed,
+                    duration: view.duration,
+                    order: position.0,
+                    song_length: view.song_length,
+                    row: position.1,
+                };
+                let body = if let Some(editor) = &self.editor {
+                    Body::Editor(editor)
+                } else if self.piano_roll {
+                    Body::PianoRoll(&roll, &state.muted)
+                } else {
+                    Body::Pattern
+                };
+                draw(
+                    frame,
+                    view,
+                    waveforms.as_deref(),
+                    levels.as_deref(),
+                    body,
+                    &progress,
+                    &status,
+                )
+            })?;
+
+            if event::poll(INPUT_POLL)? {
+                if let Event::Key(key) = event::read()? {
+                    if let Some(event) = self.handle_key(key, view, state, position, player) {
+                        return Ok(event);
+                    }
+                }
+            }
+
+            // Only the latest position matters, skip the rows we missed while drawing. The
+            // piano roll gets all of them
+            loop {
+                match events.try_recv() {
+                    Ok(PlaybackEvent::Position {
+                        order,
+                        row,
+                        elapsed: time,
+                        channels,
+                    }) => {
+                        position = (order, row);
+                        elapsed = time;
+                        if roll.len() == ROLL_LENGTH {
+                            roll.pop_front();
+                        }
+                        roll.push_back(channels);
+                    }
+                    Ok(event) => return Ok(event),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(PlaybackEvent::Quit),
+                }
+            }
+        }
+    }
+}
+
+impl Tui {
+    // Returns the event to end the song with, if the key does that
+    fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        view: &PatternView,
+        state: &mut TrackState,
+        position: (usize, usize),
+        player: &Handle,
+    ) -> Option<PlaybackEvent> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Some(PlaybackEvent::Quit);
+        }
+
+        if let Some(editor) = &mut self.editor {
+            match editor.handle_key(key) {
+                Action::None => {}
+                Action::Close => self.editor = None,
+                // The saved file is played from where it is, like with --watch
+                Action::Save => match editor.save() {
+                    Ok(()) => return Some(PlaybackEvent::Changed(view.index)),
+                    Err(e) => editor.set_message(format!("Not saved: {e}")),
+                },
+            }
+            return None;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(PlaybackEvent::Quit),
+            KeyCode::Char('n') => return Some(PlaybackEvent::Next),
+            KeyCode::Char('p') => return Some(PlaybackEvent::Previous),
+
+            KeyCode::Char(' ') if self.paused() => player.play(),
+            KeyCode::Char(' ') => player.pause(),
+            KeyCode::Char(digit @ '0'..='9') => {
+                // 1 is the first channel, 0 the tenth
+                let channel = (digit as usize - '0' as usize + 9) % 10;
+                let muted = state.muted.get_mut(channel)?;
+                *muted = !*muted;
+                player.mute_channel(channel, *muted);
+            }
+            KeyCode::Char('l') => {
+                if let Some(region) = state.next_loop_point(position) {
+                    player.set_loop_region(region);
+                }
+            }
+            KeyCode::Char('r') => self.piano_roll = !self.piano_roll,
+            // Starts at the playing row
+            KeyCode::Char('e') => {
+                let pattern = view.pattern_table.get(position.0).copied().unwrap_or(0);
+                let song = view.song.clone();
+                let editor =
+                    Editor::new(view.index, &view.path, song, pattern as usize, position.1);
+                self.editor = Some(editor);
+            }
+            KeyCode::Left => player.seek_orders(-1),
+            KeyCode::Right => player.seek_orders(1),
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                self.volume = (self.volume + VOLUME_STEP).min(MAX_VOLUME);
+                player.set_volume(self.volume);
+            }
+            KeyCode::Char('-') => {
+                self.volume = (self.volume - VOLUME_STEP).max(0.0);
+                player.set_volume(self.volume);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn status(&self, state: &TrackState) -> String {
+        let mut status = format!("Volume {:>3.0}%", self.volume * 100.0);
+
+        let muted: Vec<String> = (state.muted.iter().enumerate())
+            .filter(|(_, &muted)| muted)
+            .map(|(channel, _)| (channel + 1).to_string())
+            .collect();
+        if !muted.is_empty() {
+            status += &format!("   Muted {}", muted.join(","));
+        }
+
+        if let Some(LoopRegion { start, end }) = state.loop_region {
+            status += &format!("   Loop {}:{:02}-{}:{:02}", start.0, start.1, end.0, end.1);
+        } else if let Some(start) = state.loop_start {
+            status += &format!("   Loop {}:{:02}-", start.0, start.1);
+        }
+
+        if self.paused() {
+            status += "   Paused";
+        }
+
+        let load = self.status.lock().map_or(0.0, |status| status.load.recent);
+        if load > 0.0 {
+            status += &format!("   CPU {:>3.0}%", load * 100.0);
+        }
+
+        status
+    }
+
+    fn paused(&self) -> bool {
+        self.status.lock().is_ok_and(|status| status.paused)
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+// What fills the screen below the header and the meters
+enum Body<'a> {
+    Pattern,
+    // The rows played last, and which channels are muted
+    PianoRoll(&'a VecDeque<Vec<ChannelSnapshot>>, &'a [bool]),
+    // A pattern of the song as it's edited, instead of the playing one
+    Editor(&'a Editor),
+}
+
+fn draw(
+    frame: &mut Frame,
+    view: &PatternView,
+    waveforms: Option<&[Vec<f32>]>,
+    levels: Option<&[f32]>,
+    shown: Body,
+    progress: &Progress,
+    status: &str,
+) {
+    let (order, row) = (progress.order, progress.row);
+    let scope_height = if waveforms.is_some() { SCOPE_HEIGHT } else { 0 };
+    let spectrum_height = if levels.is_some() { SPECTRUM_HEIGHT } else { 0 };
+    let [header, scopes, spectrum, body] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Length(scope_height),
+        Constraint::Length(spectrum_height),
+        Constraint::Min(0),
+    ])
+    .areas(frame.area());
+
+    if let Some(waveforms) = waveforms {
+        draw_scopes(frame, waveforms, scopes);
+    }
+    if let Some(levels) = levels {
+        draw_spectrum(frame, levels, spectrum);
+    }
+
+    let pattern_index = view.pattern_table.get(order).copied().unwrap_or(0) as usize;
+    let lines = view
+        .patterns
+        .get(pattern_index)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let position = format!("{progress}  pat {pattern_index:02}   {status}");
+
+    frame.render_widget(
+        Paragraph::new(vec![Line::raw(&view.description), Line::raw(position)])
+            .block(Block::bordered().title(view.title.as_str())),
+        header,
+    );
+
+    match shown {
+        Body::Pattern => {}
+        Body::PianoRoll(roll, muted) => return draw_piano_roll(frame, view, roll, muted, body),
+        Body::Editor(editor) => return draw_editor(frame, editor, body),
+    }
+
+    // Keep the current row in the middle of the view
+    let height = body.height.saturating_sub(2) as usize;
+    let first = row.saturating_sub(height / 2);
+
+    let rows: Vec<Line> = lines
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(height)
+        .map(|(lineno, line)| {
+            if lineno == row {
+                Line::styled(line.as_str(), Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                Line::raw(line.as_str())
+            }
+        })
+        .collect();
+
+    frame.render_widget(
+        Paragraph::new(rows).block(Block::bordered().title(format!("Pattern {pattern_index}"))),
+        body,
+    );
+}
+
+// The edited pattern with the cursor row in the middle, like the playing one, and the
+// field under the cursor highlighted
+fn draw_editor(frame: &mut Frame, editor: &Editor, area: Rect) {
+    let (row, _, _) = editor.cursor();
+    let columns = editor.cursor_columns();
+    let height = area.height.saturating_sub(2) as usize;
+    let first = row.saturating_sub(height / 2);
+
+    let rows: Vec<Line> = (editor.lines().into_iter().enumerate())
+        .skip(first)
+        .take(height)
+        .map(|(lineno, line)| {
+            if lineno != row {
+                return Line::raw(line);
+            }
+
+            // Lines are ASCII, so characters are bytes
+            let end = columns.end.min(line.len());
+            let start = columns.start.min(end);
+            let cursor = (Style::new().fg(Color::Black).bg(Color::Yellow))
+                .remove_modifier(Modifier::REVERSED);
+            Line::from(vec![
+                Span::raw(line[..start].to_owned()),
+                Span::styled(line[start..end].to_owned(), cursor),
+                Span::raw(line[end..].to_owned()),
+            ])
+            .style(Style::new().add_modifier(Modifier::REVERSED))
+        })
+        .collect();
+
+    let title = format!("Pattern {} (editing)", editor.pattern());
+    frame.render_widget(
+        Paragraph::new(rows).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+// One oscilloscope per channel, side by side
+fn draw_scopes(frame: &mut Frame, waveforms: &[Vec<f32>], area: Rect) {
+    let columns = Layout::horizontal(vec![
+        Constraint::Ratio(1, waveforms.len().max(1) as u32);
+        waveforms.len()
+    ])
+    .split(area);
+
+    for (channel, (waveform, &column)) in waveforms.iter().zip(columns.iter()).enumerate() {
+        let coords: Vec<(f64, f64)> = waveform
+            .iter()
+            .enumerate()
+            .map(|(x, &y)| (x as f64, y as f64))
+            .collect();
+
+        let canvas = Canvas::default()
+            .block(Block::bordered().title(format!("Ch {}", channel + 1)))
+            .marker(Marker::Braille)
+            .x_bounds([0.0, waveform.len() as f64])
+            .y_bounds([-1.0, 1.0])
+            .paint(|ctx| {
+                ctx.draw(&Points {
+                    coords: &coords,
+                    color: Color::Reset,
+                })
+            });
+
+        frame.render_widget(canvas, column);
+    }
+}
+
+// One bar per band, as wide as fits the area
+fn draw_spectrum(frame: &mut Frame, levels: &[f32], area: Rect) {
+    const GAP: u16 = 1;
+    let bands = levels.len() as u16;
+    let inner = area.width.saturating_sub(2);
+    let width = (inner.saturating_sub(GAP * bands.saturating_sub(1)) / bands.max(1)).max(1);
+
+    let bars: Vec<Bar> = levels
+        .iter()
+        .map(|level| {
+            Bar::default()
+                .value((level * 100.0) as u64)
+                .text_value(String::new())
+        })
+        .collect();
+
+    let chart = BarChart::default()
+        .block(Block::bordered().title("Spectrum"))
+        .data(BarGroup::default().bars(&bars))
+        .bar_width(width)
+        .bar_gap(GAP)
+        .max(100);
+
+    frame.render_widget(chart, area);
+}
+
+// One lane per channel, one above the other, with every row a step to the right and notes
+// as high as their pitch
+fn draw_piano_roll(
+    frame: &mut Frame,
+    view: &PatternView,
+    roll: &VecDeque<Vec<ChannelSnapshot>>,
+    muted: &[bool],
+    area: Rect,
+) {
+    let lanes = Layout::vertical(vec![
+        Constraint::Ratio(1, view.channels.max(1) as u32);
+        view.channels
+    ])
+    .split(area);
+    // The newest row ends at the right edge
+    let start = ROLL_LENGTH - roll.len();
+
+    for (channel, &lane) in lanes.iter().enumerate() {
+        let muted = muted.get(channel).copied().unwrap_or(false);
+
+        let canvas = Canvas::default()
+            .block(Block::bordered().title(format!("Ch {}", channel + 1)))
+            .marker(Marker::Braille)
+            .x_bounds([0.0, ROLL_LENGTH as f64])
+            .y_bounds([view.pitches.0 as f64, view.pitches.1 as f64])
+            .paint(|ctx| {
+                for (x, channels) in roll.iter().enumerate() {
+                    let Some(snapshot) = channels.get(channel) else {
+                        continue;
+                    };
+                    let Some(pitch) = snapshot.pitch() else {
+                        continue;
+                    };
+
+                    let color = if muted {
+                        Color::DarkGray
+                    } else {
+                        SAMPLE_COLORS[snapshot.sample as usize % SAMPLE_COLORS.len()]
+                    };
+                    let x = (start + x) as f64;
+                    ctx.draw(&canvas::Line::new(
+                        x,
+                        pitch as f64,
+                        x + 1.0,
+                        pitch as f64,
+                        color,
+                    ));
+                }
+            });
+
+        frame.render_widget(canvas, lane);
+    }
+}
@@ -0,0 +1,117 @@
+//! Playing songs in the browser through [wasm-bindgen](https://docs.rs/wasm-bindgen)
+//!
+//! Build the library as a `cdylib` and run `wasm-bindgen` on the result:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown \
+//!     --no-default-features --features wasm --crate-type cdylib
+//! ```
+//!
+//! The player only renders audio, feeding it to Web Audio is left to JavaScript, typically
+//! from the `process` method of an `AudioWorkletProcessor`:
+//!
+//! ```js
+//! process(inputs, outputs) {
+//!     const [left, right] = outputs[0];
+//!     this.player.render_planar(left, right);
+//!     return !this.player.is_finished();
+//! }
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::engine::{Engine, TrackerEngine};
+use crate::song::Song;
+
+/// A song played in stereo, for use from JavaScript
+#[wasm_bindgen]
+pub struct WasmPlayer {
+    engine: Engine,
+    title: String,
+    // Interleaved frames for `render_planar`, kept between calls so rendering doesn't allocate
+    scratch: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl WasmPlayer {
+    /// Loads a module from the bytes of its file, to be played at `sample_rate`
+    /// (`AudioContext.sampleRate`)
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<u8>, sample_rate: u32) -> Result<WasmPlayer, JsError> {
+        let song = Song::from_bytes(data)?;
+        let title = song.metadata.name.clone();
+
+        let mut engine = Engine::new(song)?;
+        engine.set_channel_count(2);
+        engine.set_sample_rate(sample_rate);
+
+        Ok(WasmPlayer {
+            engine,
+            title,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Renders interleaved stereo into `buffer`, returning the amount of frames rendered.
+    /// That's less than requested once the song finished, the rest is filled with silence
+    pub fn render_into(&mut self, buffer: &mut [f32]) -> usize {
+        self.engine.render(buffer)
+    }
+
+    /// Renders into separate buffers for the left and right channel, the layout Web Audio
+    /// uses. Returns the amount of frames rendered like `render_into`
+    pub fn render_planar(&mut self, left: &mut [f32], right: &mut [f32]) -> usize {
+        let frames = left.len().min(right.len());
+        self.scratch.resize(frames * 2, 0.0);
+
+        let rendered = self.engine.render(&mut self.scratch);
+        for (i, frame) in self.scratch.chunks_exact(2).enumerate() {
+            left[i] = frame[0];
+            right[i] = frame[1];
+        }
+
+        rendered
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.engine.is_finished()
+    }
+
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// The order (position in the pattern table) that plays next
+    pub fn order(&self) -> usize {
+        self.engine.position().0
+    }
+
+    /// The row that plays next
+    pub fn row(&self) -> usize {
+        self.engine.position().1
+    }
+
+    /// Continues playback from the given order and row, returns false if it's outside the song
+    pub fn seek(&mut self, order: usize, row: usize) -> bool {
+        self.engine.seek(order, row)
+    }
+
+    /// Length of a single playthrough of the song in seconds
+    pub fn duration(&self) -> f64 {
+        self.engine.duration()
+    }
+
+    /// How far into that playthrough the current position is, if it's reached at all
+    pub fn elapsed(&self) -> Option<f64> {
+        self.engine.elapsed()
+    }
+
+    /// How many times the song is played before it ends, 0 loops forever
+    pub fn set_loop_count(&mut self, count: u32) {
+        self.engine.set_loop_count(count);
+    }
+
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.engine.set_channel_muted(channel, muted);
+    }
+}
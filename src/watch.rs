@@ -0,0 +1,45 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::PlaybackEvent;
+
+// How often the modification times are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches `paths` for changes on a background thread, sending
+/// [`PlaybackEvent::Changed`] with the index of a file once it has been modified.
+///
+/// The event is only sent once the file stopped changing for a poll interval, so a
+/// file that is still being written isn't reloaded halfway. Watching stops when the
+/// receiving end of `events` is dropped.
+pub fn watch(paths: Vec<PathBuf>, events: Sender<PlaybackEvent>) {
+    thread::spawn(move || {
+        let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        let mut times: Vec<Option<SystemTime>> = paths.iter().map(modified).collect();
+        // Files that changed in the previous poll, waiting to settle
+        let mut changed = vec![false; paths.len()];
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            for (index, path) in paths.iter().enumerate() {
+                let time = modified(path);
+
+                if time != times[index] {
+                    times[index] = time;
+                    changed[index] = true;
+                } else if changed[index] {
+                    changed[index] = false;
+
+                    if events.send(PlaybackEvent::Changed(index)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the integration tests
+
+use rustune::engine::{Engine, Mixer};
+use rustune::song::Note;
+use rustune::{Song, TrackerEngine};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Renders the whole song in stereo with the fixed-point mixer, which gives the same
+/// output on every platform
+pub fn render(song: Song) -> Vec<f32> {
+    let mut engine = Engine::with_mixer(song, Mixer::FixedPoint).unwrap();
+    engine.set_channel_count(2);
+    engine.set_sample_rate(SAMPLE_RATE);
+
+    let mut output = Vec::new();
+    let mut buffer = vec![0.0; 4096];
+    while !engine.is_finished() {
+        let frames = engine.render(&mut buffer);
+        output.extend_from_slice(&buffer[..frames * 2]);
+    }
+
+    output
+}
+
+/// A note that only has an effect
+pub fn effect(effect: u8, argument: u8) -> Note {
+    Note {
+        sample: 0,
+        period: 0,
+        effect,
+        argument,
+    }
+}
@@ -0,0 +1,383 @@
+//! Renders small modules and compares them against reference renders, to check that
+//! effects play the way ProTracker plays them.
+//!
+//! Every module plays a sample that holds a single value, so the output only depends on
+//! volume and timing, not on resampling. The references are written out by hand from how
+//! the effects are documented to behave, as levels held for a number of frames. Rendering
+//! uses the fixed-point mixer, so it's the same on every platform.
+
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{effect, render, SAMPLE_RATE};
+use rustune::engine::{Engine, Mixer, PanLaw};
+use rustune::song::{Note, PCMData};
+use rustune::{Song, SongBuilder, TrackerEngine};
+
+// Frames in a tick at the default tempo of 125 BPM (2.5 / 125 seconds)
+const TICK: usize = 882;
+const SPEED: usize = 6;
+const ROW: usize = TICK * SPEED;
+
+// The value every sample of the test instrument holds
+const LEVEL: i8 = 64;
+// C-1, the lowest note, so the instrument lasts as long as possible
+const PERIOD: u16 = 1712;
+// Enough for a whole pattern at the default speed and tempo
+const LENGTH: usize = 20000;
+
+// Largest difference allowed between a rendered and a reference sample, about one step of
+// 16-bit output. Covers rounding in the mixer, not timing or volume that's off
+const TOLERANCE: f32 = 1.0 / 32768.0;
+
+/// A level held for a number of frames in the reference
+struct Segment {
+    frames: usize,
+    left: f32,
+    right: f32,
+}
+
+/// The test instrument playing at `volume` on a channel panned to the middle
+fn level(frames: usize, volume: u8) -> Segment {
+    let value = LEVEL as f32 / 128.0 * volume as f32 / 64.0;
+    let pan = 128.0 / 255.0;
+
+    Segment {
+        frames,
+        left: value * (1.0 - pan),
+        right: value * pan,
+    }
+}
+
+fn silence(frames: usize) -> Segment {
+    Segment {
+        frames,
+        left: 0.0,
+        right: 0.0,
+    }
+}
+
+fn note(effect: u8, argument: u8) -> Note {
+    Note {
+        sample: 1,
+        period: PERIOD,
+        effect,
+        argument,
+    }
+}
+
+/// A song with the test instrument and `patterns` empty patterns, played in order
+fn song(patterns: usize) -> SongBuilder {
+    let mut builder = SongBuilder::new(4).sample("Level", PCMData::I8(vec![LEVEL; LENGTH]));
+
+    for _ in 0..patterns {
+        builder = builder.empty_pattern();
+    }
+
+    let orders: Vec<u8> = (0..patterns as u8).collect();
+    builder.orders(&orders)
+}
+
+/// Renders `song` and compares it with the reference, frame by frame
+fn assert_conforms(name: &str, song: Song, reference: &[Segment]) {
+    let rendered = render(song);
+
+    let mut expected = Vec::new();
+    for segment in reference {
+        for _ in 0..segment.frames {
+            expected.extend_from_slice(&[segment.left, segment.right]);
+        }
+    }
+
+    let mismatch = (rendered.iter().zip(&expected))
+        .position(|(rendered, expected)| (rendered - expected).abs() > TOLERANCE);
+    if let Some(index) = mismatch {
+        let frame = index / 2;
+        panic!(
+            "{name}: frame {frame} ({:.3}s, tick {}) is {}, the reference has {}",
+            frame as f64 / SAMPLE_RATE as f64,
+            frame / TICK,
+            rendered[index],
+            expected[index]
+        );
+    }
+
+    assert_eq!(
+        rendered.len() / 2,
+        expected.len() / 2,
+        "{name}: the render and the reference differ in length"
+    );
+}
+
+#[test]
+fn set_volume() {
+    let song = song(1)
+        .note(0, 0, 0, note(0xC, 32))
+        .note(0, 1, 0, effect(0xC, 16))
+        // Volumes above 64 play at 64
+        .note(0, 2, 0, effect(0xC, 80))
+        .build()
+        .unwrap();
+
+    assert_conforms(
+        "C20, C10, C50",
+        song,
+        &[level(ROW, 32), level(ROW, 16), level(ROW * 62, 64)],
+    );
+}
+
+#[test]
+fn volume_slide() {
+    // Slides on every tick but the first of the row
+    let song = song(1)
+        .note(0, 0, 0, note(0xC, 32))
+        .note(0, 1, 0, effect(0xA, 0x20))
+        .note(0, 2, 0, effect(0xA, 0x03))
+        .build()
+        .unwrap();
+
+    let mut reference = vec![level(ROW + TICK, 32)];
+    reference.extend((1..SPEED as u8).map(|tick| level(TICK, 32 + tick * 2)));
+    reference.push(level(TICK, 42));
+    reference.extend((1..SPEED as u8).map(|tick| level(TICK, 42 - tick * 3)));
+    reference.push(level(ROW * 61, 27));
+
+    assert_conforms("A20, A03", song, &reference);
+}
+
+#[test]
+fn set_speed() {
+    let song = song(1)
+        .note(0, 0, 0, note(0xF, 3))
+        .note(0, 1, 0, effect(0xC, 16))
+        .build()
+        .unwrap();
+
+    assert_conforms(
+        "F03",
+        song,
+        &[level(TICK * 3, 64), level(TICK * 3 * 63, 16)],
+    );
+}
+
+#[test]
+fn set_tempo() {
+    // A tick lasts 2.5 / 150 seconds at 150 BPM
+    let tick = SAMPLE_RATE as usize * 25 / 1500;
+    let song = song(1)
+        .note(0, 0, 0, note(0xF, 150))
+        .note(0, 1, 0, effect(0xC, 16))
+        .build()
+        .unwrap();
+
+    assert_conforms(
+        "F96",
+        song,
+        &[level(tick * SPEED, 64), level(tick * SPEED * 63, 16)],
+    );
+}
+
+#[test]
+fn pattern_break() {
+    // Breaks to row 62 of the next pattern, which plays its last two rows
+    let song = song(2)
+        .note(0, 0, 0, note(0xD, 0x62))
+        .note(1, 63, 0, effect(0xC, 16))
+        .build()
+        .unwrap();
+
+    assert_conforms("D62", song, &[level(ROW * 2, 64), level(ROW, 16)]);
+}
+
+#[test]
+fn position_jump() {
+    // Jumps over the second order, to the last row of the third
+    let song = song(3)
+        .note(0, 0, 0, note(0xB, 2))
+        .note(0, 0, 1, effect(0xD, 0x63))
+        .note(1, 0, 0, effect(0xC, 0))
+        .note(2, 63, 0, effect(0xC, 16))
+        .build()
+        .unwrap();
+
+    assert_conforms("B02 D63", song, &[level(ROW, 64), level(ROW, 16)]);
+}
+
+#[test]
+fn silent_until_first_note() {
+    let song = song(1).note(0, 2, 0, note(0, 0)).build().unwrap();
+
+    assert_conforms(
+        "Note on row 2",
+        song,
+        &[silence(ROW * 2), level(ROW * 62, 64)],
+    );
+}
+
+#[test]
+#[ignore = "sample loops aren't played yet"]
+fn sample_loop() {
+    // Loops the last 32 bytes, so the instrument never ends
+    let song = SongBuilder::new(4)
+        .sample("Level", PCMData::I8(vec![LEVEL; 64]))
+        .sample_loop(0, 32, 32)
+        .empty_pattern()
+        .note(0, 0, 0, note(0, 0))
+        .build()
+        .unwrap();
+
+    assert_conforms("Loop", song, &[level(ROW * 64, 64)]);
+}
+
+// Renders `song` with the engine set up by `configure`, for behavior the defaults don't show
+fn render_with(song: Song, configure: impl FnOnce(&mut Engine)) -> Vec<f32> {
+    let mut engine = Engine::with_mixer(song, Mixer::FixedPoint).unwrap();
+    engine.set_channel_count(2);
+    engine.set_sample_rate(SAMPLE_RATE);
+    configure(&mut engine);
+
+    let mut output = Vec::new();
+    let mut buffer = vec![0.0; 4096];
+    while !engine.is_finished() {
+        let frames = engine.render(&mut buffer);
+        output.extend_from_slice(&buffer[..frames * 2]);
+        assert!(
+            output.len() < SAMPLE_RATE as usize * 60,
+            "The song didn't stop"
+        );
+    }
+
+    output
+}
+
+// Checks that the output fades out linearly over a second from `start`, ending within the
+// tick the fade completes in
+fn assert_fades_out(name: &str, output: &[f32], start: usize) {
+    let full = level(1, 64).left;
+    let left = |frame: usize| output[frame * 2];
+    assert!(
+        (left(start) - full).abs() <= TOLERANCE,
+        "{name}: faded early"
+    );
+
+    let fade = SAMPLE_RATE as usize;
+    for (fraction, frame) in [(0.75, start + fade / 4), (0.25, start + fade * 3 / 4)] {
+        let expected = full * fraction;
+        assert!(
+            (left(frame) - expected).abs() < full * 0.01,
+            "{name}: frame {frame} is {}, expected about {expected}",
+            left(frame)
+        );
+    }
+
+    let frames = output.len() / 2;
+    let end = start + fade;
+    assert!(
+        (end..end + TICK).contains(&frames),
+        "{name}: {frames} frames"
+    );
+    assert!(output[end * 2..].iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn fade_out_after_last_loop() {
+    // The note plays for longer than the pattern, it's played again while fading
+    let song = song(1).note(0, 0, 0, note(0, 0)).build().unwrap();
+    let output = render_with(song, |engine| engine.set_fade_out(1.0));
+
+    // The whole song plays at full volume, the fade starts where it loops
+    assert_fades_out("Fade at the loop", &output, ROW * 64);
+}
+
+#[test]
+fn fade_out_at_max_time() {
+    let song = song(1).note(0, 0, 0, note(0, 0)).build().unwrap();
+    let output = render_with(song, |engine| {
+        engine.set_fade_out(1.0);
+        engine.set_max_time(Some(2.0));
+    });
+
+    // The time limit is checked at the start of every tick, adding up their lengths may take
+    // one more
+    let full = level(1, 64).left;
+    let start = [100 * TICK, 101 * TICK]
+        .into_iter()
+        .find(|&start| output[(start + TICK / 2) * 2] < full - TOLERANCE)
+        .expect("The fade starts at 2 seconds");
+    assert_fades_out("Fade at the time limit", &output, start);
+}
+
+#[test]
+fn panning_override() {
+    let song = song(1).note(0, 0, 0, note(0, 0)).build().unwrap();
+    let output = render_with(song.clone(), |engine| {
+        engine.set_channel_panning(0, Some(0))
+    });
+
+    // Hard left, at the level both sides have together when centered
+    let centered = level(1, 64);
+    let (left, right) = (output[0], output[1]);
+    assert!((left - (centered.left + centered.right)).abs() <= TOLERANCE);
+    assert_eq!(right, 0.0);
+
+    // Swapped, the channel panned left is heard on the right
+    let swapped = render_with(song, |engine| {
+        engine.set_channel_panning(0, Some(0));
+        engine.set_swap_stereo(true);
+    });
+    assert_eq!((swapped[0], swapped[1]), (right, left));
+}
+
+#[test]
+fn constant_power_panning() {
+    let song = song(1).note(0, 0, 0, note(0, 0)).build().unwrap();
+    let power = |panning| {
+        let output = render_with(song.clone(), |engine| {
+            engine.set_pan_law(PanLaw::ConstantPower);
+            engine.set_channel_panning(0, Some(panning));
+        });
+        output[0] * output[0] + output[1] * output[1]
+    };
+
+    // Centered, the channel is as loud as hard-panned, where the linear law loses half
+    let hard = power(0);
+    assert_eq!(power(255), hard);
+    assert!(
+        (power(128) / hard - 1.0).abs() < 0.01,
+        "{}",
+        power(128) / hard
+    );
+}
+
+#[test]
+fn played_note() {
+    // A note played live sounds like the same note in the pattern, its effect applies at once
+    let written = render(song(1).note(0, 0, 0, note(0xC, 32)).build().unwrap());
+    let played = render_with(song(1).build().unwrap(), |engine| {
+        engine.play_note(0, note(0xC, 32))
+    });
+    assert_eq!(played, written);
+    assert!((played[0] - level(1, 32).left).abs() <= TOLERANCE);
+}
+
+#[test]
+fn stop_on_silence() {
+    // The note is over after a few ticks, but the song would loop forever
+    let song = SongBuilder::new(4)
+        .sample("Short", PCMData::I8(vec![LEVEL; 100]))
+        .empty_pattern()
+        .note(0, 0, 0, note(0, 0))
+        .build()
+        .unwrap();
+    let output = render_with(song, |engine| {
+        engine.set_loop_count(0);
+        engine.set_silence_limit(Some(1.0));
+    });
+
+    // It ends with the tick that completes a second of silence
+    let sound = output.iter().rposition(|&sample| sample != 0.0).unwrap() / 2 + 1;
+    let frames = output.len() / 2;
+    let limit = sound + SAMPLE_RATE as usize;
+    assert!((limit..limit + TICK).contains(&frames), "{frames} frames");
+}
@@ -0,0 +1,138 @@
+//! Renders a small module per effect and compares a hash of the output with the one it had
+//! when the test was written, so changes to the mixer or effects can't alter playback
+//! without anyone noticing.
+//!
+//! When a change is meant to alter playback, check the new output by ear and replace the
+//! hashes of the affected tests with the ones they report.
+
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{effect, render};
+use rustune::song::{Note, PCMData};
+use rustune::{Song, SongBuilder};
+
+// The song ends after this many rows, by a pattern break on the last one
+const ROWS: usize = 16;
+
+/// A song playing a sawtooth on the first channel, starting with `note` on row 0 and
+/// continuing with the effects in `rows`, given as (row, note)
+fn song(note: Note, rows: &[(usize, Note)]) -> Song {
+    let saw = (0..16384).map(|i| (i * 8 % 256) as u8 as i8).collect();
+
+    let mut builder = SongBuilder::new(4)
+        .sample("Saw", PCMData::I8(saw))
+        .empty_pattern()
+        .note(0, 0, 0, note)
+        .note(0, ROWS - 1, 3, effect(0xD, 0));
+
+    for (row, note) in rows {
+        builder = builder.note(0, *row, 0, note.clone());
+    }
+
+    builder.build().unwrap()
+}
+
+fn play(period: u16, effect: u8, argument: u8) -> Note {
+    Note {
+        sample: 1,
+        period,
+        effect,
+        argument,
+    }
+}
+
+// FNV-1a over the bytes of every sample, stable across platforms and Rust versions
+fn hash(output: &[f32]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in output.iter().flat_map(|sample| sample.to_le_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn assert_golden(name: &str, song: Song, expected: u64) {
+    let output = render(song);
+    let actual = hash(&output);
+
+    assert_eq!(
+        actual, expected,
+        "{name}: output changed, it hashes to {actual:#018x} now"
+    );
+}
+
+#[test]
+fn no_effect() {
+    assert_golden("No effect", song(play(428, 0, 0), &[]), 0xa96b72ed63b57274);
+}
+
+#[test]
+fn arpeggio() {
+    let rows = [(1, effect(0x0, 0x37)), (2, effect(0x0, 0x37))];
+    assert_golden("047", song(play(428, 0x0, 0x47), &rows), 0x7732c2007a96ffd3);
+}
+
+#[test]
+fn portamento_up() {
+    let rows = [(1, effect(0x1, 0x04)), (2, effect(0x1, 0x10))];
+    assert_golden("102", song(play(428, 0x1, 0x02), &rows), 0x86986b2d078c329c);
+}
+
+#[test]
+fn portamento_down() {
+    let rows = [(1, effect(0x2, 0x04)), (2, effect(0x2, 0x10))];
+    assert_golden("202", song(play(428, 0x2, 0x02), &rows), 0xcf49429f3f1274ff);
+}
+
+#[test]
+#[ignore = "3xx doesn't slide yet, its output is the same as without an effect"]
+fn tone_portamento() {
+    let rows = [(2, play(320, 0x3, 0x08)), (3, effect(0x3, 0x00))];
+    assert_golden("308", song(play(428, 0, 0), &rows), 0x7dc0d4a502e86115);
+}
+
+#[test]
+#[ignore = "4xy isn't implemented yet"]
+fn vibrato() {
+    let rows = [(1, effect(0x4, 0x48)), (2, effect(0x4, 0x00))];
+    assert_golden("4xy", song(play(428, 0x4, 0x84), &rows), 0xa96b72ed63b57274);
+}
+
+#[test]
+fn volume_slide() {
+    let rows = [(2, effect(0xA, 0x04)), (6, effect(0xA, 0x20))];
+    assert_golden("Axy", song(play(428, 0xA, 0x02), &rows), 0x2756c907b987dc0d);
+}
+
+#[test]
+fn set_volume() {
+    let rows = [(2, effect(0xC, 0x10)), (4, effect(0xC, 0x40))];
+    assert_golden("Cxx", song(play(428, 0xC, 0x20), &rows), 0xefd0834eac5e54bd);
+}
+
+#[test]
+fn fine_portamento() {
+    let rows = [(1, effect(0xE, 0x14)), (2, effect(0xE, 0x28))];
+    assert_golden("E1x E2x", song(play(428, 0, 0), &rows), 0xcd2309c350690974);
+}
+
+#[test]
+#[ignore = "E9x isn't implemented yet"]
+fn retrigger() {
+    assert_golden("E93", song(play(428, 0xE, 0x93), &[]), 0xa96b72ed63b57274);
+}
+
+#[test]
+fn speed_and_tempo() {
+    let rows = [(1, effect(0xF, 0x03)), (4, effect(0xF, 0x96))];
+    assert_golden("F03 F96", song(play(428, 0, 0), &rows), 0x524181db8f874344);
+}
+
+#[test]
+fn pattern_break() {
+    // Breaks out of the only order after 5 rows, which ends the song
+    let rows = [(4, effect(0xD, 0x08))];
+    assert_golden("D08", song(play(428, 0, 0), &rows), 0xcaec2e555c286e8b);
+}